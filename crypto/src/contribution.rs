@@ -54,6 +54,14 @@ pub enum ContributionsError {
     InvalidContributionCount(usize, usize),
 }
 
+#[derive(Clone, Copy, PartialEq, Debug, Error)]
+pub enum TranscriptError {
+    #[error("Inconsistent number of potPubkeys: expected {0}, got {1}")]
+    InconsistentNumPubKeys(usize, usize),
+    #[error("Invalid potPubkey for contribution {0}")]
+    InvalidPubKey(usize),
+}
+
 #[derive(Clone, Copy, PartialEq, Debug, Error)]
 pub enum ContributionError {
     #[error("Unexpected number of G1 powers: expected {0}, got {1}")]
@@ -70,6 +78,18 @@ pub enum ContributionError {
     InvalidG2Power(usize, #[source] ParseError),
     #[error("Error parsing potPubkey: {0}")]
     InvalidPubKey(#[source] ParseError),
+    #[error("Transcript without contributions is not in the canonical initial state")]
+    NonCanonicalInitialTranscript,
+    #[error("Invalid G2 subset: {0} G2 powers for {1} G1 powers")]
+    InvalidG2Subset(usize, usize),
+    #[error("potPubkey is inconsistent with the previous product")]
+    PubKeyPairingFailed,
+    #[error("G1 powers are inconsistent with the G2 subset")]
+    G1PairingFailed,
+    #[error("G2 powers are inconsistent with the G1 powers")]
+    G2PairingFailed,
+    #[error("Transcript is invalid: {0}")]
+    InvalidTranscript(#[source] TranscriptError),
 }
 
 impl ContributionsJson {
@@ -212,6 +232,57 @@ impl Transcript {
             g2_powers: vec![G2Affine::prime_subgroup_generator(); num_g2],
         }
     }
+
+    // The initial transcript only contains the generators
+    #[must_use]
+    pub fn is_initial(&self) -> bool {
+        *self == Self::new(self.g1_powers.len(), self.g2_powers.len())
+    }
+
+    // Every contribution, including the initial one, contributes exactly one
+    // product and one pubkey. A mismatch means the transcript was tampered with.
+    // The pubkeys are checked in parallel, and the first invalid one is
+    // reported.
+    #[instrument(level = "info", skip_all, fields(n=self.products.len()))]
+    pub fn verify_pubkeys(&self) -> Result<(), TranscriptError> {
+        if self.pubkeys.len() != self.products.len() {
+            return Err(TranscriptError::InconsistentNumPubKeys(
+                self.products.len(),
+                self.pubkeys.len(),
+            ));
+        }
+        let invalid = self
+            .products
+            .par_windows(2)
+            .zip(self.pubkeys[1..].par_iter())
+            .position_first(|(products, pubkey)| {
+                !pubkey.is_in_correct_subgroup_assuming_on_curve()
+                    || Bls12_381::pairing(products[1], G2Affine::prime_subgroup_generator())
+                        != Bls12_381::pairing(products[0], *pubkey)
+            });
+        match invalid {
+            Some(i) => Err(TranscriptError::InvalidPubKey(i + 1)),
+            None => Ok(()),
+        }
+    }
+
+    // Checks the whole chain of pubkeys before checking the contribution
+    // against the head. This repeats work for every contribution, so it is
+    // quadratic over the ceremony. `Contribution::verify` alone only checks
+    // the delta against the head.
+    pub fn verify(&self, contribution: &Contribution) -> Result<(), ContributionError> {
+        self.verify_pubkeys()
+            .map_err(ContributionError::InvalidTranscript)?;
+        contribution.verify(self)
+    }
+
+    // Makes the contribution the new head
+    pub fn add(&mut self, contribution: Contribution) {
+        self.products.push(contribution.g1_powers[1]);
+        self.pubkeys.push(contribution.pubkey);
+        self.g1_powers = contribution.g1_powers;
+        self.g2_powers = contribution.g2_powers;
+    }
 }
 
 impl Contribution {
@@ -277,49 +348,77 @@ impl Contribution {
         self.g2_powers = G2Projective::batch_normalization_into_affine(&projective[..]);
     }
 
+    // The G2 powers are only a prefix of the powers of tau, as the KZG setup
+    // needs far fewer of them. The G1 chain is checked against `tau` in G2,
+    // and the G2 subset is checked against the matching G1 prefix. Only the
+    // delta since the head is checked: the pubkey must take the last product
+    // to the new first power, and every earlier contribution is trusted to
+    // have been verified when it was added.
     #[instrument(level = "info", skip_all)]
-    pub fn verify(&self, transcript: &Transcript) {
-        assert_eq!(self.g1_powers.len(), transcript.g1_powers.len());
-        assert_eq!(self.g2_powers.len(), transcript.g2_powers.len());
-        self.verify_pubkey(transcript.products.last().unwrap());
-        self.verify_g1();
-        self.verify_g2();
+    pub fn verify(&self, transcript: &Transcript) -> Result<(), ContributionError> {
+        if self.g1_powers.len() != transcript.g1_powers.len() {
+            return Err(ContributionError::UnexpectedNumG1Powers(
+                transcript.g1_powers.len(),
+                self.g1_powers.len(),
+            ));
+        }
+        if self.g2_powers.len() != transcript.g2_powers.len() {
+            return Err(ContributionError::UnexpectedNumG2Powers(
+                transcript.g2_powers.len(),
+                self.g2_powers.len(),
+            ));
+        }
+        if self.g2_powers.len() < 2 || self.g2_powers.len() > self.g1_powers.len() {
+            return Err(ContributionError::InvalidG2Subset(
+                self.g2_powers.len(),
+                self.g1_powers.len(),
+            ));
+        }
+        // The first contribution has to build on the canonical initial state
+        if transcript.products.len() == 1 && !transcript.is_initial() {
+            return Err(ContributionError::NonCanonicalInitialTranscript);
+        }
+        self.verify_pubkey(transcript.products.last().unwrap())?;
+        self.verify_g1()?;
+        self.verify_g2()
     }
 
     #[instrument(level = "info", skip_all)]
-    fn verify_pubkey(&self, prev_product: &G1Affine) {
-        assert_eq!(
-            Bls12_381::pairing(self.g1_powers[1], G2Affine::prime_subgroup_generator()),
-            Bls12_381::pairing(*prev_product, self.pubkey)
-        );
+    fn verify_pubkey(&self, prev_product: &G1Affine) -> Result<(), ContributionError> {
+        if Bls12_381::pairing(self.g1_powers[1], G2Affine::prime_subgroup_generator())
+            != Bls12_381::pairing(*prev_product, self.pubkey)
+        {
+            return Err(ContributionError::PubKeyPairingFailed);
+        }
+        Ok(())
     }
 
     #[instrument(level = "info", skip_all)]
-    fn verify_g1(&self) {
+    fn verify_g1(&self) -> Result<(), ContributionError> {
         let (factors, sum) = random_factors(self.g1_powers.len() - 1);
         let lhs_g1 = VariableBaseMSM::multi_scalar_mul(&self.g1_powers[1..], &factors[..]);
         let lhs_g2 = G2Affine::prime_subgroup_generator().mul(sum);
         let rhs_g1 =
             VariableBaseMSM::multi_scalar_mul(&self.g1_powers[..factors.len()], &factors[..]);
         let rhs_g2 = self.g2_powers[1].mul(sum);
-        assert_eq!(
-            Bls12_381::pairing(lhs_g1, lhs_g2),
-            Bls12_381::pairing(rhs_g1, rhs_g2)
-        );
+        if Bls12_381::pairing(lhs_g1, lhs_g2) != Bls12_381::pairing(rhs_g1, rhs_g2) {
+            return Err(ContributionError::G1PairingFailed);
+        }
+        Ok(())
     }
 
     #[instrument(level = "info", skip_all)]
-    fn verify_g2(&self) {
+    fn verify_g2(&self) -> Result<(), ContributionError> {
         let (factors, sum) = random_factors(self.g2_powers.len());
         let lhs_g1 =
             VariableBaseMSM::multi_scalar_mul(&self.g1_powers[..factors.len()], &factors[..]);
         let lhs_g2 = G2Affine::prime_subgroup_generator().mul(sum);
         let rhs_g1 = G1Affine::prime_subgroup_generator().mul(sum);
         let rhs_g2 = VariableBaseMSM::multi_scalar_mul(&self.g2_powers[..], &factors[..]);
-        assert_eq!(
-            Bls12_381::pairing(lhs_g1, lhs_g2),
-            Bls12_381::pairing(rhs_g1, rhs_g2)
-        );
+        if Bls12_381::pairing(lhs_g1, lhs_g2) != Bls12_381::pairing(rhs_g1, rhs_g2) {
+            return Err(ContributionError::G2PairingFailed);
+        }
+        Ok(())
     }
 }
 
@@ -345,10 +444,170 @@ pub mod test {
     fn verify() {
         let mut transcript = Transcript::new(32768, 65);
         let mut contrib = Contribution::new(32768, 65);
-        contrib.verify(&transcript);
+        assert_eq!(contrib.verify(&transcript), Ok(()));
         let mut rng = rand::thread_rng();
         contrib.add_tau(&Fr::rand(&mut rng));
-        contrib.verify(&transcript);
+        assert_eq!(contrib.verify(&transcript), Ok(()));
+    }
+
+    #[test]
+    fn verify_first_contribution_against_initial_transcript() {
+        let transcript = Transcript::new(16, 2);
+        assert!(transcript.is_initial());
+        let mut contrib = Contribution::new(16, 2);
+        contrib.add_tau(&Fr::rand(&mut rand::thread_rng()));
+        assert_eq!(contrib.verify(&transcript), Ok(()));
+
+        // A transcript that claims no contributions but has non-generator powers
+        let mut tampered = Transcript::new(16, 2);
+        tampered.g1_powers = contrib.g1_powers.clone();
+        assert!(!tampered.is_initial());
+        assert_eq!(
+            contrib.verify(&tampered),
+            Err(ContributionError::NonCanonicalInitialTranscript)
+        );
+    }
+
+    #[test]
+    fn verify_g2_subset() {
+        let transcript = Transcript::new(16, 2);
+        let mut contrib = Contribution::new(16, 2);
+        contrib.add_tau(&Fr::rand(&mut rand::thread_rng()));
+        assert_eq!(contrib.verify(&transcript), Ok(()));
+    }
+
+    #[test]
+    fn verify_rejects_tampered_g2_subset() {
+        let transcript = Transcript::new(16, 3);
+        let mut contrib = Contribution::new(16, 3);
+        contrib.add_tau(&Fr::rand(&mut rand::thread_rng()));
+        contrib.g2_powers[2] = contrib.g2_powers[1];
+        assert_eq!(
+            contrib.verify(&transcript),
+            Err(ContributionError::G2PairingFailed)
+        );
+    }
+
+    #[test]
+    fn verify_rejects_g2_subset_longer_than_g1() {
+        let transcript = Transcript::new(2, 3);
+        let mut contrib = Contribution::new(2, 3);
+        contrib.add_tau(&Fr::rand(&mut rand::thread_rng()));
+        assert_eq!(
+            contrib.verify(&transcript),
+            Err(ContributionError::InvalidG2Subset(3, 2))
+        );
+    }
+
+    // The contribution that builds on the current head
+    fn next_contribution(transcript: &Transcript) -> Contribution {
+        let mut contrib = Contribution {
+            pubkey:    G2Affine::prime_subgroup_generator(),
+            g1_powers: transcript.g1_powers.clone(),
+            g2_powers: transcript.g2_powers.clone(),
+        };
+        contrib.add_tau(&Fr::rand(&mut rand::thread_rng()));
+        contrib
+    }
+
+    // Each contribution builds on the previous one, so the pubkeys link the
+    // products
+    fn transcript_with_contributions(n: usize) -> Transcript {
+        let mut transcript = Transcript::new(4, 2);
+        for _ in 0..n {
+            let contrib = next_contribution(&transcript);
+            transcript.add(contrib);
+        }
+        transcript
+    }
+
+    #[test]
+    fn incremental_verification_matches_full_verification() {
+        let mut transcript = Transcript::new(16, 3);
+        for _ in 0..3 {
+            let contrib = next_contribution(&transcript);
+            assert_eq!(transcript.verify(&contrib), Ok(()));
+            assert_eq!(contrib.verify(&transcript), Ok(()));
+            transcript.add(contrib);
+        }
+        assert_eq!(transcript.verify_pubkeys(), Ok(()));
+    }
+
+    #[test]
+    fn incremental_verification_rejects_tampered_delta() {
+        let mut transcript = Transcript::new(16, 3);
+        let contrib = next_contribution(&transcript);
+        transcript.add(contrib);
+        let next = next_contribution(&transcript);
+
+        let mut tampered = next.clone();
+        tampered.g1_powers[5] = tampered.g1_powers[4];
+        assert_eq!(
+            tampered.verify(&transcript),
+            Err(ContributionError::G1PairingFailed)
+        );
+
+        // Powers that don't build on the head are caught by the pubkey link
+        let mut unrelated = Contribution::new(16, 3);
+        unrelated.add_tau(&Fr::rand(&mut rand::thread_rng()));
+        unrelated.pubkey = next.pubkey;
+        assert_eq!(
+            unrelated.verify(&transcript),
+            Err(ContributionError::PubKeyPairingFailed)
+        );
+    }
+
+    #[test]
+    fn full_verification_rechecks_prefix() {
+        let mut transcript = transcript_with_contributions(2);
+        let contrib = next_contribution(&transcript);
+        transcript.pubkeys[1] = transcript.pubkeys[2];
+        assert_eq!(
+            transcript.verify(&contrib),
+            Err(ContributionError::InvalidTranscript(
+                TranscriptError::InvalidPubKey(1)
+            ))
+        );
+        // The incremental check trusts the prefix
+        assert_eq!(contrib.verify(&transcript), Ok(()));
+    }
+
+    #[test]
+    fn verify_pubkeys() {
+        assert_eq!(transcript_with_contributions(3).verify_pubkeys(), Ok(()));
+    }
+
+    #[test]
+    fn verify_pubkeys_rejects_missing_pubkey() {
+        let mut transcript = transcript_with_contributions(3);
+        transcript.pubkeys.pop();
+        assert_eq!(
+            transcript.verify_pubkeys(),
+            Err(TranscriptError::InconsistentNumPubKeys(4, 3))
+        );
+    }
+
+    #[test]
+    fn verify_pubkeys_rejects_extra_pubkey() {
+        let mut transcript = transcript_with_contributions(3);
+        transcript
+            .pubkeys
+            .push(G2Affine::prime_subgroup_generator());
+        assert_eq!(
+            transcript.verify_pubkeys(),
+            Err(TranscriptError::InconsistentNumPubKeys(4, 5))
+        );
+    }
+
+    #[test]
+    fn verify_pubkeys_rejects_swapped_pubkey() {
+        // Both swapped pubkeys are invalid, the first one is reported
+        let mut transcript = transcript_with_contributions(3);
+        transcript.pubkeys.swap(1, 2);
+        assert_eq!(
+            transcript.verify_pubkeys(),
+            Err(TranscriptError::InvalidPubKey(1))
+        );
     }
 }
 
@@ -6,9 +6,11 @@ mod contribution;
 mod crypto;
 mod zcash_format;
 
-pub use contribution::{Contribution, ContributionError, ContributionsError, Transcript};
+pub use contribution::{
+    Contribution, ContributionError, ContributionsError, Transcript, TranscriptError,
+};
 pub use crypto::{g1_subgroup_check, g2_subgroup_check};
-pub use zcash_format::{parse_g, ParseError};
+pub use zcash_format::{encode_g1, encode_g2, parse_g, ParseError};
 
 pub const SIZES: [(usize, usize); 4] = [(4096, 65), (8192, 65), (16384, 65), (32768, 65)];
 
@@ -1,3 +1,4 @@
+use ark_bls12_381::{Fq, G1Affine, G2Affine};
 use ark_ec::{
     models::{ModelParameters, SWModelParameters},
     short_weierstrass_jacobian::GroupAffine,
@@ -31,6 +32,8 @@ pub enum ParseError {
     InvalidXCoordinate,
     #[error("curve point is not in prime order subgroup")]
     InvalidSubgroup,
+    #[error("Point is encoded little-endian, expected big-endian")]
+    WrongEndianness,
 }
 
 pub fn parse_hex(hex: &str, out: &mut [u8]) -> Result<(), ParseError> {
@@ -47,8 +50,36 @@ pub fn parse_hex(hex: &str, out: &mut [u8]) -> Result<(), ParseError> {
 
 /// Deserialize a ZCash spec encoded group element.
 ///
+/// The encoding is big-endian, with the flags in the most significant bits of
+/// the first byte. Points that only decode after reversing the byte order are
+/// rejected with [`ParseError::WrongEndianness`] instead of being
+/// misinterpreted.
+///
 /// See <https://github.com/zcash/librustzcash/blob/6e0364cd42a2b3d2b958a54771ef51a8db79dd29/pairing/src/bls12_381/README.md#serialization>
 pub fn parse_g<P: SWModelParameters>(hex: &str) -> Result<GroupAffine<P>, ParseError> {
+    let mut bytes = vec![0u8; encoded_size::<P>()];
+    parse_hex(hex, &mut bytes)?;
+    parse_bytes::<P>(bytes.clone()).map_err(|error| {
+        bytes.reverse();
+        if parse_bytes::<P>(bytes).is_ok() {
+            ParseError::WrongEndianness
+        } else {
+            error
+        }
+    })
+}
+
+fn encoded_size<P: SWModelParameters>() -> usize {
+    type Extension<P> = <P as ModelParameters>::BaseField;
+    type Prime<P> = <Extension<P> as Field>::BasePrimeField;
+    type Int<P> = <Prime<P> as PrimeField>::BigInt;
+    let extension: usize = Extension::<P>::extension_degree()
+        .try_into()
+        .expect("Extension degree should fit usize.");
+    extension * Int::<P>::NUM_LIMBS * 8
+}
+
+fn parse_bytes<P: SWModelParameters>(mut bytes: Vec<u8>) -> Result<GroupAffine<P>, ParseError> {
     // Create some type aliases for the base extension, field and int types.
     type Extension<P> = <P as ModelParameters>::BaseField;
     type Prime<P> = <Extension<P> as Field>::BasePrimeField;
@@ -56,21 +87,13 @@ pub fn parse_g<P: SWModelParameters>(hex: &str) -> Result<GroupAffine<P>, ParseE
     let modulus = <Prime<P> as PrimeField>::Params::MODULUS;
 
     // Compute sizes
-    let extension: usize = Extension::<P>::extension_degree()
-        .try_into()
-        .expect("Extension degree should fit usize.");
     let element_size = Int::<P>::NUM_LIMBS * 8;
-    let size = extension * element_size;
     let padding_bits = element_size * 8 - modulus.num_bits() as usize;
     assert!(
         padding_bits >= 3,
         "ZCash encoding spec requires three prefix bits, but there is not enough padding."
     );
 
-    // Read hex string
-    let mut bytes = vec![0u8; size];
-    parse_hex(hex, &mut bytes)?;
-
     // Read and mask flags
     let compressed = bytes[0] & 0x80 != 0;
     let infinity = bytes[0] & 0x40 != 0;
@@ -121,11 +144,45 @@ pub fn parse_g<P: SWModelParameters>(hex: &str) -> Result<GroupAffine<P>, ParseE
     Ok(point)
 }
 
+/// Serialize a G1 element in the ZCash spec encoding, see [`parse_g`].
+#[must_use]
+pub fn encode_g1(point: &G1Affine) -> String {
+    encode_with_flags(point.infinity, point.y > -point.y, vec![point.x])
+}
+
+/// Serialize a G2 element in the ZCash spec encoding, see [`parse_g`].
+/// The extension field components are written `c1` first.
+#[must_use]
+pub fn encode_g2(point: &G2Affine) -> String {
+    encode_with_flags(point.infinity, point.y > -point.y, vec![
+        point.x.c1, point.x.c0,
+    ])
+}
+
+fn encode_with_flags(infinity: bool, greatest: bool, elements: Vec<Fq>) -> String {
+    let mut bytes = elements
+        .into_iter()
+        .flat_map(|element| {
+            if infinity { Fq::zero() } else { element }
+                .into_repr()
+                .to_bytes_be()
+        })
+        .collect::<Vec<_>>();
+    bytes[0] |= 0x80;
+    if infinity {
+        bytes[0] |= 0x40;
+    } else if greatest {
+        bytes[0] |= 0x20;
+    }
+    format!("0x{}", hex::encode(bytes))
+}
+
 #[cfg(test)]
 pub mod test {
     use super::*;
-    use ark_bls12_381::{G1Affine, G2Affine};
-    use ark_ec::AffineCurve;
+    use ark_bls12_381::{g1, g2, Fr};
+    use ark_ec::{AffineCurve, ProjectiveCurve};
+    use ark_ff::UniformRand;
 
     #[test]
     fn test_parse_g1() {
@@ -138,6 +195,49 @@ pub mod test {
         assert_eq!(parse_g("0xc00000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000").unwrap(), G2Affine::zero());
         assert_eq!(parse_g("0x93e02b6052719f607dacd3a088274f65596bd0d09920b61ab5da61bbdc7f5049334cf11213945d57e5ac7d055d042b7e024aa2b2f08f0a91260805272dc51051c6e47ad4fa403b02b4510b647ae3d1770bac0326a805bbefd48056c8c121bdb8").unwrap(), G2Affine::prime_subgroup_generator());
     }
+
+    #[test]
+    fn test_encode_round_trip() {
+        let mut rng = rand::thread_rng();
+        let g1 = G1Affine::prime_subgroup_generator()
+            .mul(Fr::rand(&mut rng))
+            .into_affine();
+        let g2 = G2Affine::prime_subgroup_generator()
+            .mul(Fr::rand(&mut rng))
+            .into_affine();
+        assert_eq!(encode_g1(&G1Affine::prime_subgroup_generator()), "0x97f1d3a73197d7942695638c4fa9ac0fc3688c4f9774b905a14e3a3f171bac586c55e83ff97a1aeffb3af00adb22c6bb");
+        assert_eq!(parse_g::<g1::Parameters>(&encode_g1(&g1)).unwrap(), g1);
+        assert_eq!(parse_g::<g2::Parameters>(&encode_g2(&g2)).unwrap(), g2);
+        assert_eq!(
+            parse_g::<g1::Parameters>(&encode_g1(&G1Affine::zero())).unwrap(),
+            G1Affine::zero()
+        );
+        assert_eq!(
+            parse_g::<g2::Parameters>(&encode_g2(&G2Affine::zero())).unwrap(),
+            G2Affine::zero()
+        );
+    }
+
+    // Reverses the byte order of an encoded point
+    fn reversed(hex: &str) -> String {
+        let mut bytes = hex::decode(&hex[2..]).unwrap();
+        bytes.reverse();
+        format!("0x{}", hex::encode(bytes))
+    }
+
+    #[test]
+    fn test_rejects_little_endian() {
+        let g1 = encode_g1(&G1Affine::prime_subgroup_generator());
+        let g2 = encode_g2(&G2Affine::prime_subgroup_generator());
+        assert_eq!(
+            parse_g::<g1::Parameters>(&reversed(&g1)),
+            Err(ParseError::WrongEndianness)
+        );
+        assert_eq!(
+            parse_g::<g2::Parameters>(&reversed(&g2)),
+            Err(ParseError::WrongEndianness)
+        );
+    }
 }
 
 #[cfg(feature = "bench")]
@@ -0,0 +1,3 @@
+-- When identity encryption is enabled, `uid` holds a keyed HMAC of the
+-- identity and the AEAD-sealed identity itself is kept here.
+ALTER TABLE contributors ADD COLUMN uid_sealed BLOB;
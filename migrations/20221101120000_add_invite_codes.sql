@@ -0,0 +1,6 @@
+-- Single-use codes for admitting lobby sessions during controlled launches
+CREATE TABLE IF NOT EXISTS invite_codes (
+    code          TEXT     PRIMARY KEY NOT NULL,
+    created_at    INTEGER              NOT NULL,
+    used_at       INTEGER
+);
@@ -0,0 +1,8 @@
+-- Rejected contributions, for security monitoring. Only a hash of the
+-- contribution is kept, together with the reason it failed verification.
+CREATE TABLE IF NOT EXISTS rejected_contributions (
+    uid                  TEXT     NOT NULL,
+    rejected_at          INTEGER  NOT NULL,
+    contribution_hash    TEXT     NOT NULL,
+    reason               TEXT     NOT NULL
+);
@@ -0,0 +1,9 @@
+-- One row per accepted contribution, exported for auditors at ceremony end.
+-- Identities are only kept as a SHA256 hash.
+CREATE TABLE IF NOT EXISTS attestations (
+    contribution_index    INTEGER  PRIMARY KEY NOT NULL,
+    identity_hash         TEXT                 NOT NULL,
+    attested_at           INTEGER              NOT NULL,
+    provider              TEXT                 NOT NULL,
+    pubkeys               TEXT                 NOT NULL
+);
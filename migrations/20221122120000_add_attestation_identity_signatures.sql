@@ -0,0 +1,3 @@
+-- Signature of Ethereum contributors over their contribution hash, made with
+-- the key of their authenticated address
+ALTER TABLE attestations ADD COLUMN identity_signature TEXT;
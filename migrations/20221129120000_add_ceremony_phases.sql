@@ -0,0 +1,7 @@
+-- Phases the ceremony moved through after its first, in order. The sequencer
+-- resumes in the most recent phase after a restart
+CREATE TABLE IF NOT EXISTS ceremony_phases (
+    phase         INTEGER  PRIMARY KEY NOT NULL,
+    name          TEXT                 NOT NULL,
+    started_at    INTEGER              NOT NULL
+);
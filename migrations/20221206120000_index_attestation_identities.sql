@@ -0,0 +1,2 @@
+-- Participation is looked up by identity hash, see `has_contributed`
+CREATE INDEX IF NOT EXISTS attestations_identity_hash ON attestations (identity_hash);
@@ -0,0 +1,2 @@
+-- When the contributor's spot runs out, moved back if an operator extends it
+ALTER TABLE contributors ADD COLUMN deadline_at INTEGER;
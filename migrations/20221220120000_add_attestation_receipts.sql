@@ -0,0 +1,3 @@
+-- The receipt handed out for the contribution, so it can still be retrieved
+-- after the ceremony is finalized or the sequencer restarted
+ALTER TABLE attestations ADD COLUMN receipt TEXT;
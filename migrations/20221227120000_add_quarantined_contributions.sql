@@ -0,0 +1,11 @@
+-- Rejected contributions kept in full, when quarantining is enabled, together
+-- with the transcript they were verified against, so that disputed rejections
+-- can be reproduced
+CREATE TABLE IF NOT EXISTS quarantined_contributions (
+    uid                  TEXT     NOT NULL,
+    quarantined_at       INTEGER  NOT NULL,
+    contribution_hash    TEXT     NOT NULL,
+    reason               TEXT     NOT NULL,
+    transcript_hash      TEXT     NOT NULL,
+    contribution         BLOB     NOT NULL
+);
@@ -0,0 +1,4 @@
+-- Attestations form a hash chain. Each one commits to a nonce generated by
+-- the sequencer and to the chain hash of the attestation before it.
+ALTER TABLE attestations ADD COLUMN nonce TEXT;
+ALTER TABLE attestations ADD COLUMN chain_hash TEXT;
@@ -0,0 +1,3 @@
+-- The contribution spot doesn't survive a restart, so its deadline was never
+-- read back
+ALTER TABLE contributors DROP COLUMN deadline_at;
@@ -0,0 +1,26 @@
+-- Contribution indices start over in a phase that resets the transcript, so
+-- attestations are keyed by phase and index. Attestations stored before are
+-- assigned the phase that had started when they were made.
+CREATE TABLE attestations_by_phase (
+    phase                 INTEGER  NOT NULL,
+    contribution_index    INTEGER  NOT NULL,
+    identity_hash         TEXT     NOT NULL,
+    attested_at           INTEGER  NOT NULL,
+    provider              TEXT     NOT NULL,
+    pubkeys               TEXT     NOT NULL,
+    identity_signature    TEXT,
+    receipt               TEXT,
+    nonce                 TEXT,
+    chain_hash            TEXT,
+    PRIMARY KEY (phase, contribution_index)
+);
+INSERT INTO attestations_by_phase (phase, contribution_index, identity_hash, attested_at,
+    provider, pubkeys, identity_signature, receipt, nonce, chain_hash)
+SELECT COALESCE((SELECT MAX(phase) FROM ceremony_phases
+                 WHERE ceremony_phases.started_at <= attestations.attested_at), 0),
+    contribution_index, identity_hash, attested_at, provider, pubkeys, identity_signature,
+    receipt, nonce, chain_hash
+FROM attestations;
+DROP TABLE attestations;
+ALTER TABLE attestations_by_phase RENAME TO attestations;
+CREATE INDEX IF NOT EXISTS attestations_identity_hash ON attestations (identity_hash);
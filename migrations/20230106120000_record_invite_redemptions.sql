@@ -0,0 +1,4 @@
+-- The identity that redeemed each invite code, so admission survives restarts
+-- and an identity never uses up more than one code
+ALTER TABLE invite_codes ADD COLUMN used_by TEXT;
+CREATE UNIQUE INDEX IF NOT EXISTS invite_codes_used_by ON invite_codes (used_by);
@@ -0,0 +1,3 @@
+-- What the contribution bundle is rebuilt from besides the receipt, see
+-- `BundleRecord`, so bundles can still be fetched after a restart
+ALTER TABLE attestations ADD COLUMN bundle TEXT;
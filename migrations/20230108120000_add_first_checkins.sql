@@ -0,0 +1,6 @@
+-- The first check-in of each identity, with the time it joined the lobby, so
+-- that a restart doesn't grant another check-in exempt from rate limiting
+CREATE TABLE IF NOT EXISTS first_checkins (
+    uid                  TEXT     PRIMARY KEY NOT NULL,
+    joined_at            DATETIME NOT NULL
+);
@@ -0,0 +1,145 @@
+use std::process::Stdio;
+
+use serde::Serialize;
+use tokio::{io::AsyncWriteExt, process::Command, time::Duration};
+use tracing::{info, warn};
+
+// What the admission hook is asked to decide, written to its stdin as JSON
+#[derive(Debug, Serialize)]
+#[serde(tag = "stage", rename_all = "snake_case")]
+pub enum AdmissionRequest<'a> {
+    // An identity is about to enter the lobby
+    Join {
+        uid:      &'a str,
+        provider: &'a str,
+        nickname: &'a str,
+    },
+    // The participant submitted a contribution
+    Contribution {
+        uid:               &'a str,
+        provider:          &'a str,
+        contribution_hash: &'a str,
+    },
+}
+
+// An operator supplied command that can veto lobby joins and contributions,
+// for admission rules such as membership in an organisation. It accepts by
+// exiting with 0. Its output is only logged, as it may contain details not
+// meant for participants, who are given a fixed reason instead.
+#[derive(Clone, Debug)]
+pub struct AdmissionHook {
+    // The program and its arguments
+    command:   Vec<String>,
+    timeout:   Duration,
+    // Whether to admit when the command can't be run or doesn't finish in
+    // time, rather than to reject
+    fail_open: bool,
+}
+
+impl AdmissionHook {
+    pub fn new(command: Vec<String>, timeout: Duration, fail_open: bool) -> Self {
+        Self {
+            command,
+            timeout,
+            fail_open,
+        }
+    }
+
+    // Returns the reason to give the participant if the request is rejected
+    pub async fn check(&self, request: &AdmissionRequest<'_>) -> Result<(), &'static str> {
+        let input = serde_json::to_vec(request).expect("Cannot serialize admission request");
+        match tokio::time::timeout(self.timeout, self.run(&input)).await {
+            Ok(Ok(output)) if output.status.success() => Ok(()),
+            Ok(Ok(output)) => {
+                let output = String::from_utf8_lossy(&output.stdout);
+                info!(output = output.trim(), "Admission hook rejected");
+                Err("rejected by policy")
+            }
+            Ok(Err(error)) => self.failed(&error.to_string()),
+            Err(_) => self.failed("timed out"),
+        }
+    }
+
+    async fn run(&self, input: &[u8]) -> std::io::Result<std::process::Output> {
+        let (program, args) = self
+            .command
+            .split_first()
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "no command"))?;
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()?;
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        // The command may decide without reading all of its input
+        stdin.write_all(input).await.ok();
+        drop(stdin);
+        child.wait_with_output().await
+    }
+
+    fn failed(&self, cause: &str) -> Result<(), &'static str> {
+        warn!(cause, fail_open = self.fail_open, "Admission hook failed");
+        if self.fail_open {
+            Ok(())
+        } else {
+            Err("admission check is unavailable")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Admits alice only
+    fn org_members_only(fail_open: bool) -> AdmissionHook {
+        let script =
+            r#"grep -q '"uid":"github | alice"' && exit 0; echo "not an org member"; exit 1"#;
+        AdmissionHook::new(
+            vec!["sh".to_string(), "-c".to_string(), script.to_string()],
+            Duration::from_secs(5),
+            fail_open,
+        )
+    }
+
+    fn join(uid: &str) -> AdmissionRequest<'_> {
+        AdmissionRequest::Join {
+            uid,
+            provider: "github",
+            nickname: "someone",
+        }
+    }
+
+    #[tokio::test]
+    async fn hook_accepts_and_rejects() {
+        let hook = org_members_only(false);
+        assert_eq!(hook.check(&join("github | alice")).await, Ok(()));
+        // The hook's own output isn't passed on
+        assert_eq!(
+            hook.check(&join("github | bob")).await,
+            Err("rejected by policy")
+        );
+    }
+
+    #[tokio::test]
+    async fn failing_hook_follows_the_configured_policy() {
+        let stalled = |fail_open| {
+            AdmissionHook::new(
+                vec!["sleep".to_string(), "10".to_string()],
+                Duration::from_millis(100),
+                fail_open,
+            )
+        };
+        assert!(stalled(false).check(&join("github | alice")).await.is_err());
+        assert!(stalled(true).check(&join("github | alice")).await.is_ok());
+
+        let missing = AdmissionHook::new(
+            vec!["/nonexistent/validator".to_string()],
+            Duration::from_secs(5),
+            false,
+        );
+        assert!(missing.check(&join("github | alice")).await.is_err());
+    }
+}
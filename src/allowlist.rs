@@ -0,0 +1,45 @@
+use std::{collections::BTreeSet, io, path::Path};
+
+// Reads a file with one permitted identity per line, e.g. `github | alice`.
+// Empty lines and lines starting with `#` are ignored.
+pub async fn read_allowlist(path: &Path) -> io::Result<BTreeSet<String>> {
+    let contents = tokio::fs::read_to_string(path).await?;
+    Ok(entries(&contents).map(ToOwned::to_owned).collect())
+}
+
+// Reads a file with one denied contribution hash per line, hex encoded with
+// or without `0x`. Empty lines and lines starting with `#` are ignored.
+pub async fn read_denylist(path: &Path) -> io::Result<BTreeSet<String>> {
+    let contents = tokio::fs::read_to_string(path).await?;
+    Ok(entries(&contents)
+        .map(|hash| hash.trim_start_matches("0x").to_lowercase())
+        .collect())
+}
+
+fn entries(contents: &str) -> impl Iterator<Item = &str> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn skips_comments_and_blank_lines() {
+        let mut path = std::env::temp_dir();
+        path.push("read_allowlist.txt");
+        tokio::fs::write(&path, "# invited\ngithub | alice\n\n  eth | 0xabc  \n")
+            .await
+            .unwrap();
+
+        let allowlist = read_allowlist(&path).await.unwrap();
+
+        assert_eq!(
+            allowlist,
+            BTreeSet::from(["github | alice".to_string(), "eth | 0xabc".to_string()])
+        );
+    }
+}
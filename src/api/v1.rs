@@ -1,4 +1,13 @@
+pub mod admin;
 pub mod auth;
 pub mod contribute;
+pub mod format;
+pub mod identity;
 pub mod info;
+pub mod limits;
 pub mod lobby;
+pub mod response_headers;
+pub mod sse;
+pub mod strict;
+pub mod timing;
+pub mod upload;
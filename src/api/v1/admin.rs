@@ -0,0 +1,1650 @@
+use async_session::async_trait;
+use axum::{
+    body::StreamBody,
+    extract::{FromRequest, RequestParts},
+    response::{IntoResponse, Response},
+    Extension, Json, TypedHeader,
+};
+use chrono::{DateTime, Utc};
+use futures::{stream, Stream};
+use headers::{authorization::Bearer, Authorization};
+use http::{header::CONTENT_TYPE, StatusCode};
+use once_cell::sync::Lazy;
+use prometheus::{register_gauge_vec, register_int_gauge_vec, GaugeVec, IntGaugeVec};
+use rand::RngCore;
+use ring::{
+    constant_time::verify_slices_are_equal,
+    digest::{Context, SHA256},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    cmp::{max, min},
+    io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+use tokio::time::{Duration, Instant};
+
+use crate::{
+    allowlist::{read_allowlist, read_denylist},
+    data::transcript::{write_transcript_file, VerifyAllError},
+    keys::KEYS,
+    merkle::OrderCommitment,
+    storage::{CeremonyPhase, PersistentStorage, StorageError},
+    verification::{CacheStats, SharedCache, VerificationLimiter},
+    AppConfig, SessionId, SessionInfo, SharedState, SharedTranscript, Transcript,
+};
+
+static LOBBY_WAIT_SECONDS: Lazy<GaugeVec> = Lazy::new(|| {
+    register_gauge_vec!(
+        "lobby_wait_seconds",
+        "Time sessions have spent in the lobby",
+        &["quantile"]
+    )
+    .unwrap()
+});
+
+static LOBBY_MISSED_CHECKINS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "lobby_missed_checkins",
+        "Number of lobby sessions by missed check-ins",
+        &["missed"]
+    )
+    .unwrap()
+});
+
+pub enum AdminError {
+    Disabled,
+    Unauthorized,
+}
+
+impl IntoResponse for AdminError {
+    fn into_response(self) -> Response {
+        let (status, body) = match self {
+            Self::Disabled => {
+                let body = Json(json!({"error": "admin endpoints are disabled"}));
+                (StatusCode::FORBIDDEN, body)
+            }
+            Self::Unauthorized => {
+                let body = Json(json!({"error": "invalid admin token"}));
+                (StatusCode::UNAUTHORIZED, body)
+            }
+        };
+        (status, body).into_response()
+    }
+}
+
+// Proof that the request carries the configured admin token.
+// Admin endpoints are disabled if no `ADMIN_TOKEN` is configured.
+pub struct Admin;
+
+#[async_trait]
+impl<B> FromRequest<B> for Admin
+where
+    B: Send,
+{
+    type Rejection = AdminError;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let Extension(config) = Extension::<AppConfig>::from_request(req)
+            .await
+            .map_err(|_| AdminError::Disabled)?;
+        let admin_token = config.admin_token.ok_or(AdminError::Disabled)?;
+
+        let TypedHeader(Authorization(bearer)) =
+            TypedHeader::<Authorization<Bearer>>::from_request(req)
+                .await
+                .map_err(|_| AdminError::Unauthorized)?;
+
+        verify_slices_are_equal(bearer.token().as_bytes(), admin_token.as_bytes())
+            .map_err(|_| AdminError::Unauthorized)?;
+        Ok(Self)
+    }
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct MissedCheckins {
+    none:        usize,
+    one:         usize,
+    two_or_more: usize,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct LobbyStats {
+    lobby_size:        usize,
+    wait_time_p50_sec: u64,
+    wait_time_p90_sec: u64,
+    wait_time_p99_sec: u64,
+    missed_checkins:   MissedCheckins,
+}
+
+impl IntoResponse for LobbyStats {
+    fn into_response(self) -> Response {
+        (StatusCode::OK, Json(self)).into_response()
+    }
+}
+
+impl LobbyStats {
+    pub fn compute<'a>(
+        sessions: impl Iterator<Item = &'a SessionInfo>,
+        now: Instant,
+        checkin_frequency: Duration,
+    ) -> Self {
+        let mut wait_times = Vec::new();
+        let mut missed_checkins = MissedCheckins {
+            none:        0,
+            one:         0,
+            two_or_more: 0,
+        };
+        for session_info in sessions {
+            wait_times.push(now.saturating_duration_since(session_info.joined_at));
+            let since_ping = now.saturating_duration_since(session_info.last_ping_time);
+            match since_ping.as_secs() / checkin_frequency.as_secs().max(1) {
+                0 => missed_checkins.none += 1,
+                1 => missed_checkins.one += 1,
+                _ => missed_checkins.two_or_more += 1,
+            }
+        }
+        wait_times.sort_unstable();
+
+        Self {
+            lobby_size: wait_times.len(),
+            wait_time_p50_sec: percentile(&wait_times, 50).as_secs(),
+            wait_time_p90_sec: percentile(&wait_times, 90).as_secs(),
+            wait_time_p99_sec: percentile(&wait_times, 99).as_secs(),
+            missed_checkins,
+        }
+    }
+
+    // Exports these statistics as Prometheus gauges
+    #[allow(clippy::cast_precision_loss)] // Wait times are far below 2^52 seconds
+    pub fn record(&self) {
+        for (quantile, wait_time) in [
+            ("0.5", self.wait_time_p50_sec),
+            ("0.9", self.wait_time_p90_sec),
+            ("0.99", self.wait_time_p99_sec),
+        ] {
+            LOBBY_WAIT_SECONDS
+                .with_label_values(&[quantile])
+                .set(wait_time as f64);
+        }
+        for (missed, count) in [
+            ("0", self.missed_checkins.none),
+            ("1", self.missed_checkins.one),
+            ("2+", self.missed_checkins.two_or_more),
+        ] {
+            LOBBY_MISSED_CHECKINS
+                .with_label_values(&[missed])
+                .set(i64::try_from(count).unwrap_or(i64::MAX));
+        }
+    }
+}
+
+// Nearest-rank percentile of an ascending list, zero if the list is empty
+fn percentile(sorted: &[Duration], percent: usize) -> Duration {
+    let rank = (percent * sorted.len() + 99) / 100;
+    sorted
+        .get(rank.saturating_sub(1))
+        .copied()
+        .unwrap_or_default()
+}
+
+// Reports how long sessions have been waiting in the lobby
+pub async fn lobby_stats(
+    _: Admin,
+    Extension(store): Extension<SharedState>,
+    Extension(config): Extension<AppConfig>,
+) -> LobbyStats {
+    let app_state = store.read().await;
+    let stats = LobbyStats::compute(
+        app_state.lobby.values(),
+        Instant::now(),
+        config.lobby_checkin_frequency,
+    );
+    stats.record();
+    stats
+}
+
+// Percentile of observed compute times the recommended deadline should cover
+const TUNING_PERCENTILE: usize = 95;
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct TuningSuggestion {
+    samples: usize,
+    current_compute_deadline_sec: u64,
+    p95_compute_time_sec: Option<u64>,
+    recommended_compute_deadline_sec: Option<u64>,
+}
+
+impl IntoResponse for TuningSuggestion {
+    fn into_response(self) -> Response {
+        (StatusCode::OK, Json(self)).into_response()
+    }
+}
+
+impl TuningSuggestion {
+    pub fn compute(compute_times: &[Duration], compute_deadline: Duration) -> Self {
+        let mut sorted = compute_times.to_vec();
+        sorted.sort_unstable();
+        let covered = (!sorted.is_empty()).then(|| percentile(&sorted, TUNING_PERCENTILE));
+        Self {
+            samples: sorted.len(),
+            current_compute_deadline_sec: compute_deadline.as_secs(),
+            p95_compute_time_sec: covered.map(|time| time.as_secs()),
+            // Round up, so the deadline never falls short of the percentile
+            recommended_compute_deadline_sec: covered
+                .map(|time| time.as_secs() + u64::from(time.subsec_nanos() > 0)),
+        }
+    }
+}
+
+// Recommends a compute deadline based on how long participants actually took.
+// This is advisory only and does not change the configured deadline.
+pub async fn tuning(
+    _: Admin,
+    Extension(store): Extension<SharedState>,
+    Extension(config): Extension<AppConfig>,
+) -> TuningSuggestion {
+    let app_state = store.read().await;
+    TuningSuggestion::compute(
+        &app_state.compute_times,
+        config.effective_compute_deadline(),
+    )
+}
+
+#[derive(Debug, Clone)]
+pub struct Drain {
+    pub reason: String,
+    pub since:  DateTime<Utc>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct DrainRequest {
+    reason: Option<String>,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct DrainStatus {
+    draining:               bool,
+    reason:                 Option<String>,
+    since:                  Option<DateTime<Utc>>,
+    contributor_active:     bool,
+    // Needed to expire the contributor, see `expire_current`
+    contributor_session_id: Option<SessionId>,
+    remaining_deadline_sec: Option<u64>,
+}
+
+impl IntoResponse for DrainStatus {
+    fn into_response(self) -> Response {
+        (StatusCode::OK, Json(self)).into_response()
+    }
+}
+
+// Stops granting contribution spots ahead of a planned shutdown, while
+// letting the current participant finish. Draining can not be undone.
+pub async fn drain(
+    admin: Admin,
+    request: Option<Json<DrainRequest>>,
+    Extension(store): Extension<SharedState>,
+) -> DrainStatus {
+    let request = request.map_or_else(DrainRequest::default, |Json(request)| request);
+    {
+        let mut app_state = store.write().await;
+        if app_state.drain.is_none() {
+            let reason = request
+                .reason
+                .unwrap_or_else(|| "planned shutdown".to_string());
+            tracing::info!(%reason, "Draining sequencer");
+            app_state.start_drain(Drain {
+                reason,
+                since: Utc::now(),
+            });
+        }
+    }
+    drain_status(admin, Extension(store)).await
+}
+
+// Resolves once the sequencer is draining and the contribution spot is
+// free, whether the last contributor finished, failed or was expired
+pub async fn await_drained(state: &SharedState) {
+    let drained = state.read().await.drained.clone();
+    drained.notified().await;
+}
+
+pub async fn drain_status(_: Admin, Extension(store): Extension<SharedState>) -> DrainStatus {
+    let app_state = store.read().await;
+    let now = Instant::now();
+    DrainStatus {
+        draining:               app_state.drain.is_some(),
+        reason:                 app_state.drain.as_ref().map(|drain| drain.reason.clone()),
+        since:                  app_state.drain.as_ref().map(|drain| drain.since),
+        contributor_active:     app_state.participant.is_some(),
+        contributor_session_id: app_state
+            .participant
+            .as_ref()
+            .map(|(session_id, _)| session_id.clone()),
+        remaining_deadline_sec: app_state
+            .participant_deadline
+            .map(|deadline| deadline.saturating_duration_since(now).as_secs()),
+    }
+}
+
+pub enum AllowlistError {
+    NotConfigured,
+    Unreadable(std::io::Error),
+}
+
+impl IntoResponse for AllowlistError {
+    fn into_response(self) -> Response {
+        let (status, body) = match self {
+            Self::NotConfigured => {
+                let body = Json(json!({"error": "no allowlist file is configured"}));
+                (StatusCode::BAD_REQUEST, body)
+            }
+            Self::Unreadable(error) => {
+                let body = Json(json!({
+                    "error": format!("could not read allowlist file: {}", error)
+                }));
+                (StatusCode::INTERNAL_SERVER_ERROR, body)
+            }
+        };
+        (status, body).into_response()
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct AllowlistReloaded {
+    size: usize,
+}
+
+impl IntoResponse for AllowlistReloaded {
+    fn into_response(self) -> Response {
+        (StatusCode::OK, Json(self)).into_response()
+    }
+}
+
+// Re-reads the allowlist file, so identities can be added or removed
+// without restarting the sequencer
+pub async fn reload_allowlist(
+    _: Admin,
+    Extension(store): Extension<SharedState>,
+    Extension(config): Extension<AppConfig>,
+) -> Result<AllowlistReloaded, AllowlistError> {
+    let path = config.allowlist_file.ok_or(AllowlistError::NotConfigured)?;
+    let allowlist = read_allowlist(&path)
+        .await
+        .map_err(AllowlistError::Unreadable)?;
+    let size = allowlist.len();
+    store.write().await.allowlist = Some(allowlist);
+    Ok(AllowlistReloaded { size })
+}
+
+pub enum DenylistError {
+    NotConfigured,
+    Unreadable(std::io::Error),
+}
+
+impl IntoResponse for DenylistError {
+    fn into_response(self) -> Response {
+        let (status, body) = match self {
+            Self::NotConfigured => {
+                let body = Json(json!({"error": "no denylist file is configured"}));
+                (StatusCode::BAD_REQUEST, body)
+            }
+            Self::Unreadable(error) => {
+                let body = Json(json!({
+                    "error": format!("could not read denylist file: {}", error)
+                }));
+                (StatusCode::INTERNAL_SERVER_ERROR, body)
+            }
+        };
+        (status, body).into_response()
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct DenylistReloaded {
+    size: usize,
+}
+
+impl IntoResponse for DenylistReloaded {
+    fn into_response(self) -> Response {
+        (StatusCode::OK, Json(self)).into_response()
+    }
+}
+
+// Re-reads the denylist file, so a contribution that starts circulating can
+// be rejected without restarting the sequencer
+pub async fn reload_denylist(
+    _: Admin,
+    Extension(store): Extension<SharedState>,
+    Extension(config): Extension<AppConfig>,
+) -> Result<DenylistReloaded, DenylistError> {
+    let path = config.denylist_file.ok_or(DenylistError::NotConfigured)?;
+    let denylist = read_denylist(&path)
+        .await
+        .map_err(DenylistError::Unreadable)?;
+    let size = denylist.len();
+    store.write().await.denylist = denylist;
+    Ok(DenylistReloaded { size })
+}
+
+#[derive(Debug, Serialize)]
+pub struct Caches {
+    verification: CacheStats,
+}
+
+impl IntoResponse for Caches {
+    fn into_response(self) -> Response {
+        (StatusCode::OK, Json(self)).into_response()
+    }
+}
+
+// Reports how well the verification cache is doing, e.g. when a stale cached
+// outcome is suspected. The transcript snapshots are not cached, as they are
+// streamed from the file.
+#[allow(clippy::unused_async)] // Required for axum function signature
+pub async fn caches(_: Admin, Extension(verification): Extension<SharedCache>) -> Caches {
+    Caches {
+        verification: verification.stats(),
+    }
+}
+
+// Drops all cached outcomes, so the next submission of any contribution is
+// verified from scratch
+#[allow(clippy::unused_async)] // Required for axum function signature
+pub async fn clear_caches(_: Admin, Extension(verification): Extension<SharedCache>) -> Caches {
+    verification.clear();
+    Caches {
+        verification: verification.stats(),
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReverifyState {
+    #[default]
+    Idle,
+    // Waiting for a verification slot that isn't the last free one
+    Queued,
+    Running,
+    Passed,
+    Failed,
+}
+
+#[derive(Clone, Debug, Default, Serialize, PartialEq, Eq)]
+pub struct ReverifyStatus {
+    state:                  ReverifyState,
+    started_at:             Option<DateTime<Utc>>,
+    finished_at:            Option<DateTime<Utc>>,
+    // Contributions verified so far, out of `total`
+    checked:                usize,
+    total:                  usize,
+    // The first contribution that doesn't verify
+    failed_index:           Option<usize>,
+    // Set if the transcript doesn't start from the initial state
+    tampered_initial_state: bool,
+    // Set if the transcript couldn't be read at all
+    error:                  Option<String>,
+}
+
+impl IntoResponse for ReverifyStatus {
+    fn into_response(self) -> Response {
+        (StatusCode::OK, Json(self)).into_response()
+    }
+}
+
+// The status of the last re-verification of the persisted transcript. The
+// progress is updated from the blocking verification thread.
+#[derive(Clone, Default)]
+pub struct Reverification(Arc<Mutex<ReverifyStatus>>);
+
+impl Reverification {
+    fn status(&self) -> ReverifyStatus {
+        self.0.lock().expect("reverification lock poisoned").clone()
+    }
+
+    fn update(&self, update: impl FnOnce(&mut ReverifyStatus)) {
+        update(&mut self.0.lock().expect("reverification lock poisoned"));
+    }
+}
+
+pub enum ReverifyError {
+    InProgress,
+    NoSpareSlot,
+}
+
+impl IntoResponse for ReverifyError {
+    fn into_response(self) -> Response {
+        let (status, body) = match self {
+            Self::InProgress => {
+                let body = Json(json!({"error": "a reverification is already in progress"}));
+                (StatusCode::CONFLICT, body)
+            }
+            Self::NoSpareSlot => {
+                let body = Json(json!({
+                    "error": "reverification needs MAX_CONCURRENT_VERIFICATIONS of at least 2"
+                }));
+                (StatusCode::CONFLICT, body)
+            }
+        };
+        (status, body).into_response()
+    }
+}
+
+// Starts verifying every contribution of the transcript on disk again, e.g.
+// after the file was restored from a backup. It runs in the background once a
+// verification slot is free while another one stays free, so contributions
+// are never turned away because of it.
+#[allow(clippy::unused_async)] // Required for axum function signature
+pub async fn reverify<T: Transcript + Send + 'static>(
+    _: Admin,
+    Extension(config): Extension<AppConfig>,
+    Extension(verification_limiter): Extension<VerificationLimiter>,
+    Extension(reverification): Extension<Reverification>,
+) -> Result<(StatusCode, ReverifyStatus), ReverifyError> {
+    if !verification_limiter.has_spare_slot() {
+        return Err(ReverifyError::NoSpareSlot);
+    }
+    {
+        let mut status = reverification
+            .0
+            .lock()
+            .expect("reverification lock poisoned");
+        if matches!(status.state, ReverifyState::Queued | ReverifyState::Running) {
+            return Err(ReverifyError::InProgress);
+        }
+        *status = ReverifyStatus {
+            state: ReverifyState::Queued,
+            started_at: Some(Utc::now()),
+            ..ReverifyStatus::default()
+        };
+    }
+    tokio::spawn(run_reverification::<T>(
+        config,
+        verification_limiter,
+        reverification.clone(),
+    ));
+    Ok((StatusCode::ACCEPTED, reverification.status()))
+}
+
+#[allow(clippy::unused_async)] // Required for axum function signature
+pub async fn reverify_status(
+    _: Admin,
+    Extension(reverification): Extension<Reverification>,
+) -> ReverifyStatus {
+    reverification.status()
+}
+
+async fn run_reverification<T: Transcript + Send + 'static>(
+    config: AppConfig,
+    verification_limiter: VerificationLimiter,
+    reverification: Reverification,
+) {
+    let permit = verification_limiter
+        .acquire_spare()
+        .await
+        .expect("reverification is only started with a spare slot");
+    let progress = reverification.clone();
+    let result = tokio::task::spawn_blocking(move || {
+        let _permit = permit;
+        let initial = T::initial(&config).map_err(|e| e.to_string())?;
+        let json = std::fs::read(&config.transcript_file).map_err(|e| e.to_string())?;
+        let transcript = serde_json::from_slice::<T>(&json).map_err(|e| e.to_string())?;
+        progress.update(|status| {
+            status.state = ReverifyState::Running;
+            status.total = transcript.num_contributions();
+        });
+        Ok(transcript
+            .verify_all_with_progress(&initial, &mut |checked| {
+                progress.update(|status| status.checked = checked);
+            })
+            .map_err(|error| match error {
+                VerifyAllError::TamperedInitialState => None,
+                VerifyAllError::InvalidContribution(index, _) => Some(index),
+            }))
+    })
+    .await
+    .unwrap_or_else(|e| Err(e.to_string()));
+
+    reverification.update(|status| {
+        status.finished_at = Some(Utc::now());
+        match result {
+            Ok(Ok(())) => status.state = ReverifyState::Passed,
+            Ok(Err(index)) => {
+                status.state = ReverifyState::Failed;
+                status.failed_index = index;
+                status.tampered_initial_state = index.is_none();
+            }
+            Err(error) => {
+                status.state = ReverifyState::Failed;
+                status.error = Some(error);
+            }
+        }
+    });
+    let status = reverification.status();
+    tracing::info!(
+        state = ?status.state,
+        failed_index = ?status.failed_index,
+        tampered_initial_state = status.tampered_initial_state,
+        "Transcript reverified"
+    );
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MintInvitesRequest {
+    count: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MintedInvites {
+    codes: Vec<String>,
+}
+
+impl IntoResponse for MintedInvites {
+    fn into_response(self) -> Response {
+        (StatusCode::OK, Json(self)).into_response()
+    }
+}
+
+// Creates a batch of fresh single-use invite codes for `/lobby/join`
+pub async fn mint_invite_codes(
+    _: Admin,
+    Json(request): Json<MintInvitesRequest>,
+    Extension(storage): Extension<PersistentStorage>,
+) -> Result<MintedInvites, StorageError> {
+    let codes = (0..request.count)
+        .map(|_| {
+            let mut code = [0_u8; 16];
+            rand::thread_rng().fill_bytes(&mut code);
+            hex::encode(code)
+        })
+        .collect::<Vec<_>>();
+    storage.insert_invite_codes(&codes).await?;
+    Ok(MintedInvites { codes })
+}
+
+pub enum ExpireError {
+    NoActiveContributor,
+    // Another session holds the spot, e.g. the expected one already left it
+    NotCurrentContributor,
+}
+
+impl IntoResponse for ExpireError {
+    fn into_response(self) -> Response {
+        let (status, body) = match self {
+            Self::NoActiveContributor => {
+                let body = Json(json!({"error": "no contributor is active"}));
+                (StatusCode::NOT_FOUND, body)
+            }
+            Self::NotCurrentContributor => {
+                let body = Json(json!({"error": "the session is not the current contributor"}));
+                (StatusCode::CONFLICT, body)
+            }
+        };
+        (status, body).into_response()
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExpiredContributor {
+    session_id: SessionId,
+    uid:        String,
+}
+
+impl IntoResponse for ExpiredContributor {
+    fn into_response(self) -> Response {
+        (StatusCode::OK, Json(self)).into_response()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExpireRequest {
+    // The session the operator means to expire, see `/admin/drain_status`
+    session_id: SessionId,
+}
+
+// Frees the contribution spot right away, as if the current contributor had
+// missed their deadline. Only the expected session is expired, so the request
+// can't free the spot of someone who was granted it in the meantime.
+pub async fn expire_current(
+    _: Admin,
+    Json(request): Json<ExpireRequest>,
+    Extension(store): Extension<SharedState>,
+    Extension(storage): Extension<PersistentStorage>,
+) -> Result<ExpiredContributor, ExpireError> {
+    let (session_id, session_info) = {
+        let mut app_state = store.write().await;
+        if app_state.participant.is_none() {
+            return Err(ExpireError::NoActiveContributor);
+        }
+        if !app_state.is_participant(&request.session_id) {
+            return Err(ExpireError::NotCurrentContributor);
+        }
+        let deadline_task = app_state.deadline_task.take();
+        let participant = app_state
+            .expire_current_contributor(&request.session_id)
+            .ok_or(ExpireError::NoActiveContributor)?;
+        if let Some(deadline_task) = deadline_task {
+            deadline_task.abort();
+        }
+        participant
+    };
+    let uid = session_info.unique_identifier().to_owned();
+    storage.expire_contribution(&uid).await;
+    Ok(ExpiredContributor { session_id, uid })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExtendDeadlineRequest {
+    extend_by_sec: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExtendedDeadline {
+    session_id:             SessionId,
+    // Less than requested if the extension was capped
+    extended_by_sec:        u64,
+    remaining_deadline_sec: u64,
+}
+
+impl IntoResponse for ExtendedDeadline {
+    fn into_response(self) -> Response {
+        (StatusCode::OK, Json(self)).into_response()
+    }
+}
+
+// Gives the current contributor more time, such as one computing on a slow
+// device. All extensions of a contribution spot together are capped at
+// `max_deadline_extension`. The deadline timer picks up the new deadline
+// by itself.
+pub async fn extend_deadline(
+    _: Admin,
+    Json(request): Json<ExtendDeadlineRequest>,
+    Extension(store): Extension<SharedState>,
+    Extension(config): Extension<AppConfig>,
+) -> Result<ExtendedDeadline, ExpireError> {
+    let (session_id, extended_by, remaining) = {
+        let mut app_state = store.write().await;
+        let session_id = app_state
+            .participant
+            .as_ref()
+            .map(|(session_id, _)| session_id.clone())
+            .ok_or(ExpireError::NoActiveContributor)?;
+        let (granted_at, deadline) = app_state
+            .participant_granted_at
+            .zip(app_state.participant_deadline)
+            .ok_or(ExpireError::NoActiveContributor)?;
+        let latest =
+            granted_at + config.effective_compute_deadline() + config.max_deadline_extension;
+        let extended = min(
+            deadline + Duration::from_secs(request.extend_by_sec),
+            max(latest, deadline),
+        );
+        app_state.participant_deadline = Some(extended);
+        (
+            session_id,
+            extended - deadline,
+            extended.saturating_duration_since(Instant::now()),
+        )
+    };
+    Ok(ExtendedDeadline {
+        session_id,
+        extended_by_sec: extended_by.as_secs(),
+        remaining_deadline_sec: remaining.as_secs(),
+    })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PhaseRequest {
+    name:             String,
+    // Start the next phase from the initial transcript. Otherwise the next
+    // phase continues the current transcript.
+    #[serde(default)]
+    reset_transcript: bool,
+    // Keep lobby sessions and their positions. Otherwise lobby members have
+    // to join again.
+    #[serde(default)]
+    carry_lobby:      bool,
+}
+
+pub enum PhaseError {
+    ContributionInProgress,
+    Archive(io::Error),
+    InitialTranscript(eyre::Report),
+    Storage(StorageError),
+}
+
+impl IntoResponse for PhaseError {
+    fn into_response(self) -> Response {
+        let (status, body) = match self {
+            Self::ContributionInProgress => {
+                let body = Json(json!({
+                    "error": "a contribution is in progress, wait for it or expire it first",
+                }));
+                (StatusCode::CONFLICT, body)
+            }
+            Self::Archive(error) => {
+                let body = Json(json!({
+                    "error": format!("could not archive the transcript: {}", error),
+                }));
+                (StatusCode::INTERNAL_SERVER_ERROR, body)
+            }
+            Self::InitialTranscript(error) => {
+                let body = Json(json!({
+                    "error": format!("could not create the initial transcript: {}", error),
+                }));
+                (StatusCode::INTERNAL_SERVER_ERROR, body)
+            }
+            Self::Storage(storage_error) => return storage_error.into_response(),
+        };
+        (status, body).into_response()
+    }
+}
+
+impl IntoResponse for CeremonyPhase {
+    fn into_response(self) -> Response {
+        (StatusCode::OK, Json(self)).into_response()
+    }
+}
+
+// Keeps a file of the phase that just ended next to the original, e.g.
+// `transcript.json.phase-0`
+fn archived_path(path: &Path, phase: i64) -> PathBuf {
+    let mut archived = path.as_os_str().to_owned();
+    archived.push(format!(".phase-{}", phase));
+    PathBuf::from(archived)
+}
+
+// Moves the ceremony to its next phase. Any draining is lifted, so the new
+// phase accepts contributions right away. The cutover happens under the
+// state lock, so no contribution spot can be granted halfway through it.
+pub async fn transition_phase<T: Transcript + Send + Sync + 'static>(
+    _: Admin,
+    Json(request): Json<PhaseRequest>,
+    Extension(store): Extension<SharedState>,
+    Extension(config): Extension<AppConfig>,
+    Extension(storage): Extension<PersistentStorage>,
+    Extension(transcript): Extension<SharedTranscript<T>>,
+) -> Result<CeremonyPhase, PhaseError> {
+    let mut app_state = store.write().await;
+    if app_state.participant.is_some() {
+        return Err(PhaseError::ContributionInProgress);
+    }
+    let ended = app_state.phase.as_ref().map_or(0, |phase| phase.phase);
+
+    let initial = if request.reset_transcript {
+        let initial = T::initial(&config).map_err(PhaseError::InitialTranscript)?;
+        for path in [&config.transcript_file, &config.transcript_signature_file] {
+            tokio::fs::copy(path, archived_path(path, ended))
+                .await
+                .map_err(PhaseError::Archive)?;
+        }
+        Some(initial)
+    } else {
+        None
+    };
+    let phase = storage
+        .insert_phase(ended + 1, &request.name)
+        .await
+        .map_err(PhaseError::Storage)?;
+
+    if let Some(initial) = initial {
+        app_state.resume_from(&initial);
+        app_state.contribution_bundles.clear();
+        app_state.order_commitment = OrderCommitment::default();
+        *transcript.write().await = initial;
+        write_transcript_file(
+            config.transcript_file,
+            config.transcript_in_progress_file,
+            config.transcript_signature_file,
+            config.transcript_objects,
+            transcript,
+        )
+        .await;
+    }
+    if !request.carry_lobby {
+        app_state.lobby.clear();
+        app_state.unique_id_session.clear();
+    }
+    app_state.drain = None;
+    app_state.finalized_at = None;
+    tracing::info!(phase = phase.phase, name = %phase.name, "Ceremony moved to next phase");
+    app_state.phase = Some(phase.clone());
+    Ok(phase)
+}
+
+// Number of attestations read from storage at a time during an export
+const EXPORT_PAGE_SIZE: u32 = 1_000;
+
+// The last line of an attestation export
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportSignature {
+    // 0x prefixed SHA256 of all preceding lines, including their newlines
+    pub export_hash: String,
+    pub signature:   String,
+}
+
+enum ExportState {
+    Page {
+        after:   Option<(i64, i64)>,
+        context: Context,
+    },
+    Done,
+}
+
+// Streams the whole attestation log as newline delimited JSON, one
+// attestation per line, followed by the sequencer's signature over them
+pub async fn export_attestations(
+    _: Admin,
+    Extension(storage): Extension<PersistentStorage>,
+) -> Response {
+    let body = StreamBody::new(attestation_export(storage, EXPORT_PAGE_SIZE));
+    ([(CONTENT_TYPE, "application/x-ndjson")], body).into_response()
+}
+
+fn attestation_export(
+    storage: PersistentStorage,
+    page_size: u32,
+) -> impl Stream<Item = io::Result<Vec<u8>>> {
+    let initial = ExportState::Page {
+        after:   None,
+        context: Context::new(&SHA256),
+    };
+    stream::unfold(initial, move |state| {
+        let storage = storage.clone();
+        async move {
+            let (after, mut context) = match state {
+                ExportState::Page { after, context } => (after, context),
+                ExportState::Done => return None,
+            };
+            let page = match storage.attestations_page(after, page_size).await {
+                Ok(page) => page,
+                Err(error) => {
+                    let error = io::Error::new(io::ErrorKind::Other, format!("{:?}", error));
+                    return Some((Err(error), ExportState::Done));
+                }
+            };
+            let last = match page.last() {
+                Some(last) => last.key(),
+                None => return Some((export_signature(context), ExportState::Done)),
+            };
+            let mut lines = Vec::new();
+            for attestation in &page {
+                serde_json::to_writer(&mut lines, attestation)
+                    .expect("Cannot serialize attestation");
+                lines.push(b'\n');
+            }
+            context.update(&lines);
+            Some((Ok(lines), ExportState::Page {
+                after: Some(last),
+                context,
+            }))
+        }
+    })
+}
+
+fn export_signature(context: Context) -> io::Result<Vec<u8>> {
+    let export_hash = format!("0x{}", hex::encode(context.finish()));
+    let signature = KEYS
+        .get()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "keys are not loaded"))?
+        .sign(export_hash.as_bytes())
+        .map_err(|error| io::Error::new(io::ErrorKind::Other, error))?;
+    let mut line = serde_json::to_vec(&ExportSignature {
+        export_hash,
+        signature,
+    })
+    .expect("Cannot serialize export signature");
+    line.push(b'\n');
+    Ok(line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{create_test_session_info, test_config};
+
+    #[tokio::test]
+    async fn computes_lobby_wait_percentiles() {
+        let start = Instant::now();
+        let sessions = (0..100)
+            .map(|i| {
+                let mut session_info = create_test_session_info(100);
+                session_info.joined_at = start + Duration::from_secs(i);
+                session_info.last_ping_time = session_info.joined_at;
+                session_info
+            })
+            .collect::<Vec<_>>();
+        let now = start + Duration::from_secs(100);
+
+        let stats = LobbyStats::compute(sessions.iter(), now, Duration::from_secs(30));
+
+        assert_eq!(stats, LobbyStats {
+            lobby_size:        100,
+            wait_time_p50_sec: 50,
+            wait_time_p90_sec: 90,
+            wait_time_p99_sec: 99,
+            missed_checkins:   MissedCheckins {
+                none:        29,
+                one:         30,
+                two_or_more: 41,
+            },
+        });
+    }
+
+    #[tokio::test]
+    async fn lobby_stats_reports_empty_lobby() {
+        let stats = lobby_stats(
+            Admin,
+            Extension(SharedState::default()),
+            Extension(test_config()),
+        )
+        .await;
+        assert_eq!(stats.lobby_size, 0);
+        assert_eq!(stats.wait_time_p99_sec, 0);
+    }
+
+    #[tokio::test]
+    async fn lobby_stats_counts_waiting_sessions() {
+        let state = SharedState::default();
+        state
+            .write()
+            .await
+            .lobby
+            .insert(SessionId::new(), create_test_session_info(100));
+        let stats = lobby_stats(Admin, Extension(state), Extension(test_config())).await;
+        assert_eq!(stats.lobby_size, 1);
+        assert_eq!(stats.missed_checkins.none, 1);
+    }
+
+    #[test]
+    fn recommends_deadline_covering_p95() {
+        let compute_times = (1..=100)
+            .rev()
+            .map(|secs| Duration::from_millis(secs * 1000 + 500))
+            .collect::<Vec<_>>();
+
+        let suggestion = TuningSuggestion::compute(&compute_times, Duration::from_secs(180));
+
+        assert_eq!(suggestion, TuningSuggestion {
+            samples: 100,
+            current_compute_deadline_sec: 180,
+            p95_compute_time_sec: Some(95),
+            recommended_compute_deadline_sec: Some(96),
+        });
+    }
+
+    #[tokio::test]
+    async fn tuning_without_samples_has_no_recommendation() {
+        let suggestion = tuning(
+            Admin,
+            Extension(SharedState::default()),
+            Extension(test_config()),
+        )
+        .await;
+        assert_eq!(suggestion.samples, 0);
+        assert_eq!(suggestion.recommended_compute_deadline_sec, None);
+    }
+
+    #[tokio::test]
+    async fn draining_refuses_new_grants_but_keeps_active_slot() {
+        use crate::{
+            api::v1::lobby::{try_contribute, ClientVersion, SlotOutcome, TryContributeError},
+            storage::test_storage_client,
+            SharedTranscript, TestTranscript,
+        };
+
+        let db = test_storage_client().await;
+        tokio::time::pause();
+        let state = SharedState::default();
+        let config = test_config();
+        let active = SessionId::new();
+        let waiting = SessionId::new();
+        {
+            let mut app_state = state.write().await;
+            app_state
+                .lobby
+                .insert(active.clone(), create_test_session_info(100));
+            app_state
+                .lobby
+                .insert(waiting.clone(), create_test_session_info(100));
+            assert!(
+                app_state.try_set_current_contributor(active, config.effective_compute_deadline())
+            );
+        }
+
+        let status = drain(
+            Admin,
+            Some(Json(DrainRequest {
+                reason: Some("maintenance".to_string()),
+            })),
+            Extension(state.clone()),
+        )
+        .await;
+        assert!(status.draining);
+        assert_eq!(status.reason.as_deref(), Some("maintenance"));
+        assert!(status.contributor_active);
+        assert_eq!(
+            status.remaining_deadline_sec,
+            Some(config.compute_deadline.as_secs())
+        );
+
+        // The active contributor finishes
+        state
+            .write()
+            .await
+            .clear_current_contributor(SlotOutcome::Completed);
+
+        let response = try_contribute(
+            waiting,
+            ClientVersion(None),
+            Extension(state.clone()),
+            Extension(db),
+            Extension(SharedTranscript::<TestTranscript>::default()),
+            Extension(config.clone()),
+        )
+        .await;
+        assert!(matches!(response, Err(TryContributeError::Draining)));
+
+        let status = drain_status(Admin, Extension(state)).await;
+        assert!(status.draining);
+        assert!(!status.contributor_active);
+        assert_eq!(status.remaining_deadline_sec, None);
+    }
+
+    #[tokio::test]
+    async fn drained_once_the_active_contributor_is_expired() {
+        use crate::storage::test_storage_client;
+
+        let db = test_storage_client().await;
+        let state = SharedState::default();
+        let active = SessionId::new();
+        {
+            let mut app_state = state.write().await;
+            app_state
+                .lobby
+                .insert(active.clone(), create_test_session_info(100));
+            assert!(app_state.try_set_current_contributor(active, test_config().compute_deadline));
+        }
+        let drained = {
+            let state = state.clone();
+            tokio::spawn(async move { await_drained(&state).await })
+        };
+
+        drain(Admin, None, Extension(state.clone())).await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!drained.is_finished());
+
+        // Nobody is waiting for the deadline of a contributor that is gone
+        let session_id = drain_status(Admin, Extension(state.clone()))
+            .await
+            .contributor_session_id
+            .unwrap();
+        assert!(expire_current(
+            Admin,
+            Json(ExpireRequest { session_id }),
+            Extension(state.clone()),
+            Extension(db)
+        )
+        .await
+        .is_ok());
+        assert!(tokio::time::timeout(Duration::from_secs(1), drained)
+            .await
+            .is_ok());
+
+        // Draining without an active contributor is done right away
+        let idle = SharedState::default();
+        drain(Admin, None, Extension(idle.clone())).await;
+        assert!(
+            tokio::time::timeout(Duration::from_secs(1), await_drained(&idle))
+                .await
+                .is_ok()
+        );
+    }
+
+    #[tokio::test]
+    async fn expire_current_frees_the_slot_and_cancels_the_timer() {
+        use crate::{
+            api::v1::lobby::{try_contribute, ClientVersion, SlotOutcome},
+            storage::test_storage_client,
+            SharedTranscript, TestTranscript,
+        };
+
+        let db = test_storage_client().await;
+        tokio::time::pause();
+        let state = SharedState::default();
+        let config = test_config();
+
+        let expire = |session_id: SessionId| {
+            expire_current(
+                Admin,
+                Json(ExpireRequest { session_id }),
+                Extension(state.clone()),
+                Extension(db.clone()),
+            )
+        };
+        let response = expire(SessionId::new()).await;
+        assert!(matches!(response, Err(ExpireError::NoActiveContributor)));
+
+        let session_id = SessionId::new();
+        state
+            .write()
+            .await
+            .lobby
+            .insert(session_id.clone(), create_test_session_info(100));
+        let response = try_contribute(
+            session_id.clone(),
+            ClientVersion(None),
+            Extension(state.clone()),
+            Extension(db.clone()),
+            Extension(SharedTranscript::<TestTranscript>::default()),
+            Extension(config.clone()),
+        )
+        .await;
+        assert!(response.is_ok());
+        assert!(state.read().await.deadline_task.is_some());
+
+        // A request meant for an earlier contributor leaves the spot alone
+        let response = expire(SessionId::new()).await;
+        assert!(matches!(response, Err(ExpireError::NotCurrentContributor)));
+        assert!(state.read().await.deadline_task.is_some());
+
+        let expired = expire(session_id.clone())
+            .await
+            .ok()
+            .expect("a contributor is active");
+        assert_eq!(expired.session_id, session_id);
+        assert_eq!(expired.uid, "foo");
+        {
+            let app_state = state.read().await;
+            assert!(app_state.participant.is_none());
+            assert!(app_state.deadline_task.is_none());
+            assert_eq!(app_state.num_expired, 1);
+            assert_eq!(app_state.last_slot_outcome, Some(SlotOutcome::Expired));
+        }
+
+        // The cancelled timer doesn't count the slot a second time
+        tokio::time::advance(config.compute_deadline * 2).await;
+        tokio::task::yield_now().await;
+        assert_eq!(state.read().await.num_expired, 1);
+    }
+
+    #[tokio::test]
+    async fn extend_deadline_postpones_expiry() {
+        use crate::{
+            api::v1::lobby::{try_contribute, ClientVersion},
+            storage::test_storage_client,
+            SharedTranscript, TestTranscript,
+        };
+
+        let db = test_storage_client().await;
+        tokio::time::pause();
+        let state = SharedState::default();
+        let config = test_config();
+        let extend = |extend_by_sec| {
+            extend_deadline(
+                Admin,
+                Json(ExtendDeadlineRequest { extend_by_sec }),
+                Extension(state.clone()),
+                Extension(config.clone()),
+            )
+        };
+
+        assert!(matches!(
+            extend(60).await,
+            Err(ExpireError::NoActiveContributor)
+        ));
+
+        let session_id = SessionId::new();
+        state
+            .write()
+            .await
+            .lobby
+            .insert(session_id.clone(), create_test_session_info(100));
+        let response = try_contribute(
+            session_id.clone(),
+            ClientVersion(None),
+            Extension(state.clone()),
+            Extension(db.clone()),
+            Extension(SharedTranscript::<TestTranscript>::default()),
+            Extension(config.clone()),
+        )
+        .await;
+        assert!(response.is_ok());
+        let granted_at = state.read().await.participant_granted_at.unwrap();
+
+        let extended = extend(60).await.ok().expect("a contributor is active");
+        assert_eq!(extended.session_id, session_id);
+        assert_eq!(extended.extended_by_sec, 60);
+        assert_eq!(
+            state.read().await.participant_deadline,
+            Some(granted_at + config.compute_deadline + Duration::from_secs(60))
+        );
+
+        // Extensions are capped in total
+        let capped = extend(10_000).await.ok().expect("a contributor is active");
+        let max_extension = config.max_deadline_extension.as_secs();
+        assert_eq!(capped.extended_by_sec, max_extension - 60);
+        let expected_deadline = config.compute_deadline + config.max_deadline_extension;
+
+        // Still there after the original deadline, until the extended one
+        tokio::time::advance(config.compute_deadline + Duration::from_secs(1)).await;
+        tokio::task::yield_now().await;
+        assert!(state.read().await.participant.is_some());
+        tokio::time::advance(expected_deadline - granted_at.elapsed() - Duration::from_secs(1))
+            .await;
+        tokio::task::yield_now().await;
+        assert!(state.read().await.participant.is_some());
+
+        tokio::time::advance(Duration::from_secs(2)).await;
+        let deadline_task = state.write().await.deadline_task.take().unwrap();
+        deadline_task.await.unwrap();
+        let app_state = state.read().await;
+        assert!(app_state.participant.is_none());
+        assert_eq!(app_state.num_expired, 1);
+    }
+
+    #[tokio::test]
+    async fn exports_signed_attestation_log() {
+        use crate::{
+            attestation_chain::GENESIS,
+            storage::{test_storage_client, Attestation},
+            test_util::{init_keys, response_body},
+        };
+        use futures::TryStreamExt;
+        use ring::digest::digest;
+
+        init_keys().await;
+        let db = test_storage_client().await;
+        let mut prev = GENESIS.to_string();
+        for index in 0..5 {
+            let attestation = Attestation::new(
+                0,
+                index,
+                db.identity_hash(&format!("github | {}", index)),
+                "github",
+                vec![format!("0x{:02x}", index)],
+                None,
+                &prev,
+            );
+            prev = attestation.chain_hash.clone().unwrap();
+            db.insert_attestation(&attestation, None, None)
+                .await
+                .unwrap();
+        }
+
+        // Pages smaller than the log make sure pagination doesn't skip rows
+        let export = attestation_export(db.clone(), 2)
+            .try_concat()
+            .await
+            .unwrap();
+
+        let export = String::from_utf8(export).unwrap();
+        let lines = export.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 6);
+        let indices = lines[..5]
+            .iter()
+            .map(|line| {
+                serde_json::from_str::<serde_json::Value>(line).unwrap()["contribution_index"]
+                    .clone()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            indices,
+            (0..5).map(|index| json!(index)).collect::<Vec<_>>()
+        );
+
+        // Signatures are randomized, but the attestations are the same
+        let handler_export = response_body(export_attestations(Admin, Extension(db)).await).await;
+        let handler_export = String::from_utf8(handler_export).unwrap();
+        assert_eq!(
+            handler_export.lines().take(5).collect::<Vec<_>>(),
+            lines[..5]
+        );
+
+        let signed_len = export.len() - lines[5].len() - 1;
+        let signature = serde_json::from_str::<ExportSignature>(lines[5]).unwrap();
+        assert_eq!(
+            signature.export_hash,
+            format!(
+                "0x{}",
+                hex::encode(digest(&SHA256, export[..signed_len].as_bytes()))
+            )
+        );
+        assert!(KEYS
+            .get()
+            .unwrap()
+            .verify(&signature.signature, signature.export_hash.as_bytes()));
+    }
+
+    #[tokio::test]
+    async fn phase_transition_continues_or_resets_the_transcript() {
+        use crate::{
+            storage::test_storage_client, test_transcript::TestContribution, test_util::init_keys,
+            TestTranscript,
+        };
+        use tokio::sync::RwLock;
+
+        init_keys().await;
+        let db = test_storage_client().await;
+        let dir = std::env::temp_dir();
+        let config = AppConfig {
+            transcript_file: dir.join("phase_transcript.json"),
+            transcript_in_progress_file: dir.join("phase_transcript.json.new"),
+            transcript_signature_file: dir.join("phase_transcript.json.sig"),
+            ..test_config()
+        };
+        let phase_one = TestTranscript::default().update(&TestContribution::ValidContribution(1));
+        let transcript = SharedTranscript::new(RwLock::new(phase_one.clone()));
+        write_transcript_file(
+            config.transcript_file.clone(),
+            config.transcript_in_progress_file.clone(),
+            config.transcript_signature_file.clone(),
+            config.transcript_objects.clone(),
+            transcript.clone(),
+        )
+        .await;
+
+        let state = SharedState::default();
+        let waiting = SessionId::new();
+        {
+            let mut app_state = state.write().await;
+            app_state.resume_from(&phase_one);
+            app_state
+                .lobby
+                .insert(waiting.clone(), create_test_session_info(100));
+            app_state.drain = Some(Drain {
+                reason: "end of phase".to_string(),
+                since:  Utc::now(),
+            });
+        }
+        let transition = |name: &str, reset_transcript, carry_lobby| {
+            transition_phase::<TestTranscript>(
+                Admin,
+                Json(PhaseRequest {
+                    name: name.to_string(),
+                    reset_transcript,
+                    carry_lobby,
+                }),
+                Extension(state.clone()),
+                Extension(config.clone()),
+                Extension(db.clone()),
+                Extension(transcript.clone()),
+            )
+        };
+
+        // Continuing keeps the transcript and, if asked to, the lobby
+        let phase = transition("extension", false, true).await.ok().unwrap();
+        assert_eq!(phase.phase, 1);
+        assert_eq!(*transcript.read().await, phase_one);
+        {
+            let app_state = state.read().await;
+            assert_eq!(app_state.ceremony_status(), "waiting_for_participant");
+            assert_eq!(app_state.num_contributions, 1);
+            assert!(app_state.lobby.contains_key(&waiting));
+        }
+
+        // Resetting starts over from the initial transcript
+        let phase = transition("circuit specific", true, false)
+            .await
+            .ok()
+            .unwrap();
+        assert_eq!(phase.phase, 2);
+        assert_eq!(*transcript.read().await, TestTranscript::default());
+        {
+            let app_state = state.read().await;
+            assert_eq!(app_state.num_contributions, 0);
+            assert!(app_state.seen_pubkeys.is_empty());
+            assert!(app_state.lobby.is_empty());
+        }
+        let archived = std::fs::read(archived_path(&config.transcript_file, 1)).unwrap();
+        assert_eq!(
+            serde_json::from_slice::<TestTranscript>(&archived).unwrap(),
+            phase_one
+        );
+        assert_eq!(db.current_phase().await.unwrap(), Some(phase));
+
+        // The cutover waits for the active contributor
+        state.write().await.participant = Some((SessionId::new(), create_test_session_info(100)));
+        assert!(matches!(
+            transition("too early", false, false).await,
+            Err(PhaseError::ContributionInProgress)
+        ));
+    }
+
+    #[tokio::test]
+    async fn clearing_caches_empties_them() {
+        use crate::{
+            data::transcript::transcript_hash,
+            test_transcript::TestContribution,
+            verification::{CachedVerifier, FullVerifier, SharedVerifier, Verifier},
+            TestTranscript,
+        };
+
+        let inner: SharedVerifier<TestTranscript> =
+            Arc::new(FullVerifier::new(TestTranscript::default()));
+        let cached = Arc::new(CachedVerifier::new(inner, Duration::from_secs(60)));
+        let transcript = TestTranscript::default();
+        let hash = transcript_hash(&transcript);
+        for contribution in [1, 2, 1] {
+            assert!(cached
+                .verify(
+                    &transcript,
+                    &hash,
+                    &TestContribution::ValidContribution(contribution),
+                    None
+                )
+                .is_ok());
+        }
+        let cache: SharedCache = cached;
+
+        let before = caches(Admin, Extension(cache.clone())).await;
+        assert_eq!(before.verification, CacheStats {
+            hits:   1,
+            misses: 2,
+            size:   2,
+        });
+
+        let cleared = clear_caches(Admin, Extension(cache.clone())).await;
+        assert_eq!(cleared.verification.size, 0);
+        assert_eq!(
+            caches(Admin, Extension(cache)).await.verification,
+            CacheStats {
+                hits:   1,
+                misses: 2,
+                size:   0,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn reverification_reports_the_failing_index() {
+        use crate::{
+            test_transcript::TestContribution::{InvalidContribution, ValidContribution},
+            TestTranscript,
+        };
+
+        let reverify_file = |name: &str, transcript: &TestTranscript| {
+            let path = std::env::temp_dir().join(name);
+            std::fs::write(&path, serde_json::to_vec(transcript).unwrap()).unwrap();
+            let config = AppConfig {
+                transcript_file: path,
+                ..test_config()
+            };
+            async move {
+                let reverification = Reverification::default();
+                let (status, started) = reverify::<TestTranscript>(
+                    Admin,
+                    Extension(config),
+                    Extension(VerificationLimiter::new(2)),
+                    Extension(reverification.clone()),
+                )
+                .await
+                .ok()
+                .unwrap();
+                assert_eq!(status, StatusCode::ACCEPTED);
+                assert!(matches!(
+                    started.state,
+                    ReverifyState::Queued | ReverifyState::Running
+                ));
+                loop {
+                    let status = reverify_status(Admin, Extension(reverification.clone())).await;
+                    if status.finished_at.is_some() {
+                        return status;
+                    }
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            }
+        };
+
+        let valid = TestTranscript {
+            contributions: vec![ValidContribution(1), ValidContribution(2)],
+            ..TestTranscript::default()
+        };
+        let passed = reverify_file("reverify_valid.json", &valid).await;
+        assert_eq!(passed.state, ReverifyState::Passed);
+        assert_eq!((passed.checked, passed.total), (2, 2));
+        assert_eq!(passed.failed_index, None);
+
+        let tampered = TestTranscript {
+            contributions: vec![
+                ValidContribution(1),
+                ValidContribution(2),
+                InvalidContribution(3),
+                ValidContribution(4),
+            ],
+            ..TestTranscript::default()
+        };
+        let failed = reverify_file("reverify_tampered.json", &tampered).await;
+        assert_eq!(failed.state, ReverifyState::Failed);
+        assert_eq!(failed.failed_index, Some(2));
+        assert!(!failed.tampered_initial_state);
+        assert_eq!((failed.checked, failed.total), (2, 4));
+
+        let tampered_initial = TestTranscript {
+            initial:       ValidContribution(42),
+            contributions: vec![ValidContribution(1)],
+        };
+        let failed = reverify_file("reverify_tampered_initial.json", &tampered_initial).await;
+        assert_eq!(failed.state, ReverifyState::Failed);
+        assert_eq!(failed.failed_index, None);
+        assert!(failed.tampered_initial_state);
+        assert_eq!(failed.checked, 0);
+
+        // The only slot is never taken from contributions
+        let config = AppConfig {
+            transcript_file: std::env::temp_dir().join("reverify_valid.json"),
+            ..test_config()
+        };
+        let refused = reverify::<TestTranscript>(
+            Admin,
+            Extension(config),
+            Extension(VerificationLimiter::new(1)),
+            Extension(Reverification::default()),
+        )
+        .await;
+        assert!(matches!(refused, Err(ReverifyError::NoSpareSlot)));
+    }
+}
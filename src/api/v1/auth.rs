@@ -1,55 +1,79 @@
 use crate::{
-    constants::MAX_LOBBY_SIZE,
-    jwt::{errors::JwtError, IdToken},
+    admission::AdmissionRequest,
+    api::v1::{
+        identity::{Identity, IdentityProviders},
+        limits::ClientLimiter,
+    },
+    constants::{self, MAX_LOBBY_SIZE},
+    jwt::{errors::JwtError, IdToken, ResumeToken},
+    sessions::{SessionInfoBuilder, ANONYMOUS_PROVIDER},
     storage::{PersistentStorage, StorageError},
-    AppConfig, GithubOAuthClient, SessionId, SessionInfo, SharedState, SiweOAuthClient,
+    verification::VerificationLimiter,
+    AppConfig, AppState, GithubOAuthClient, SessionId, SessionInfo, SharedState, SiweOAuthClient,
 };
 use axum::{
-    extract::Query,
+    extract::{ConnectInfo, Path, Query},
     response::{IntoResponse, Response},
     Extension, Json,
 };
-use chrono::DateTime;
-use http::StatusCode;
-use oauth2::{
-    reqwest::async_http_client, AuthorizationCode, CsrfToken, RedirectUrl, Scope, TokenResponse,
-};
-use serde::{Deserialize, Serialize};
+use http::{header::RETRY_AFTER, StatusCode};
+use oauth2::{CsrfToken, RedirectUrl, Scope};
+use serde::Deserialize;
 use serde_json::json;
-use std::{borrow::Cow, ops::Deref};
-use tokio::time::Instant;
-
-// These are the providers that are supported
-// via oauth
-pub enum AuthProvider {
-    Github,
-    Ethereum,
-}
-
-impl AuthProvider {
-    pub const fn to_string(&self) -> &str {
-        match self {
-            Self::Github => "Github",
-            Self::Ethereum => "Ethereum",
-        }
-    }
-}
+use std::{borrow::Cow, collections::BTreeSet, net::SocketAddr};
+use tokio::time::{Duration, Instant};
 
 pub enum AuthError {
+    UnknownProvider,
     LobbyIsFull,
+    NotAllowed,
     UserAlreadyContributed,
     InvalidCsrf,
     Jwt(JwtError),
     InvalidAuthCode,
     FetchUserDataError,
     CouldNotExtractUserData,
+    // The identity provider kept failing recently, sign-ins through it are
+    // refused for a while
+    ProviderUnavailable,
     UserCreatedAfterDeadline,
+    // Contains how long until the identity may rejoin
+    Cooldown(Duration),
+    // The sign-in is on hold until the session also authenticated with the
+    // listed providers
+    MissingAuthFactor {
+        session_id: String,
+        missing:    Vec<String>,
+    },
+    // A further auth factor was presented for a session not waiting for one
+    UnknownSession,
+    // The identity already vouched for another session
+    AuthFactorInUse,
+    // Verification is saturated and the lobby is long, contains when to retry
+    Overloaded(Duration),
+    // The operator's admission hook vetoed the join, contains its reason
+    CustomPolicyRejected(&'static str),
+    AnonymousDisabled,
+    // The client started too many anonymous sessions, contains when to retry
+    TooManyAnonymousJoins(Duration),
     Storage(StorageError),
 }
 
+impl AuthError {
+    // Whether the identity provider, rather than the user, is at fault
+    pub const fn is_upstream_failure(&self) -> bool {
+        matches!(
+            self,
+            Self::FetchUserDataError | Self::CouldNotExtractUserData
+        )
+    }
+}
+
 pub struct UserVerified {
-    id_token:   String,
-    session_id: String,
+    // Unset for anonymous participants
+    id_token:     Option<String>,
+    session_id:   String,
+    resume_token: String,
 }
 
 pub struct AuthUrl {
@@ -72,6 +96,7 @@ impl IntoResponse for UserVerified {
         Json(json!({
             "id_token" : self.id_token,
             "session_id" : self.session_id,
+            "resume_token" : self.resume_token,
         }))
         .into_response()
     }
@@ -80,6 +105,12 @@ impl IntoResponse for UserVerified {
 impl IntoResponse for AuthError {
     fn into_response(self) -> Response {
         let (status, body) = match self {
+            Self::UnknownProvider => {
+                let body = Json(json!({
+                    "error": "unknown identity provider",
+                }));
+                (StatusCode::NOT_FOUND, body)
+            }
             Self::InvalidAuthCode => {
                 let body = Json(json!({
                     "error": "invalid authorisation code",
@@ -92,6 +123,18 @@ impl IntoResponse for AuthError {
                 }));
                 (StatusCode::INTERNAL_SERVER_ERROR, body)
             }
+            Self::ProviderUnavailable => {
+                let body = Json(json!({
+                    "error": "identity provider is unavailable, try again later",
+                }));
+                let retry_after = constants::AUTH_PROVIDER_COOLDOWN_SEC.to_string();
+                return (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    [(RETRY_AFTER, retry_after)],
+                    body,
+                )
+                    .into_response();
+            }
             Self::CouldNotExtractUserData => {
                 let body = Json(json!({
                     "error": "could not extract user data from auth server response",
@@ -106,6 +149,12 @@ impl IntoResponse for AuthError {
                 }));
                 (StatusCode::SERVICE_UNAVAILABLE, body)
             }
+            Self::NotAllowed => {
+                let body = Json(json!({
+                    "error": "identity is not allowed to contribute",
+                }));
+                (StatusCode::FORBIDDEN, body)
+            }
             Self::InvalidCsrf => {
                 let body = Json(json!({
                     "error": "invalid csrf token",
@@ -120,6 +169,69 @@ impl IntoResponse for AuthError {
                 let body = Json(json!({ "error": "user account was created after the deadline"}));
                 (StatusCode::UNAUTHORIZED, body)
             }
+            Self::Cooldown(remaining) => {
+                let body =
+                    Json(json!({ "error": "identity contributed recently, try again later"}));
+                let retry_after = remaining.as_secs_f64().ceil().to_string();
+                return (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    [(RETRY_AFTER, retry_after)],
+                    body,
+                )
+                    .into_response();
+            }
+            Self::MissingAuthFactor {
+                session_id,
+                missing,
+            } => {
+                let body = Json(json!({
+                    "error": "sign in with the missing providers to join the lobby",
+                    "session_id": session_id,
+                    "missing": missing,
+                }));
+                (StatusCode::UNAUTHORIZED, body)
+            }
+            Self::UnknownSession => {
+                let body = Json(json!({ "error": "unknown session id" }));
+                (StatusCode::BAD_REQUEST, body)
+            }
+            Self::AuthFactorInUse => {
+                let body = Json(json!({
+                    "error": "identity was already used to sign in another session",
+                }));
+                (StatusCode::BAD_REQUEST, body)
+            }
+            Self::Overloaded(retry_after) => {
+                let body = Json(json!({ "error": "sequencer is overloaded, try again later"}));
+                let retry_after = retry_after.as_secs().to_string();
+                return (
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    [(RETRY_AFTER, retry_after)],
+                    body,
+                )
+                    .into_response();
+            }
+            Self::CustomPolicyRejected(reason) => {
+                let body = Json(json!({
+                    "error": "rejected by custom policy",
+                    "reason": reason,
+                }));
+                (StatusCode::FORBIDDEN, body)
+            }
+            Self::AnonymousDisabled => {
+                let body = Json(json!({ "error": "anonymous participation is disabled" }));
+                (StatusCode::FORBIDDEN, body)
+            }
+            Self::TooManyAnonymousJoins(retry_after) => {
+                let body = Json(json!({ "error": "too many anonymous joins, try again later" }));
+                let retry_after = retry_after.as_secs_f64().ceil().to_string();
+                return (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    [(RETRY_AFTER, retry_after)],
+                    body,
+                )
+                    .into_response();
+            }
             Self::Storage(storage_error) => return storage_error.into_response(),
         };
         (status, body).into_response()
@@ -197,155 +309,131 @@ pub async fn auth_client_link(
 // an identity provider
 #[derive(Debug, Deserialize)]
 pub struct AuthPayload {
-    code:  String,
-    state: String,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct AuthenticatedUser {
-    uid:      String,
-    nickname: String,
+    pub(crate) code:  String,
+    pub(crate) state: String,
 }
 
-#[derive(Debug, Deserialize)]
-struct GhUserInfo {
-    login:      String,
-    created_at: String,
+// A sign-in waiting for the remaining providers in
+// `AppConfig::required_auth_providers`. Forgotten after
+// `PENDING_SESSION_TTL_SEC`.
+pub struct PendingSession {
+    token:      IdToken,
+    // Callback routes of the providers the session authenticated with
+    satisfied:  BTreeSet<String>,
+    started_at: Instant,
 }
 
-pub async fn github_callback(
-    Query(payload): Query<AuthPayload>,
-    Extension(config): Extension<AppConfig>,
-    Extension(store): Extension<SharedState>,
-    Extension(storage): Extension<PersistentStorage>,
-    Extension(gh_oauth_client): Extension<GithubOAuthClient>,
-    Extension(http_client): Extension<reqwest::Client>,
-) -> Result<UserVerified, AuthError> {
-    verify_csrf(&payload, &store).await?;
-    let token = gh_oauth_client
-        .exchange_code(AuthorizationCode::new(payload.code))
-        .request_async(async_http_client)
-        .await
-        .map_err(|_| AuthError::InvalidAuthCode)?;
-
-    let response = http_client
-        .get("https://api.github.com/user")
-        .bearer_auth(token.access_token().secret())
-        .header("User-Agent", "ethereum-kzg-ceremony-sequencer")
-        .send()
-        .await
-        .map_err(|_| AuthError::FetchUserDataError)?;
-    let gh_user_info = response
-        .json::<GhUserInfo>()
-        .await
-        .map_err(|_| AuthError::CouldNotExtractUserData)?;
-    let creation_time = DateTime::parse_from_rfc3339(&gh_user_info.created_at)
-        .map_err(|_| AuthError::CouldNotExtractUserData)?;
-    if creation_time > config.github_max_creation_time {
-        return Err(AuthError::UserCreatedAfterDeadline);
-    }
-    let user = AuthenticatedUser {
-        uid:      format!("github | {}", gh_user_info.login),
-        nickname: gh_user_info.login,
-    };
-    post_authenticate(store, storage, user, AuthProvider::Github).await
-}
+impl PendingSession {
+    fn missing(&self, required: &[String]) -> Vec<String> {
+        required
+            .iter()
+            .filter(|route| !self.satisfied.contains(*route))
+            .cloned()
+            .collect()
+    }
 
-#[derive(Debug, Deserialize)]
-struct SiweUserInfo {
-    sub:                String,
-    preferred_username: String,
+    pub fn is_expired(&self, now: Instant, ttl: Duration) -> bool {
+        now.saturating_duration_since(self.started_at) >= ttl
+    }
 }
 
-// This endpoint allows one to consume an oAUTH authorisation code
-//  and produce a JWT token
-// So Sequencer could give out fake identities, we are trusting the sequencer
-// to not do that.
-//
-// Now this is catchable by the client. They will clearly see that the sequencer
-// was malicious. What can happen is sequencer can claim that someone
-// participated when they did not. Is this Okay? Maybe that person can then just
-// say they did not
-pub async fn siwe_callback(
+// Completes the oAUTH flow of the provider named in the path, e.g. `github`,
+// and produces a JWT token. If several providers are required, sessions that
+// authenticated with some of them present their session id when signing in
+// with the next.
+pub async fn callback(
+    session_id: Option<SessionId>,
+    Path(route): Path<String>,
     Query(payload): Query<AuthPayload>,
     Extension(config): Extension<AppConfig>,
     Extension(store): Extension<SharedState>,
     Extension(storage): Extension<PersistentStorage>,
-    Extension(oauth_client): Extension<SiweOAuthClient>,
-    Extension(http_client): Extension<reqwest::Client>,
+    Extension(verification_limiter): Extension<VerificationLimiter>,
+    Extension(providers): Extension<IdentityProviders>,
 ) -> Result<UserVerified, AuthError> {
+    let provider = providers.get(&route).ok_or(AuthError::UnknownProvider)?;
     verify_csrf(&payload, &store).await?;
-    let token = oauth_client
-        .exchange_code(AuthorizationCode::new(payload.code))
-        .request_async(async_http_client)
-        .await
-        .map_err(|_| AuthError::InvalidAuthCode)?;
-
-    let response = http_client
-        .get("https://oidc.signinwithethereum.org/userinfo")
-        .bearer_auth(token.access_token().secret())
-        .send()
-        .await
-        .map_err(|_| AuthError::FetchUserDataError)?;
-
-    let siwe_user = response
-        .json::<SiweUserInfo>()
-        .await
-        .map_err(|_| AuthError::CouldNotExtractUserData)?;
-
-    let addr_parts: Vec<_> = siwe_user.sub.split(':').collect();
-    let address = addr_parts
-        .get(2)
-        .ok_or(AuthError::CouldNotExtractUserData)?
-        .deref()
-        .to_string();
-
-    let tx_count = get_tx_count(
-        &address,
-        &config.eth_check_nonce_at_block,
-        &http_client,
+    let identity = provider.authorize(payload).await?;
+    if let Some(session_id) = session_id {
+        return add_auth_factor(
+            store,
+            storage,
+            session_id,
+            identity,
+            &route,
+            &config,
+            &verification_limiter,
+        )
+        .await;
+    }
+    post_authenticate(
+        store,
+        storage,
+        identity,
+        provider.name(),
+        &route,
         &config,
+        &verification_limiter,
     )
     .await
-    .ok_or(AuthError::CouldNotExtractUserData)?;
-
-    if tx_count < config.eth_min_nonce {
-        return Err(AuthError::UserCreatedAfterDeadline);
-    }
-
-    let user_data = AuthenticatedUser {
-        uid:      format!("eth | {}", address),
-        nickname: siwe_user.preferred_username,
-    };
-
-    post_authenticate(store, storage, user_data, AuthProvider::Ethereum).await
 }
 
-async fn get_tx_count(
-    address: &str,
-    at_block: &str,
-    client: &reqwest::Client,
+// Records that a pending session also authenticated with `route`, and lets
+// it into the lobby once all required providers are satisfied
+async fn add_auth_factor(
+    store: SharedState,
+    storage: PersistentStorage,
+    session_id: SessionId,
+    identity: Identity,
+    route: &str,
     config: &AppConfig,
-) -> Option<i64> {
-    let rpc_payload = json!({
-        "id": 1,
-        "jsonrpc": "2.0",
-        "params": [&address, &at_block],
-        "method": "eth_getTransactionCount"
-    });
-
-    let rpc_response = client
-        .post(&config.eth_rpc_url)
-        .json(&rpc_payload)
-        .send()
-        .await
-        .ok()?;
-
-    let rpc_response_json = rpc_response.json::<serde_json::Value>().await.ok()?;
+    verification_limiter: &VerificationLimiter,
+) -> Result<UserVerified, AuthError> {
+    match storage.has_contributed(&identity.uid).await {
+        Err(error) => return Err(AuthError::Storage(error)),
+        Ok(true) => return Err(AuthError::UserAlreadyContributed),
+        Ok(false) => (),
+    }
 
-    let rpc_result = rpc_response_json.get("result")?.as_str()?;
+    let mut app_state = store.write().await;
+    let uid = match app_state.pending_sessions.get(&session_id) {
+        Some(pending) => pending.token.sub.clone(),
+        None => return Err(AuthError::UnknownSession),
+    };
+    // The session's identity passed these when it signed in, but the
+    // allowlist or the load may have changed since
+    check_admission(&app_state, &uid, true, config, verification_limiter)?;
+    match app_state.auth_factor_sessions.get(&identity.uid) {
+        Some(vouched_for) if vouched_for != &session_id => {
+            return Err(AuthError::AuthFactorInUse);
+        }
+        _ => (),
+    }
+    app_state
+        .auth_factor_sessions
+        .insert(identity.uid, session_id.clone());
 
-    i64::from_str_radix(rpc_result.trim_start_matches("0x"), 16).ok()
+    let pending = app_state
+        .pending_sessions
+        .get_mut(&session_id)
+        .expect("pending session checked above");
+    pending.satisfied.insert(route.to_owned());
+    let missing = pending.missing(&config.required_auth_providers);
+    if !missing.is_empty() {
+        return Err(AuthError::MissingAuthFactor {
+            session_id: session_id.to_string(),
+            missing,
+        });
+    }
+    let pending = app_state
+        .pending_sessions
+        .remove(&session_id)
+        .expect("pending session checked above");
+    admit(
+        &mut app_state,
+        session_id,
+        SessionInfo::signed_in(pending.token),
+    )
 }
 
 async fn verify_csrf(payload: &AuthPayload, store: &SharedState) -> Result<(), AuthError> {
@@ -360,8 +448,11 @@ async fn verify_csrf(payload: &AuthPayload, store: &SharedState) -> Result<(), A
 async fn post_authenticate(
     store: SharedState,
     storage: PersistentStorage,
-    user_data: AuthenticatedUser,
-    auth_provider: AuthProvider,
+    user_data: Identity,
+    provider: &str,
+    route: &str,
+    config: &AppConfig,
+    verification_limiter: &VerificationLimiter,
 ) -> Result<UserVerified, AuthError> {
     // Check if they have already contributed
     match storage.has_contributed(&user_data.uid).await {
@@ -370,8 +461,38 @@ async fn post_authenticate(
         Ok(false) => (),
     }
 
+    if let Some(hook) = &config.admission_hook {
+        hook.check(&AdmissionRequest::Join {
+            uid: &user_data.uid,
+            provider,
+            nickname: &user_data.nickname,
+        })
+        .await
+        .map_err(AuthError::CustomPolicyRejected)?;
+    }
+
     let mut app_state = store.write().await;
 
+    let is_new = !app_state.unique_id_session.contains_key(&user_data.uid);
+    check_admission(
+        &app_state,
+        &user_data.uid,
+        is_new,
+        config,
+        verification_limiter,
+    )?;
+
+    // An identity used as a further auth factor for some session can't start
+    // a session of its own
+    let requires_factors = !config.required_auth_providers.is_empty();
+    if requires_factors {
+        if let Some(vouched_for) = app_state.auth_factor_sessions.get(&user_data.uid) {
+            if app_state.unique_id_session.get(&user_data.uid) != Some(vouched_for) {
+                return Err(AuthError::AuthFactorInUse);
+            }
+        }
+    }
+
     // Check if this user is already in the lobby
     // If so, we send them back their session id
     let session_id = if let Some(session_id) = app_state.unique_id_session.get(&user_data.uid) {
@@ -386,21 +507,734 @@ async fn post_authenticate(
 
     let id_token = IdToken {
         sub:      user_data.uid,
-        provider: auth_provider.to_string().to_owned(),
+        provider: provider.to_owned(),
         nickname: user_data.nickname,
         exp:      u64::MAX,
     };
 
-    let id_token_encoded = id_token.encode().map_err(AuthError::Jwt)?;
+    // Sessions are held back until every required provider vouched for them.
+    // Signing in again after that goes straight to the lobby.
+    if requires_factors && !app_state.lobby.contains_key(&session_id) {
+        app_state
+            .auth_factor_sessions
+            .insert(id_token.sub.clone(), session_id.clone());
+        let pending = app_state
+            .pending_sessions
+            .entry(session_id.clone())
+            .or_insert_with(|| PendingSession {
+                token:      id_token.clone(),
+                satisfied:  BTreeSet::new(),
+                started_at: Instant::now(),
+            });
+        pending.satisfied.insert(route.to_owned());
+        let missing = pending.missing(&config.required_auth_providers);
+        if !missing.is_empty() {
+            return Err(AuthError::MissingAuthFactor {
+                session_id: session_id.to_string(),
+                missing,
+            });
+        }
+        app_state.pending_sessions.remove(&session_id);
+    }
+
+    admit(&mut app_state, session_id, SessionInfo::signed_in(id_token))
+}
+
+// The checks an identity passes before its session enters the lobby. New
+// sessions would most likely time out waiting for verification, so they are
+// shed while verification is the bottleneck. Sessions already in the lobby
+// are unaffected.
+fn check_admission(
+    app_state: &AppState,
+    uid: &str,
+    is_new: bool,
+    config: &AppConfig,
+    verification_limiter: &VerificationLimiter,
+) -> Result<(), AuthError> {
+    if !app_state.is_allowed(uid) {
+        return Err(AuthError::NotAllowed);
+    }
 
-    app_state.lobby.insert(session_id.clone(), SessionInfo {
-        token:                 id_token,
-        last_ping_time:        Instant::now(),
-        is_first_ping_attempt: true,
-    });
+    if let Some(remaining) = config
+        .rejoin_cooldown
+        .and_then(|cooldown| app_state.rejoin_cooldown_remaining(uid, cooldown))
+    {
+        return Err(AuthError::Cooldown(remaining));
+    }
+
+    if is_new
+        && verification_limiter.is_saturated()
+        && app_state.lobby.len() >= config.overload_lobby_size
+    {
+        return Err(AuthError::Overloaded(config.effective_compute_deadline()));
+    }
+    Ok(())
+}
+
+// Caps how many anonymous sessions each client can start per minute, as
+// nothing else keeps one client from filling the lobby
+#[derive(Clone)]
+pub struct AnonymousJoinLimiter(ClientLimiter);
+
+impl AnonymousJoinLimiter {
+    pub fn new(per_minute: u32) -> Self {
+        Self(ClientLimiter::new(per_minute))
+    }
+}
+
+// Joins the lobby without signing in, if `ANONYMOUS_PARTICIPANTS` is set. Each
+// call starts a new session with an identity of its own.
+pub async fn join_anonymously(
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
+    Extension(config): Extension<AppConfig>,
+    Extension(store): Extension<SharedState>,
+    Extension(verification_limiter): Extension<VerificationLimiter>,
+    Extension(join_limiter): Extension<AnonymousJoinLimiter>,
+) -> Result<UserVerified, AuthError> {
+    if !config.anonymous_participants {
+        return Err(AuthError::AnonymousDisabled);
+    }
+    join_limiter
+        .0
+        .try_acquire(client.ip())
+        .map_err(AuthError::TooManyAnonymousJoins)?;
+    let session = SessionInfo::anonymous();
+    let uid = session.unique_identifier().to_owned();
+
+    if let Some(hook) = &config.admission_hook {
+        hook.check(&AdmissionRequest::Join {
+            uid:      &uid,
+            provider: ANONYMOUS_PROVIDER,
+            nickname: "",
+        })
+        .await
+        .map_err(AuthError::CustomPolicyRejected)?;
+    }
+
+    let mut app_state = store.write().await;
+    if app_state.lobby.len() >= MAX_LOBBY_SIZE {
+        return Err(AuthError::LobbyIsFull);
+    }
+    if !app_state.is_allowed(&uid) {
+        return Err(AuthError::NotAllowed);
+    }
+    // Every anonymous session is new, so they are always shed when overloaded
+    if verification_limiter.is_saturated() && app_state.lobby.len() >= config.overload_lobby_size {
+        return Err(AuthError::Overloaded(config.effective_compute_deadline()));
+    }
+
+    let session_id = SessionId::new();
+    app_state.unique_id_session.insert(uid, session_id.clone());
+    admit(&mut app_state, session_id, session)
+}
+
+// Puts the session into the lobby, or refreshes its entry
+fn admit(
+    app_state: &mut AppState,
+    session_id: SessionId,
+    session: SessionInfoBuilder,
+) -> Result<UserVerified, AuthError> {
+    // A refreshed entry keeps its place, a new one goes to the back
+    let position = app_state
+        .lobby
+        .get_index_of(&session_id)
+        .unwrap_or_else(|| app_state.lobby.len());
+    let id_token_encoded = session
+        .token()
+        .map(IdToken::encode)
+        .transpose()
+        .map_err(AuthError::Jwt)?;
+    let resume_token = ResumeToken::new(session_id.clone(), position)
+        .encode()
+        .map_err(AuthError::Jwt)?;
+
+    // Users signing in again keep their original lobby entry time, and their
+    // check-ins so far still count, so signing in again doesn't grant another
+    // first check-in
+    let session = match app_state.lobby.get(&session_id) {
+        Some(session_info) => session
+            .joined_at(session_info.joined_at)
+            .first_ping_attempt(session_info.is_first_ping_attempt)
+            .checkins(session_info.checkins.clone()),
+        None => session,
+    };
+    app_state.lobby.insert(session_id.clone(), session.build());
 
     Ok(UserVerified {
-        id_token:   id_token_encoded,
+        id_token: id_token_encoded,
         session_id: session_id.to_string(),
+        resume_token,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api::v1::{
+            identity::IdentityProvider,
+            lobby::{try_contribute, ClientVersion, TryContributeError},
+        },
+        constants::{
+            AUTH_PROVIDER_COOLDOWN_SEC, AUTH_PROVIDER_FAILURE_THRESHOLD, PENDING_SESSION_TTL_SEC,
+        },
+        storage::test_storage_client,
+        test_util::{create_test_session_info, init_keys, test_config},
+        SharedTranscript, TestTranscript,
+    };
+    use async_session::async_trait;
+    use std::{
+        collections::BTreeSet,
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            Arc,
+        },
+    };
+
+    // Vouches for whoever is named in the authorisation code
+    struct MockProvider;
+
+    #[async_trait]
+    impl IdentityProvider for MockProvider {
+        fn name(&self) -> &'static str {
+            "Mock"
+        }
+
+        async fn authorize(&self, payload: AuthPayload) -> Result<Identity, AuthError> {
+            if payload.code.is_empty() {
+                return Err(AuthError::InvalidAuthCode);
+            }
+            Ok(Identity {
+                uid:      format!("mock | {}", payload.code),
+                nickname: payload.code,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn lobby_flow_works_through_a_custom_provider() {
+        use crate::{
+            api::v1::lobby::{try_contribute, ClientVersion},
+            SharedTranscript, TestTranscript,
+        };
+
+        init_keys().await;
+        let storage = test_storage_client().await;
+        let store = SharedState::default();
+        store.write().await.csrf_tokens.insert("csrf".to_string());
+        let mut providers = IdentityProviders::default();
+        providers.register("mock", MockProvider);
+
+        let sign_in = |provider: &str, code: &str| {
+            callback(
+                None,
+                Path(provider.to_string()),
+                Query(AuthPayload {
+                    code:  code.to_string(),
+                    state: "csrf".to_string(),
+                }),
+                Extension(test_config()),
+                Extension(store.clone()),
+                Extension(storage.clone()),
+                Extension(VerificationLimiter::new(1)),
+                Extension(providers.clone()),
+            )
+        };
+        assert!(matches!(
+            sign_in("github", "alice").await,
+            Err(AuthError::UnknownProvider)
+        ));
+        assert!(matches!(
+            sign_in("mock", "").await,
+            Err(AuthError::InvalidAuthCode)
+        ));
+        assert!(sign_in("mock", "alice").await.is_ok());
+
+        let session_id = store.read().await.unique_id_session["mock | alice"].clone();
+        assert_eq!(store.read().await.lobby[&session_id].provider(), "Mock");
+        let response = try_contribute(
+            session_id,
+            ClientVersion(None),
+            Extension(store.clone()),
+            Extension(storage),
+            Extension(SharedTranscript::<TestTranscript>::default()),
+            Extension(test_config()),
+        )
+        .await;
+        assert!(response.is_ok());
+    }
+
+    // Fails like an unreachable upstream while `down` is set
+    struct FlakyProvider {
+        down:  Arc<AtomicBool>,
+        calls: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl IdentityProvider for FlakyProvider {
+        fn name(&self) -> &'static str {
+            "Flaky"
+        }
+
+        async fn authorize(&self, payload: AuthPayload) -> Result<Identity, AuthError> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            if self.down.load(Ordering::SeqCst) {
+                return Err(AuthError::FetchUserDataError);
+            }
+            Ok(Identity {
+                uid:      format!("flaky | {}", payload.code),
+                nickname: payload.code,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn signing_in_again_does_not_grant_another_first_check_in() {
+        init_keys().await;
+        tokio::time::pause();
+        let storage = test_storage_client().await;
+        let mut providers = IdentityProviders::default();
+        providers.register("mock", MockProvider);
+        // A restart forgets the lobby, only the storage is kept
+        let start = || async {
+            let store = SharedState::default();
+            store.write().await.csrf_tokens.insert("csrf".to_string());
+            // Someone else is contributing, so check-ins only get told to wait
+            store.write().await.participant =
+                Some((SessionId::new(), create_test_session_info(100)));
+            store
+        };
+        let sign_in = |store: SharedState| {
+            callback(
+                None,
+                Path("mock".to_string()),
+                Query(AuthPayload {
+                    code:  "alice".to_string(),
+                    state: "csrf".to_string(),
+                }),
+                Extension(test_config()),
+                Extension(store),
+                Extension(storage.clone()),
+                Extension(VerificationLimiter::new(1)),
+                Extension(providers.clone()),
+            )
+        };
+        let check_in = |store: SharedState| {
+            let storage = storage.clone();
+            async move {
+                let session_id = store.read().await.unique_id_session["mock | alice"].clone();
+                try_contribute(
+                    session_id,
+                    ClientVersion(None),
+                    Extension(store.clone()),
+                    Extension(storage),
+                    Extension(SharedTranscript::<TestTranscript>::default()),
+                    Extension(test_config()),
+                )
+                .await
+            }
+        };
+
+        let store = start().await;
+        assert!(sign_in(store.clone()).await.is_ok());
+        assert!(matches!(
+            check_in(store.clone()).await,
+            Err(TryContributeError::AnotherContributionInProgress(_))
+        ));
+
+        // Signing in again refreshes the lobby entry
+        assert!(sign_in(store.clone()).await.is_ok());
+        assert!(matches!(
+            check_in(store).await,
+            Err(TryContributeError::RateLimited(_))
+        ));
+
+        // Nor does signing in after a restart
+        let store = start().await;
+        assert!(sign_in(store.clone()).await.is_ok());
+        assert!(matches!(
+            check_in(store.clone()).await,
+            Err(TryContributeError::RateLimited(_))
+        ));
+        assert!(store
+            .read()
+            .await
+            .lobby
+            .values()
+            .all(|info| !info.is_first_ping_attempt));
+    }
+
+    #[tokio::test]
+    async fn failing_provider_trips_the_breaker() {
+        tokio::time::pause();
+        let down = Arc::new(AtomicBool::new(true));
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut providers = IdentityProviders::default();
+        providers.register("flaky", FlakyProvider {
+            down:  down.clone(),
+            calls: calls.clone(),
+        });
+        let provider = providers.get("flaky").unwrap();
+        let authorize = || {
+            provider.authorize(AuthPayload {
+                code:  "alice".to_string(),
+                state: "csrf".to_string(),
+            })
+        };
+
+        for _ in 0..AUTH_PROVIDER_FAILURE_THRESHOLD {
+            assert!(matches!(
+                authorize().await,
+                Err(AuthError::FetchUserDataError)
+            ));
+        }
+        // The provider is no longer called while the breaker is open
+        assert!(matches!(
+            authorize().await,
+            Err(AuthError::ProviderUnavailable)
+        ));
+        assert_eq!(
+            calls.load(Ordering::SeqCst),
+            AUTH_PROVIDER_FAILURE_THRESHOLD
+        );
+
+        // Once the cooldown passed, a recovered provider is used again
+        down.store(false, Ordering::SeqCst);
+        tokio::time::advance(Duration::from_secs(AUTH_PROVIDER_COOLDOWN_SEC as u64)).await;
+        assert!(authorize().await.is_ok());
+        assert!(authorize().await.is_ok());
+        assert_eq!(
+            calls.load(Ordering::SeqCst),
+            AUTH_PROVIDER_FAILURE_THRESHOLD + 2
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_identities_missing_from_allowlist() {
+        let store = SharedState::default();
+        store.write().await.allowlist = Some(BTreeSet::from(["github | alice".to_string()]));
+        let user = Identity {
+            uid:      "github | mallory".to_string(),
+            nickname: "mallory".to_string(),
+        };
+
+        let result = post_authenticate(
+            store.clone(),
+            test_storage_client().await,
+            user,
+            "Github",
+            "github",
+            &test_config(),
+            &VerificationLimiter::new(1),
+        )
+        .await;
+
+        assert!(matches!(result, Err(AuthError::NotAllowed)));
+        assert!(store.read().await.lobby.is_empty());
+    }
+
+    #[tokio::test]
+    async fn rejects_rejoining_during_cooldown() {
+        init_keys().await;
+        let storage = test_storage_client().await;
+        tokio::time::pause();
+        let store = SharedState::default();
+        store.write().await.record_contributor("github | alice");
+        let user = || Identity {
+            uid:      "github | alice".to_string(),
+            nickname: "alice".to_string(),
+        };
+        let config = AppConfig {
+            rejoin_cooldown: Some(Duration::from_secs(60)),
+            ..test_config()
+        };
+
+        tokio::time::advance(Duration::from_secs(20)).await;
+        let result = post_authenticate(
+            store.clone(),
+            storage.clone(),
+            user(),
+            "Github",
+            "github",
+            &config,
+            &VerificationLimiter::new(1),
+        )
+        .await;
+        assert!(matches!(
+            result,
+            Err(AuthError::Cooldown(remaining)) if remaining == Duration::from_secs(40)
+        ));
+        assert!(store.read().await.lobby.is_empty());
+
+        tokio::time::advance(Duration::from_secs(40)).await;
+        let result = post_authenticate(
+            store.clone(),
+            storage,
+            user(),
+            "Github",
+            "github",
+            &config,
+            &VerificationLimiter::new(1),
+        )
+        .await;
+        assert!(result.is_ok());
+        assert_eq!(store.read().await.lobby.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn sheds_new_joins_while_verification_is_saturated() {
+        init_keys().await;
+        let storage = test_storage_client().await;
+        let store = SharedState::default();
+        let config = AppConfig {
+            overload_lobby_size: 1,
+            ..test_config()
+        };
+        let limiter = VerificationLimiter::new(1);
+        let user = |name: &str| Identity {
+            uid:      format!("github | {}", name),
+            nickname: name.to_string(),
+        };
+
+        assert!(post_authenticate(
+            store.clone(),
+            storage.clone(),
+            user("alice"),
+            "Github",
+            "github",
+            &config,
+            &limiter,
+        )
+        .await
+        .is_ok());
+
+        let _permit = limiter.try_acquire().unwrap();
+        let result = post_authenticate(
+            store.clone(),
+            storage.clone(),
+            user("bob"),
+            "Github",
+            "github",
+            &config,
+            &limiter,
+        )
+        .await;
+        assert!(matches!(result, Err(AuthError::Overloaded(_))));
+        assert_eq!(store.read().await.lobby.len(), 1);
+
+        // Sessions already in the lobby can still sign in again
+        assert!(post_authenticate(
+            store.clone(),
+            storage,
+            user("alice"),
+            "Github",
+            "github",
+            &config,
+            &limiter,
+        )
+        .await
+        .is_ok());
+    }
+
+    #[tokio::test]
+    async fn sessions_join_once_all_required_providers_vouched() {
+        init_keys().await;
+        let storage = test_storage_client().await;
+        let store = SharedState::default();
+        store.write().await.csrf_tokens.insert("csrf".to_string());
+        let mut providers = IdentityProviders::default();
+        providers.register("github", MockProvider);
+        providers.register("siwe", MockProvider);
+        let config = AppConfig {
+            required_auth_providers: vec!["github".to_string(), "siwe".to_string()],
+            ..test_config()
+        };
+
+        let sign_in = |session_id: Option<SessionId>, provider: &str, code: &str| {
+            callback(
+                session_id,
+                Path(provider.to_string()),
+                Query(AuthPayload {
+                    code:  code.to_string(),
+                    state: "csrf".to_string(),
+                }),
+                Extension(config.clone()),
+                Extension(store.clone()),
+                Extension(storage.clone()),
+                Extension(VerificationLimiter::new(1)),
+                Extension(providers.clone()),
+            )
+        };
+
+        // A single provider is not enough to join
+        let pending = sign_in(None, "github", "alice").await;
+        let session_id = store.read().await.unique_id_session["mock | alice"].clone();
+        assert!(matches!(
+            pending,
+            Err(AuthError::MissingAuthFactor { session_id: ref id, ref missing })
+                if id == &session_id.to_string() && missing == &["siwe".to_string()]
+        ));
+        assert!(store.read().await.lobby.is_empty());
+
+        // Presenting the same provider again doesn't help
+        assert!(matches!(
+            sign_in(Some(session_id.clone()), "github", "alice").await,
+            Err(AuthError::MissingAuthFactor { .. })
+        ));
+        assert!(matches!(
+            sign_in(Some(SessionId::new()), "siwe", "0xalice").await,
+            Err(AuthError::UnknownSession)
+        ));
+
+        assert!(sign_in(Some(session_id.clone()), "siwe", "0xalice")
+            .await
+            .is_ok());
+        assert_eq!(
+            store.read().await.lobby[&session_id].unique_identifier(),
+            "mock | alice"
+        );
+
+        // The second factor can't vouch for anyone else
+        assert!(matches!(
+            sign_in(None, "siwe", "0xalice").await,
+            Err(AuthError::AuthFactorInUse)
+        ));
+        assert!(matches!(
+            sign_in(None, "siwe", "0xbob").await,
+            Err(AuthError::MissingAuthFactor { .. })
+        ));
+        let bob = store.read().await.unique_id_session["mock | 0xbob"].clone();
+        assert!(matches!(
+            sign_in(Some(bob), "github", "0xalice").await,
+            Err(AuthError::AuthFactorInUse)
+        ));
+        assert_eq!(store.read().await.lobby.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn pending_sessions_expire_and_are_checked_again() {
+        init_keys().await;
+        tokio::time::pause();
+        let storage = test_storage_client().await;
+        let store = SharedState::default();
+        store.write().await.csrf_tokens.insert("csrf".to_string());
+        let mut providers = IdentityProviders::default();
+        providers.register("github", MockProvider);
+        providers.register("siwe", MockProvider);
+        let config = AppConfig {
+            required_auth_providers: vec!["github".to_string(), "siwe".to_string()],
+            ..test_config()
+        };
+        let ttl = Duration::from_secs(PENDING_SESSION_TTL_SEC as u64);
+
+        let sign_in = |session_id: Option<SessionId>, provider: &str, code: &str| {
+            callback(
+                session_id,
+                Path(provider.to_string()),
+                Query(AuthPayload {
+                    code:  code.to_string(),
+                    state: "csrf".to_string(),
+                }),
+                Extension(config.clone()),
+                Extension(store.clone()),
+                Extension(storage.clone()),
+                Extension(VerificationLimiter::new(1)),
+                Extension(providers.clone()),
+            )
+        };
+
+        // The allowlist changed after the first sign-in
+        assert!(sign_in(None, "github", "alice").await.is_err());
+        let alice = store.read().await.unique_id_session["mock | alice"].clone();
+        store.write().await.allowlist = Some(BTreeSet::from(["mock | bob".to_string()]));
+        assert!(matches!(
+            sign_in(Some(alice), "siwe", "0xalice").await,
+            Err(AuthError::NotAllowed)
+        ));
+
+        assert!(sign_in(None, "github", "bob").await.is_err());
+        let bob = store.read().await.unique_id_session["mock | bob"].clone();
+        tokio::time::advance(ttl).await;
+        {
+            let mut app_state = store.write().await;
+            app_state.prune_pending_sessions(Instant::now(), ttl);
+            assert!(app_state.pending_sessions.is_empty());
+            assert!(app_state.auth_factor_sessions.is_empty());
+        }
+        assert!(matches!(
+            sign_in(Some(bob), "siwe", "0xbob").await,
+            Err(AuthError::UnknownSession)
+        ));
+    }
+
+    #[tokio::test]
+    async fn lobby_flow_works_for_anonymous_participants() {
+        use crate::{
+            test_transcript::TestContribution::ValidContribution, test_util::TestCeremony,
+        };
+
+        init_keys().await;
+        let mut ceremony = TestCeremony::new("anonymous").await;
+        let store = ceremony.store.clone();
+        let join_limiter = AnonymousJoinLimiter::new(2);
+        let join = |config: AppConfig| {
+            join_anonymously(
+                ConnectInfo(SocketAddr::from(([10, 0, 0, 1], 443))),
+                Extension(config),
+                Extension(store.clone()),
+                Extension(VerificationLimiter::new(1)),
+                Extension(join_limiter.clone()),
+            )
+        };
+        assert!(matches!(
+            join(test_config()).await,
+            Err(AuthError::AnonymousDisabled)
+        ));
+
+        ceremony.config.anonymous_participants = true;
+        let config = ceremony.config.clone();
+        let verified = join(config.clone()).await.ok().unwrap();
+        assert!(verified.id_token.is_none());
+        assert!(join(config.clone()).await.is_ok());
+        // Each client can only start so many sessions
+        assert!(matches!(
+            join(config.clone()).await,
+            Err(AuthError::TooManyAnonymousJoins(_))
+        ));
+        let (first, second) = {
+            let app_state = store.read().await;
+            let sessions: Vec<_> = app_state.lobby.keys().cloned().collect();
+            assert_eq!(sessions.len(), 2);
+            assert_eq!(sessions[0].to_string(), verified.session_id);
+            // Each session has an identity of its own
+            let first = &app_state.lobby[&sessions[0]];
+            let second = &app_state.lobby[&sessions[1]];
+            assert_ne!(first.unique_identifier(), second.unique_identifier());
+            assert_eq!(first.provider(), ANONYMOUS_PROVIDER);
+            (sessions[0].clone(), sessions[1].clone())
+        };
+
+        let check_in = |session_id: SessionId| {
+            try_contribute(
+                session_id,
+                ClientVersion(None),
+                Extension(store.clone()),
+                Extension(ceremony.storage.clone()),
+                Extension(ceremony.transcript.clone()),
+                Extension(config.clone()),
+            )
+        };
+        assert!(check_in(first.clone()).await.is_ok());
+        let receipt = ceremony
+            .contribute(first.clone(), ValidContribution(123))
+            .await;
+        assert!(receipt.is_ok());
+
+        // The session can't contribute twice, while the other one still can
+        assert!(matches!(
+            check_in(first).await,
+            Err(TryContributeError::AlreadyContributed { .. })
+        ));
+        assert!(check_in(second).await.is_ok());
+    }
+}
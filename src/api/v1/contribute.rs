@@ -1,30 +1,269 @@
 use axum::{
+    extract::{BodyStream, Path},
     response::{IntoResponse, Response},
     Extension, Json,
 };
-use http::StatusCode;
-use serde_json::json;
+use http::{
+    header::{AUTHORIZATION, CONNECTION},
+    HeaderMap, StatusCode,
+};
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_int_counter, register_int_counter_vec, register_int_gauge, IntCounter, IntCounterVec,
+    IntGauge,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::path::{Path as FilePath, PathBuf};
+use tokio::time::{timeout, Instant, Interval};
 
 use crate::{
-    data::transcript::write_transcript_file,
-    jwt::{errors::JwtError, Receipt},
-    storage::PersistentStorage,
-    AppConfig, Contribution, SessionId, SharedState, SharedTranscript, Transcript,
+    admission::AdmissionRequest,
+    api::v1::{info::Counters, lobby::SlotOutcome, strict::StrictJson},
+    attestation_chain,
+    data::{
+        hash::TranscriptHash,
+        transcript::{
+            dimensions_shrank, max_contribution_size, transcript_hash, write_transcript_file,
+        },
+    },
+    ethereum::{recover_address, ETH_UID_PREFIX},
+    framing::{decode_framed_checked, FramingError, MAX_FRAME_SIZE},
+    jwt::{errors::JwtError, Receipt, ResumeToken},
+    keys::KEYS,
+    storage::{Attestation, BundleRecord, PersistentStorage, StorageError},
+    verification::{json_hash, RejectionFingerprint, SharedVerifier, VerificationLimiter},
+    AppConfig, AppState, Contribution, SessionId, SharedState, SharedTranscript, Transcript,
 };
 
+static CONTRIBUTIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "contributions_total",
+        "Number of accepted contributions, by auth provider",
+        &["provider"]
+    )
+    .unwrap()
+});
+
+static ATTESTATION_WRITE_FAILURES: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "attestation_write_failures_total",
+        "Number of failed attempts to store an attestation"
+    )
+    .unwrap()
+});
+
+static ATTESTATION_CONFLICTS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "attestation_conflicts_total",
+        "Number of attestations dropped because another one was stored in their place"
+    )
+    .unwrap()
+});
+
+static PENDING_ATTESTATIONS: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "pending_attestations",
+        "Number of attestations waiting to be stored"
+    )
+    .unwrap()
+});
+
 pub struct ContributeReceipt {
     encoded_receipt_token: String,
+    // The contribution is in the transcript, but its attestation is not
+    // stored yet
+    attestation_pending:   bool,
 }
 
 impl IntoResponse for ContributeReceipt {
     fn into_response(self) -> Response {
-        (StatusCode::OK, self.encoded_receipt_token).into_response()
+        let status = if self.attestation_pending {
+            StatusCode::ACCEPTED
+        } else {
+            StatusCode::OK
+        };
+        (status, self.encoded_receipt_token).into_response()
+    }
+}
+
+// An attestation that couldn't be stored yet. The transcript is the source of
+// truth, so the contribution stands and the attestation is retried later.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PendingAttestation {
+    attestation: Attestation,
+    receipt:     String,
+    // Absent in journals written before bundles were stored
+    #[serde(default)]
+    bundle:      Option<BundleRecord>,
+}
+
+impl PendingAttestation {
+    pub const fn attestation(&self) -> &Attestation {
+        &self.attestation
+    }
+
+    pub fn receipt(&self) -> &str {
+        &self.receipt
+    }
+
+    // Tries to store the attestation. Returns whether it is settled, which
+    // is also the case if retrying could never store it.
+    async fn settle(&self, storage: &PersistentStorage) -> bool {
+        let index = self.attestation.contribution_index;
+        let error = match storage
+            .insert_attestation(&self.attestation, Some(&self.receipt), self.bundle.as_ref())
+            .await
+        {
+            Ok(()) => return true,
+            Err(error) => error,
+        };
+        ATTESTATION_WRITE_FAILURES.inc();
+        if !error.is_conflict() {
+            tracing::error!(
+                contribution_index = index,
+                ?error,
+                "Cannot store attestation"
+            );
+            return false;
+        }
+        // An earlier attempt may have stored it without the queue learning
+        // about it. Otherwise another attestation holds its place, which is
+        // final.
+        let chain_hash = self.attestation.chain_hash.as_deref().unwrap_or_default();
+        match storage.has_attestation(chain_hash).await {
+            Ok(true) => true,
+            Ok(false) => {
+                ATTESTATION_CONFLICTS.inc();
+                tracing::error!(
+                    contribution_index = index,
+                    receipt = %self.receipt,
+                    "Attestation conflicts with a stored one and is dropped"
+                );
+                true
+            }
+            Err(error) => {
+                tracing::error!(
+                    contribution_index = index,
+                    ?error,
+                    "Cannot store attestation"
+                );
+                false
+            }
+        }
+    }
+}
+
+// Where attestations are kept until they are stored, next to the transcript,
+// so those of acknowledged contributions survive a restart
+pub fn attestation_journal_path(transcript_file: &FilePath) -> PathBuf {
+    let mut path = transcript_file.as_os_str().to_owned();
+    path.push(".attestations");
+    PathBuf::from(path)
+}
+
+// The attestation chain as far as storage may not know it yet
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct AttestationJournal {
+    // The chain hash of the latest attestation, stored or not. Attestations
+    // may be stored out of order when a retry succeeds, so this can't be
+    // derived from the pending ones.
+    pub chain_head: Option<String>,
+    // In contribution order
+    pub pending:    Vec<PendingAttestation>,
+}
+
+// Replaces the journal with the chain head and pending attestations of
+// `app_state`. It is written under the state lock, so it can't be replaced by
+// an older state, and it is small enough for that.
+fn write_attestation_journal(path: &FilePath, app_state: &AppState) {
+    let journal = AttestationJournal {
+        chain_head: app_state.attestation_chain_head.clone(),
+        pending:    app_state.pending_attestations.clone(),
+    };
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    let encoded = serde_json::to_vec(&journal).expect("Cannot serialize attestations");
+    let written = std::fs::write(&temp, encoded).and_then(|()| std::fs::rename(&temp, path));
+    if let Err(error) = written {
+        ATTESTATION_WRITE_FAILURES.inc();
+        tracing::error!(?error, "Cannot write attestation journal");
+    }
+    PENDING_ATTESTATIONS.set(i64::try_from(journal.pending.len()).unwrap_or(i64::MAX));
+}
+
+// The journal left by a previous run, which is empty if there was none
+pub async fn read_attestation_journal(path: &FilePath) -> std::io::Result<AttestationJournal> {
+    match tokio::fs::read(path).await {
+        Ok(journal) => serde_json::from_slice(&journal).map_err(std::io::Error::from),
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+            Ok(AttestationJournal::default())
+        }
+        Err(error) => Err(error),
+    }
+}
+
+// Tries to store the attestations that failed before, in contribution order.
+// Those that fail again stay queued.
+pub async fn retry_pending_attestations(
+    store: &SharedState,
+    storage: &PersistentStorage,
+    journal: &FilePath,
+) {
+    let pending = store.read().await.pending_attestations.clone();
+    let mut settled = Vec::new();
+    for pending in pending {
+        if pending.settle(storage).await {
+            settled.push(pending.attestation.chain_hash);
+        }
+    }
+    if settled.is_empty() {
+        return;
+    }
+    let mut app_state = store.write().await;
+    app_state
+        .pending_attestations
+        .retain(|pending| !settled.contains(&pending.attestation.chain_hash));
+    write_attestation_journal(journal, &app_state);
+}
+
+pub async fn retry_attestations_on_interval(
+    store: SharedState,
+    storage: PersistentStorage,
+    journal: PathBuf,
+    mut interval: Interval,
+) {
+    loop {
+        interval.tick().await;
+        retry_pending_attestations(&store, &storage, &journal).await;
     }
 }
 
 pub enum ContributeError {
     NotUsersTurn,
     InvalidContribution,
+    // Contains the degenerate pattern the contribution matched
+    SuspiciousContribution(&'static str),
+    MalformedStream(FramingError),
+    // A streamed upload failed a check before it was fully received
+    RejectedMidStream,
+    DuplicatePubkey,
+    // The contribution's hash is on the operator's denylist
+    DeniedContribution,
+    // Appending the contribution would have removed powers from the transcript
+    TranscriptShrank,
+    VerificationTimeout,
+    IdentitySignatureMismatch,
+    // The contribution was computed from another challenge than the current
+    // one, see `CHALLENGE_HASH_HEADER`
+    ChallengeMismatch,
+    // Submitted sooner after the spot was granted than the minimum compute
+    // time allows
+    TooFast,
+    // The operator's admission hook vetoed the contribution, contains its
+    // reason
+    CustomPolicyRejected(&'static str),
+    Busy,
     Auth(JwtError),
 }
 
@@ -39,6 +278,81 @@ impl IntoResponse for ContributeError {
                 let body = Json(json!({"error" : "contribution invalid"}));
                 (StatusCode::BAD_REQUEST, body)
             }
+            Self::SuspiciousContribution(pattern) => {
+                let body = Json(json!({
+                    "error" : "contribution looks degenerate",
+                    "pattern": pattern,
+                }));
+                (StatusCode::BAD_REQUEST, body)
+            }
+            Self::MalformedStream(error) => {
+                let message = match error {
+                    FramingError::MalformedFrame => "malformed frame".to_string(),
+                    FramingError::FrameTooLarge(size) => format!(
+                        "frame of {} bytes exceeds the limit of {} bytes",
+                        size, MAX_FRAME_SIZE
+                    ),
+                    FramingError::BodyTooLarge(limit) => {
+                        format!("contribution exceeds the limit of {} bytes", limit)
+                    }
+                    FramingError::InvalidEncoding => {
+                        "contribution could not be decoded".to_string()
+                    }
+                };
+                let body = Json(json!({ "error": message }));
+                (StatusCode::BAD_REQUEST, body)
+            }
+            Self::RejectedMidStream => {
+                let body = Json(json!({"error" : "contribution invalid"}));
+                // The rest of the upload is not read, don't let the client
+                // keep sending it
+                return (StatusCode::BAD_REQUEST, [(CONNECTION, "close")], body).into_response();
+            }
+            Self::DuplicatePubkey => {
+                let body =
+                    Json(json!({"error" : "pubkey was already used by a previous contribution"}));
+                (StatusCode::BAD_REQUEST, body)
+            }
+            Self::DeniedContribution => {
+                let body = Json(json!({"error" : "contribution is denied"}));
+                (StatusCode::BAD_REQUEST, body)
+            }
+            Self::TranscriptShrank => {
+                let body =
+                    Json(json!({"error" : "contribution would remove powers from the transcript"}));
+                (StatusCode::BAD_REQUEST, body)
+            }
+            Self::VerificationTimeout => {
+                let body = Json(json!({"error" : "contribution took too long to verify"}));
+                (StatusCode::BAD_REQUEST, body)
+            }
+            Self::IdentitySignatureMismatch => {
+                let body = Json(json!({
+                    "error" : "contribution is not signed by the authenticated address"
+                }));
+                (StatusCode::UNAUTHORIZED, body)
+            }
+            Self::TooFast => {
+                let body = Json(json!({"error" : "contribution was submitted too quickly"}));
+                (StatusCode::BAD_REQUEST, body)
+            }
+            Self::ChallengeMismatch => {
+                let body = Json(
+                    json!({"error" : "contribution was not computed from the current challenge"}),
+                );
+                (StatusCode::CONFLICT, body)
+            }
+            Self::CustomPolicyRejected(reason) => {
+                let body = Json(json!({
+                    "error" : "rejected by custom policy",
+                    "reason": reason,
+                }));
+                (StatusCode::FORBIDDEN, body)
+            }
+            Self::Busy => {
+                let body = Json(json!({"error" : "too many verifications in progress"}));
+                (StatusCode::SERVICE_UNAVAILABLE, body)
+            }
             Self::Auth(err) => return err.into_response(),
         };
 
@@ -46,21 +360,36 @@ impl IntoResponse for ContributeError {
     }
 }
 
+// Signature of the contribution hash by the contributor's Ethereum address,
+// made like `personal_sign`
+pub const IDENTITY_SIGNATURE_HEADER: &str = "x-identity-signature";
+
+// Optional MAC of a trusted verifier over the transcript and contribution,
+// see `PreverifiedVerifier`
+pub const VERIFICATION_MAC_HEADER: &str = "x-verification-mac";
+
+// Optional `challenge_hash` of the challenge the contribution was computed
+// from, see `challenge`
+pub const CHALLENGE_HASH_HEADER: &str = "x-challenge-hash";
+
 pub async fn contribute<T>(
     session_id: SessionId,
+    headers: HeaderMap,
     Json(contribution): Json<T::ContributionType>,
     Extension(store): Extension<SharedState>,
     Extension(config): Extension<AppConfig>,
     Extension(shared_transcript): Extension<SharedTranscript<T>>,
     Extension(storage): Extension<PersistentStorage>,
+    Extension(verification_limiter): Extension<VerificationLimiter>,
+    Extension(verifier): Extension<SharedVerifier<T>>,
 ) -> Result<ContributeReceipt, ContributeError>
 where
     T: Transcript + Send + Sync + 'static,
-    T::ContributionType: Send,
+    T::ContributionType: Send + 'static,
     <<T as Transcript>::ContributionType as Contribution>::Receipt: Send,
 {
     // 1. Check if this person should be contributing
-    let id_token = {
+    let (contributor, provider, id_token) = {
         let app_state = store.read().await;
         let (id, session_info) = &app_state
             .participant
@@ -69,114 +398,749 @@ where
         if &session_id != id {
             return Err(ContributeError::NotUsersTurn);
         }
-        session_info.token.clone()
+        if arrived_too_fast(&app_state, &config) {
+            return Err(ContributeError::TooFast);
+        }
+        (
+            session_info.unique_identifier().to_owned(),
+            session_info.provider().to_owned(),
+            session_info.token.clone(),
+        )
     };
 
     // We also know that if they were in the lobby
     // then they did not participate already because
     // when we auth participants, this is checked
 
-    // 2. Check if the program state transition was correct
-    {
-        let transcript = shared_transcript.read().await;
-        if transcript.verify_contribution(&contribution).is_err() {
-            let mut app_state = store.write().await;
-            app_state.clear_current_contributor();
-            storage
-                .expire_contribution(id_token.unique_identifier())
-                .await;
-            return Err(ContributeError::InvalidContribution);
+    // A contribution computed from a challenge that is no longer current
+    // can't verify. Only the participant changes the transcript, so they
+    // keep their spot and can fetch the current challenge.
+    if let Some(submitted) = headers.get(CHALLENGE_HASH_HEADER) {
+        let current = challenge_hash(&shared_transcript.read().await.get_contribution());
+        if !submitted
+            .to_str()
+            .map_or(false, |submitted| submitted.eq_ignore_ascii_case(&current))
+        {
+            return Err(ContributeError::ChallengeMismatch);
         }
     }
 
-    {
-        let mut transcript = shared_transcript.write().await;
-        *transcript = transcript.update(&contribution);
+    let contribution_hash = hex::encode(json_hash(&contribution));
+
+    // 2. Ethereum contributors sign the contribution hash with the key of
+    // their address, binding the contribution to their identity
+    let identity_signature = headers
+        .get(IDENTITY_SIGNATURE_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned);
+    if config.require_identity_signature {
+        if let Some(address) = contributor.strip_prefix(ETH_UID_PREFIX) {
+            let signed_hash = format!("0x{}", contribution_hash);
+            let recovered = identity_signature
+                .as_deref()
+                .and_then(|signature| recover_address(signed_hash.as_bytes(), signature));
+            if !recovered.map_or(false, |recovered| recovered.eq_ignore_ascii_case(address)) {
+                return Err(ContributeError::IdentitySignatureMismatch);
+            }
+        }
+    }
+
+    // 3. Check that the contribution isn't denied by the operator, that it
+    // doesn't reuse an earlier contributor's pubkey to pass as them, and that
+    // the operator's admission hook, if any, accepts it
+    let pubkeys = contribution.pubkeys();
+    let rejection = {
+        let mut app_state = store.write().await;
+        if !app_state.is_participant(&session_id) {
+            return Err(ContributeError::NotUsersTurn);
+        }
+        let rejection = if app_state.denylist.contains(&contribution_hash) {
+            Some(ContributeError::DeniedContribution)
+        } else if pubkeys
+            .iter()
+            .any(|pubkey| app_state.seen_pubkeys.contains(pubkey))
+        {
+            Some(ContributeError::DuplicatePubkey)
+        } else {
+            None
+        };
+        if rejection.is_some() {
+            app_state.clear_current_contributor(SlotOutcome::Invalid);
+        }
+        rejection
+    };
+    if let Some(rejection) = rejection {
+        storage.expire_contribution(&contributor).await;
+        return Err(rejection);
+    }
+    if let Some(hook) = &config.admission_hook {
+        let verdict = hook
+            .check(&AdmissionRequest::Contribution {
+                uid:               &contributor,
+                provider:          &provider,
+                contribution_hash: &contribution_hash,
+            })
+            .await;
+        if let Err(reason) = verdict {
+            release_rejected(&store, &storage, &session_id, &contributor).await;
+            return Err(ContributeError::CustomPolicyRejected(reason));
+        }
+    }
+
+    // 4. Check if the program state transition was correct
+    let contribution = {
+        let permit = verification_limiter
+            .try_acquire()
+            .ok_or(ContributeError::Busy)?;
+
+        // Taken together with the transcript, so it is the hash of the state
+        // the contribution is verified against, whatever happens meanwhile
+        let (verified_against, transcript) = {
+            let app_state = store.read().await;
+            let transcript = shared_transcript.clone().read_owned().await;
+            (app_state.transcript_hash.clone(), transcript)
+        };
+        let mac = headers
+            .get(VERIFICATION_MAC_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned);
+        let check_entropy = config.check_contribution_entropy;
+        let transcript_hash = verified_against.clone();
+        let verification = tokio::task::spawn_blocking(move || {
+            // A verification we stopped waiting for keeps its slot until it
+            // is actually done, so slow contributions can't pile up
+            let _permit = permit;
+            // The error is reduced to its fingerprint right away, so only
+            // plain data is sent back to the handler
+            let rejection = match verifier.verify(
+                &*transcript,
+                &transcript_hash,
+                &contribution,
+                mac.as_deref(),
+            ) {
+                Err(error) => Some((RejectionFingerprint::new::<T>(&contribution, &error), None)),
+                // Valid contributions can still be degenerate, this is a
+                // best-effort check on top of verification
+                Ok(()) if check_entropy => {
+                    transcript.suspicious_pattern(&contribution).map(|pattern| {
+                        let rejection =
+                            RejectionFingerprint::suspicious::<T>(&contribution, pattern);
+                        (rejection, Some(pattern))
+                    })
+                }
+                Ok(()) => None,
+            };
+            (contribution, rejection)
+        });
+        let (contribution, rejection) = match timeout(config.verify_timeout, verification).await {
+            Ok(outcome) => outcome.expect("verification panicked"),
+            Err(_) => {
+                release_rejected(&store, &storage, &session_id, &contributor).await;
+                return Err(ContributeError::VerificationTimeout);
+            }
+        };
+        if let Some((rejection, pattern)) = rejection {
+            rejection.record(&provider);
+            let released = store
+                .write()
+                .await
+                .clear_contributor_if_current(&session_id, SlotOutcome::Invalid);
+            if released {
+                storage.expire_contribution(&contributor).await;
+            }
+            if config.log_rejected_contributions {
+                storage
+                    .insert_rejected_contribution(
+                        &contributor,
+                        &rejection.contribution_hash,
+                        &rejection.reason,
+                    )
+                    .await;
+            }
+            if config.quarantine_contributions {
+                let contribution =
+                    serde_json::to_vec(&contribution).expect("Cannot serialize contribution");
+                storage
+                    .quarantine_contribution(
+                        &contributor,
+                        &rejection.contribution_hash,
+                        &rejection.reason,
+                        &verified_against.to_string(),
+                        &contribution,
+                    )
+                    .await;
+            }
+            return Err(pattern.map_or(
+                ContributeError::InvalidContribution,
+                ContributeError::SuspiciousContribution,
+            ));
+        }
+        contribution
+    };
+
+    // 5. Append the contribution, unless that would leave the transcript with
+    // fewer powers. A valid contribution only transforms the existing powers.
+    // The updated transcript is built and hashed before any lock is taken for
+    // writing. Only the participant changes the transcript, so it is still
+    // the one the contribution was verified against.
+    let (updated, shrank, checks) = {
+        let transcript = shared_transcript.read().await;
+        let updated = transcript.update(&contribution);
+        let shrank = dimensions_shrank(&transcript.dimensions(), &updated.dimensions());
+        (updated, shrank, transcript.verification_checks())
+    };
+    let transcript_hash_after = transcript_hash(&updated);
+
+    // The state lock is held from here until the spot is freed, so the spot
+    // can't expire and go to someone else while the contribution is applied.
+    let mut app_state = store.write().await;
+    if !app_state.is_participant(&session_id) {
+        return Err(ContributeError::NotUsersTurn);
     }
+    if shrank {
+        app_state.clear_current_contributor(SlotOutcome::Invalid);
+        drop(app_state);
+        storage.expire_contribution(&contributor).await;
+        return Err(ContributeError::TranscriptShrank);
+    }
+    assert_eq!(
+        app_state.transcript_hash, verified_against,
+        "only the participant changes the transcript"
+    );
+    *shared_transcript.write().await = updated;
+    let transcript_hash_before = verified_against;
 
     let receipt = {
         Receipt {
             id_token,
             witness: contribution.get_receipt(),
+            transcript_hash_before,
+            transcript_hash_after,
+            checks: checks.iter().map(ToString::to_string).collect(),
         }
     };
 
     let encoded_receipt_token = receipt.encode().map_err(ContributeError::Auth)?;
 
+    if config.order_commitment {
+        app_state
+            .order_commitment
+            .push(encoded_receipt_token.as_bytes());
+    }
+    let bundle = ContributionBundle {
+        uid:                contributor.clone(),
+        contribution_index: app_state.num_contributions,
+        witness:            serde_json::to_value(&receipt.witness)
+            .expect("Cannot serialize receipt"),
+        receipt:            encoded_receipt_token.clone(),
+        transcript_hash:    receipt.transcript_hash_after.clone(),
+    };
+    app_state
+        .contribution_bundles
+        .insert(contributor.clone(), bundle.clone());
+    let contribution_index = app_state.num_contributions;
+    app_state.seen_pubkeys.extend(pubkeys.iter().cloned());
+    app_state
+        .transcript_hash
+        .clone_from(&receipt.transcript_hash_after);
+    app_state.num_contributions += 1;
+    Counters::count(&app_state.counters.contributions);
+    if config.drain_at_max_contributions {
+        app_state.drain_at_cap(config.max_contributions);
+    }
+    app_state.record_compute_time();
+    app_state.record_contributor(&contributor);
+    CONTRIBUTIONS.with_label_values(&[&provider]).inc();
+
+    // Only the participant gets here, this was checked under the same lock
+    // before appending
+    assert!(
+        app_state.is_participant(&session_id),
+        "participant is guaranteed to be this session here"
+    );
+    app_state
+        .finished_sessions
+        .insert(session_id, (contributor.clone(), Instant::now()));
+
+    // Linked to the chain under the lock, so the chain follows the
+    // contribution order
+    let attestation = Attestation::new(
+        app_state.phase.as_ref().map_or(0, |phase| phase.phase),
+        contribution_index,
+        storage.identity_hash(&contributor),
+        &provider,
+        pubkeys,
+        identity_signature,
+        app_state
+            .attestation_chain_head
+            .as_deref()
+            .unwrap_or(attestation_chain::GENESIS),
+    );
+    app_state
+        .attestation_chain_head
+        .clone_from(&attestation.chain_hash);
+    // Journaled before the participant learns about the contribution, and
+    // only dropped from the journal once it is stored
+    let pending = PendingAttestation {
+        attestation,
+        receipt: encoded_receipt_token.clone(),
+        bundle: Some(BundleRecord {
+            witness:         bundle.witness,
+            transcript_hash: bundle.transcript_hash,
+        }),
+    };
+    let journal = attestation_journal_path(&config.transcript_file);
+    app_state.pending_attestations.push(pending.clone());
+    write_attestation_journal(&journal, &app_state);
+
+    // Remove this person from the contribution spot. It was checked to be
+    // theirs above, under the same lock.
+    app_state.clear_current_contributor(SlotOutcome::Completed);
+
+    drop(app_state); // Release AppState lock
+
+    // The next participant may already be computing on the transcript in
+    // memory. Writes are serialized, so the file still ends up with the
+    // latest transcript.
     write_transcript_file(
         config.transcript_file,
         config.transcript_in_progress_file,
+        config.transcript_signature_file,
+        config.transcript_objects,
         shared_transcript,
     )
     .await;
 
+    storage.finish_contribution(&contributor).await;
+    let attestation_pending = !pending.settle(&storage).await;
+    if !attestation_pending {
+        let mut app_state = store.write().await;
+        app_state
+            .pending_attestations
+            .retain(|queued| queued.attestation.chain_hash != pending.attestation.chain_hash);
+        write_attestation_journal(&journal, &app_state);
+    }
+
+    Ok(ContributeReceipt {
+        encoded_receipt_token,
+        attestation_pending,
+    })
+}
+
+// A contribution that arrives right after the spot was granted was likely
+// computed ahead of time. This is only a heuristic, so the participant keeps
+// their spot and may submit again.
+fn arrived_too_fast(app_state: &AppState, config: &AppConfig) -> bool {
+    match (config.min_compute_time, app_state.participant_granted_at) {
+        (Some(min_compute_time), Some(granted_at)) => granted_at.elapsed() < min_compute_time,
+        _ => false,
+    }
+}
+
+// Frees the spot of a participant whose contribution was turned down, unless
+// it was freed in the meantime, e.g. because their deadline passed
+async fn release_rejected(
+    store: &SharedState,
+    storage: &PersistentStorage,
+    session_id: &SessionId,
+    contributor: &str,
+) {
+    let released = store
+        .write()
+        .await
+        .clear_contributor_if_current(session_id, SlotOutcome::Invalid);
+    if released {
+        storage.expire_contribution(contributor).await;
+    }
+}
+
+// Takes a contribution sent as a JSON body, which is read and parsed once,
+// see `StrictJson`
+pub async fn contribute_json<T>(
+    session_id: SessionId,
+    headers: HeaderMap,
+    StrictJson(contribution): StrictJson<T::ContributionType>,
+    Extension(store): Extension<SharedState>,
+    Extension(config): Extension<AppConfig>,
+    Extension(shared_transcript): Extension<SharedTranscript<T>>,
+    Extension(storage): Extension<PersistentStorage>,
+    Extension(verification_limiter): Extension<VerificationLimiter>,
+    Extension(verifier): Extension<SharedVerifier<T>>,
+) -> Result<ContributeReceipt, ContributeError>
+where
+    T: Transcript + Send + Sync + 'static,
+    T::ContributionType: Send + 'static,
+    <<T as Transcript>::ContributionType as Contribution>::Receipt: Send,
+{
+    contribute::<T>(
+        session_id,
+        headers,
+        Json(contribution),
+        Extension(store),
+        Extension(config),
+        Extension(shared_transcript),
+        Extension(storage),
+        Extension(verification_limiter),
+        Extension(verifier),
+    )
+    .await
+}
+
+// Like `contribute`, but the contribution arrives as a stream of
+// length-prefixed frames that is decoded as it arrives, see `decode_framed`.
+// Points are checked as soon as they are decoded, and the upload is aborted
+// on the first bad one. The pairing checks run once it is complete.
+pub async fn contribute_stream<T>(
+    session_id: SessionId,
+    headers: HeaderMap,
+    body: BodyStream,
+    Extension(store): Extension<SharedState>,
+    Extension(config): Extension<AppConfig>,
+    Extension(shared_transcript): Extension<SharedTranscript<T>>,
+    Extension(storage): Extension<PersistentStorage>,
+    Extension(verification_limiter): Extension<VerificationLimiter>,
+    Extension(verifier): Extension<SharedVerifier<T>>,
+) -> Result<ContributeReceipt, ContributeError>
+where
+    T: Transcript + Send + Sync + 'static,
+    T::ContributionType: Send + 'static,
+    T::ValidationError: Send,
+    <<T as Transcript>::ContributionType as Contribution>::Receipt: Send,
+{
+    // Don't spend time decoding for someone who can't contribute anyway, or
+    // whose contribution would be turned down for arriving too fast
+    let (contributor, provider) = {
+        let app_state = store.read().await;
+        let identity = match &app_state.participant {
+            Some((id, session_info)) if id == &session_id => (
+                session_info.unique_identifier().to_owned(),
+                session_info.provider().to_owned(),
+            ),
+            _ => return Err(ContributeError::NotUsersTurn),
+        };
+        if arrived_too_fast(&app_state, &config) {
+            return Err(ContributeError::TooFast);
+        }
+        identity
+    };
+
+    // Points are checked without the transcript, which is only read once the
+    // whole contribution arrived
+    let max_size = max_contribution_size(&config.ceremony_sizes);
+    let decoded =
+        decode_framed_checked::<T::ContributionType, _, _, _, _>(body, max_size, T::check_point)
+            .await
+            .map_err(ContributeError::MalformedStream)?;
+    let contribution = match decoded {
+        Ok(contribution) => contribution,
+        Err(error) => {
+            RejectionFingerprint::mid_stream::<T>(&error).record(&provider);
+            release_rejected(&store, &storage, &session_id, &contributor).await;
+            return Err(ContributeError::RejectedMidStream);
+        }
+    };
+    contribute::<T>(
+        session_id,
+        headers,
+        Json(contribution),
+        Extension(store),
+        Extension(config),
+        Extension(shared_transcript),
+        Extension(storage),
+        Extension(verification_limiter),
+        Extension(verifier),
+    )
+    .await
+}
+
+// Lets the current participant signal that they are still computing their
+// contribution, so they are not expired for missing heartbeats
+pub async fn heartbeat(
+    session_id: SessionId,
+    Extension(store): Extension<SharedState>,
+) -> Result<StatusCode, ContributeError> {
     let mut app_state = store.write().await;
+    match &mut app_state.participant {
+        Some((id, session_info)) if id == &session_id => {
+            session_info.last_ping_time = Instant::now();
+            Ok(StatusCode::OK)
+        }
+        _ => Err(ContributeError::NotUsersTurn),
+    }
+}
 
-    app_state.num_contributions += 1;
+#[derive(Debug, Serialize)]
+pub struct Challenge<C> {
+    // The position the contribution will take in the transcript
+    contribution_index: usize,
+    // Hex encoded hash of `challenge`, so clients can tell whether a
+    // challenge they kept is still current
+    challenge_hash:     String,
+    // The powers to transform, as of the latest contribution
+    challenge:          C,
+}
+
+impl<C: Serialize> IntoResponse for Challenge<C> {
+    fn into_response(self) -> Response {
+        (StatusCode::OK, Json(self)).into_response()
+    }
+}
+
+// The minimal input the current participant needs to compute their
+// contribution, without the rest of the transcript. Only the participant can
+// change the transcript, so this stays the state their submission is verified
+// against until they submit. Submissions can name the challenge they were
+// computed from, which is then checked against the current one.
+pub async fn challenge<T: Transcript + Send + Sync>(
+    session_id: SessionId,
+    Extension(store): Extension<SharedState>,
+    Extension(transcript): Extension<SharedTranscript<T>>,
+) -> Result<Challenge<T::ContributionType>, ContributeError> {
+    let contribution_index = {
+        let app_state = store.read().await;
+        match &app_state.participant {
+            Some((id, _)) if id == &session_id => app_state.num_contributions,
+            _ => return Err(ContributeError::NotUsersTurn),
+        }
+    };
+    let challenge = transcript.read().await.get_contribution();
+    Ok(Challenge {
+        contribution_index,
+        challenge_hash: challenge_hash(&challenge),
+        challenge,
+    })
+}
 
-    let uid = app_state
-        .participant
-        .as_ref()
-        .expect("participant is guaranteed non-empty here")
-        .0
-        .to_string();
+fn challenge_hash<C: Serialize>(challenge: &C) -> String {
+    hex::encode(json_hash(challenge))
+}
 
-    // Remove this person from the contribution spot
-    app_state.clear_current_contributor();
+// Everything a participant needs to later prove their participation
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ContributionBundle {
+    pub uid:                String,
+    // Position of the contribution in the transcript, starting at zero
+    pub contribution_index: usize,
+    // The part of the contribution identifying it, such as its public keys
+    pub witness:            Value,
+    pub receipt:            String,
+    // Hash of the transcript right after the contribution was applied
+    pub transcript_hash:    TranscriptHash,
+}
 
-    drop(app_state); // Release AppState lock
-    storage.finish_contribution(&uid).await;
+// A bundle together with the sequencer's signature over its canonical
+// encoding, see `canonical_json`
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SignedContributionBundle {
+    pub bundle:    ContributionBundle,
+    pub signature: String,
+}
+
+impl IntoResponse for SignedContributionBundle {
+    fn into_response(self) -> Response {
+        (StatusCode::OK, Json(self)).into_response()
+    }
+}
+
+#[derive(Debug)]
+pub enum BundleError {
+    NotFound,
+    // Unless bundles are public, only the owner may fetch theirs. Bundles
+    // are fetched with the receipt as bearer token, receipts with the session
+    // or resume token of the session that contributed.
+    Forbidden,
+    Signing,
+    Storage(StorageError),
+}
+
+impl IntoResponse for BundleError {
+    fn into_response(self) -> Response {
+        let (status, message) = match self {
+            Self::NotFound => (StatusCode::NOT_FOUND, "no contribution for this identity"),
+            Self::Forbidden => (
+                StatusCode::FORBIDDEN,
+                "bundle is only available to its owner",
+            ),
+            Self::Signing => (StatusCode::INTERNAL_SERVER_ERROR, "could not sign bundle"),
+            Self::Storage(error) => return error.into_response(),
+        };
+        let body = Json(json!({ "error": message }));
+        (status, body).into_response()
+    }
+}
+
+// Bundles of contributions made since the sequencer started are kept in
+// memory. Older ones, of the current phase, are rebuilt from storage.
+pub async fn contribution_bundle(
+    Path(uid): Path<String>,
+    headers: HeaderMap,
+    Extension(store): Extension<SharedState>,
+    Extension(storage): Extension<PersistentStorage>,
+    Extension(config): Extension<AppConfig>,
+) -> Result<SignedContributionBundle, BundleError> {
+    let (bundle, phase) = {
+        let app_state = store.read().await;
+        let phase = app_state.phase.as_ref().map_or(0, |phase| phase.phase);
+        (app_state.contribution_bundles.get(&uid).cloned(), phase)
+    };
+    let bundle = match bundle {
+        Some(bundle) => bundle,
+        None => {
+            let (contribution_index, receipt, record) = storage
+                .bundle_of(&uid, phase)
+                .await
+                .map_err(BundleError::Storage)?
+                .ok_or(BundleError::NotFound)?;
+            ContributionBundle {
+                contribution_index: usize::try_from(contribution_index)
+                    .map_err(|_| BundleError::Storage(StorageError::CorruptedAttestation))?,
+                witness: record.witness,
+                receipt,
+                transcript_hash: record.transcript_hash,
+                uid,
+            }
+        }
+    };
+
+    if !config.public_contribution_bundles {
+        let presented = headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        if presented != Some(bundle.receipt.as_str()) {
+            return Err(BundleError::Forbidden);
+        }
+    }
+
+    let signature = KEYS
+        .get()
+        .ok_or(BundleError::Signing)?
+        .sign_canonical(&bundle)
+        .map_err(|_| BundleError::Signing)?;
+    Ok(SignedContributionBundle { bundle, signature })
+}
 
+// The identity of the session whose id, or resume token, the request
+// presents as bearer token. Sessions that contributed are only known while
+// `finished_sessions` retains them.
+fn presented_identity(
+    app_state: &AppState,
+    headers: &HeaderMap,
+    config: &AppConfig,
+) -> Option<String> {
+    let bearer = SessionId::from_headers(headers)?;
+    let session_id = ResumeToken::decode(&bearer.to_string(), config.clock_skew)
+        .map_or(bearer, |token| token.session_id);
+    app_state
+        .finished_sessions
+        .get(&session_id)
+        .map(|(uid, _)| uid.clone())
+        .or_else(|| {
+            app_state
+                .lobby
+                .get(&session_id)
+                .map(|info| info.unique_identifier().to_owned())
+        })
+}
+
+// Returns the receipt of the identity's contribution. Receipts are read from
+// storage, so they stay available once the ceremony is finalized. Unless
+// bundles are public, they are only served to the owner, see `BundleError`.
+// Receipts of attestations that are not stored yet are served as pending.
+pub async fn contribution_receipt(
+    Path(uid): Path<String>,
+    headers: HeaderMap,
+    Extension(store): Extension<SharedState>,
+    Extension(storage): Extension<PersistentStorage>,
+    Extension(config): Extension<AppConfig>,
+) -> Result<ContributeReceipt, BundleError> {
+    if !config.public_contribution_bundles
+        && presented_identity(&store.read().await, &headers, &config).as_ref() != Some(&uid)
+    {
+        return Err(BundleError::Forbidden);
+    }
+    let identity_hash = storage.identity_hash(&uid);
+    let pending = store
+        .read()
+        .await
+        .pending_attestations
+        .iter()
+        .find(|pending| pending.attestation.identity_hash == identity_hash)
+        .map(|pending| pending.receipt.clone());
+    if let Some(encoded_receipt_token) = pending {
+        return Ok(ContributeReceipt {
+            encoded_receipt_token,
+            attestation_pending: true,
+        });
+    }
+    let encoded_receipt_token = storage
+        .receipt_of(&uid)
+        .await
+        .map_err(BundleError::Storage)?
+        .ok_or(BundleError::NotFound)?;
     Ok(ContributeReceipt {
         encoded_receipt_token,
+        attestation_pending: false,
     })
 }
 
 #[cfg(test)]
 mod tests {
-    use std::path::PathBuf;
-
-    use axum::{Extension, Json};
-    use chrono::DateTime;
+    use axum::{
+        body::{Body, Bytes},
+        extract::{BodyStream, FromRequest, Path, RequestParts},
+        middleware::{from_fn, Next},
+        response::IntoResponse,
+        routing::post,
+        Extension, Json, Router,
+    };
+    use http::{
+        header::{AUTHORIZATION, CONTENT_TYPE},
+        HeaderMap, StatusCode,
+    };
+    use k256::ecdsa::SigningKey;
+    use ring::digest::{digest, SHA256};
+    use serde_json::Value;
+    use std::sync::Arc;
+    use tokio::{
+        sync::RwLock,
+        time::{timeout, Duration},
+    };
+    use tower::ServiceExt;
 
     use crate::{
-        api::v1::contribute::ContributeError,
-        constants, contribute, keys, read_transcript_file,
-        storage::test_storage_client,
-        test_transcript::TestContribution::{InvalidContribution, ValidContribution},
-        test_util::create_test_session_info,
-        AppConfig, Keys, SessionId, SharedState, SharedTranscript, TestTranscript,
+        admission::AdmissionHook,
+        api::v1::{
+            contribute::{
+                attestation_journal_path, challenge, contribute, contribute_stream,
+                contribution_bundle, contribution_receipt, heartbeat, read_attestation_journal,
+                retry_pending_attestations, BundleError, ContributeError, ContributeReceipt,
+                PendingAttestation, ATTESTATION_CONFLICTS, CHALLENGE_HASH_HEADER, CONTRIBUTIONS,
+                IDENTITY_SIGNATURE_HEADER,
+            },
+            lobby::remove_participant_on_deadline,
+            timing::release_slot_on_timeout,
+        },
+        attestation_chain,
+        data::transcript::transcript_hash,
+        ethereum::{address, personal_sign},
+        fast_forward::fast_forward,
+        jwt::Receipt,
+        keys::{canonical_json, KEYS},
+        merkle::OrderCommitment,
+        read_transcript_file,
+        storage::{test_storage_client, Attestation, PersistentStorage},
+        test_transcript::{
+            TestContribution,
+            TestContribution::{InvalidContribution, ValidContribution},
+        },
+        test_util::{create_test_session_info, init_keys, response_body, test_config},
+        verification::{json_hash, FullVerifier, SharedVerifier, VerificationLimiter, Verifier},
+        AppConfig, AppState, SessionId, SharedState, SharedTranscript, TestTranscript, Transcript,
     };
 
-    fn config() -> AppConfig {
-        let mut transcript = std::env::temp_dir();
-        transcript.push("transcript.json");
-        let mut transcript_work = std::env::temp_dir();
-        transcript_work.push("transcript.json.new");
-        AppConfig {
-            eth_check_nonce_at_block:    "".to_string(),
-            eth_min_nonce:               0,
-            github_max_creation_time:    DateTime::parse_from_rfc3339(
-                constants::GITHUB_ACCOUNT_CREATION_DEADLINE,
-            )
-            .unwrap(),
-            eth_rpc_url:                 "".to_string(),
-            transcript_file:             transcript,
-            transcript_in_progress_file: transcript_work,
-        }
-    }
-
-    async fn init_keys() {
-        keys::KEYS
-            .set(
-                Keys::new(keys::Options {
-                    private_key: PathBuf::from("private.key"),
-                    public_key:  PathBuf::from("publickey.pem"),
-                })
-                .await
-                .unwrap(),
-            )
-            .ok();
+    fn full_verifier() -> SharedVerifier<TestTranscript> {
+        Arc::new(FullVerifier::new(TestTranscript::default()))
     }
 
     #[tokio::test]
@@ -186,11 +1150,14 @@ mod tests {
         app_state.write().await.participant = None;
         let result = contribute::<TestTranscript>(
             SessionId::new(),
+            HeaderMap::new(),
             Json(ValidContribution(123)),
             Extension(app_state),
-            Extension(config()),
+            Extension(test_config()),
             Extension(SharedTranscript::default()),
             Extension(db),
+            Extension(VerificationLimiter::new(1)),
+            Extension(full_verifier()),
         )
         .await;
         assert!(matches!(result, Err(ContributeError::NotUsersTurn)));
@@ -206,53 +1173,513 @@ mod tests {
             Some((participant.clone(), create_test_session_info(100)));
         let result = contribute::<TestTranscript>(
             participant,
+            HeaderMap::new(),
             Json(InvalidContribution(123)),
             Extension(app_state),
-            Extension(config()),
+            Extension(test_config()),
             Extension(SharedTranscript::default()),
             Extension(db),
+            Extension(VerificationLimiter::new(1)),
+            Extension(full_verifier()),
         )
         .await;
         assert!(matches!(result, Err(ContributeError::InvalidContribution)));
     }
 
     #[tokio::test]
-    async fn accepts_valid_contribution() {
+    async fn rejects_degenerate_contribution() {
         init_keys().await;
         let db = test_storage_client().await;
         let app_state = SharedState::default();
-        let participant = SessionId::new();
-        let cfg = config();
-        let shared_transcript = SharedTranscript::<TestTranscript>::default();
+        let submit = |contribution, config: AppConfig| {
+            let participant = SessionId::new();
+            let app_state = app_state.clone();
+            let db = db.clone();
+            async move {
+                app_state.write().await.participant =
+                    Some((participant.clone(), create_test_session_info(100)));
+                contribute::<TestTranscript>(
+                    participant,
+                    HeaderMap::new(),
+                    Json(contribution),
+                    Extension(app_state),
+                    Extension(config),
+                    Extension(SharedTranscript::default()),
+                    Extension(db),
+                    Extension(VerificationLimiter::new(1)),
+                    Extension(full_verifier()),
+                )
+                .await
+            }
+        };
 
-        app_state.write().await.participant =
-            Some((participant.clone(), create_test_session_info(100)));
-        let result = contribute::<TestTranscript>(
-            participant.clone(),
-            Json(ValidContribution(123)),
-            Extension(app_state.clone()),
-            Extension(cfg.clone()),
-            Extension(shared_transcript.clone()),
-            Extension(db.clone()),
-        )
-        .await;
+        let checked = AppConfig {
+            check_contribution_entropy: true,
+            ..test_config()
+        };
+        let result = submit(ValidContribution(1), checked.clone()).await;
+        assert!(matches!(
+            result,
+            Err(ContributeError::SuspiciousContribution(
+                "small_scalar_delta"
+            ))
+        ));
+        assert!(app_state.read().await.participant.is_none());
 
-        assert!(matches!(result, Ok(_)));
-        let transcript = read_transcript_file::<TestTranscript>(cfg.transcript_file.clone()).await;
-        assert_eq!(transcript, TestTranscript {
-            initial:       ValidContribution(0),
-            contributions: vec![ValidContribution(123)],
-        });
+        assert!(submit(ValidContribution(123), checked).await.is_ok());
+
+        // The check is off by default
+        assert!(submit(ValidContribution(1), test_config()).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn challenge_holds_the_current_powers_only() {
+        init_keys().await;
+        let ceremony = TestCeremony::new("challenge").await;
+        let (app_state, shared_transcript) = (&ceremony.store, &ceremony.transcript);
+        {
+            let mut app_state = app_state.write().await;
+            let mut transcript = shared_transcript.write().await;
+            fast_forward(&mut app_state, &mut transcript, 5);
+        }
+        let participant = SessionId::new();
+        let challenge_of = |session_id: SessionId| {
+            challenge::<TestTranscript>(
+                session_id,
+                Extension(app_state.clone()),
+                Extension(shared_transcript.clone()),
+            )
+        };
+        assert!(matches!(
+            challenge_of(participant.clone()).await,
+            Err(ContributeError::NotUsersTurn)
+        ));
+        app_state.write().await.participant =
+            Some((participant.clone(), create_test_session_info(100)));
+
+        let response = challenge_of(participant.clone()).await.ok().unwrap();
+        let transcript = shared_transcript.read().await.clone();
+        assert_eq!(response.contribution_index, 5);
+        assert_eq!(response.challenge, transcript.get_contribution());
+        assert_eq!(
+            response.challenge_hash,
+            hex::encode(json_hash(&transcript.get_contribution()))
+        );
+        assert!(
+            serde_json::to_vec(&response.challenge).unwrap().len()
+                < serde_json::to_vec(&transcript).unwrap().len()
+        );
+
+        let contribution = ValidContribution(123);
+        assert!(transcript
+            .verify_contribution(&TestTranscript::default(), &contribution)
+            .is_ok());
+        let submit = |hash: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(CHALLENGE_HASH_HEADER, hash.parse().unwrap());
+            ceremony.contribute_with(participant.clone(), headers, contribution.clone())
+        };
+
+        // Computed from a challenge that is no longer current
+        let stale = hex::encode(json_hash(&ValidContribution(4)));
+        assert!(matches!(
+            submit(&stale).await,
+            Err(ContributeError::ChallengeMismatch)
+        ));
+        assert!(app_state.read().await.is_participant(&participant));
+
+        assert!(submit(&response.challenge_hash).await.is_ok());
+        assert_eq!(
+            shared_transcript.read().await.get_contribution(),
+            contribution
+        );
+    }
+
+    #[tokio::test]
+    async fn admission_hook_can_veto_contributions() {
+        init_keys().await;
+        let mut ceremony = TestCeremony::new("admission").await;
+        let script =
+            r#"grep -q '"stage":"contribution"' && echo "outside the allowed window"; exit 1"#;
+        ceremony.config.admission_hook = Some(AdmissionHook::new(
+            vec!["sh".to_string(), "-c".to_string(), script.to_string()],
+            Duration::from_secs(5),
+            false,
+        ));
+        let result = ceremony
+            .contribute_as_new_participant(ValidContribution(123))
+            .await;
+        assert!(matches!(
+            result,
+            Err(ContributeError::CustomPolicyRejected("rejected by policy"))
+        ));
+        assert!(ceremony.store.read().await.participant.is_none());
+        assert!(ceremony.transcript.read().await.contributions.is_empty());
+    }
+
+    #[tokio::test]
+    async fn rejects_contribution_before_min_compute_time() {
+        init_keys().await;
+        let db = test_storage_client().await;
+        let app_state = SharedState::default();
+        let config = AppConfig {
+            min_compute_time: Some(Duration::from_secs(30)),
+            ..test_config()
+        };
+        tokio::time::pause();
+
+        let participant = SessionId::new();
+        {
+            let mut state = app_state.write().await;
+            state
+                .lobby
+                .insert(participant.clone(), create_test_session_info(100));
+            assert!(state.try_set_current_contributor(participant.clone(), config.compute_deadline));
+        }
+        let submit = || {
+            contribute::<TestTranscript>(
+                participant.clone(),
+                HeaderMap::new(),
+                Json(ValidContribution(123)),
+                Extension(app_state.clone()),
+                Extension(config.clone()),
+                Extension(SharedTranscript::default()),
+                Extension(db.clone()),
+                Extension(VerificationLimiter::new(1)),
+                Extension(full_verifier()),
+            )
+        };
+
+        assert!(matches!(submit().await, Err(ContributeError::TooFast)));
+        // The participant keeps their spot
+        assert!(app_state.read().await.participant.is_some());
+
+        // A streamed contribution is turned down before its body is read,
+        // even though the client never finishes sending it
+        let mut request = RequestParts::new(http::Request::new(Body::wrap_stream(
+            futures::stream::pending::<Result<Bytes, std::io::Error>>(),
+        )));
+        let body = BodyStream::from_request(&mut request).await.unwrap();
+        let streamed = contribute_stream::<TestTranscript>(
+            participant.clone(),
+            HeaderMap::new(),
+            body,
+            Extension(app_state.clone()),
+            Extension(config.clone()),
+            Extension(SharedTranscript::default()),
+            Extension(db.clone()),
+            Extension(VerificationLimiter::new(1)),
+            Extension(full_verifier()),
+        )
+        .await;
+        assert!(matches!(streamed, Err(ContributeError::TooFast)));
+        assert!(app_state.read().await.participant.is_some());
+
+        tokio::time::advance(Duration::from_secs(30)).await;
+        // Verification runs on a blocking thread, don't let the paused clock
+        // skip past its timeout
+        tokio::time::resume();
+        assert!(submit().await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn counts_contributions_by_provider() {
+        init_keys().await;
+        let db = test_storage_client().await;
+        let app_state = SharedState::default();
+        let participant = SessionId::new();
+        let mut session_info = create_test_session_info(100);
+        session_info.token.as_mut().unwrap().provider = "counted-provider".to_string();
+        app_state.write().await.participant = Some((participant.clone(), session_info));
+        let before = CONTRIBUTIONS.with_label_values(&["counted-provider"]).get();
+
+        let result = contribute::<TestTranscript>(
+            participant,
+            HeaderMap::new(),
+            Json(ValidContribution(123)),
+            Extension(app_state),
+            Extension(test_config()),
+            Extension(SharedTranscript::default()),
+            Extension(db),
+            Extension(VerificationLimiter::new(1)),
+            Extension(full_verifier()),
+        )
+        .await;
+        assert!(result.is_ok());
+        assert_eq!(
+            CONTRIBUTIONS.with_label_values(&["counted-provider"]).get(),
+            before + 1
+        );
+    }
+
+    #[tokio::test]
+    async fn logs_rejected_contribution() {
+        init_keys().await;
+        let db = test_storage_client().await;
+        let app_state = SharedState::default();
+        let participant = SessionId::new();
+        app_state.write().await.participant =
+            Some((participant.clone(), create_test_session_info(100)));
+        let config = AppConfig {
+            log_rejected_contributions: true,
+            ..test_config()
+        };
+        let result = contribute::<TestTranscript>(
+            participant,
+            HeaderMap::new(),
+            Json(InvalidContribution(123)),
+            Extension(app_state),
+            Extension(config),
+            Extension(SharedTranscript::default()),
+            Extension(db.clone()),
+            Extension(VerificationLimiter::new(1)),
+            Extension(full_verifier()),
+        )
+        .await;
+        assert!(matches!(result, Err(ContributeError::InvalidContribution)));
+
+        let contribution = serde_json::to_vec(&InvalidContribution(123)).unwrap();
+        assert_eq!(db.rejected_contributions().await, vec![(
+            "foo".to_string(),
+            hex::encode(digest(&SHA256, &contribution)),
+            "null".to_string()
+        )]);
+    }
+
+    #[tokio::test]
+    async fn quarantined_rejection_can_be_reproduced() {
+        init_keys().await;
+        let db = test_storage_client().await;
+        let app_state = SharedState::default();
+        let transcript = TestTranscript::default().update(&ValidContribution(1));
+        let participant = SessionId::new();
+        {
+            let mut state = app_state.write().await;
+            state.resume_from(&transcript);
+            state.participant = Some((participant.clone(), create_test_session_info(100)));
+        }
+        let config = AppConfig {
+            quarantine_contributions: true,
+            ..test_config()
+        };
+        let result = contribute::<TestTranscript>(
+            participant,
+            HeaderMap::new(),
+            Json(InvalidContribution(123)),
+            Extension(app_state),
+            Extension(config),
+            Extension(SharedTranscript::new(RwLock::new(transcript.clone()))),
+            Extension(db.clone()),
+            Extension(VerificationLimiter::new(1)),
+            Extension(full_verifier()),
+        )
+        .await;
+        assert!(matches!(result, Err(ContributeError::InvalidContribution)));
+
+        let contribution_hash = hex::encode(json_hash(&InvalidContribution(123)));
+        let (reason, quarantined_hash, contribution) = db
+            .quarantined_contribution(&contribution_hash)
+            .await
+            .unwrap();
+        assert_eq!(quarantined_hash, transcript_hash(&transcript).to_string());
+        let contribution = serde_json::from_slice::<TestContribution>(&contribution).unwrap();
+        let reproduced = transcript.verify_contribution(&TestTranscript::default(), &contribution);
+        assert_eq!(
+            reason,
+            serde_json::to_string(&reproduced.unwrap_err()).unwrap()
+        );
+    }
+
+    // Takes far longer than any sensible timeout
+    struct SlowVerifier;
+
+    impl Verifier<TestTranscript> for SlowVerifier {
+        fn verify(
+            &self,
+            _transcript: &TestTranscript,
+            _transcript_hash: &TranscriptHash,
+            _contribution: &TestContribution,
+            _mac: Option<&str>,
+        ) -> Result<(), ()> {
+            std::thread::sleep(Duration::from_millis(500));
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_contribution_that_is_too_slow_to_verify() {
+        let db = test_storage_client().await;
+        let app_state = SharedState::default();
+        let participant = SessionId::new();
+        app_state.write().await.participant =
+            Some((participant.clone(), create_test_session_info(100)));
+        let config = AppConfig {
+            verify_timeout: Duration::from_millis(50),
+            ..test_config()
+        };
+        let limiter = VerificationLimiter::new(1);
+        let shared_transcript = SharedTranscript::<TestTranscript>::default();
+        let verifier: SharedVerifier<TestTranscript> = Arc::new(SlowVerifier);
+        let result = contribute::<TestTranscript>(
+            participant,
+            HeaderMap::new(),
+            Json(ValidContribution(123)),
+            Extension(app_state.clone()),
+            Extension(config),
+            Extension(shared_transcript.clone()),
+            Extension(db),
+            Extension(limiter.clone()),
+            Extension(verifier),
+        )
+        .await;
+        assert!(matches!(result, Err(ContributeError::VerificationTimeout)));
+        assert!(app_state.read().await.participant.is_none());
+        // The abandoned verification holds on to its slot until it's done
+        assert!(limiter.is_saturated());
+        assert!(shared_transcript.read().await.contributions.is_empty());
+    }
+
+    #[tokio::test]
+    async fn timed_out_contribution_frees_the_spot() {
+        let db = test_storage_client().await;
+        let app_state = SharedState::default();
+        let participant = SessionId::new();
+        app_state.write().await.participant =
+            Some((participant.clone(), create_test_session_info(100)));
+        let verifier: SharedVerifier<TestTranscript> = Arc::new(SlowVerifier);
+        let app = Router::new()
+            .route("/contribute", post(contribute::<TestTranscript>))
+            .layer(from_fn(|request: http::Request<Body>, next: Next<Body>| {
+                release_slot_on_timeout(Duration::from_millis(50), request, next)
+            }))
+            .layer(Extension(app_state.clone()))
+            .layer(Extension(test_config()))
+            .layer(Extension(SharedTranscript::<TestTranscript>::default()))
+            .layer(Extension(db))
+            .layer(Extension(VerificationLimiter::new(1)))
+            .layer(Extension(verifier));
+
+        let request = http::Request::post("/contribute")
+            .header(AUTHORIZATION, format!("Bearer {}", participant))
+            .header(CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                serde_json::to_vec(&ValidContribution(123)).unwrap(),
+            ))
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+        assert!(app_state.read().await.participant.is_none());
+        assert_eq!(app_state.read().await.num_expired, 1);
+    }
+
+    #[tokio::test]
+    async fn keeps_the_next_participants_spot_when_expired_during_verification() {
+        init_keys().await;
+        let db = test_storage_client().await;
+        let app_state = SharedState::default();
+        let expired = SessionId::new();
+        let next = SessionId::new();
+        app_state.write().await.participant =
+            Some((expired.clone(), create_test_session_info(100)));
+        let shared_transcript = SharedTranscript::<TestTranscript>::default();
+        let verifier: SharedVerifier<TestTranscript> = Arc::new(SlowVerifier);
+        let submission = tokio::spawn(contribute::<TestTranscript>(
+            expired.clone(),
+            HeaderMap::new(),
+            Json(ValidContribution(123)),
+            Extension(app_state.clone()),
+            Extension(test_config()),
+            Extension(shared_transcript.clone()),
+            Extension(db),
+            Extension(VerificationLimiter::new(1)),
+            Extension(verifier),
+        ));
+
+        // The deadline passes while the contribution is verified, and the
+        // spot goes to the next participant
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        {
+            let mut state = app_state.write().await;
+            assert!(state.expire_current_contributor(&expired).is_some());
+            state.participant = Some((next.clone(), create_test_session_info(100)));
+        }
+
+        let result = submission.await.unwrap();
+        assert!(matches!(result, Err(ContributeError::NotUsersTurn)));
+        assert!(app_state.read().await.is_participant(&next));
+        assert!(shared_transcript.read().await.contributions.is_empty());
+    }
+
+    #[tokio::test]
+    async fn rejects_contribution_when_verification_is_saturated() {
+        let db = test_storage_client().await;
+        let app_state = SharedState::default();
+        let participant = SessionId::new();
+        app_state.write().await.participant =
+            Some((participant.clone(), create_test_session_info(100)));
+        let limiter = VerificationLimiter::new(1);
+        let _permit = limiter.try_acquire().unwrap();
+        let result = contribute::<TestTranscript>(
+            participant.clone(),
+            HeaderMap::new(),
+            Json(ValidContribution(123)),
+            Extension(app_state.clone()),
+            Extension(test_config()),
+            Extension(SharedTranscript::default()),
+            Extension(db),
+            Extension(limiter),
+            Extension(full_verifier()),
+        )
+        .await;
+        assert!(matches!(result, Err(ContributeError::Busy)));
+        // The participant keeps their spot and may retry
+        assert!(app_state.read().await.participant.is_some());
+    }
+
+    #[tokio::test]
+    async fn accepts_valid_contribution() {
+        init_keys().await;
+        let db = test_storage_client().await;
+        let app_state = SharedState::default();
+        let participant = SessionId::new();
+        let cfg = test_config();
+        let shared_transcript = SharedTranscript::<TestTranscript>::default();
+
+        app_state.write().await.participant =
+            Some((participant.clone(), create_test_session_info(100)));
+        let result = contribute::<TestTranscript>(
+            participant.clone(),
+            HeaderMap::new(),
+            Json(ValidContribution(123)),
+            Extension(app_state.clone()),
+            Extension(cfg.clone()),
+            Extension(shared_transcript.clone()),
+            Extension(db.clone()),
+            Extension(VerificationLimiter::new(1)),
+            Extension(full_verifier()),
+        )
+        .await;
+
+        assert!(matches!(result, Ok(_)));
+        let transcript = read_transcript_file::<TestTranscript>(cfg.transcript_file.clone()).await;
+        assert_eq!(transcript, TestTranscript {
+            initial:       ValidContribution(0),
+            contributions: vec![ValidContribution(123)],
+        });
 
         app_state.write().await.participant =
             Some((participant.clone(), create_test_session_info(100)));
         let result = contribute::<TestTranscript>(
             participant.clone(),
+            HeaderMap::new(),
             Json(ValidContribution(175)),
             Extension(app_state.clone()),
             Extension(cfg.clone()),
             Extension(shared_transcript.clone()),
             Extension(db.clone()),
+            Extension(VerificationLimiter::new(1)),
+            Extension(full_verifier()),
         )
         .await;
 
@@ -263,4 +1690,772 @@ mod tests {
             contributions: vec![ValidContribution(123), ValidContribution(175)],
         });
     }
+
+    #[tokio::test]
+    async fn reaching_the_contribution_cap_finalizes_the_ceremony() {
+        init_keys().await;
+        let mut ceremony = TestCeremony::new("capped").await;
+        ceremony.config.max_contributions = Some(100);
+        fast_forward(
+            &mut *ceremony.store.write().await,
+            &mut *ceremony.transcript.write().await,
+            99,
+        );
+        assert_eq!(ceremony.store.read().await.num_contributions, 99);
+        assert_eq!(
+            ceremony.store.read().await.ceremony_status(),
+            "waiting_for_participant"
+        );
+
+        // The cap is only an estimate unless draining at it is enabled
+        assert!(ceremony
+            .contribute_as_new_participant(ValidContribution(123))
+            .await
+            .is_ok());
+        assert_eq!(ceremony.store.read().await.num_contributions, 100);
+        assert_eq!(
+            ceremony.store.read().await.ceremony_status(),
+            "waiting_for_participant"
+        );
+
+        ceremony.config.drain_at_max_contributions = true;
+        assert!(ceremony
+            .contribute_as_new_participant(ValidContribution(124))
+            .await
+            .is_ok());
+        assert_eq!(ceremony.transcript.read().await.num_contributions(), 101);
+        let app_state = ceremony.store.read().await;
+        assert_eq!(app_state.num_contributions, 101);
+        assert_eq!(app_state.ceremony_status(), "finalized");
+        assert!(app_state.finalized_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn rejects_contribution_that_shrinks_the_transcript() {
+        init_keys().await;
+        let ceremony = TestCeremony::new("shrinking").await;
+
+        assert!(matches!(
+            ceremony
+                .contribute_as_new_participant(TestContribution::TruncatedContribution(123))
+                .await,
+            Err(ContributeError::TranscriptShrank)
+        ));
+        assert_eq!(*ceremony.transcript.read().await, TestTranscript::default());
+        assert!(ceremony.store.read().await.participant.is_none());
+        assert_eq!(ceremony.store.read().await.num_contributions, 0);
+
+        assert!(ceremony
+            .contribute_as_new_participant(ValidContribution(175))
+            .await
+            .is_ok());
+        assert_eq!(ceremony.transcript.read().await.contributions, vec![
+            ValidContribution(175)
+        ]);
+    }
+
+    // Submits a contribution as the Ethereum identity of `key`, signed by
+    // `signer`
+    async fn submit_signed(
+        app_state: &SharedState,
+        db: &PersistentStorage,
+        key: &SigningKey,
+        signer: &SigningKey,
+    ) -> Result<ContributeReceipt, ContributeError> {
+        let contribution = ValidContribution(123);
+        let contribution_hash = format!("0x{}", hex::encode(json_hash(&contribution)));
+        let participant = SessionId::new();
+        let mut session_info = create_test_session_info(100);
+        session_info.token.as_mut().unwrap().sub =
+            format!("eth | {}", address(&key.verifying_key()));
+        app_state.write().await.participant = Some((participant.clone(), session_info));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            IDENTITY_SIGNATURE_HEADER,
+            personal_sign(signer, contribution_hash.as_bytes())
+                .parse()
+                .unwrap(),
+        );
+        contribute::<TestTranscript>(
+            participant,
+            headers,
+            Json(contribution),
+            Extension(app_state.clone()),
+            Extension(test_config()),
+            Extension(SharedTranscript::default()),
+            Extension(db.clone()),
+            Extension(VerificationLimiter::new(1)),
+            Extension(full_verifier()),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn checks_identity_signature_of_ethereum_contributors() {
+        init_keys().await;
+        let db = test_storage_client().await;
+        let app_state = SharedState::default();
+        let key = SigningKey::from_bytes(&[1; 32]).unwrap();
+        let other_key = SigningKey::from_bytes(&[2; 32]).unwrap();
+
+        let result = submit_signed(&app_state, &db, &key, &other_key).await;
+        assert!(matches!(
+            result,
+            Err(ContributeError::IdentitySignatureMismatch)
+        ));
+        // The contributor keeps their spot and may retry
+        assert!(app_state.read().await.participant.is_some());
+
+        assert!(submit_signed(&app_state, &db, &key, &key).await.is_ok());
+        let attestations = db.attestations_page(None, 10).await.unwrap();
+        assert_eq!(attestations.len(), 1);
+        assert!(attestations[0].identity_signature.is_some());
+    }
+
+    #[tokio::test]
+    async fn rejects_reused_pubkey() {
+        init_keys().await;
+        let db = test_storage_client().await;
+        let app_state = SharedState::default();
+        let shared_transcript = SharedTranscript::<TestTranscript>::default();
+        let submit = |participant: SessionId, contribution| {
+            contribute::<TestTranscript>(
+                participant,
+                HeaderMap::new(),
+                Json(contribution),
+                Extension(app_state.clone()),
+                Extension(test_config()),
+                Extension(shared_transcript.clone()),
+                Extension(db.clone()),
+                Extension(VerificationLimiter::new(1)),
+                Extension(full_verifier()),
+            )
+        };
+
+        let first = SessionId::new();
+        app_state.write().await.participant = Some((first.clone(), create_test_session_info(100)));
+        assert!(submit(first, ValidContribution(123)).await.is_ok());
+
+        let second = SessionId::new();
+        let mut session_info = create_test_session_info(100);
+        session_info.token.as_mut().unwrap().sub = "bar".to_string();
+        app_state.write().await.participant = Some((second.clone(), session_info));
+        let result = submit(second, ValidContribution(123)).await;
+        assert!(matches!(result, Err(ContributeError::DuplicatePubkey)));
+
+        assert!(app_state.read().await.participant.is_none());
+        assert_eq!(shared_transcript.read().await.contributions, vec![
+            ValidContribution(123)
+        ]);
+    }
+
+    #[tokio::test]
+    async fn rejects_denylisted_contribution() {
+        use crate::api::v1::admin::{reload_denylist, Admin};
+
+        init_keys().await;
+        let db = test_storage_client().await;
+        let app_state = SharedState::default();
+        let shared_transcript = SharedTranscript::<TestTranscript>::default();
+        let denylist_file = std::env::temp_dir().join("contribution_denylist.txt");
+        let denied = hex::encode(json_hash(&ValidContribution(666)));
+        std::fs::write(&denylist_file, format!("# leaked\n0x{}\n", denied)).unwrap();
+        let config = AppConfig {
+            denylist_file: Some(denylist_file),
+            ..test_config()
+        };
+        assert!(reload_denylist(
+            Admin,
+            Extension(app_state.clone()),
+            Extension(config.clone())
+        )
+        .await
+        .is_ok());
+        let submit = |participant: SessionId, contribution| {
+            contribute::<TestTranscript>(
+                participant,
+                HeaderMap::new(),
+                Json(contribution),
+                Extension(app_state.clone()),
+                Extension(config.clone()),
+                Extension(shared_transcript.clone()),
+                Extension(db.clone()),
+                Extension(VerificationLimiter::new(1)),
+                Extension(full_verifier()),
+            )
+        };
+
+        let first = SessionId::new();
+        app_state.write().await.participant = Some((first.clone(), create_test_session_info(100)));
+        let result = submit(first, ValidContribution(666)).await;
+        assert!(matches!(result, Err(ContributeError::DeniedContribution)));
+        assert!(app_state.read().await.participant.is_none());
+
+        let second = SessionId::new();
+        let mut session_info = create_test_session_info(100);
+        session_info.token.as_mut().unwrap().sub = "bar".to_string();
+        app_state.write().await.participant = Some((second.clone(), session_info));
+        assert!(submit(second, ValidContribution(123)).await.is_ok());
+        assert_eq!(shared_transcript.read().await.contributions, vec![
+            ValidContribution(123)
+        ]);
+    }
+
+    #[tokio::test]
+    async fn contribution_bundle_verifies_independently() {
+        init_keys().await;
+        let db = test_storage_client().await;
+        let app_state = SharedState::default();
+        let participant = SessionId::new();
+        let shared_transcript = SharedTranscript::<TestTranscript>::default();
+        app_state.write().await.participant =
+            Some((participant.clone(), create_test_session_info(100)));
+        let receipt = contribute::<TestTranscript>(
+            participant,
+            HeaderMap::new(),
+            Json(ValidContribution(123)),
+            Extension(app_state.clone()),
+            Extension(test_config()),
+            Extension(shared_transcript.clone()),
+            Extension(db.clone()),
+            Extension(VerificationLimiter::new(1)),
+            Extension(full_verifier()),
+        )
+        .await
+        .ok()
+        .expect("valid contribution is accepted")
+        .encoded_receipt_token;
+
+        let fetch = |headers: HeaderMap, config: AppConfig| {
+            contribution_bundle(
+                Path("foo".to_string()),
+                headers,
+                Extension(app_state.clone()),
+                Extension(db.clone()),
+                Extension(config),
+            )
+        };
+        assert!(matches!(
+            fetch(HeaderMap::new(), test_config()).await,
+            Err(BundleError::Forbidden)
+        ));
+        let public = AppConfig {
+            public_contribution_bundles: true,
+            ..test_config()
+        };
+        assert!(fetch(HeaderMap::new(), public).await.is_ok());
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            AUTHORIZATION,
+            format!("Bearer {}", receipt).parse().unwrap(),
+        );
+        let signed = fetch(headers.clone(), test_config()).await.unwrap();
+
+        // Clients verify the canonical encoding of the bundle they received
+        let response = fetch(headers.clone(), test_config()).await.into_response();
+        let received = serde_json::from_slice::<Value>(&response_body(response).await).unwrap();
+        let message = canonical_json(&received["bundle"]);
+        let signature = received["signature"].as_str().unwrap();
+        assert!(KEYS.get().unwrap().verify(signature, &message));
+        let transcript = serde_json::to_vec_pretty(&*shared_transcript.read().await).unwrap();
+        assert_eq!(
+            signed.bundle.transcript_hash.hash,
+            format!("0x{}", hex::encode(digest(&SHA256, &transcript)))
+        );
+        assert_eq!(signed.bundle.receipt, receipt);
+        assert_eq!(signed.bundle.contribution_index, 0);
+
+        // Bundles are rebuilt from storage after a restart
+        app_state.write().await.contribution_bundles.clear();
+        let restored = fetch(headers, test_config()).await.unwrap();
+        assert_eq!(restored.bundle, signed.bundle);
+        assert!(KEYS
+            .get()
+            .unwrap()
+            .verify(&restored.signature, &canonical_json(&restored.bundle)));
+    }
+
+    #[tokio::test]
+    async fn receipts_remain_available_after_finalization() {
+        use crate::api::v1::{
+            admin::{drain, Admin},
+            lobby::{try_contribute, ClientVersion, TryContributeError},
+        };
+
+        init_keys().await;
+        let db = test_storage_client().await;
+        let app_state = SharedState::default();
+        let participant = SessionId::new();
+        let config = AppConfig {
+            public_contribution_bundles: true,
+            ..test_config()
+        };
+        app_state.write().await.participant =
+            Some((participant.clone(), create_test_session_info(100)));
+        let receipt = contribute::<TestTranscript>(
+            participant,
+            HeaderMap::new(),
+            Json(ValidContribution(123)),
+            Extension(app_state.clone()),
+            Extension(config.clone()),
+            Extension(SharedTranscript::default()),
+            Extension(db.clone()),
+            Extension(VerificationLimiter::new(1)),
+            Extension(full_verifier()),
+        )
+        .await
+        .ok()
+        .expect("valid contribution is accepted")
+        .encoded_receipt_token;
+
+        drain(Admin, None, Extension(app_state.clone())).await;
+        assert_eq!(app_state.read().await.ceremony_status(), "finalized");
+
+        let late = SessionId::new();
+        let mut late_session = create_test_session_info(100);
+        late_session.token.as_mut().unwrap().sub = "bar".to_string();
+        app_state
+            .write()
+            .await
+            .lobby
+            .insert(late.clone(), late_session);
+        let response = try_contribute(
+            late,
+            ClientVersion(None),
+            Extension(app_state.clone()),
+            Extension(db.clone()),
+            Extension(SharedTranscript::<TestTranscript>::default()),
+            Extension(config.clone()),
+        )
+        .await;
+        assert!(matches!(response, Err(TryContributeError::Draining)));
+
+        let retrieved = contribution_receipt(
+            Path("foo".to_string()),
+            HeaderMap::new(),
+            Extension(app_state.clone()),
+            Extension(db.clone()),
+            Extension(config.clone()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(retrieved.encoded_receipt_token, receipt);
+        let bundle = contribution_bundle(
+            Path("foo".to_string()),
+            HeaderMap::new(),
+            Extension(app_state.clone()),
+            Extension(db.clone()),
+            Extension(config.clone()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(bundle.bundle.receipt, receipt);
+
+        assert!(matches!(
+            contribution_receipt(
+                Path("bar".to_string()),
+                HeaderMap::new(),
+                Extension(app_state),
+                Extension(db),
+                Extension(config)
+            )
+            .await,
+            Err(BundleError::NotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn receipts_are_served_to_their_owner() {
+        use crate::jwt::ResumeToken;
+
+        init_keys().await;
+        let db = test_storage_client().await;
+        let app_state = SharedState::default();
+        let participant = SessionId::new();
+        app_state.write().await.participant =
+            Some((participant.clone(), create_test_session_info(100)));
+        let receipt = contribute::<TestTranscript>(
+            participant.clone(),
+            HeaderMap::new(),
+            Json(ValidContribution(123)),
+            Extension(app_state.clone()),
+            Extension(test_config()),
+            Extension(SharedTranscript::default()),
+            Extension(db.clone()),
+            Extension(VerificationLimiter::new(1)),
+            Extension(full_verifier()),
+        )
+        .await
+        .ok()
+        .unwrap()
+        .encoded_receipt_token;
+
+        let fetch = |bearer: Option<String>| {
+            let mut headers = HeaderMap::new();
+            if let Some(bearer) = bearer {
+                headers.insert(AUTHORIZATION, format!("Bearer {}", bearer).parse().unwrap());
+            }
+            contribution_receipt(
+                Path("foo".to_string()),
+                headers,
+                Extension(app_state.clone()),
+                Extension(db.clone()),
+                Extension(test_config()),
+            )
+        };
+        assert!(matches!(fetch(None).await, Err(BundleError::Forbidden)));
+        assert!(matches!(
+            fetch(Some(SessionId::new().to_string())).await,
+            Err(BundleError::Forbidden)
+        ));
+
+        let by_session = fetch(Some(participant.to_string())).await.ok().unwrap();
+        assert_eq!(by_session.encoded_receipt_token, receipt);
+        let resume_token = ResumeToken::new(participant, 0).encode().ok().unwrap();
+        let by_resume_token = fetch(Some(resume_token)).await.ok().unwrap();
+        assert_eq!(by_resume_token.encoded_receipt_token, receipt);
+    }
+
+    #[tokio::test]
+    async fn contribution_is_accepted_while_attestations_are_down() {
+        init_keys().await;
+        let mut ceremony = TestCeremony::new("degraded").await;
+        ceremony.config.public_contribution_bundles = true;
+        let (app_state, db, config) = (&ceremony.store, &ceremony.storage, &ceremony.config);
+        let participant = SessionId::new();
+        let set_attestations_available = |available: bool| {
+            let db = db.clone();
+            async move {
+                let (from, to) = if available {
+                    ("attestations_offline", "attestations")
+                } else {
+                    ("attestations", "attestations_offline")
+                };
+                let sql = format!("ALTER TABLE {} RENAME TO {}", from, to);
+                let mut connection = db.acquire_connection().await;
+                sqlx::query(&sql).execute(&mut *connection).await.unwrap();
+            }
+        };
+
+        set_attestations_available(false).await;
+        app_state.write().await.participant =
+            Some((participant.clone(), create_test_session_info(100)));
+        let receipt = ceremony
+            .contribute(participant, ValidContribution(123))
+            .await
+            .ok()
+            .unwrap();
+        assert!(receipt.attestation_pending);
+        assert_eq!(ceremony.transcript.read().await.contributions, vec![
+            ValidContribution(123)
+        ]);
+        let uid = create_test_session_info(100).unique_identifier().to_owned();
+        let receipt_of = || {
+            contribution_receipt(
+                Path(uid.clone()),
+                HeaderMap::new(),
+                Extension(app_state.clone()),
+                Extension(db.clone()),
+                Extension(config.clone()),
+            )
+        };
+        let pending = receipt_of().await.ok().unwrap();
+        assert!(pending.attestation_pending);
+        assert_eq!(pending.encoded_receipt_token, receipt.encoded_receipt_token);
+
+        // Still down, so the attestation stays queued
+        let journal = attestation_journal_path(&config.transcript_file);
+        retry_pending_attestations(app_state, db, &journal).await;
+        assert_eq!(app_state.read().await.pending_attestations.len(), 1);
+
+        // A restart picks the attestation up from the journal
+        let restarted = SharedState::default();
+        let restored = read_attestation_journal(&journal).await.unwrap();
+        assert_eq!(
+            restored.chain_head,
+            app_state.read().await.attestation_chain_head
+        );
+        restarted.write().await.pending_attestations = restored.pending;
+        assert_eq!(restarted.read().await.pending_attestations.len(), 1);
+
+        set_attestations_available(true).await;
+        retry_pending_attestations(&restarted, db, &journal).await;
+        assert!(restarted.read().await.pending_attestations.is_empty());
+        assert!(read_attestation_journal(&journal)
+            .await
+            .unwrap()
+            .pending
+            .is_empty());
+
+        // Storing it again, as the queue from before the restart would, is
+        // harmless
+        retry_pending_attestations(app_state, db, &journal).await;
+        assert!(app_state.read().await.pending_attestations.is_empty());
+        let stored = receipt_of().await.ok().unwrap();
+        assert!(!stored.attestation_pending);
+        assert_eq!(stored.encoded_receipt_token, receipt.encoded_receipt_token);
+    }
+
+    #[tokio::test]
+    async fn conflicting_attestation_is_dropped_and_counted() {
+        let db = test_storage_client().await;
+        let attest = |uid: &str| PendingAttestation {
+            attestation: Attestation::new(
+                0,
+                0,
+                db.identity_hash(uid),
+                "github",
+                vec!["0xa1".to_string()],
+                None,
+                attestation_chain::GENESIS,
+            ),
+            receipt:     format!("receipt of {}", uid),
+            bundle:      None,
+        };
+        let stored = attest("github | alice");
+        db.insert_attestation(&stored.attestation, Some(&stored.receipt), None)
+            .await
+            .unwrap();
+
+        let app_state = SharedState::default();
+        app_state.write().await.pending_attestations = vec![stored, attest("github | bob")];
+        let journal = std::env::temp_dir().join("conflicting_transcript.json.attestations");
+        let conflicts = ATTESTATION_CONFLICTS.get();
+        retry_pending_attestations(&app_state, &db, &journal).await;
+
+        // Retrying can't store either of them, so both leave the queue, but
+        // only the one that isn't stored is an alert
+        assert!(app_state.read().await.pending_attestations.is_empty());
+        assert_eq!(ATTESTATION_CONFLICTS.get(), conflicts + 1);
+        assert_eq!(
+            db.receipt_of("github | alice").await.unwrap().as_deref(),
+            Some("receipt of github | alice")
+        );
+    }
+
+    #[tokio::test]
+    async fn order_commitment_is_restored_from_stored_receipts() {
+        let db = test_storage_client().await;
+        let attest = |index: i64, uid: &str| PendingAttestation {
+            attestation: Attestation::new(
+                0,
+                index,
+                db.identity_hash(uid),
+                "github",
+                vec![format!("0xa{}", index)],
+                None,
+                attestation_chain::GENESIS,
+            ),
+            receipt:     format!("receipt of {}", uid),
+            bundle:      None,
+        };
+        let uids = ["github | alice", "github | bob", "github | carol"];
+        let mut live = OrderCommitment::default();
+        for uid in uids {
+            live.push(format!("receipt of {}", uid).as_bytes());
+        }
+        for (index, uid) in (0..).zip(&uids[..2]) {
+            let stored = attest(index, uid);
+            db.insert_attestation(&stored.attestation, Some(&stored.receipt), None)
+                .await
+                .unwrap();
+        }
+
+        // The last receipt is still in the journal after the restart
+        let mut app_state = AppState::default();
+        app_state.pending_attestations = vec![attest(2, uids[2])];
+        app_state.restore_order_commitment(db.receipts_of_phase(0).await.unwrap());
+        assert_eq!(app_state.order_commitment.len(), 3);
+        assert_eq!(app_state.order_commitment.root(), live.root());
+        assert_eq!(app_state.order_commitment.proof(1), live.proof(1));
+    }
+
+    #[tokio::test]
+    async fn receipt_pins_prior_and_resulting_transcript() {
+        init_keys().await;
+        let db = test_storage_client().await;
+        let app_state = SharedState::default();
+        let participant = SessionId::new();
+        let prior = TestTranscript::default().update(&ValidContribution(1));
+        let shared_transcript = SharedTranscript::new(RwLock::new(prior.clone()));
+        {
+            let mut state = app_state.write().await;
+            state.resume_from(&prior);
+            state.participant = Some((participant.clone(), create_test_session_info(100)));
+        }
+        let token = contribute::<TestTranscript>(
+            participant,
+            HeaderMap::new(),
+            Json(ValidContribution(123)),
+            Extension(app_state),
+            Extension(test_config()),
+            Extension(shared_transcript),
+            Extension(db),
+            Extension(VerificationLimiter::new(1)),
+            Extension(full_verifier()),
+        )
+        .await
+        .ok()
+        .expect("valid contribution is accepted")
+        .encoded_receipt_token;
+
+        let receipt = Receipt::<i64>::decode(&token).unwrap();
+        assert_eq!(receipt.witness, 123);
+        assert_eq!(receipt.transcript_hash_before, transcript_hash(&prior));
+        assert_eq!(
+            receipt.transcript_hash_after,
+            transcript_hash(&prior.update(&ValidContribution(123)))
+        );
+        assert_eq!(receipt.checks, prior.verification_checks());
+
+        // Swapping in other hashes invalidates the sequencer's signature
+        let signature = token.rsplit('.').next().unwrap();
+        let tampers: [fn(&mut Receipt<i64>); 2] = [
+            |receipt| receipt.transcript_hash_before.hash = "0x00".to_string(),
+            |receipt| receipt.transcript_hash_after.hash = "0x00".to_string(),
+        ];
+        for tamper in tampers {
+            let mut tampered = Receipt::<i64>::decode(&token).unwrap();
+            tamper(&mut tampered);
+            let reencoded = tampered.encode().unwrap();
+            let (unsigned, _) = reencoded.rsplit_once('.').unwrap();
+            let forged = format!("{}.{}", unsigned, signature);
+            assert!(Receipt::<i64>::decode(&forged).is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn heartbeating_participant_keeps_their_spot() {
+        let db = test_storage_client().await;
+        tokio::time::pause();
+        let app_state = SharedState::default();
+        let participant = SessionId::new();
+        app_state.write().await.participant =
+            Some((participant.clone(), create_test_session_info(100)));
+        let slot_released = app_state.write().await.slot_released();
+        tokio::spawn(remove_participant_on_deadline(
+            app_state.clone(),
+            db,
+            participant.clone(),
+            "foo".to_string(),
+            Duration::from_secs(180),
+            Some(Duration::from_secs(30)),
+            slot_released,
+        ));
+
+        for _ in 0..5 {
+            tokio::time::sleep(Duration::from_secs(20)).await;
+            assert!(heartbeat(participant.clone(), Extension(app_state.clone()))
+                .await
+                .is_ok());
+        }
+        assert!(app_state.read().await.participant.is_some());
+    }
+
+    #[tokio::test]
+    async fn silent_participant_is_expired_early() {
+        let db = test_storage_client().await;
+        tokio::time::pause();
+        let app_state = SharedState::default();
+        let participant = SessionId::new();
+        app_state.write().await.participant =
+            Some((participant.clone(), create_test_session_info(100)));
+        let slot_released = app_state.write().await.slot_released();
+        tokio::spawn(remove_participant_on_deadline(
+            app_state.clone(),
+            db,
+            participant.clone(),
+            "foo".to_string(),
+            Duration::from_secs(180),
+            Some(Duration::from_secs(30)),
+            slot_released,
+        ));
+
+        tokio::time::sleep(Duration::from_secs(20)).await;
+        assert!(app_state.read().await.participant.is_some());
+        tokio::time::sleep(Duration::from_secs(11)).await;
+        assert!(app_state.read().await.participant.is_none());
+        assert!(matches!(
+            heartbeat(participant, Extension(app_state.clone())).await,
+            Err(ContributeError::NotUsersTurn)
+        ));
+    }
+
+    #[tokio::test]
+    async fn heartbeat_timeout_starts_when_the_spot_is_granted() {
+        let db = test_storage_client().await;
+        tokio::time::pause();
+        let app_state = SharedState::default();
+        let participant = SessionId::new();
+        app_state
+            .write()
+            .await
+            .lobby
+            .insert(participant.clone(), create_test_session_info(100));
+        // The last lobby check-in was most of a heartbeat timeout ago
+        tokio::time::advance(Duration::from_secs(25)).await;
+        let slot_released = {
+            let mut app_state = app_state.write().await;
+            assert!(app_state
+                .try_set_current_contributor(participant.clone(), Duration::from_secs(180)));
+            app_state.slot_released()
+        };
+        tokio::spawn(remove_participant_on_deadline(
+            app_state.clone(),
+            db,
+            participant.clone(),
+            "foo".to_string(),
+            Duration::from_secs(180),
+            Some(Duration::from_secs(30)),
+            slot_released,
+        ));
+
+        tokio::time::sleep(Duration::from_secs(20)).await;
+        assert!(app_state.read().await.participant.is_some());
+        tokio::time::sleep(Duration::from_secs(11)).await;
+        assert!(app_state.read().await.participant.is_none());
+    }
+
+    #[tokio::test]
+    async fn deadline_timer_exits_once_participant_contributed() {
+        init_keys().await;
+        let db = test_storage_client().await;
+        let app_state = SharedState::default();
+        let participant = SessionId::new();
+        app_state.write().await.participant =
+            Some((participant.clone(), create_test_session_info(100)));
+        let slot_released = app_state.write().await.slot_released();
+        let timer = tokio::spawn(remove_participant_on_deadline(
+            app_state.clone(),
+            db.clone(),
+            participant.clone(),
+            "foo".to_string(),
+            Duration::from_secs(180),
+            None,
+            slot_released,
+        ));
+        // Let the timer start waiting
+        tokio::task::yield_now().await;
+
+        assert!(contribute::<TestTranscript>(
+            participant,
+            HeaderMap::new(),
+            Json(ValidContribution(123)),
+            Extension(app_state.clone()),
+            Extension(test_config()),
+            Extension(SharedTranscript::default()),
+            Extension(db),
+            Extension(VerificationLimiter::new(1)),
+            Extension(full_verifier()),
+        )
+        .await
+        .is_ok());
+        // Far sooner than the deadline
+        timeout(Duration::from_secs(5), timer)
+            .await
+            .expect("timer outlived the contribution")
+            .unwrap();
+    }
 }
@@ -0,0 +1,132 @@
+use axum::{
+    body::{boxed, Full, HttpBody},
+    http::Request,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use http::{
+    header::{CONTENT_LENGTH, CONTENT_TYPE},
+    StatusCode,
+};
+use serde_json::Value;
+
+use crate::constants::MAX_FORMATTED_RESPONSE_BYTES;
+
+// Re-encodes buffered JSON responses so that every endpoint is uniformly
+// pretty printed or compact, depending on `pretty`. Streamed bodies, such as
+// the transcript download, and bodies too large to buffer twice are passed
+// through untouched.
+pub async fn format_json<B: Send>(pretty: bool, request: Request<B>, next: Next<B>) -> Response {
+    let response = next.run(request).await;
+    let is_json = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map_or(false, |value| value.starts_with("application/json"));
+    let small = response
+        .body()
+        .size_hint()
+        .exact()
+        .map_or(false, |size| size <= MAX_FORMATTED_RESPONSE_BYTES);
+    if !is_json || !small {
+        return response;
+    }
+
+    let (mut parts, mut body) = response.into_parts();
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        match chunk {
+            Ok(chunk) => bytes.extend_from_slice(&chunk),
+            Err(_) => return StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        }
+    }
+    let formatted = serde_json::from_slice::<Value>(&bytes)
+        .and_then(|value| {
+            if pretty {
+                serde_json::to_vec_pretty(&value)
+            } else {
+                serde_json::to_vec(&value)
+            }
+        })
+        .unwrap_or(bytes);
+    parts.headers.remove(CONTENT_LENGTH);
+    Response::from_parts(parts, boxed(Full::from(formatted)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api::v1::info::parameters,
+        test_util::{response_body, test_config},
+    };
+    use axum::{
+        body::{Body, StreamBody},
+        middleware::from_fn,
+        routing::get,
+        Extension, Json, Router,
+    };
+    use std::convert::Infallible;
+    use tower::ServiceExt;
+
+    async fn get_parameters(pretty: bool) -> String {
+        let app = Router::new()
+            .route("/info/parameters", get(parameters))
+            .layer(from_fn(move |request: Request<Body>, next: Next<Body>| {
+                format_json(pretty, request, next)
+            }))
+            .layer(Extension(test_config()));
+        let request = Request::builder()
+            .uri("/info/parameters")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        String::from_utf8(response_body(response).await).unwrap()
+    }
+
+    async fn get_formatted(uri: &'static str) -> String {
+        let large = serde_json::to_string(&vec![1; MAX_FORMATTED_RESPONSE_BYTES as usize]).unwrap();
+        let app = Router::new()
+            .route("/small", get(|| async { Json(vec![1, 2]) }))
+            .route(
+                "/large",
+                get(move || async move { ([(CONTENT_TYPE, "application/json")], large) }),
+            )
+            .route(
+                "/streamed",
+                get(|| async {
+                    let chunks = futures::stream::iter([Ok::<_, Infallible>("[1,"), Ok("2]")]);
+                    (
+                        [(CONTENT_TYPE, "application/json")],
+                        StreamBody::new(chunks),
+                    )
+                }),
+            )
+            .layer(from_fn(|request: Request<Body>, next: Next<Body>| {
+                format_json(true, request, next)
+            }));
+        let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        String::from_utf8(response_body(response).await).unwrap()
+    }
+
+    #[tokio::test]
+    async fn only_small_buffered_responses_are_formatted() {
+        assert_eq!(get_formatted("/small").await, "[\n  1,\n  2\n]");
+        assert_eq!(get_formatted("/streamed").await, "[1,2]");
+        assert!(!get_formatted("/large").await.contains('\n'));
+    }
+
+    #[tokio::test]
+    async fn pretty_responses_toggle_formatting() {
+        let pretty = get_parameters(true).await;
+        let compact = get_parameters(false).await;
+
+        assert!(pretty.contains("\n  \"compute_deadline_sec\""));
+        assert!(!compact.contains('\n'));
+        assert_eq!(
+            serde_json::from_str::<Value>(&pretty).unwrap(),
+            serde_json::from_str::<Value>(&compact).unwrap()
+        );
+    }
+}
@@ -0,0 +1,369 @@
+use crate::{
+    api::v1::auth::{AuthError, AuthPayload},
+    constants,
+    ethereum::ETH_UID_PREFIX,
+    AppConfig, GithubOAuthClient, SiweOAuthClient,
+};
+use async_session::async_trait;
+use chrono::{DateTime, FixedOffset};
+use oauth2::{
+    reqwest::async_http_client, AuthorizationCode, ErrorResponse, RequestTokenError, TokenResponse,
+};
+use once_cell::sync::Lazy;
+use prometheus::{register_histogram_vec, register_int_counter_vec, HistogramVec, IntCounterVec};
+use serde::Deserialize;
+use serde_json::json;
+use std::{
+    collections::BTreeMap,
+    ops::Deref,
+    sync::{Arc, Mutex},
+};
+use tokio::time::{Duration, Instant};
+use tracing::{info_span, warn, Instrument};
+
+static PROVIDER_REQUEST_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "auth_provider_request_seconds",
+        "Time spent authorizing sign-ins with an identity provider",
+        &["provider"]
+    )
+    .unwrap()
+});
+
+static PROVIDER_ERRORS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "auth_provider_errors_total",
+        "Number of sign-ins that failed because an identity provider could not be reached or \
+         answered unexpectedly",
+        &["provider"]
+    )
+    .unwrap()
+});
+
+static PROVIDER_SHORT_CIRCUITS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "auth_provider_short_circuits_total",
+        "Number of sign-ins refused because their identity provider is considered down",
+        &["provider"]
+    )
+    .unwrap()
+});
+
+// An identity vouched for by a provider
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identity {
+    // Unique across providers, used to tell whether someone already
+    // contributed
+    pub uid:      String,
+    pub nickname: String,
+}
+
+// A backend users can authenticate with. Implementations decide how the
+// callback payload is turned into an identity, and how its unique identifier
+// is derived.
+#[async_trait]
+pub trait IdentityProvider: Send + Sync {
+    // The provider name recorded in id tokens, e.g. "Github"
+    fn name(&self) -> &'static str;
+
+    async fn authorize(&self, payload: AuthPayload) -> Result<Identity, AuthError>;
+}
+
+// Enabled providers, keyed by the name used in their callback route
+#[derive(Clone, Default)]
+pub struct IdentityProviders(BTreeMap<String, Arc<dyn IdentityProvider>>);
+
+impl IdentityProviders {
+    // Every provider is monitored, and short-circuited while it keeps failing
+    pub fn register(&mut self, route: &str, provider: impl IdentityProvider + 'static) {
+        self.0.insert(
+            route.to_owned(),
+            Arc::new(Monitored {
+                route:   route.to_owned(),
+                inner:   provider,
+                breaker: CircuitBreaker::new(
+                    constants::AUTH_PROVIDER_FAILURE_THRESHOLD,
+                    Duration::from_secs(constants::AUTH_PROVIDER_COOLDOWN_SEC as u64),
+                ),
+            }),
+        );
+    }
+
+    pub fn get(&self, route: &str) -> Option<Arc<dyn IdentityProvider>> {
+        self.0.get(route).cloned()
+    }
+
+    // Sets up the providers listed in `config.identity_providers`
+    pub fn from_config(
+        config: &AppConfig,
+        github_client: GithubOAuthClient,
+        siwe_client: SiweOAuthClient,
+        http_client: reqwest::Client,
+    ) -> Self {
+        let mut providers = Self::default();
+        for route in &config.identity_providers {
+            match route.as_str() {
+                "github" => providers.register(route, GithubProvider {
+                    client:            github_client.clone(),
+                    http_client:       http_client.clone(),
+                    max_creation_time: config.github_max_creation_time,
+                }),
+                "siwe" => providers.register(route, SiweProvider {
+                    client:         siwe_client.clone(),
+                    http_client:    http_client.clone(),
+                    rpc_url:        config.eth_rpc_url.clone(),
+                    nonce_at_block: config.eth_check_nonce_at_block.clone(),
+                    min_nonce:      config.eth_min_nonce,
+                }),
+                other => panic!("Unknown identity provider {}", other),
+            }
+        }
+        providers
+    }
+}
+
+// Opens after `threshold` failures in a row. While open, calls are refused
+// until `cooldown` has passed, after which the next call is let through to
+// probe the provider again.
+struct CircuitBreaker {
+    threshold: usize,
+    cooldown:  Duration,
+    state:     Mutex<BreakerState>,
+}
+
+#[derive(Default)]
+struct BreakerState {
+    consecutive_failures: usize,
+    open_until:           Option<Instant>,
+}
+
+impl CircuitBreaker {
+    fn new(threshold: usize, cooldown: Duration) -> Self {
+        Self {
+            threshold,
+            cooldown,
+            state: Mutex::default(),
+        }
+    }
+
+    fn allows_call(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        state
+            .open_until
+            .map_or(true, |open_until| Instant::now() >= open_until)
+    }
+
+    // Returns whether this failure opened the breaker
+    fn record(&self, failed: bool) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if !failed {
+            *state = BreakerState::default();
+            return false;
+        }
+        state.consecutive_failures += 1;
+        if state.consecutive_failures >= self.threshold {
+            state.open_until = Some(Instant::now() + self.cooldown);
+            return true;
+        }
+        false
+    }
+}
+
+// Records latency and upstream failures of a provider
+struct Monitored<P> {
+    route:   String,
+    inner:   P,
+    breaker: CircuitBreaker,
+}
+
+#[async_trait]
+impl<P: IdentityProvider> IdentityProvider for Monitored<P> {
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    async fn authorize(&self, payload: AuthPayload) -> Result<Identity, AuthError> {
+        let labels = [self.route.as_str()];
+        if !self.breaker.allows_call() {
+            PROVIDER_SHORT_CIRCUITS.with_label_values(&labels).inc();
+            return Err(AuthError::ProviderUnavailable);
+        }
+
+        let started = Instant::now();
+        let result = self
+            .inner
+            .authorize(payload)
+            .instrument(info_span!("identity_provider", provider = %self.route))
+            .await;
+        PROVIDER_REQUEST_SECONDS
+            .with_label_values(&labels)
+            .observe(started.elapsed().as_secs_f64());
+
+        let failed = matches!(&result, Err(error) if error.is_upstream_failure());
+        if failed {
+            PROVIDER_ERRORS.with_label_values(&labels).inc();
+        }
+        if self.breaker.record(failed) {
+            warn!(provider = %self.route, "Identity provider keeps failing, refusing sign-ins");
+        }
+        result
+    }
+}
+
+// A rejected code is the user's problem, failing to reach the provider is not
+fn exchange_error<RE, T>(error: RequestTokenError<RE, T>) -> AuthError
+where
+    RE: std::error::Error + 'static,
+    T: ErrorResponse + 'static,
+{
+    match error {
+        RequestTokenError::ServerResponse(_) => AuthError::InvalidAuthCode,
+        _ => AuthError::FetchUserDataError,
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct GhUserInfo {
+    login:      String,
+    created_at: String,
+}
+
+pub struct GithubProvider {
+    client:            GithubOAuthClient,
+    http_client:       reqwest::Client,
+    max_creation_time: DateTime<FixedOffset>,
+}
+
+#[async_trait]
+impl IdentityProvider for GithubProvider {
+    fn name(&self) -> &'static str {
+        "Github"
+    }
+
+    async fn authorize(&self, payload: AuthPayload) -> Result<Identity, AuthError> {
+        let token = self
+            .client
+            .exchange_code(AuthorizationCode::new(payload.code))
+            .request_async(async_http_client)
+            .await
+            .map_err(exchange_error)?;
+
+        let response = self
+            .http_client
+            .get("https://api.github.com/user")
+            .bearer_auth(token.access_token().secret())
+            .header("User-Agent", "ethereum-kzg-ceremony-sequencer")
+            .send()
+            .await
+            .map_err(|_| AuthError::FetchUserDataError)?;
+        let gh_user_info = response
+            .json::<GhUserInfo>()
+            .await
+            .map_err(|_| AuthError::CouldNotExtractUserData)?;
+        let creation_time = DateTime::parse_from_rfc3339(&gh_user_info.created_at)
+            .map_err(|_| AuthError::CouldNotExtractUserData)?;
+        if creation_time > self.max_creation_time {
+            return Err(AuthError::UserCreatedAfterDeadline);
+        }
+        Ok(Identity {
+            uid:      format!("github | {}", gh_user_info.login),
+            nickname: gh_user_info.login,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SiweUserInfo {
+    sub:                String,
+    preferred_username: String,
+}
+
+// So Sequencer could give out fake identities, we are trusting the sequencer
+// to not do that.
+//
+// Now this is catchable by the client. They will clearly see that the sequencer
+// was malicious. What can happen is sequencer can claim that someone
+// participated when they did not. Is this Okay? Maybe that person can then just
+// say they did not
+pub struct SiweProvider {
+    client:         SiweOAuthClient,
+    http_client:    reqwest::Client,
+    rpc_url:        String,
+    nonce_at_block: String,
+    min_nonce:      i64,
+}
+
+#[async_trait]
+impl IdentityProvider for SiweProvider {
+    fn name(&self) -> &'static str {
+        "Ethereum"
+    }
+
+    async fn authorize(&self, payload: AuthPayload) -> Result<Identity, AuthError> {
+        let token = self
+            .client
+            .exchange_code(AuthorizationCode::new(payload.code))
+            .request_async(async_http_client)
+            .await
+            .map_err(exchange_error)?;
+
+        let response = self
+            .http_client
+            .get("https://oidc.signinwithethereum.org/userinfo")
+            .bearer_auth(token.access_token().secret())
+            .send()
+            .await
+            .map_err(|_| AuthError::FetchUserDataError)?;
+
+        let siwe_user = response
+            .json::<SiweUserInfo>()
+            .await
+            .map_err(|_| AuthError::CouldNotExtractUserData)?;
+
+        let addr_parts: Vec<_> = siwe_user.sub.split(':').collect();
+        let address = addr_parts
+            .get(2)
+            .ok_or(AuthError::CouldNotExtractUserData)?
+            .deref()
+            .to_string();
+
+        let tx_count = self
+            .get_tx_count(&address)
+            .await
+            .ok_or(AuthError::CouldNotExtractUserData)?;
+
+        if tx_count < self.min_nonce {
+            return Err(AuthError::UserCreatedAfterDeadline);
+        }
+
+        Ok(Identity {
+            uid:      format!("{}{}", ETH_UID_PREFIX, address),
+            nickname: siwe_user.preferred_username,
+        })
+    }
+}
+
+impl SiweProvider {
+    async fn get_tx_count(&self, address: &str) -> Option<i64> {
+        let rpc_payload = json!({
+            "id": 1,
+            "jsonrpc": "2.0",
+            "params": [&address, &self.nonce_at_block],
+            "method": "eth_getTransactionCount"
+        });
+
+        let rpc_response = self
+            .http_client
+            .post(&self.rpc_url)
+            .json(&rpc_payload)
+            .send()
+            .await
+            .ok()?;
+
+        let rpc_response_json = rpc_response.json::<serde_json::Value>().await.ok()?;
+
+        let rpc_result = rpc_response_json.get("result")?.as_str()?;
+
+        i64::from_str_radix(rpc_result.trim_start_matches("0x"), 16).ok()
+    }
+}
@@ -1,22 +1,62 @@
 use crate::{
+    api::v1::{limits::ClientLimiter, lobby::PollAdvice},
+    constants::{POINT_ENCODING, POINT_ENDIANNESS, SELECTION_POLICY, TRANSCRIPT_SNAPSHOT_WAIT_MS},
+    data::{
+        hash::{HashAlgorithm, TranscriptHash},
+        objects::{TranscriptObjects, TranscriptVersion},
+        transcript::{
+            committed_transcript, read_transcript_signature, transcript_file_digest,
+            TranscriptSignature,
+        },
+    },
     keys::{Keys, KEYS},
-    AppConfig, SharedState,
+    merkle::{hash_to_hex, ProofStep},
+    storage::{PersistentStorage, StorageError},
+    verification::recent_throughput,
+    AppConfig, SharedState, SharedTranscript, Transcript,
 };
 use axum::{
     body::StreamBody,
+    extract::{ConnectInfo, Path, Query},
     response::{IntoResponse, Response},
     Extension, Json,
 };
-use axum_extra::response::ErasedJson;
-use http::StatusCode;
+use chrono::{DateTime, Utc};
+use http::{
+    header::{
+        HeaderName, HeaderValue, ACCEPT, ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE,
+        CONTENT_TYPE, ETAG, RANGE, RETRY_AFTER,
+    },
+    HeaderMap, StatusCode,
+};
 use serde::{Deserialize, Serialize};
-use tokio::fs::File;
+use serde_json::json;
+use std::{
+    cmp::min,
+    io::ErrorKind,
+    net::SocketAddr,
+    ops::Range,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+use tokio::{fs::File, io::AsyncReadExt, time::Duration};
 use tokio_util::io::ReaderStream;
 
 #[derive(Debug, Serialize, PartialEq, Eq)]
 pub struct StatusResponse {
-    lobby_size:        usize,
+    lobby_size: usize,
     num_contributions: usize,
+    // Changes when the ceremony moves to its next phase, 0 is the first
+    phase: i64,
+    phase_name: Option<String>,
+    // Hash of the served transcript, also returned by `try_contribute`
+    pub(crate) transcript_hash: TranscriptHash,
+    // Grows with the lobby, so status pollers back off under load
+    #[serde(flatten)]
+    pub(crate) poll_advice: PollAdvice,
 }
 
 impl IntoResponse for StatusResponse {
@@ -26,7 +66,50 @@ impl IntoResponse for StatusResponse {
     }
 }
 
-pub async fn status(Extension(store): Extension<SharedState>) -> StatusResponse {
+// Core counters kept without the Prometheus registry, for deployments that
+// don't scrape `/metrics`. They count since the sequencer started.
+#[derive(Debug, Default)]
+pub struct Counters {
+    pub contributions: AtomicU64,
+    pub expirations:   AtomicU64,
+    pub rate_limited:  AtomicU64,
+}
+
+impl Counters {
+    pub fn count(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct CountersResponse {
+    contributions: u64,
+    expirations:   u64,
+    rate_limited:  u64,
+    lobby_size:    usize,
+}
+
+impl IntoResponse for CountersResponse {
+    fn into_response(self) -> Response {
+        (StatusCode::OK, Json(self)).into_response()
+    }
+}
+
+pub async fn counters(Extension(store): Extension<SharedState>) -> CountersResponse {
+    let app_state = store.read().await;
+    let counters = &app_state.counters;
+    CountersResponse {
+        contributions: counters.contributions.load(Ordering::Relaxed),
+        expirations:   counters.expirations.load(Ordering::Relaxed),
+        rate_limited:  counters.rate_limited.load(Ordering::Relaxed),
+        lobby_size:    app_state.lobby.len(),
+    }
+}
+
+pub async fn status(
+    Extension(store): Extension<SharedState>,
+    Extension(config): Extension<AppConfig>,
+) -> StatusResponse {
     let app_state = store.read().await;
 
     let lobby_size = app_state.lobby.len();
@@ -35,22 +118,642 @@ pub async fn status(Extension(store): Extension<SharedState>) -> StatusResponse
     StatusResponse {
         lobby_size,
         num_contributions,
+        phase: app_state.phase.as_ref().map_or(0, |phase| phase.phase),
+        phase_name: app_state.phase.as_ref().map(|phase| phase.name.clone()),
+        transcript_hash: app_state.transcript_hash.clone(),
+        poll_advice: PollAdvice::for_status(lobby_size, &config),
+    }
+}
+
+// The transcript representations clients can ask for via `Accept`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TranscriptFormat {
+    // The transcript file as stored, streamed from disk
+    Raw,
+    // Compact JSON of the in-memory transcript
+    Json,
+    // Bincode encoding of the in-memory transcript
+    Binary,
+}
+
+impl TranscriptFormat {
+    const fn content_type(self) -> &'static str {
+        match self {
+            Self::Raw => "application/octet-stream",
+            Self::Json => "application/json",
+            Self::Binary => "application/x-kzg-binary",
+        }
+    }
+
+    // Picks the first supported media type. Quality values are ignored.
+    pub fn from_accept(accept: Option<&str>) -> Option<Self> {
+        let accept = match accept {
+            Some(accept) if !accept.trim().is_empty() => accept,
+            _ => return Some(Self::Raw),
+        };
+        accept.split(',').find_map(|media_type| {
+            let media_type = media_type.split(';').next().unwrap_or_default().trim();
+            match media_type {
+                "application/octet-stream" | "application/*" | "*/*" => Some(Self::Raw),
+                "application/json" => Some(Self::Json),
+                "application/x-kzg-binary" => Some(Self::Binary),
+                _ => None,
+            }
+        })
+    }
+}
+
+pub async fn current_state<T: Transcript + Send + Sync>(
+    headers: HeaderMap,
+    Extension(config): Extension<AppConfig>,
+    Extension(transcript): Extension<SharedTranscript<T>>,
+) -> Response {
+    let accept = headers.get(ACCEPT).and_then(|value| value.to_str().ok());
+    let format = match TranscriptFormat::from_accept(accept) {
+        Some(format) => format,
+        None => {
+            let body = Json(json!({
+                "error": "unsupported transcript format",
+            }));
+            return (StatusCode::NOT_ACCEPTABLE, body).into_response();
+        }
+    };
+    let content_type = [(CONTENT_TYPE, format.content_type())];
+
+    match format {
+        TranscriptFormat::Raw => {
+            // Until the current transcript has been uploaded, the local file
+            // is served instead
+            if let Some(objects) = config.transcript_objects {
+                if let Some(version) = objects.current() {
+                    let range = headers.get(RANGE).and_then(|value| value.to_str().ok());
+                    let mut response = transcript_object(&objects, &version, range).await;
+                    if let Ok(signature) =
+                        read_transcript_signature(config.transcript_signature_file).await
+                    {
+                        if signature.transcript_hash == version.transcript_hash {
+                            attach_signature(&mut response, signature);
+                        }
+                    }
+                    return response;
+                }
+            }
+            // The file is only opened once a write in progress is committed.
+            // The open handle keeps reading that snapshot, even if the file is
+            // replaced while it is streamed.
+            let commit = match committed_transcript(Duration::from_millis(
+                TRANSCRIPT_SNAPSHOT_WAIT_MS as u64,
+            ))
+            .await
+            {
+                Some(commit) => commit,
+                None => {
+                    let body = Json(json!({
+                        "error": "transcript is being updated, try again shortly",
+                    }));
+                    return (StatusCode::SERVICE_UNAVAILABLE, [(RETRY_AFTER, "1")], body)
+                        .into_response();
+                }
+            };
+            let opened = match File::open(config.transcript_file).await {
+                Ok(file) => file.metadata().await.map(|metadata| (file, metadata.len())),
+                Err(error) => Err(error),
+            };
+            // Renamed into place under the same commit as the file
+            let signature = read_transcript_signature(config.transcript_signature_file).await;
+            drop(commit);
+            let (f, size) = match opened {
+                Ok(opened) => opened,
+                Err(error) => {
+                    let transcript = transcript.read().await;
+                    if error.kind() == ErrorKind::NotFound && transcript.num_contributions() == 0 {
+                        return uninitialized_transcript(config.missing_transcript, &*transcript);
+                    }
+                    return (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "could not open transcript file",
+                    )
+                        .into_response();
+                }
+            };
+            // With the length announced, clients can tell a cut off body
+            // from a complete one
+            let stream = ReaderStream::new(f.take(size));
+            let body = StreamBody::new(stream);
+            let headers = [
+                (CONTENT_TYPE, format.content_type().to_string()),
+                (CONTENT_LENGTH, size.to_string()),
+            ];
+            let mut response = (StatusCode::OK, headers, body).into_response();
+            if let Ok(signature) = signature {
+                attach_signature(&mut response, signature);
+            }
+            response
+        }
+        TranscriptFormat::Json => {
+            let body = serde_json::to_vec(&*transcript.read().await);
+            match body {
+                Ok(body) => (StatusCode::OK, content_type, body).into_response(),
+                Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+            }
+        }
+        TranscriptFormat::Binary => {
+            let body = bincode::serialize(&*transcript.read().await);
+            match body {
+                Ok(body) => (StatusCode::OK, content_type, body).into_response(),
+                Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+            }
+        }
     }
 }
 
-pub async fn current_state(Extension(config): Extension<AppConfig>) -> impl IntoResponse {
-    let f = match File::open(config.transcript_file).await {
-        Ok(file) => file,
+// What `current_state` serves before the transcript file is first written,
+// e.g. while a new ceremony is being set up. Either way the response is
+// marked with `x-transcript-state: uninitialized`. Once the file exists, or
+// if it went missing after contributions were accepted, failing to open it
+// is an error.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MissingTranscript {
+    // The initial transcript, as it will be written
+    #[default]
+    Initial,
+    // An empty `204 No Content`
+    NoContent,
+}
+
+impl FromStr for MissingTranscript {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_ascii_lowercase().as_str() {
+            "initial" => Ok(Self::Initial),
+            "no_content" => Ok(Self::NoContent),
+            other => Err(format!("unknown missing transcript response {}", other)),
+        }
+    }
+}
+
+fn uninitialized_transcript<T: Transcript>(missing: MissingTranscript, initial: &T) -> Response {
+    let state = (
+        HeaderName::from_static("x-transcript-state"),
+        "uninitialized",
+    );
+    match missing {
+        MissingTranscript::Initial => match serde_json::to_vec_pretty(initial) {
+            Ok(body) => {
+                let content_type = (CONTENT_TYPE, TranscriptFormat::Raw.content_type());
+                (StatusCode::OK, [state, content_type], body).into_response()
+            }
+            Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        },
+        MissingTranscript::NoContent => (StatusCode::NO_CONTENT, [state]).into_response(),
+    }
+}
+
+// Parses a single range like `bytes=0-99`, `bytes=100-` or `bytes=-100` of
+// an object of `size` bytes. Returns `None` if it can't be satisfied.
+fn parse_byte_range(range: &str, size: usize) -> Option<Range<usize>> {
+    let (start, end) = range.strip_prefix("bytes=")?.split_once('-')?;
+    let (start, end) = match (start.trim(), end.trim()) {
+        ("", suffix) => (size.checked_sub(suffix.parse().ok()?)?, size),
+        (start, "") => (start.parse().ok()?, size),
+        (start, end) => (
+            start.parse().ok()?,
+            min(end.parse::<usize>().ok()? + 1, size),
+        ),
+    };
+    (start < end).then_some(start..end)
+}
+
+// Adds the sequencer's signature over a served transcript file to its
+// response, so a download can be checked without fetching the signature
+// separately, and without racing a transcript update in between
+fn attach_signature(response: &mut Response, signature: TranscriptSignature) {
+    let headers = [
+        ("x-transcript-signature", signature.signature),
+        ("x-transcript-hash", signature.transcript_hash.hash),
+        (
+            "x-transcript-hash-alg",
+            signature.transcript_hash.alg.to_string(),
+        ),
+    ];
+    for (name, value) in headers {
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            response
+                .headers_mut()
+                .insert(HeaderName::from_static(name), value);
+        }
+    }
+}
+
+// Streams an uploaded transcript from object storage. Range requests are
+// passed through to the store, so clients can resume large downloads.
+async fn transcript_object(
+    objects: &TranscriptObjects,
+    version: &TranscriptVersion,
+    range: Option<&str>,
+) -> Response {
+    let unavailable = || {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "could not read transcript object",
+        )
+            .into_response()
+    };
+    let content_type = (
+        CONTENT_TYPE,
+        TranscriptFormat::Raw.content_type().to_string(),
+    );
+    let accept_ranges = (ACCEPT_RANGES, "bytes".to_string());
+    let size = version.size;
+
+    if let Some(range) = range {
+        let range = match parse_byte_range(range, size) {
+            Some(range) => range,
+            None => {
+                let headers = [(CONTENT_RANGE, format!("bytes */{}", size))];
+                return (StatusCode::RANGE_NOT_SATISFIABLE, headers).into_response();
+            }
+        };
+        let content_range = format!("bytes {}-{}/{}", range.start, range.end - 1, size);
+        return match objects.range(version, range).await {
+            Ok(body) => {
+                let headers = [
+                    content_type,
+                    accept_ranges,
+                    (CONTENT_RANGE, content_range),
+                    (CONTENT_LENGTH, body.len().to_string()),
+                ];
+                (StatusCode::PARTIAL_CONTENT, headers, body).into_response()
+            }
+            Err(_) => unavailable(),
+        };
+    }
+
+    match objects.stream(version).await {
+        Ok(stream) => {
+            let headers = [
+                content_type,
+                accept_ranges,
+                (CONTENT_LENGTH, size.to_string()),
+            ];
+            (StatusCode::OK, headers, StreamBody::new(stream)).into_response()
+        }
+        Err(_) => unavailable(),
+    }
+}
+
+// Describes the transcript served by `current_state` without sending it, so
+// clients can check its size and hash before downloading
+pub async fn current_state_head(Extension(config): Extension<AppConfig>) -> Response {
+    let (size, hash) = match transcript_file_digest(config.transcript_file).await {
+        Ok(digest) => digest,
         Err(_) => {
-            return Err((
+            return (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "could not open transcript file",
-            ))
+            )
+                .into_response()
         }
     };
-    let stream = ReaderStream::new(f);
-    let body = StreamBody::new(stream);
-    Ok((StatusCode::OK, body))
+    let headers = [
+        (CONTENT_LENGTH, size.to_string()),
+        (ETAG, format!("\"{}\"", hash.hash.trim_start_matches("0x"))),
+        (HeaderName::from_static("x-transcript-hash"), hash.hash),
+        (
+            HeaderName::from_static("x-transcript-hash-alg"),
+            hash.alg.to_string(),
+        ),
+    ];
+    (StatusCode::OK, headers).into_response()
+}
+
+// Returns the sequencer's signature over the current transcript
+pub async fn transcript_signature(Extension(config): Extension<AppConfig>) -> Response {
+    match read_transcript_signature(config.transcript_signature_file).await {
+        Ok(signature) => (StatusCode::OK, Json(signature)).into_response(),
+        Err(_) => (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "could not read transcript signature",
+        )
+            .into_response(),
+    }
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct DashboardResponse {
+    lobby_size:                     usize,
+    num_contributions:              usize,
+    num_expired:                    usize,
+    ceremony_status:                &'static str,
+    // Time until the current lobby is worked through, at the average
+    // compute time so far
+    estimated_wait_sec:             u64,
+    // When the last of `max_contributions` is expected at the same pace,
+    // unset if the ceremony has no planned end
+    estimated_completion_time:      Option<DateTime<Utc>>,
+    transcript_size_bytes:          Option<u64>,
+    transcript_hash:                TranscriptHash,
+    verification_powers_per_second: f64,
+    // The session at the head of the lobby, unset while the lobby is empty
+    next_in_line:                   Option<NextInLine>,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct NextInLine {
+    handle:      String,
+    waiting_sec: u64,
+}
+
+impl IntoResponse for DashboardResponse {
+    fn into_response(self) -> Response {
+        (StatusCode::OK, Json(self)).into_response()
+    }
+}
+
+// Aggregates the monitoring data from a single read of the app state, so
+// the figures are consistent with each other
+pub async fn dashboard(
+    Extension(store): Extension<SharedState>,
+    Extension(config): Extension<AppConfig>,
+) -> DashboardResponse {
+    let transcript_size_bytes = tokio::fs::metadata(&config.transcript_file)
+        .await
+        .ok()
+        .map(|metadata| metadata.len());
+
+    let app_state = store.read().await;
+
+    let average_compute_time = if app_state.compute_times.is_empty() {
+        config.effective_compute_deadline()
+    } else {
+        app_state.compute_times.iter().sum::<Duration>()
+            / u32::try_from(app_state.compute_times.len()).unwrap_or(u32::MAX)
+    };
+    let lobby_size = app_state.lobby.len();
+    let estimated_completion_time = config.max_contributions.and_then(|max_contributions| {
+        let remaining = max_contributions.saturating_sub(app_state.num_contributions);
+        let remaining = average_compute_time * u32::try_from(remaining).unwrap_or(u32::MAX);
+        chrono::Duration::from_std(remaining)
+            .ok()
+            .map(|remaining| Utc::now() + remaining)
+    });
+
+    let next_in_line = app_state
+        .lobby
+        .first()
+        .map(|(session_id, session_info)| NextInLine {
+            handle:      session_id.public_handle(),
+            waiting_sec: session_info.joined_at.elapsed().as_secs(),
+        });
+
+    DashboardResponse {
+        lobby_size,
+        num_contributions: app_state.num_contributions,
+        num_expired: app_state.num_expired,
+        ceremony_status: app_state.ceremony_status(),
+        estimated_wait_sec: (average_compute_time * u32::try_from(lobby_size).unwrap_or(u32::MAX))
+            .as_secs(),
+        estimated_completion_time,
+        transcript_size_bytes,
+        transcript_hash: app_state.transcript_hash.clone(),
+        verification_powers_per_second: recent_throughput(),
+        next_in_line,
+    }
+}
+
+pub enum OrderCommitmentError {
+    Disabled,
+    UnknownIndex,
+}
+
+impl IntoResponse for OrderCommitmentError {
+    fn into_response(self) -> Response {
+        let message = match self {
+            Self::Disabled => "order commitment is not enabled",
+            Self::UnknownIndex => "no contribution with this index",
+        };
+        let body = Json(json!({ "error": message }));
+        (StatusCode::NOT_FOUND, body).into_response()
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct OrderCommitmentResponse {
+    // Absent until the first contribution
+    root:              Option<String>,
+    num_contributions: usize,
+}
+
+impl IntoResponse for OrderCommitmentResponse {
+    fn into_response(self) -> Response {
+        (StatusCode::OK, Json(self)).into_response()
+    }
+}
+
+pub async fn order_commitment(
+    Extension(store): Extension<SharedState>,
+    Extension(config): Extension<AppConfig>,
+) -> Result<OrderCommitmentResponse, OrderCommitmentError> {
+    if !config.order_commitment {
+        return Err(OrderCommitmentError::Disabled);
+    }
+    let app_state = store.read().await;
+    Ok(OrderCommitmentResponse {
+        root:              app_state.order_commitment.root().as_ref().map(hash_to_hex),
+        num_contributions: app_state.order_commitment.len(),
+    })
+}
+
+#[derive(Debug, Serialize)]
+pub struct OrderProofResponse {
+    index: usize,
+    root:  String,
+    proof: Vec<ProofStep>,
+}
+
+impl IntoResponse for OrderProofResponse {
+    fn into_response(self) -> Response {
+        (StatusCode::OK, Json(self)).into_response()
+    }
+}
+
+// Returns the inclusion proof for the contribution at `index`. The leaf is
+// recomputed by the participant from their receipt.
+pub async fn order_proof(
+    Path(index): Path<usize>,
+    Extension(store): Extension<SharedState>,
+    Extension(config): Extension<AppConfig>,
+) -> Result<OrderProofResponse, OrderCommitmentError> {
+    if !config.order_commitment {
+        return Err(OrderCommitmentError::Disabled);
+    }
+    let app_state = store.read().await;
+    let commitment = &app_state.order_commitment;
+    let proof = commitment
+        .proof(index)
+        .ok_or(OrderCommitmentError::UnknownIndex)?;
+    let root = commitment
+        .root()
+        .ok_or(OrderCommitmentError::UnknownIndex)?;
+    Ok(OrderProofResponse {
+        index,
+        root: hash_to_hex(&root),
+        proof,
+    })
+}
+
+#[derive(Debug, Serialize)]
+pub struct AttestationChainResponse {
+    // Absent until the first contribution
+    head:              Option<String>,
+    num_contributions: usize,
+}
+
+impl IntoResponse for AttestationChainResponse {
+    fn into_response(self) -> Response {
+        (StatusCode::OK, Json(self)).into_response()
+    }
+}
+
+// The chain hash of the latest attestation. Auditors holding the exported
+// attestation log can check that it ends here, so no attestation was dropped
+// or reordered.
+pub async fn attestation_chain_head(
+    Extension(store): Extension<SharedState>,
+) -> AttestationChainResponse {
+    let app_state = store.read().await;
+    AttestationChainResponse {
+        head:              app_state.attestation_chain_head.clone(),
+        num_contributions: app_state.num_contributions,
+    }
+}
+
+// Caps how many identity lookups each client gets answered per minute, so
+// that `has_contributed` can't be used to enumerate identities
+#[derive(Clone)]
+pub struct LookupLimiter(ClientLimiter);
+
+impl LookupLimiter {
+    pub fn new(per_minute: u32) -> Self {
+        Self(ClientLimiter::new(per_minute))
+    }
+}
+
+pub enum HasContributedError {
+    RateLimited(Duration),
+    Storage(StorageError),
+}
+
+impl IntoResponse for HasContributedError {
+    fn into_response(self) -> Response {
+        match self {
+            Self::RateLimited(retry_after) => {
+                let body = Json(json!({ "error": "too many lookups, try again later" }));
+                let retry_after = retry_after.as_secs_f64().ceil().to_string();
+                (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    [(RETRY_AFTER, retry_after)],
+                    body,
+                )
+                    .into_response()
+            }
+            Self::Storage(error) => error.into_response(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HasContributedQuery {
+    // Hex encoded identity hash, as in the attestation export
+    id: String,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct HasContributedResponse {
+    contributed:        bool,
+    // Absent unless the identity contributed
+    contribution_index: Option<i64>,
+    // The ceremony phase the contribution index is counted in
+    phase:              Option<i64>,
+    attested_at:        Option<DateTime<Utc>>,
+}
+
+impl IntoResponse for HasContributedResponse {
+    fn into_response(self) -> Response {
+        (StatusCode::OK, Json(self)).into_response()
+    }
+}
+
+pub async fn has_contributed(
+    ConnectInfo(client): ConnectInfo<SocketAddr>,
+    Query(query): Query<HasContributedQuery>,
+    Extension(storage): Extension<PersistentStorage>,
+    Extension(limiter): Extension<LookupLimiter>,
+) -> Result<HasContributedResponse, HasContributedError> {
+    limiter
+        .0
+        .try_acquire(client.ip())
+        .map_err(HasContributedError::RateLimited)?;
+    let identity_hash = query.id.trim_start_matches("0x").to_ascii_lowercase();
+    let attestation = storage
+        .attestation_of(&identity_hash)
+        .await
+        .map_err(HasContributedError::Storage)?;
+    Ok(HasContributedResponse {
+        contributed:        attestation.is_some(),
+        contribution_index: attestation
+            .as_ref()
+            .map(|attestation| attestation.contribution_index),
+        phase:              attestation.as_ref().map(|attestation| attestation.phase),
+        attested_at:        attestation.map(|attestation| attestation.attested_at),
+    })
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct CeremonySize {
+    num_g1_powers: usize,
+    num_g2_powers: usize,
+}
+
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct ParametersResponse {
+    compute_deadline_sec:        u64,
+    lobby_checkin_frequency_sec: u64,
+    lobby_checkin_tolerance_sec: u64,
+    ceremony_sizes:              Vec<CeremonySize>,
+    point_encoding:              &'static str,
+    point_endianness:            &'static str,
+    selection_policy:            &'static str,
+    transcript_hash_alg:         HashAlgorithm,
+}
+
+impl IntoResponse for ParametersResponse {
+    fn into_response(self) -> Response {
+        (StatusCode::OK, Json(self)).into_response()
+    }
+}
+
+// Returns the ceremony constants clients need to configure themselves
+#[allow(clippy::unused_async)] // Required for axum function signature
+pub async fn parameters(Extension(config): Extension<AppConfig>) -> ParametersResponse {
+    ParametersResponse {
+        compute_deadline_sec:        config.effective_compute_deadline().as_secs(),
+        lobby_checkin_frequency_sec: config.lobby_checkin_frequency.as_secs(),
+        lobby_checkin_tolerance_sec: config.lobby_checkin_tolerance.as_secs(),
+        ceremony_sizes:              config
+            .ceremony_sizes
+            .iter()
+            .map(|(num_g1_powers, num_g2_powers)| CeremonySize {
+                num_g1_powers: *num_g1_powers,
+                num_g2_powers: *num_g2_powers,
+            })
+            .collect(),
+        point_encoding:              POINT_ENCODING,
+        point_endianness:            POINT_ENDIANNESS,
+        selection_policy:            SELECTION_POLICY,
+        transcript_hash_alg:         config.transcript_hash_algorithm,
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -61,17 +764,813 @@ pub struct JwtInfoResponse {
 
 impl IntoResponse for JwtInfoResponse {
     fn into_response(self) -> Response {
-        (StatusCode::OK, ErasedJson::pretty(self)).into_response()
+        (StatusCode::OK, Json(self)).into_response()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum KeysError {
+    // The signing keys are loaded during startup, requests can arrive before
+    KeysNotReady,
+    Signing,
+}
+
+impl IntoResponse for KeysError {
+    fn into_response(self) -> Response {
+        let (status, message) = match self {
+            Self::KeysNotReady => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "signing keys are not loaded yet",
+            ),
+            Self::Signing => (StatusCode::INTERNAL_SERVER_ERROR, "could not sign"),
+        };
+        let body = Json(json!({ "error": message }));
+        (status, body).into_response()
     }
 }
 
 // Returns the relevant JWT information
 #[allow(clippy::unused_async)] // Required for axum function signature
-pub async fn jwt_info() -> JwtInfoResponse {
-    let rsa_public_key_pem_as_string = KEYS.get().unwrap().decode_key_to_string();
+pub async fn jwt_info() -> Result<JwtInfoResponse, KeysError> {
+    jwt_info_from(KEYS.get())
+}
 
-    JwtInfoResponse {
+fn jwt_info_from(keys: Option<&Keys>) -> Result<JwtInfoResponse, KeysError> {
+    let keys = keys.ok_or(KeysError::KeysNotReady)?;
+    Ok(JwtInfoResponse {
         alg:         Keys::alg_str(),
-        rsa_pem_key: rsa_public_key_pem_as_string,
+        rsa_pem_key: keys.decode_key_to_string(),
+    })
+}
+
+// Readiness probe, fails until the server can sign and verify tokens
+#[allow(clippy::unused_async)] // Required for axum function signature
+pub async fn ready() -> Result<&'static str, KeysError> {
+    KEYS.get().map(|_| "ready").ok_or(KeysError::KeysNotReady)
+}
+
+// A small summary of the ceremony, which external parties can reference
+// instead of the full transcript
+#[derive(Debug, Serialize, PartialEq, Eq)]
+pub struct CeremonyManifest {
+    ceremony_sizes:    Vec<CeremonySize>,
+    num_contributions: usize,
+    phase:             i64,
+    transcript_hash:   TranscriptHash,
+    // Identifies the key the manifest is signed with, see `Keys::key_id`
+    sequencer_key_id:  String,
+    // Unset until the ceremony is finalized by draining it
+    finalized_at:      Option<DateTime<Utc>>,
+}
+
+// A manifest together with the sequencer's signature over its canonical
+// encoding, see `canonical_json`
+#[derive(Debug, Serialize)]
+pub struct SignedManifest {
+    manifest:  CeremonyManifest,
+    signature: String,
+}
+
+impl IntoResponse for SignedManifest {
+    fn into_response(self) -> Response {
+        (StatusCode::OK, Json(self)).into_response()
+    }
+}
+
+// Describes the ceremony as it currently is, so once it is finalized the
+// manifest is final too
+pub async fn manifest(
+    Extension(store): Extension<SharedState>,
+    Extension(config): Extension<AppConfig>,
+) -> Result<SignedManifest, KeysError> {
+    let keys = KEYS.get().ok_or(KeysError::KeysNotReady)?;
+    let manifest = {
+        let app_state = store.read().await;
+        CeremonyManifest {
+            ceremony_sizes:    config
+                .ceremony_sizes
+                .iter()
+                .map(|(num_g1_powers, num_g2_powers)| CeremonySize {
+                    num_g1_powers: *num_g1_powers,
+                    num_g2_powers: *num_g2_powers,
+                })
+                .collect(),
+            num_contributions: app_state.num_contributions,
+            phase:             app_state.phase.as_ref().map_or(0, |phase| phase.phase),
+            transcript_hash:   app_state.transcript_hash.clone(),
+            sequencer_key_id:  keys.key_id(),
+            finalized_at:      app_state.finalized_at,
+        }
+    };
+    let signature = keys
+        .sign_canonical(&manifest)
+        .map_err(|_| KeysError::Signing)?;
+    Ok(SignedManifest {
+        manifest,
+        signature,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        data::transcript::write_transcript_file,
+        keys::canonical_json,
+        test_transcript::TestContribution,
+        test_util::{create_test_session_info, init_keys, response_body, test_config},
+        SessionId, TestTranscript,
+    };
+    use std::path::PathBuf;
+    use tokio::sync::RwLock;
+
+    #[tokio::test]
+    async fn current_state_head_describes_transcript() {
+        let mut transcript_file = std::env::temp_dir();
+        transcript_file.push("current_state_head.json");
+        std::fs::write(&transcript_file, b"hello").unwrap();
+        let config = AppConfig {
+            transcript_file,
+            ..test_config()
+        };
+
+        let response = current_state_head(Extension(config)).await;
+
+        let sha256_hello = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_LENGTH], "5");
+        assert_eq!(
+            response.headers()[ETAG],
+            format!("\"{}\"", sha256_hello).as_str()
+        );
+        assert_eq!(
+            response.headers()["x-transcript-hash"],
+            format!("0x{}", sha256_hello).as_str()
+        );
+        assert!(response_body(response).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn parameters_match_config() {
+        let config = AppConfig {
+            compute_deadline: Duration::from_secs(300),
+            ceremony_sizes: vec![(16, 4)],
+            ..test_config()
+        };
+
+        let response = parameters(Extension(config.clone())).await;
+
+        assert_eq!(response, ParametersResponse {
+            compute_deadline_sec:        300,
+            lobby_checkin_frequency_sec: config.lobby_checkin_frequency.as_secs(),
+            lobby_checkin_tolerance_sec: config.lobby_checkin_tolerance.as_secs(),
+            ceremony_sizes:              vec![CeremonySize {
+                num_g1_powers: 16,
+                num_g2_powers: 4,
+            }],
+            point_encoding:              POINT_ENCODING,
+            point_endianness:            POINT_ENDIANNESS,
+            selection_policy:            SELECTION_POLICY,
+            transcript_hash_alg:         HashAlgorithm::Sha256,
+        });
+    }
+
+    #[tokio::test]
+    async fn jwt_info_without_keys_is_unavailable() {
+        // `KEYS` is process wide and other tests load it, so pass it explicitly
+        let response = jwt_info_from(None).into_response();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        init_keys().await;
+        let response = jwt_info_from(KEYS.get()).unwrap();
+        assert_eq!(response.alg, Keys::alg_str());
+    }
+
+    #[tokio::test]
+    async fn dashboard_matches_individual_endpoints() {
+        let store = SharedState::default();
+        {
+            let mut state = store.write().await;
+            for _ in 0..3 {
+                state
+                    .lobby
+                    .insert(SessionId::new(), create_test_session_info(100));
+            }
+            state.num_contributions = 5;
+            state.num_expired = 2;
+            state.compute_times = vec![Duration::from_secs(10), Duration::from_secs(30)];
+        }
+        let mut transcript_file = std::env::temp_dir();
+        transcript_file.push("dashboard_transcript.json");
+        std::fs::write(&transcript_file, b"{}").unwrap();
+        let config = AppConfig {
+            transcript_file,
+            ..test_config()
+        };
+
+        let dashboard = dashboard(Extension(store.clone()), Extension(config)).await;
+        let status = status(Extension(store), Extension(test_config())).await;
+
+        assert_eq!(dashboard.lobby_size, status.lobby_size);
+        assert_eq!(dashboard.num_contributions, status.num_contributions);
+        assert_eq!(dashboard.num_expired, 2);
+        assert_eq!(dashboard.ceremony_status, "waiting_for_participant");
+        assert_eq!(dashboard.estimated_wait_sec, 60);
+        assert_eq!(dashboard.transcript_size_bytes, Some(2));
+    }
+
+    #[tokio::test]
+    async fn dashboard_previews_head_of_lobby() {
+        tokio::time::pause();
+        let store = SharedState::default();
+        let sessions = (0..3).map(|_| SessionId::new()).collect::<Vec<_>>();
+        for session_id in &sessions {
+            store
+                .write()
+                .await
+                .lobby
+                .insert(session_id.clone(), create_test_session_info(100));
+            tokio::time::advance(Duration::from_secs(10)).await;
+        }
+
+        let preview = dashboard(Extension(store.clone()), Extension(test_config())).await;
+        assert_eq!(
+            preview.next_in_line,
+            Some(NextInLine {
+                handle:      sessions[0].public_handle(),
+                waiting_sec: 30,
+            })
+        );
+
+        assert!(store
+            .write()
+            .await
+            .try_set_current_contributor(sessions[0].clone(), Duration::from_secs(180)));
+        let preview = dashboard(Extension(store), Extension(test_config())).await;
+        assert_eq!(
+            preview.next_in_line,
+            Some(NextInLine {
+                handle:      sessions[1].public_handle(),
+                waiting_sec: 20,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn dashboard_estimates_completion_from_throughput() {
+        let store = SharedState::default();
+        {
+            let mut state = store.write().await;
+            state.num_contributions = 5;
+            state.compute_times = vec![Duration::from_secs(10), Duration::from_secs(30)];
+        }
+
+        let unbounded = dashboard(Extension(store.clone()), Extension(test_config())).await;
+        assert_eq!(unbounded.estimated_completion_time, None);
+
+        let config = AppConfig {
+            max_contributions: Some(10),
+            ..test_config()
+        };
+        let before = Utc::now();
+        let capped = dashboard(Extension(store.clone()), Extension(config.clone())).await;
+        let after = Utc::now();
+        // Five more contributions, at 20 seconds each
+        let eta = capped.estimated_completion_time.unwrap();
+        assert!(eta >= before + chrono::Duration::seconds(100));
+        assert!(eta <= after + chrono::Duration::seconds(100));
+
+        store.write().await.num_contributions = 12;
+        let done = dashboard(Extension(store), Extension(config)).await;
+        assert!(done.estimated_completion_time.unwrap() <= Utc::now());
+    }
+
+    #[tokio::test]
+    async fn transcript_signature_verifies_until_tampered() {
+        init_keys().await;
+        let dir = std::env::temp_dir();
+        let config = AppConfig {
+            transcript_file: dir.join("signed_transcript.json"),
+            transcript_in_progress_file: dir.join("signed_transcript.json.new"),
+            transcript_signature_file: dir.join("signed_transcript.json.sig"),
+            ..test_config()
+        };
+        let transcript = SharedTranscript::<TestTranscript>::default();
+        write_transcript_file(
+            config.transcript_file.clone(),
+            config.transcript_in_progress_file.clone(),
+            config.transcript_signature_file.clone(),
+            config.transcript_objects.clone(),
+            transcript.clone(),
+        )
+        .await;
+
+        let response = transcript_signature(Extension(config.clone())).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let signature: TranscriptSignature =
+            serde_json::from_slice(&response_body(response).await).unwrap();
+
+        let keys = KEYS.get().unwrap();
+        let transcript = transcript.read().await;
+        assert!(transcript.verify_signature(keys, &signature));
+        let (_, file_hash) = transcript_file_digest(config.transcript_file)
+            .await
+            .unwrap();
+        assert_eq!(signature.transcript_hash, file_hash);
+
+        let tampered = transcript.update(&TestContribution::ValidContribution(1));
+        assert!(!tampered.verify_signature(keys, &signature));
+
+        // The served transcript carries the same signature
+        let served = current_state(
+            HeaderMap::new(),
+            Extension(config),
+            Extension(SharedTranscript::<TestTranscript>::default()),
+        )
+        .await;
+        assert_eq!(
+            served.headers()["x-transcript-signature"],
+            signature.signature.as_str()
+        );
+        assert_eq!(
+            served.headers()["x-transcript-hash"],
+            signature.transcript_hash.hash.as_str()
+        );
+    }
+
+    #[test]
+    fn negotiates_transcript_format() {
+        assert_eq!(
+            TranscriptFormat::from_accept(None),
+            Some(TranscriptFormat::Raw)
+        );
+        assert_eq!(
+            TranscriptFormat::from_accept(Some("*/*")),
+            Some(TranscriptFormat::Raw)
+        );
+        assert_eq!(
+            TranscriptFormat::from_accept(Some("text/html, application/json;q=0.9")),
+            Some(TranscriptFormat::Json)
+        );
+        assert_eq!(
+            TranscriptFormat::from_accept(Some("application/x-kzg-binary")),
+            Some(TranscriptFormat::Binary)
+        );
+        assert_eq!(TranscriptFormat::from_accept(Some("text/html")), None);
+    }
+
+    async fn current_state_with_accept(accept: Option<&'static str>) -> Response {
+        let mut transcript_file = std::env::temp_dir();
+        transcript_file.push("current_state_negotiation.json");
+        std::fs::write(&transcript_file, b"raw transcript").unwrap();
+        let config = AppConfig {
+            transcript_file,
+            ..test_config()
+        };
+        let transcript = SharedTranscript::<TestTranscript>::default();
+        transcript
+            .write()
+            .await
+            .contributions
+            .push(TestContribution::ValidContribution(7));
+        let mut headers = HeaderMap::new();
+        if let Some(accept) = accept {
+            headers.insert(ACCEPT, accept.parse().unwrap());
+        }
+        current_state(headers, Extension(config), Extension(transcript)).await
+    }
+
+    #[tokio::test]
+    async fn current_state_serves_negotiated_formats() {
+        let expected = TestTranscript {
+            initial:       TestContribution::ValidContribution(0),
+            contributions: vec![TestContribution::ValidContribution(7)],
+        };
+
+        let raw = current_state_with_accept(None).await;
+        assert_eq!(raw.status(), StatusCode::OK);
+        assert_eq!(raw.headers()[CONTENT_TYPE], "application/octet-stream");
+        assert_eq!(response_body(raw).await, b"raw transcript");
+
+        let json = current_state_with_accept(Some("application/json")).await;
+        assert_eq!(json.headers()[CONTENT_TYPE], "application/json");
+        let body = response_body(json).await;
+        assert_eq!(
+            serde_json::from_slice::<TestTranscript>(&body).unwrap(),
+            expected
+        );
+
+        let binary = current_state_with_accept(Some("application/x-kzg-binary")).await;
+        assert_eq!(binary.headers()[CONTENT_TYPE], "application/x-kzg-binary");
+        let body = response_body(binary).await;
+        assert_eq!(
+            bincode::deserialize::<TestTranscript>(&body).unwrap(),
+            expected
+        );
+
+        let unsupported = current_state_with_accept(Some("text/html")).await;
+        assert_eq!(unsupported.status(), StatusCode::NOT_ACCEPTABLE);
+    }
+
+    #[tokio::test]
+    async fn current_state_tells_uninitialized_transcript_from_io_error() {
+        let request = |transcript_file: PathBuf, missing_transcript, contributions| {
+            let config = AppConfig {
+                transcript_file,
+                missing_transcript,
+                ..test_config()
+            };
+            let transcript = SharedTranscript::new(RwLock::new(TestTranscript {
+                contributions,
+                ..TestTranscript::default()
+            }));
+            current_state(HeaderMap::new(), Extension(config), Extension(transcript))
+        };
+        let missing = std::env::temp_dir().join("current_state_missing.json");
+        std::fs::remove_file(&missing).ok();
+
+        let initial = request(missing.clone(), MissingTranscript::Initial, vec![]).await;
+        assert_eq!(initial.status(), StatusCode::OK);
+        assert_eq!(initial.headers()["x-transcript-state"], "uninitialized");
+        let body = response_body(initial).await;
+        assert_eq!(
+            serde_json::from_slice::<TestTranscript>(&body).unwrap(),
+            TestTranscript::default()
+        );
+
+        let empty = request(missing.clone(), MissingTranscript::NoContent, vec![]).await;
+        assert_eq!(empty.status(), StatusCode::NO_CONTENT);
+        assert_eq!(empty.headers()["x-transcript-state"], "uninitialized");
+        assert!(response_body(empty).await.is_empty());
+
+        // The file went missing after contributions were accepted
+        let lost = request(missing, MissingTranscript::Initial, vec![
+            TestContribution::ValidContribution(7),
+        ])
+        .await;
+        assert_eq!(lost.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        // The path can't be opened at all, as its parent is a file
+        let parent = std::env::temp_dir().join("current_state_not_a_directory");
+        std::fs::write(&parent, b"").unwrap();
+        let unreadable = request(
+            parent.join("transcript.json"),
+            MissingTranscript::Initial,
+            vec![],
+        )
+        .await;
+        assert_eq!(unreadable.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(!unreadable.headers().contains_key("x-transcript-state"));
+        assert_eq!(
+            response_body(unreadable).await,
+            b"could not open transcript file"
+        );
+    }
+
+    #[tokio::test]
+    async fn current_state_streams_transcript_from_object_storage() {
+        use object_store::{memory::InMemory, path::Path as ObjectPath};
+
+        let objects = TranscriptObjects::new(
+            Arc::new(InMemory::new()),
+            ObjectPath::from("ceremony/transcript.json"),
+        );
+        let transcript_file = std::env::temp_dir().join("object_served_transcript.json");
+        std::fs::write(&transcript_file, b"local transcript").unwrap();
+        let config = AppConfig {
+            transcript_file: transcript_file.clone(),
+            transcript_objects: Some(objects.clone()),
+            ..test_config()
+        };
+        let request = |range: Option<&'static str>| {
+            let mut headers = HeaderMap::new();
+            if let Some(range) = range {
+                headers.insert(RANGE, range.parse().unwrap());
+            }
+            current_state(
+                headers,
+                Extension(config.clone()),
+                Extension(SharedTranscript::<TestTranscript>::default()),
+            )
+        };
+
+        // Nothing was uploaded yet
+        let response = request(None).await;
+        assert_eq!(response_body(response).await, b"local transcript");
+
+        let uploaded = std::env::temp_dir().join("object_uploaded_transcript.json");
+        std::fs::write(&uploaded, b"transcript in the bucket").unwrap();
+        let (_, hash) = transcript_file_digest(uploaded.clone()).await.unwrap();
+        objects.put_file(&uploaded, &hash).await.unwrap();
+
+        let response = request(None).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_LENGTH], "24");
+        assert_eq!(response.headers()[ACCEPT_RANGES], "bytes");
+        assert_eq!(response_body(response).await, b"transcript in the bucket");
+
+        let response = request(Some("bytes=14-")).await;
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.headers()[CONTENT_RANGE], "bytes 14-23/24");
+        assert_eq!(response_body(response).await, b"the bucket");
+
+        let response = request(Some("bytes=-6")).await;
+        assert_eq!(response_body(response).await, b"bucket");
+
+        let response = request(Some("bytes=30-40")).await;
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(response.headers()[CONTENT_RANGE], "bytes */24");
+    }
+
+    #[tokio::test]
+    async fn current_state_never_serves_partial_transcript() {
+        use crate::data::transcript::TRANSCRIPT_COMMIT;
+
+        let mut transcript_file = std::env::temp_dir();
+        transcript_file.push("current_state_partial.json");
+        std::fs::write(&transcript_file, b"committed transcript").unwrap();
+        let config = AppConfig {
+            transcript_file: transcript_file.clone(),
+            ..test_config()
+        };
+        let request = || {
+            current_state(
+                HeaderMap::new(),
+                Extension(config.clone()),
+                Extension(SharedTranscript::<TestTranscript>::default()),
+            )
+        };
+
+        // A write in progress that has only written part of the file
+        let commit = TRANSCRIPT_COMMIT.write().await;
+        std::fs::write(&transcript_file, b"updated tr").unwrap();
+        let response = request().await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[RETRY_AFTER], "1");
+
+        std::fs::write(&transcript_file, b"updated transcript").unwrap();
+        drop(commit);
+        let response = request().await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_LENGTH], "18");
+
+        // Replacing the file while it is streamed doesn't affect the body
+        let mut replacement = transcript_file.clone();
+        replacement.set_extension("replacement");
+        std::fs::write(&replacement, b"newer").unwrap();
+        std::fs::rename(&replacement, &transcript_file).unwrap();
+        assert_eq!(response_body(response).await, b"updated transcript");
+    }
+
+    #[tokio::test]
+    async fn order_proof_verifies_against_published_root() {
+        use crate::merkle::{verify_proof, OrderCommitment};
+
+        let store = SharedState::default();
+        for receipt in ["receipt a", "receipt b", "receipt c"] {
+            store
+                .write()
+                .await
+                .order_commitment
+                .push(receipt.as_bytes());
+        }
+        let config = AppConfig {
+            order_commitment: true,
+            ..test_config()
+        };
+
+        let commitment = order_commitment(Extension(store.clone()), Extension(config.clone()))
+            .await
+            .ok()
+            .unwrap();
+        let proof = order_proof(Path(1), Extension(store.clone()), Extension(config.clone()))
+            .await
+            .ok()
+            .unwrap();
+        assert_eq!(commitment.root.as_ref(), Some(&proof.root));
+        assert_eq!(commitment.num_contributions, 3);
+
+        let root = store.read().await.order_commitment.root().unwrap();
+        let leaf = OrderCommitment::leaf(1, b"receipt b");
+        assert!(verify_proof(leaf, &proof.proof, root));
+        let forged = OrderCommitment::leaf(1, b"receipt x");
+        assert!(!verify_proof(forged, &proof.proof, root));
+
+        assert!(matches!(
+            order_proof(Path(3), Extension(store.clone()), Extension(config)).await,
+            Err(OrderCommitmentError::UnknownIndex)
+        ));
+        assert!(matches!(
+            order_proof(Path(0), Extension(store), Extension(test_config())).await,
+            Err(OrderCommitmentError::Disabled)
+        ));
+    }
+
+    const ALICE: [u8; 4] = [10, 0, 0, 1];
+    const BOB: [u8; 4] = [10, 0, 0, 2];
+
+    async fn lookup(
+        storage: &PersistentStorage,
+        limiter: &LookupLimiter,
+        client: [u8; 4],
+        id: &str,
+    ) -> Result<HasContributedResponse, HasContributedError> {
+        has_contributed(
+            ConnectInfo(SocketAddr::from((client, 443))),
+            Query(HasContributedQuery { id: id.to_string() }),
+            Extension(storage.clone()),
+            Extension(limiter.clone()),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn reports_participation_by_identity_hash() {
+        use crate::{
+            attestation_chain::GENESIS,
+            storage::{test_storage_client_with_key, Attestation},
+        };
+        use ring::digest::{digest, SHA256};
+
+        let storage = test_storage_client_with_key(Some(&[7; 32])).await;
+        let attestation = Attestation::new(
+            0,
+            0,
+            storage.identity_hash("github | alice"),
+            "Github",
+            vec!["0xa1".to_string()],
+            None,
+            GENESIS,
+        );
+        storage
+            .insert_attestation(&attestation, None, None)
+            .await
+            .unwrap();
+        let limiter = LookupLimiter::new(10);
+        let alice = storage.identity_hash("github | alice");
+        let bob = storage.identity_hash("github | bob");
+        // The identity can't be found by hashing it without the key
+        let unkeyed = hex::encode(digest(&SHA256, b"github | alice"));
+        assert_ne!(alice, unkeyed);
+        assert!(
+            !lookup(&storage, &limiter, ALICE, &unkeyed)
+                .await
+                .ok()
+                .unwrap()
+                .contributed
+        );
+
+        for id in [alice.clone(), format!("0x{}", alice.to_uppercase())] {
+            let response = lookup(&storage, &limiter, ALICE, &id).await.ok().unwrap();
+            assert!(response.contributed);
+            assert_eq!(response.contribution_index, Some(0));
+            assert_eq!(response.phase, Some(0));
+            assert!(response.attested_at.is_some());
+        }
+        assert_eq!(
+            lookup(&storage, &limiter, ALICE, &bob).await.ok().unwrap(),
+            HasContributedResponse {
+                contributed:        false,
+                contribution_index: None,
+                phase:              None,
+                attested_at:        None,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn identity_lookups_are_rate_limited() {
+        use crate::storage::test_storage_client;
+
+        let storage = test_storage_client().await;
+        let limiter = LookupLimiter::new(3);
+        for i in 0..3 {
+            assert!(lookup(&storage, &limiter, ALICE, &format!("{:064x}", i))
+                .await
+                .is_ok());
+        }
+        // Other clients have a limit of their own
+        assert!(lookup(&storage, &limiter, BOB, &format!("{:064x}", 0))
+            .await
+            .is_ok());
+        let response = lookup(&storage, &limiter, ALICE, &format!("{:064x}", 3)).await;
+        assert!(matches!(
+            response,
+            Err(HasContributedError::RateLimited(retry_after))
+                if retry_after <= Duration::from_secs(60)
+        ));
+        let response = response.err().unwrap().into_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(response.headers().contains_key(RETRY_AFTER));
+    }
+
+    #[tokio::test]
+    async fn manifest_is_signed_and_records_finalization() {
+        use crate::api::v1::admin::Drain;
+
+        init_keys().await;
+        let store = SharedState::default();
+        store.write().await.num_contributions = 4;
+        let config = AppConfig {
+            ceremony_sizes: vec![(16, 4)],
+            ..test_config()
+        };
+
+        let signed = manifest(Extension(store.clone()), Extension(config.clone()))
+            .await
+            .unwrap();
+        assert_eq!(signed.manifest.num_contributions, 4);
+        assert_eq!(signed.manifest.ceremony_sizes, vec![CeremonySize {
+            num_g1_powers: 16,
+            num_g2_powers: 4,
+        }]);
+        assert_eq!(signed.manifest.finalized_at, None);
+        let keys = KEYS.get().unwrap();
+        assert_eq!(signed.manifest.sequencer_key_id, keys.key_id());
+        // Clients verify the canonical encoding of the manifest they received
+        let received = response_body(
+            manifest(Extension(store.clone()), Extension(config.clone()))
+                .await
+                .into_response(),
+        )
+        .await;
+        let received = serde_json::from_slice::<serde_json::Value>(&received).unwrap();
+        assert!(keys.verify(
+            received["signature"].as_str().unwrap(),
+            &canonical_json(&received["manifest"])
+        ));
+        assert!(keys.verify(&signed.signature, &canonical_json(&signed.manifest)));
+
+        store.write().await.start_drain(Drain {
+            reason: "done".to_string(),
+            since:  Utc::now(),
+        });
+        let finalized = manifest(Extension(store), Extension(config)).await.unwrap();
+        assert!(finalized.manifest.finalized_at.is_some());
+        let message = canonical_json(&finalized.manifest);
+        assert!(keys.verify(&finalized.signature, &message));
+        assert!(!keys.verify(&signed.signature, &message));
+    }
+
+    #[tokio::test]
+    async fn counters_follow_the_ceremony() {
+        use crate::{
+            api::v1::lobby::{try_contribute, ClientVersion, TryContributeError},
+            test_util::TestCeremony,
+        };
+
+        init_keys().await;
+        let ceremony = TestCeremony::new("counters").await;
+        let store = &ceremony.store;
+        let waiting = SessionId::new();
+        let participant = SessionId::new();
+        {
+            let mut state = store.write().await;
+            state
+                .lobby
+                .insert(waiting.clone(), create_test_session_info(100));
+            state.participant = Some((participant.clone(), create_test_session_info(200)));
+        }
+
+        // The waiting session checks in three times in a row
+        let check_in = || {
+            try_contribute(
+                waiting.clone(),
+                ClientVersion(None),
+                Extension(store.clone()),
+                Extension(ceremony.storage.clone()),
+                Extension(ceremony.transcript.clone()),
+                Extension(ceremony.config.clone()),
+            )
+        };
+        assert!(matches!(
+            check_in().await,
+            Err(TryContributeError::AnotherContributionInProgress(_))
+        ));
+        for _ in 0..2 {
+            assert!(matches!(
+                check_in().await,
+                Err(TryContributeError::RateLimited(_))
+            ));
+        }
+
+        let contributed = ceremony
+            .contribute(participant, TestContribution::ValidContribution(123))
+            .await;
+        assert!(contributed.is_ok());
+
+        {
+            let mut state = store.write().await;
+            let session_id = SessionId::new();
+            state.participant = Some((session_id.clone(), create_test_session_info(300)));
+            assert!(state.expire_current_contributor(&session_id).is_some());
+        }
+
+        assert_eq!(counters(Extension(store.clone())).await, CountersResponse {
+            contributions: 1,
+            expirations:   1,
+            rate_limited:  2,
+            lobby_size:    1,
+        });
     }
 }
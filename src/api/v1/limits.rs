@@ -0,0 +1,179 @@
+use axum::{
+    http::Request,
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use http::{header::ALLOW, HeaderValue, Method, StatusCode};
+use serde_json::json;
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+};
+use tokio::time::{Duration, Instant};
+
+// The methods of endpoints that only read
+pub static READ_METHODS: &[Method] = &[Method::GET, Method::HEAD];
+
+// The methods of endpoints that submit data
+pub static SUBMIT_METHODS: &[Method] = &[Method::POST];
+
+// The methods of resumable uploads, see `upload`
+pub static UPLOAD_METHODS: &[Method] = &[Method::HEAD, Method::POST, Method::PATCH];
+
+// Rejects requests whose headers, names and values together, are larger than
+// `max_bytes`
+pub async fn limit_header_size<B: Send>(
+    max_bytes: usize,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let size = request
+        .headers()
+        .iter()
+        .map(|(name, value)| name.as_str().len() + value.len())
+        .sum::<usize>();
+    if size > max_bytes {
+        let body = Json(json!({ "error": "request headers are too large" }));
+        return (StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE, body).into_response();
+    }
+    next.run(request).await
+}
+
+// Rejects requests with a method other than `allowed`, listing the allowed
+// methods in the `Allow` header
+pub async fn allow_methods<B: Send>(
+    allowed: &'static [Method],
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if allowed.contains(request.method()) {
+        return next.run(request).await;
+    }
+    let allow = allowed
+        .iter()
+        .map(Method::as_str)
+        .collect::<Vec<_>>()
+        .join(", ");
+    let body = Json(json!({ "error": "method not allowed" }));
+    let mut response = (StatusCode::METHOD_NOT_ALLOWED, body).into_response();
+    if let Ok(allow) = HeaderValue::from_str(&allow) {
+        response.headers_mut().insert(ALLOW, allow);
+    }
+    response
+}
+
+// Caps how many requests each client gets answered per minute. Clients are
+// told apart by their address, so one client can't use up the limit of all.
+#[derive(Clone)]
+pub struct ClientLimiter {
+    per_minute: u32,
+    // When each client's current minute started, and its requests in it
+    windows:    Arc<Mutex<HashMap<IpAddr, (Instant, u32)>>>,
+}
+
+impl ClientLimiter {
+    const WINDOW: Duration = Duration::from_secs(60);
+
+    pub fn new(per_minute: u32) -> Self {
+        Self {
+            per_minute,
+            windows: Arc::default(),
+        }
+    }
+
+    // Returns how long until requests of `client` are answered again, if they
+    // are not
+    pub fn try_acquire(&self, client: IpAddr) -> Result<(), Duration> {
+        let mut windows = self.windows.lock().unwrap();
+        let now = Instant::now();
+        // Clients whose minute is over start from scratch anyway
+        windows.retain(|_, (start, _)| now < *start + Self::WINDOW);
+        let (start, count) = windows.entry(client).or_insert((now, 0));
+        if *count >= self.per_minute {
+            return Err(*start + Self::WINDOW - now);
+        }
+        *count += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        body::Body,
+        middleware::from_fn,
+        routing::{get, post},
+        Router,
+    };
+    use tower::ServiceExt;
+
+    fn app() -> Router {
+        let info = Router::new()
+            .route("/info", get(|| async { StatusCode::OK }))
+            .layer(from_fn(|request: Request<Body>, next: Next<Body>| {
+                allow_methods(READ_METHODS, request, next)
+            }));
+        let contribute = Router::new()
+            .route("/contribute", post(|| async { StatusCode::OK }))
+            .layer(from_fn(|request: Request<Body>, next: Next<Body>| {
+                allow_methods(SUBMIT_METHODS, request, next)
+            }));
+        Router::new().merge(info).merge(contribute).layer(from_fn(
+            |request: Request<Body>, next: Next<Body>| limit_header_size(64, request, next),
+        ))
+    }
+
+    async fn send(method: Method, uri: &str, header: &str) -> Response {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("x-padding", header)
+            .body(Body::empty())
+            .unwrap();
+        app().oneshot(request).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn rejects_oversized_headers() {
+        let response = send(Method::GET, "/info", "short").await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = send(Method::GET, "/info", &"x".repeat(64)).await;
+        assert_eq!(
+            response.status(),
+            StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_unexpected_methods_with_allow() {
+        let response = send(Method::POST, "/info", "").await;
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.headers()[ALLOW], "GET, HEAD");
+
+        let response = send(Method::GET, "/contribute", "").await;
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.headers()[ALLOW], "POST");
+
+        let response = send(Method::POST, "/contribute", "").await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn limits_each_client_separately() {
+        let limiter = ClientLimiter::new(2);
+        let alice = IpAddr::from([10, 0, 0, 1]);
+        let bob = IpAddr::from([10, 0, 0, 2]);
+        assert!(limiter.try_acquire(alice).is_ok());
+        assert!(limiter.try_acquire(alice).is_ok());
+        assert_eq!(limiter.try_acquire(alice), Err(Duration::from_secs(60)));
+        assert!(limiter.try_acquire(bob).is_ok());
+
+        tokio::time::advance(Duration::from_secs(60)).await;
+        assert!(limiter.try_acquire(alice).is_ok());
+        assert_eq!(limiter.windows.lock().unwrap().len(), 1);
+    }
+}
@@ -1,24 +1,51 @@
+use async_session::async_trait;
 use axum::{
+    extract::{FromRequest, RequestParts},
     response::{IntoResponse, Response},
     Extension, Json,
 };
-use http::StatusCode;
-use serde::Serialize;
+use chrono::Utc;
+use http::{
+    header::{RETRY_AFTER, USER_AGENT},
+    StatusCode,
+};
+use semver::Version;
+use serde::{Deserialize, Serialize};
 use serde_json::json;
-use tokio::time::{Duration, Instant};
+use std::{cmp::min, convert::Infallible};
+use tokio::{
+    sync::oneshot,
+    time::{Duration, Instant},
+};
 
 use crate::{
-    constants::{COMPUTE_DEADLINE, LOBBY_CHECKIN_FREQUENCY_SEC, LOBBY_CHECKIN_TOLERANCE_SEC},
-    storage::PersistentStorage,
-    SessionId, SharedState, SharedTranscript, Transcript,
+    api::v1::info::Counters,
+    data::hash::TranscriptHash,
+    jwt::{errors::JwtError, ResumeToken},
+    storage::{PersistentStorage, StorageError},
+    AppConfig, SessionId, SharedState, SharedTranscript, Transcript,
 };
 
 #[derive(Debug)]
 #[allow(clippy::large_enum_variant)] // TODO: Discuss this
 pub enum TryContributeError {
     UnknownSessionId,
-    RateLimited,
-    AnotherContributionInProgress,
+    // The session already contributed, this is terminal
+    AlreadyContributed {
+        message:            String,
+        contribution_index: usize,
+        receipt:            String,
+    },
+    SessionExpired,
+    NotAllowed,
+    InviteRequired,
+    // Contains how long until the next check-in is accepted
+    RateLimited(Duration),
+    AnotherContributionInProgress(PollAdvice),
+    Draining,
+    // Contains the url clients should upgrade from, if configured
+    UnsupportedClient(Option<String>),
+    Storage(StorageError),
 }
 
 impl IntoResponse for TryContributeError {
@@ -31,114 +58,594 @@ impl IntoResponse for TryContributeError {
                 (StatusCode::BAD_REQUEST, body)
             }
 
-            Self::RateLimited => {
+            Self::AlreadyContributed {
+                message,
+                contribution_index,
+                receipt,
+            } => {
+                let body = Json(json!({
+                    "error": "already contributed",
+                    "message": message,
+                    "contribution_index": contribution_index,
+                    "receipt": receipt,
+                }));
+                (StatusCode::CONFLICT, body)
+            }
+
+            Self::SessionExpired => {
+                let body = Json(json!({
+                    "error": "session exceeded its maximum lifetime, please sign in again",
+                }));
+                (StatusCode::GONE, body)
+            }
+
+            Self::NotAllowed => {
+                let body = Json(json!({
+                    "error": "identity is not allowed to contribute",
+                }));
+                (StatusCode::FORBIDDEN, body)
+            }
+
+            Self::InviteRequired => {
+                let body = Json(json!({
+                    "error": "an invite code is required, see /lobby/join",
+                }));
+                (StatusCode::FORBIDDEN, body)
+            }
+
+            Self::RateLimited(retry_after) => {
                 let body = Json(json!({
                     "error": "call came too early. rate limited",
                 }));
-                (StatusCode::BAD_REQUEST, body)
+                let retry_after = retry_after.as_secs_f64().ceil().to_string();
+                return (StatusCode::BAD_REQUEST, [(RETRY_AFTER, retry_after)], body)
+                    .into_response();
             }
 
-            Self::AnotherContributionInProgress => {
+            Self::AnotherContributionInProgress(advice) => {
                 let body = Json(json!({
                     "message": "another contribution in progress",
+                    "poll_after_secs": advice.poll_after_secs,
+                    "poll_jitter_secs": advice.poll_jitter_secs,
                 }));
                 (StatusCode::OK, body)
             }
+
+            Self::Draining => {
+                let body = Json(json!({
+                    "error": "sequencer is shutting down and not accepting contributions",
+                }));
+                (StatusCode::SERVICE_UNAVAILABLE, body)
+            }
+
+            Self::UnsupportedClient(upgrade_url) => {
+                let body = Json(json!({
+                    "error": "client version is not supported, please upgrade",
+                    "upgrade_url": upgrade_url,
+                }));
+                (StatusCode::BAD_REQUEST, body)
+            }
+
+            Self::Storage(err) => return err.into_response(),
         };
 
         (status, body).into_response()
     }
 }
 
-#[derive(Debug)]
+// When clients should poll again: after `poll_after_secs`, plus a random
+// delay of up to `poll_jitter_secs` so polls don't arrive in bursts. The
+// interval grows with the lobby, up to where it is considered overloaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct PollAdvice {
+    pub poll_after_secs:  u64,
+    pub poll_jitter_secs: u64,
+}
+
+impl PollAdvice {
+    // Lobby members must check in within the tolerance around the check-in
+    // frequency, so the advice moves from the start of that window towards
+    // its middle, and the jitter keeps them short of its end
+    pub fn for_lobby(lobby_size: usize, config: &AppConfig) -> Self {
+        let tolerance = config.lobby_checkin_tolerance.as_secs();
+        let earliest = config.lobby_checkin_frequency.as_secs() - tolerance;
+        Self {
+            poll_after_secs:  earliest + scale_by_load(tolerance, lobby_size, config),
+            poll_jitter_secs: tolerance / 2,
+        }
+    }
+
+    // Status pollers aren't rate limited, so they are slowed down from once a
+    // second up to once per check-in period
+    pub fn for_status(lobby_size: usize, config: &AppConfig) -> Self {
+        let frequency = config.lobby_checkin_frequency.as_secs().max(1);
+        let poll_after_secs = 1 + scale_by_load(frequency - 1, lobby_size, config);
+        Self {
+            poll_after_secs,
+            poll_jitter_secs: (poll_after_secs / 2).max(1),
+        }
+    }
+}
+
+// `value` scaled by how full the lobby is, relative to its overload size
+fn scale_by_load(value: u64, lobby_size: usize, config: &AppConfig) -> u64 {
+    let overload = config.overload_lobby_size.max(1) as u64;
+    value * (lobby_size as u64).min(overload) / overload
+}
+
+// How a contribution spot was freed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum SlotOutcome {
+    Completed,
+    Invalid,
+    Expired,
+}
+
+#[derive(Debug, Serialize)]
 pub struct TryContributeResponse<C> {
-    contribution: C,
+    // The body is the contribution itself, as it always was, and the fields
+    // below are added next to its own, so existing clients still read it
+    #[serde(flatten)]
+    contribution:       C,
+    // The index this contribution will have in the transcript. This is
+    // prospective: the transcript hash binds the receipt to the actual state.
+    contribution_index: usize,
+    // How the previous spot ended, if there was one
+    last_slot_outcome:  Option<SlotOutcome>,
+    // Hash of the transcript the contribution was taken from, the same as
+    // reported by `/info/status`
+    transcript_hash:    TranscriptHash,
 }
 
 impl<C: Serialize> IntoResponse for TryContributeResponse<C> {
     fn into_response(self) -> Response {
-        (StatusCode::OK, Json(self.contribution)).into_response()
+        (StatusCode::OK, Json(self)).into_response()
+    }
+}
+
+// The version of the contributor client, taken from the `X-Client-Version`
+// header, or else from a `User-Agent` of the form `name/version`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientVersion(pub Option<Version>);
+
+#[async_trait]
+impl<B> FromRequest<B> for ClientVersion
+where
+    B: Send,
+{
+    type Rejection = Infallible;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let headers = req.headers();
+        let version = headers
+            .get("x-client-version")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| Version::parse(value.trim()).ok())
+            .or_else(|| {
+                headers
+                    .get(USER_AGENT)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(user_agent_version)
+            });
+        Ok(Self(version))
     }
 }
 
+fn user_agent_version(user_agent: &str) -> Option<Version> {
+    let product = user_agent.split_whitespace().next()?;
+    let (_, version) = product.split_once('/')?;
+    Version::parse(version).ok()
+}
+
 pub async fn try_contribute<T: Transcript + Send + Sync>(
     session_id: SessionId,
+    client_version: ClientVersion,
     Extension(store): Extension<SharedState>,
     Extension(storage): Extension<PersistentStorage>,
     Extension(transcript): Extension<SharedTranscript<T>>,
+    Extension(config): Extension<AppConfig>,
 ) -> Result<TryContributeResponse<T::ContributionType>, TryContributeError> {
-    let store_clone = store.clone();
-    let app_state = &mut store.write().await;
+    // Turn away clients that are known to produce invalid contributions
+    if let Some(version) = &client_version.0 {
+        if config
+            .denied_client_versions
+            .iter()
+            .any(|denied| denied.matches(version))
+        {
+            return Err(TryContributeError::UnsupportedClient(
+                config.client_upgrade_url,
+            ));
+        }
+    }
+
+    // Admission and first check-ins are persisted, so they are looked up
+    // before taking the lock
+    let entry = store.read().await.lobby.get(&session_id).map(|info| {
+        (
+            info.unique_identifier().to_owned(),
+            info.is_first_ping_attempt,
+            info.joined_at,
+        )
+    });
+    let admitted = match &entry {
+        Some((uid, ..)) if config.require_invite_code => storage
+            .is_admitted(uid)
+            .await
+            .map_err(TryContributeError::Storage)?,
+        _ => !config.require_invite_code,
+    };
+    // A restart forgets the lobby, but not whether the identity already had
+    // its first check-in, nor when it joined. The first check-in is recorded
+    // as soon as it is attempted.
+    let first_checkin = match entry {
+        Some((uid, true, joined_at)) => {
+            let joined_at = Utc::now()
+                - chrono::Duration::from_std(joined_at.elapsed())
+                    .unwrap_or_else(|_| chrono::Duration::zero());
+            let (first, joined_at) = storage
+                .record_first_checkin(&uid, joined_at)
+                .await
+                .map_err(TryContributeError::Storage)?;
+            Some((first, (Utc::now() - joined_at).to_std().unwrap_or_default()))
+        }
+        _ => None,
+    };
+
+    // Only in-memory checks happen under the lock, so that status reads are
+    // not blocked behind storage or transcript access
+    let mut app_state = store.write().await;
+
+    let min_diff = config.lobby_checkin_frequency - config.lobby_checkin_tolerance;
+
+    if let Some((first, since_joined)) = first_checkin {
+        if let Some(info) = app_state.lobby.get_mut(&session_id) {
+            let now = Instant::now();
+            if let Some(joined_at) = now.checked_sub(since_joined) {
+                info.joined_at = info.joined_at.min(joined_at);
+            }
+            // When the first check-in was isn't known, so it counts as just now
+            if !first {
+                info.is_first_ping_attempt = false;
+                info.checkins.check_in(now, config.checkin_burst);
+            }
+        }
+    }
 
     let uid: String;
 
     // 1. Check if this is a valid session. If so, we log the ping time
     {
-        let info = app_state
-            .lobby
-            .get_mut(&session_id)
-            .ok_or(TryContributeError::UnknownSessionId)?;
-
-        let min_diff =
-            Duration::from_secs((LOBBY_CHECKIN_FREQUENCY_SEC - LOBBY_CHECKIN_TOLERANCE_SEC) as u64);
+        let info = match app_state.lobby.get(&session_id) {
+            Some(info) => info,
+            None => {
+                let bundle = app_state
+                    .finished_sessions
+                    .get(&session_id)
+                    .and_then(|(uid, _)| app_state.contribution_bundles.get(uid));
+                return Err(
+                    bundle.map_or(TryContributeError::UnknownSessionId, |bundle| {
+                        TryContributeError::AlreadyContributed {
+                            message:            config.contributed_message.clone(),
+                            contribution_index: bundle.contribution_index,
+                            receipt:            bundle.receipt.clone(),
+                        }
+                    }),
+                );
+            }
+        };
 
         let now = Instant::now();
-        if !info.is_first_ping_attempt && now < info.last_ping_time + min_diff {
-            return Err(TryContributeError::RateLimited);
+        if info.is_past_lifetime(config.session_max_lifetime, now) {
+            return Err(TryContributeError::SessionExpired);
+        }
+        let burst = config.checkin_burst;
+        if !info.in_first_checkin_grace(config.first_checkin_grace, now) {
+            if let Some(wait) = info.checkins.wait(now, min_diff, burst) {
+                Counters::count(&app_state.counters.rate_limited);
+                return Err(TryContributeError::RateLimited(wait));
+            }
+        }
+
+        uid = info.unique_identifier().to_owned();
+
+        // The limit also applies across all sessions of the same identity, so
+        // opening more sessions doesn't allow checking in more often
+        let identity_checkins = app_state.identity_checkins.entry(uid.clone()).or_default();
+        if let Some(wait) = identity_checkins.wait(now, min_diff, burst) {
+            Counters::count(&app_state.counters.rate_limited);
+            return Err(TryContributeError::RateLimited(wait));
         }
+        identity_checkins.check_in(now, burst);
 
+        let info = app_state
+            .lobby
+            .get_mut(&session_id)
+            .expect("session was found above");
         info.is_first_ping_attempt = false;
         info.last_ping_time = now;
+        info.checkins.check_in(now, burst);
+    }
+
+    // The allowlist may have changed since this user joined the lobby
+    if !app_state.is_allowed(&uid) {
+        return Err(TryContributeError::NotAllowed);
+    }
 
-        uid = info.token.unique_identifier().to_owned();
+    if !admitted {
+        return Err(TryContributeError::InviteRequired);
     }
 
-    // Check if there is an existing contribution in progress
-    if app_state.participant.is_some() {
-        return Err(TryContributeError::AnotherContributionInProgress);
+    if app_state.drain.is_some() {
+        return Err(TryContributeError::Draining);
     }
 
+    // This user now reserves this spot, unless there is an existing
+    // contribution in progress. This also removes them from the lobby.
+    let compute_deadline = config.effective_compute_deadline();
+    if !app_state.try_set_current_contributor(session_id.clone(), compute_deadline) {
+        let advice = PollAdvice::for_lobby(app_state.lobby.len(), &config);
+        return Err(TryContributeError::AnotherContributionInProgress(advice));
+    }
+    let slot_released = app_state.slot_released();
+    let contribution_index = app_state.num_contributions;
+    let last_slot_outcome = app_state.last_slot_outcome;
+    // Only the participant can change the transcript, so it stays at this
+    // hash until they contribute
+    let transcript_hash = app_state.transcript_hash.clone();
+    drop(app_state);
+
     // If this insertion fails, worst case we allow multiple contributions from the
     // same participant
     storage.insert_contributor(&uid).await;
 
+    // Start a timer to remove this user if they go over the compute deadline
+    let deadline_task = tokio::spawn(remove_participant_on_deadline(
+        store.clone(),
+        storage,
+        session_id.clone(),
+        uid,
+        compute_deadline,
+        config.compute_heartbeat_timeout,
+        slot_released,
+    ));
     {
-        // This user now reserves this spot. This also removes them from the lobby
-        app_state.set_current_contributor(session_id.clone());
-        // Start a timer to remove this user if they go over the `COMPUTE_DEADLINE`
-        tokio::spawn(async move {
-            remove_participant_on_deadline(store_clone, storage.clone(), session_id, uid).await;
-        });
+        // Kept so that operators can cancel it when expiring the slot early
+        let mut app_state = store.write().await;
+        if app_state.is_participant(&session_id) {
+            app_state.deadline_task = Some(deadline_task);
+        }
     }
 
     let transcript = transcript.read().await;
 
     Ok(TryContributeResponse {
         contribution: transcript.get_contribution(),
+        contribution_index,
+        last_slot_outcome,
+        transcript_hash,
+    })
+}
+
+pub enum ResumeError {
+    InvalidToken,
+    SessionExpired,
+    Jwt(JwtError),
+}
+
+impl IntoResponse for ResumeError {
+    fn into_response(self) -> Response {
+        let (status, body) = match self {
+            Self::InvalidToken => {
+                let body = Json(json!({
+                    "error": "invalid or expired resume token",
+                }));
+                (StatusCode::BAD_REQUEST, body)
+            }
+
+            Self::SessionExpired => {
+                let body = Json(json!({
+                    "error": "session is no longer in the lobby",
+                }));
+                (StatusCode::GONE, body)
+            }
+
+            Self::Jwt(err) => return err.into_response(),
+        };
+
+        (status, body).into_response()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResumePayload {
+    resume_token: String,
+}
+
+pub struct ResumedSession {
+    session_id:   SessionId,
+    resume_token: String,
+    // The current place in the lobby, which may be ahead of the one in the
+    // token that was used
+    position:     usize,
+}
+
+impl IntoResponse for ResumedSession {
+    fn into_response(self) -> Response {
+        Json(json!({
+            "session_id": self.session_id,
+            "resume_token": self.resume_token,
+            "position": self.position,
+        }))
+        .into_response()
+    }
+}
+
+// Restores a lobby session for a client that lost its session id.
+// The session is moved to a fresh id, so a resume token can only be used once
+pub async fn resume(
+    Json(payload): Json<ResumePayload>,
+    Extension(store): Extension<SharedState>,
+    Extension(config): Extension<AppConfig>,
+) -> Result<ResumedSession, ResumeError> {
+    let token = ResumeToken::decode(&payload.resume_token, config.clock_skew)
+        .map_err(|_| ResumeError::InvalidToken)?;
+
+    let mut app_state = store.write().await;
+    let now = Instant::now();
+    if app_state.lobby.get(&token.session_id).map_or(true, |info| {
+        info.is_past_lifetime(config.session_max_lifetime, now)
+    }) {
+        return Err(ResumeError::SessionExpired);
+    }
+    let (index, _, mut info) = app_state
+        .lobby
+        .shift_remove_full(&token.session_id)
+        .expect("session was found above");
+    info.last_ping_time = now;
+
+    let session_id = SessionId::new();
+    app_state
+        .unique_id_session
+        .insert(info.unique_identifier().to_owned(), session_id.clone());
+    // The resumed session keeps its place in the lobby
+    let (last, _) = app_state.lobby.insert_full(session_id.clone(), info);
+    app_state.lobby.move_index(last, index);
+
+    let resume_token = ResumeToken::new(session_id.clone(), index)
+        .encode()
+        .map_err(ResumeError::Jwt)?;
+
+    Ok(ResumedSession {
+        session_id,
+        resume_token,
+        position: index,
     })
 }
 
-// Clears the contribution spot on `COMPUTE_DEADLINE` interval
-// We use the session_id to avoid needing a channel to check if
+pub enum JoinError {
+    UnknownSessionId,
+    SessionExpired,
+    InvalidInvite,
+    Storage(StorageError),
+}
+
+impl IntoResponse for JoinError {
+    fn into_response(self) -> Response {
+        let (status, body) = match self {
+            Self::UnknownSessionId => {
+                let body = Json(json!({
+                    "error": "unknown session id",
+                }));
+                (StatusCode::BAD_REQUEST, body)
+            }
+
+            Self::SessionExpired => {
+                let body = Json(json!({
+                    "error": "session exceeded its maximum lifetime, please sign in again",
+                }));
+                (StatusCode::GONE, body)
+            }
+
+            Self::InvalidInvite => {
+                let body = Json(json!({
+                    "error": "invite code is unknown or has already been used",
+                }));
+                (StatusCode::FORBIDDEN, body)
+            }
+
+            Self::Storage(err) => return err.into_response(),
+        };
+
+        (status, body).into_response()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct JoinPayload {
+    invite_code: String,
+}
+
+// Admits the identity of a lobby session by redeeming a single-use invite
+// code. Only needed when `REQUIRE_INVITE_CODE` is set.
+pub async fn join(
+    session_id: SessionId,
+    Json(payload): Json<JoinPayload>,
+    Extension(store): Extension<SharedState>,
+    Extension(storage): Extension<PersistentStorage>,
+    Extension(config): Extension<AppConfig>,
+) -> Result<StatusCode, JoinError> {
+    let uid = {
+        let app_state = store.read().await;
+        let info = app_state
+            .lobby
+            .get(&session_id)
+            .ok_or(JoinError::UnknownSessionId)?;
+        if info.is_past_lifetime(config.session_max_lifetime, Instant::now()) {
+            return Err(JoinError::SessionExpired);
+        }
+        info.unique_identifier().to_owned()
+    };
+    if !storage
+        .redeem_invite_code(&payload.invite_code, &uid)
+        .await
+        .map_err(JoinError::Storage)?
+    {
+        return Err(JoinError::InvalidInvite);
+    }
+    Ok(StatusCode::OK)
+}
+
+// Clears the contribution spot once `compute_deadline` has passed, or earlier
+// if the participant stops sending heartbeats for `heartbeat_timeout`.
+// Returns as soon as `slot_released` resolves, so timers of participants that
+// are done don't linger until their deadline.
 pub async fn remove_participant_on_deadline(
     state: SharedState,
     storage: PersistentStorage,
     session_id: SessionId,
     uid: String,
+    compute_deadline: Duration,
+    heartbeat_timeout: Option<Duration>,
+    mut slot_released: oneshot::Receiver<()>,
 ) {
-    tokio::time::sleep(Duration::from_secs(COMPUTE_DEADLINE as u64)).await;
+    let granted_deadline = Instant::now() + compute_deadline;
 
-    {
-        // Check if the contributor has already left the position
-        if let Some((participant_session_id, _)) = &state.read().await.participant {
-            //
-            if participant_session_id != &session_id {
+    loop {
+        let (deadline, heartbeat_deadline) = {
+            let app_state = state.read().await;
+            // Check if the contributor has already left the position
+            match &app_state.participant {
+                Some((participant_session_id, session_info))
+                    if participant_session_id == &session_id =>
+                {
+                    // Operators may have extended the deadline since it was
+                    // granted
+                    // Until the first heartbeat, the last lobby check-in is
+                    // from before the spot was granted, and doesn't count
+                    let last_heartbeat = app_state
+                        .participant_granted_at
+                        .map_or(session_info.last_ping_time, |granted_at| {
+                            granted_at.max(session_info.last_ping_time)
+                        });
+                    (
+                        app_state.participant_deadline.unwrap_or(granted_deadline),
+                        heartbeat_timeout.map(|timeout| last_heartbeat + timeout),
+                    )
+                }
                 // Abort, this means that the participant has already contributed and
                 // the /contribute endpoint has removed them from the contribution spot
-                return;
+                _ => return,
             }
-        } else {
-            return;
+        };
+
+        let wake_at = heartbeat_deadline.map_or(deadline, |heartbeat| min(heartbeat, deadline));
+        if Instant::now() >= wake_at {
+            break;
+        }
+        tokio::select! {
+            () = tokio::time::sleep_until(wake_at) => {}
+            // The participant contributed, or an operator freed the spot
+            _ = &mut slot_released => return,
         }
     }
 
@@ -147,15 +654,23 @@ pub async fn remove_participant_on_deadline(
         &session_id.to_string()
     );
 
-    storage.expire_contribution(&uid).await;
-    state.write().await.clear_current_contributor();
+    if state
+        .write()
+        .await
+        .expire_current_contributor(&session_id)
+        .is_some()
+    {
+        storage.expire_contribution(&uid).await;
+    }
 }
 
 #[tokio::test]
 async fn lobby_try_contribute_test() {
     use crate::{
-        storage::test_storage_client, test_transcript::TestContribution,
-        test_util::create_test_session_info, TestTranscript,
+        storage::test_storage_client,
+        test_transcript::TestContribution,
+        test_util::{create_test_session_info, test_config},
+        TestTranscript,
     };
 
     let shared_state = SharedState::default();
@@ -171,9 +686,11 @@ async fn lobby_try_contribute_test() {
     // no users in lobby
     let unknown_session_response = try_contribute(
         session_id.clone(),
+        ClientVersion(None),
         Extension(shared_state.clone()),
         Extension(db.clone()),
         Extension(transcript.clone()),
+        Extension(test_config()),
     )
     .await;
     assert!(matches!(
@@ -187,44 +704,52 @@ async fn lobby_try_contribute_test() {
         state
             .lobby
             .insert(session_id.clone(), create_test_session_info(100));
+        let mut other_session_info = create_test_session_info(100);
+        other_session_info.token.as_mut().unwrap().sub = "bar".to_string();
         state
             .lobby
-            .insert(other_session_id.clone(), create_test_session_info(100));
+            .insert(other_session_id.clone(), other_session_info);
     }
 
     // "other participant" is contributing
     try_contribute(
         other_session_id.clone(),
+        ClientVersion(None),
         Extension(shared_state.clone()),
         Extension(db.clone()),
         Extension(transcript.clone()),
+        Extension(test_config()),
     )
     .await
     .ok();
     let contribution_in_progress_response = try_contribute(
         session_id.clone(),
+        ClientVersion(None),
         Extension(shared_state.clone()),
         Extension(db.clone()),
         Extension(transcript.clone()),
+        Extension(test_config()),
     )
     .await;
     assert!(matches!(
         contribution_in_progress_response,
-        Err(TryContributeError::AnotherContributionInProgress)
+        Err(TryContributeError::AnotherContributionInProgress(_))
     ));
 
     // call the endpoint too soon - rate limited, other participant computing
     tokio::time::advance(Duration::from_secs(5)).await;
     let too_soon_response = try_contribute(
         session_id.clone(),
+        ClientVersion(None),
         Extension(shared_state.clone()),
         Extension(db.clone()),
         Extension(transcript.clone()),
+        Extension(test_config()),
     )
     .await;
     assert!(matches!(
         too_soon_response,
-        Err(TryContributeError::RateLimited)
+        Err(TryContributeError::RateLimited(_))
     ));
 
     // "other participant" finished contributing
@@ -237,29 +762,1028 @@ async fn lobby_try_contribute_test() {
     tokio::time::advance(Duration::from_secs(5)).await;
     let too_soon_response = try_contribute(
         session_id.clone(),
+        ClientVersion(None),
         Extension(shared_state.clone()),
         Extension(db.clone()),
         Extension(transcript.clone()),
+        Extension(test_config()),
     )
     .await;
     assert!(matches!(
         too_soon_response,
-        Err(TryContributeError::RateLimited)
+        Err(TryContributeError::RateLimited(_))
     ));
 
     // wait enough time to be able to contribute
     tokio::time::advance(Duration::from_secs(19)).await;
     let success_response = try_contribute(
         session_id.clone(),
+        ClientVersion(None),
         Extension(shared_state.clone()),
         Extension(db.clone()),
         Extension(transcript.clone()),
+        Extension(test_config()),
     )
     .await;
     assert!(matches!(
         success_response,
         Ok(TryContributeResponse {
             contribution: TestContribution::ValidContribution(0),
+            contribution_index: 0,
+            last_slot_outcome: None,
+            ..
         })
     ));
 }
+
+#[tokio::test]
+async fn try_contribute_returns_contribution_index() {
+    use crate::{
+        storage::test_storage_client,
+        test_util::{create_test_session_info, response_body, test_config},
+        TestTranscript,
+    };
+
+    let shared_state = SharedState::default();
+    let transcript = SharedTranscript::<TestTranscript>::default();
+    let db = test_storage_client().await;
+    let session_id = SessionId::new();
+
+    {
+        let mut state = shared_state.write().await;
+        state.num_contributions = 7;
+        state
+            .lobby
+            .insert(session_id.clone(), create_test_session_info(100));
+    }
+
+    let response = try_contribute(
+        session_id,
+        ClientVersion(None),
+        Extension(shared_state.clone()),
+        Extension(db),
+        Extension(transcript),
+        Extension(test_config()),
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.contribution_index, 7);
+
+    // The contribution is still the body, with the index next to it
+    let body = response_body(response.into_response()).await;
+    let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    assert_eq!(body["ValidContribution"], 0);
+    assert_eq!(body["contribution_index"], 7);
+}
+
+#[tokio::test]
+async fn try_contribute_rejects_denied_client_versions() {
+    use crate::{
+        storage::test_storage_client,
+        test_util::{create_test_session_info, test_config},
+        TestTranscript,
+    };
+    use semver::VersionReq;
+
+    let shared_state = SharedState::default();
+    let transcript = SharedTranscript::<TestTranscript>::default();
+    let db = test_storage_client().await;
+    let session_id = SessionId::new();
+    shared_state
+        .write()
+        .await
+        .lobby
+        .insert(session_id.clone(), create_test_session_info(100));
+    let config = AppConfig {
+        denied_client_versions: vec![VersionReq::parse("<1.2.0").unwrap()],
+        client_upgrade_url: Some("https://example.com/upgrade".to_string()),
+        ..test_config()
+    };
+
+    let denied_response = try_contribute(
+        session_id.clone(),
+        ClientVersion(Some(Version::new(1, 1, 9))),
+        Extension(shared_state.clone()),
+        Extension(db.clone()),
+        Extension(transcript.clone()),
+        Extension(config.clone()),
+    )
+    .await;
+    assert!(matches!(
+        denied_response,
+        Err(TryContributeError::UnsupportedClient(Some(url))) if url == "https://example.com/upgrade"
+    ));
+
+    let allowed_response = try_contribute(
+        session_id,
+        ClientVersion(Some(Version::new(1, 2, 0))),
+        Extension(shared_state.clone()),
+        Extension(db),
+        Extension(transcript),
+        Extension(config),
+    )
+    .await;
+    assert!(allowed_response.is_ok());
+}
+
+#[test]
+fn parses_user_agent_versions() {
+    assert_eq!(
+        user_agent_version("kzg-client/1.2.3 (linux)"),
+        Some(Version::new(1, 2, 3))
+    );
+    assert_eq!(user_agent_version("Mozilla/5.0 (X11; Linux x86_64)"), None);
+    assert_eq!(user_agent_version("curl"), None);
+}
+
+#[tokio::test]
+async fn try_contribute_enforces_reloaded_allowlist() {
+    use crate::{
+        api::v1::admin::{reload_allowlist, Admin},
+        storage::test_storage_client,
+        test_util::{create_test_session_info, test_config},
+        TestTranscript,
+    };
+    use std::collections::BTreeSet;
+
+    let shared_state = SharedState::default();
+    let transcript = SharedTranscript::<TestTranscript>::default();
+    let db = test_storage_client().await;
+    let session_id = SessionId::new();
+    let mut allowlist_file = std::env::temp_dir();
+    allowlist_file.push("try_contribute_allowlist.txt");
+    let config = AppConfig {
+        allowlist_file: Some(allowlist_file.clone()),
+        ..test_config()
+    };
+    {
+        let mut state = shared_state.write().await;
+        state.allowlist = Some(BTreeSet::new());
+        state
+            .lobby
+            .insert(session_id.clone(), create_test_session_info(100));
+    }
+    tokio::time::pause();
+
+    let rejected_response = try_contribute(
+        session_id.clone(),
+        ClientVersion(None),
+        Extension(shared_state.clone()),
+        Extension(db.clone()),
+        Extension(transcript.clone()),
+        Extension(config.clone()),
+    )
+    .await;
+    assert!(matches!(
+        rejected_response,
+        Err(TryContributeError::NotAllowed)
+    ));
+
+    // test_jwt identities are "foo"
+    std::fs::write(&allowlist_file, "github | bar\nfoo\n").unwrap();
+    assert!(reload_allowlist(
+        Admin,
+        Extension(shared_state.clone()),
+        Extension(config.clone())
+    )
+    .await
+    .is_ok());
+
+    tokio::time::advance(Duration::from_secs(30)).await;
+    let admitted_response = try_contribute(
+        session_id,
+        ClientVersion(None),
+        Extension(shared_state.clone()),
+        Extension(db),
+        Extension(transcript),
+        Extension(config),
+    )
+    .await;
+    assert!(admitted_response.is_ok());
+}
+
+#[tokio::test]
+async fn resume_restores_lobby_session_once() {
+    use crate::test_util::{create_test_session_info, init_keys, test_config};
+
+    init_keys().await;
+    let shared_state = SharedState::default();
+    let ahead = SessionId::new();
+    let session_id = SessionId::new();
+    {
+        let mut state = shared_state.write().await;
+        state
+            .lobby
+            .insert(ahead.clone(), create_test_session_info(100));
+        state
+            .lobby
+            .insert(session_id.clone(), create_test_session_info(100));
+    }
+    let resume_token = ResumeToken::new(session_id.clone(), 1).encode().unwrap();
+    // The session ahead leaves after the token was issued
+    shared_state.write().await.lobby.shift_remove(&ahead);
+
+    let resumed = resume(
+        Json(ResumePayload {
+            resume_token: resume_token.clone(),
+        }),
+        Extension(shared_state.clone()),
+        Extension(test_config()),
+    )
+    .await;
+    let resumed = match resumed {
+        Ok(resumed) => resumed,
+        Err(_) => panic!("expected session to be resumed"),
+    };
+    {
+        let state = shared_state.read().await;
+        assert!(!state.lobby.contains_key(&session_id));
+        assert!(state.lobby.contains_key(&resumed.session_id));
+        assert_eq!(
+            state.unique_id_session.get("foo"),
+            Some(&resumed.session_id)
+        );
+    }
+    assert_eq!(resumed.position, 0);
+    let reissued = ResumeToken::decode(&resumed.resume_token, Duration::ZERO).unwrap();
+    assert_eq!(reissued.position, 0);
+
+    // Replaying the same token must not hand out the session again
+    let replayed = resume(
+        Json(ResumePayload { resume_token }),
+        Extension(shared_state.clone()),
+        Extension(test_config()),
+    )
+    .await;
+    assert!(matches!(replayed, Err(ResumeError::SessionExpired)));
+}
+
+#[tokio::test]
+async fn resume_rejects_expired_and_evicted_sessions() {
+    use crate::test_util::{create_test_session_info, init_keys, test_config};
+
+    init_keys().await;
+    let shared_state = SharedState::default();
+    let session_id = SessionId::new();
+    shared_state
+        .write()
+        .await
+        .lobby
+        .insert(session_id.clone(), create_test_session_info(100));
+
+    let expired_token = ResumeToken {
+        session_id: session_id.clone(),
+        position:   0,
+        exp:        1,
+    }
+    .encode()
+    .unwrap();
+    let expired = resume(
+        Json(ResumePayload {
+            resume_token: expired_token,
+        }),
+        Extension(shared_state.clone()),
+        Extension(test_config()),
+    )
+    .await;
+    assert!(matches!(expired, Err(ResumeError::InvalidToken)));
+
+    // Evicted from the lobby for missing check-ins
+    shared_state.write().await.lobby.clear();
+    let evicted = resume(
+        Json(ResumePayload {
+            resume_token: ResumeToken::new(session_id, 0).encode().unwrap(),
+        }),
+        Extension(shared_state),
+        Extension(test_config()),
+    )
+    .await;
+    assert!(matches!(evicted, Err(ResumeError::SessionExpired)));
+}
+
+#[tokio::test]
+async fn status_is_not_blocked_by_a_pending_grant() {
+    use crate::{
+        api::v1::info::status,
+        storage::test_storage_client,
+        test_util::{create_test_session_info, test_config},
+        TestTranscript,
+    };
+
+    let shared_state = SharedState::default();
+    let db = test_storage_client().await;
+    let session_id = SessionId::new();
+    shared_state
+        .write()
+        .await
+        .lobby
+        .insert(session_id.clone(), create_test_session_info(100));
+
+    // Hold the only database connection, so the grant stalls on storage
+    let connection = db.acquire_connection().await;
+    let grant = tokio::spawn(try_contribute(
+        session_id,
+        ClientVersion(None),
+        Extension(shared_state.clone()),
+        Extension(db.clone()),
+        Extension(SharedTranscript::<TestTranscript>::default()),
+        Extension(test_config()),
+    ));
+    while shared_state.read().await.participant.is_none() {
+        tokio::task::yield_now().await;
+    }
+
+    let response = tokio::time::timeout(
+        Duration::from_secs(1),
+        status(Extension(shared_state.clone()), Extension(test_config())),
+    )
+    .await;
+    assert!(response.is_ok());
+    assert!(!grant.is_finished());
+
+    drop(connection);
+    assert!(grant.await.unwrap().is_ok());
+}
+
+#[tokio::test]
+async fn resume_tolerates_clock_skew_up_to_leeway() {
+    use crate::test_util::{create_test_session_info, init_keys, test_config};
+
+    init_keys().await;
+    let shared_state = SharedState::default();
+    let session_id = SessionId::new();
+    shared_state
+        .write()
+        .await
+        .lobby
+        .insert(session_id.clone(), create_test_session_info(100));
+    let config = AppConfig {
+        clock_skew: Duration::from_secs(60),
+        ..test_config()
+    };
+    let token_expired_secs_ago = |secs: u64| {
+        ResumeToken {
+            session_id: session_id.clone(),
+            position:   0,
+            exp:        chrono::Utc::now().timestamp().unsigned_abs() - secs,
+        }
+        .encode()
+        .unwrap()
+    };
+
+    let outside_leeway = resume(
+        Json(ResumePayload {
+            resume_token: token_expired_secs_ago(120),
+        }),
+        Extension(shared_state.clone()),
+        Extension(config.clone()),
+    )
+    .await;
+    assert!(matches!(outside_leeway, Err(ResumeError::InvalidToken)));
+
+    let within_leeway = resume(
+        Json(ResumePayload {
+            resume_token: token_expired_secs_ago(10),
+        }),
+        Extension(shared_state.clone()),
+        Extension(config),
+    )
+    .await;
+    assert!(within_leeway.is_ok());
+}
+
+#[tokio::test]
+async fn try_contribute_limits_identities_across_sessions() {
+    use crate::{
+        storage::test_storage_client,
+        test_util::{create_test_session_info, test_config},
+        TestTranscript,
+    };
+
+    let shared_state = SharedState::default();
+    let transcript = SharedTranscript::<TestTranscript>::default();
+    let db = test_storage_client().await;
+    tokio::time::pause();
+
+    // Two sessions of the same identity, and someone else computing
+    let first_session = SessionId::new();
+    let second_session = SessionId::new();
+    {
+        let mut state = shared_state.write().await;
+        state
+            .lobby
+            .insert(first_session.clone(), create_test_session_info(100));
+        state
+            .lobby
+            .insert(second_session.clone(), create_test_session_info(100));
+        let mut other = create_test_session_info(100);
+        other.token.as_mut().unwrap().sub = "bar".to_string();
+        state.participant = Some((SessionId::new(), other));
+    }
+
+    let first_response = try_contribute(
+        first_session.clone(),
+        ClientVersion(None),
+        Extension(shared_state.clone()),
+        Extension(db.clone()),
+        Extension(transcript.clone()),
+        Extension(test_config()),
+    )
+    .await;
+    assert!(matches!(
+        first_response,
+        Err(TryContributeError::AnotherContributionInProgress(_))
+    ));
+
+    // The second session's first check-in counts against the same identity
+    tokio::time::advance(Duration::from_secs(10)).await;
+    let second_response = try_contribute(
+        second_session.clone(),
+        ClientVersion(None),
+        Extension(shared_state.clone()),
+        Extension(db.clone()),
+        Extension(transcript.clone()),
+        Extension(test_config()),
+    )
+    .await;
+    assert!(matches!(
+        second_response,
+        Err(TryContributeError::RateLimited(retry_after)) if retry_after == Duration::from_secs(18)
+    ));
+
+    tokio::time::advance(Duration::from_secs(18)).await;
+    let later_response = try_contribute(
+        second_session,
+        ClientVersion(None),
+        Extension(shared_state.clone()),
+        Extension(db),
+        Extension(transcript),
+        Extension(test_config()),
+    )
+    .await;
+    assert!(matches!(
+        later_response,
+        Err(TryContributeError::AnotherContributionInProgress(_))
+    ));
+}
+
+#[tokio::test]
+async fn try_contribute_reports_expired_previous_slot() {
+    use crate::{
+        storage::test_storage_client,
+        test_util::{create_test_session_info, test_config},
+        TestTranscript,
+    };
+
+    let shared_state = SharedState::default();
+    let db = test_storage_client().await;
+    tokio::time::pause();
+    let expired_session = SessionId::new();
+    let next_session = SessionId::new();
+    {
+        let mut state = shared_state.write().await;
+        state
+            .lobby
+            .insert(expired_session.clone(), create_test_session_info(100));
+        let mut next = create_test_session_info(100);
+        next.token.as_mut().unwrap().sub = "bar".to_string();
+        state.lobby.insert(next_session.clone(), next);
+        assert!(
+            state.try_set_current_contributor(expired_session.clone(), Duration::from_secs(180))
+        );
+    }
+    let slot_released = shared_state.write().await.slot_released();
+    remove_participant_on_deadline(
+        shared_state.clone(),
+        db.clone(),
+        expired_session,
+        "foo".to_string(),
+        Duration::from_secs(180),
+        None,
+        slot_released,
+    )
+    .await;
+
+    let response = try_contribute(
+        next_session,
+        ClientVersion(None),
+        Extension(shared_state),
+        Extension(db),
+        Extension(SharedTranscript::<TestTranscript>::default()),
+        Extension(test_config()),
+    )
+    .await;
+    assert!(matches!(
+        response,
+        Ok(TryContributeResponse {
+            last_slot_outcome: Some(SlotOutcome::Expired),
+            ..
+        })
+    ));
+}
+
+#[tokio::test]
+async fn invite_codes_are_single_use() {
+    use crate::{
+        storage::test_storage_client,
+        test_util::{create_test_session_info, test_config},
+        TestTranscript,
+    };
+
+    let shared_state = SharedState::default();
+    let transcript = SharedTranscript::<TestTranscript>::default();
+    let db = test_storage_client().await;
+    db.insert_invite_codes(&["welcome".to_string(), "spare".to_string()])
+        .await
+        .unwrap();
+    let config = AppConfig {
+        require_invite_code: true,
+        ..test_config()
+    };
+
+    let session_id = SessionId::new();
+    let other_session_id = SessionId::new();
+    {
+        let mut state = shared_state.write().await;
+        state
+            .lobby
+            .insert(session_id.clone(), create_test_session_info(100));
+        let mut other_session_info = create_test_session_info(100);
+        other_session_info.token.as_mut().unwrap().sub = "bar".to_string();
+        state
+            .lobby
+            .insert(other_session_id.clone(), other_session_info);
+    }
+    let join_with = |session_id: SessionId, invite_code: &str| {
+        join(
+            session_id,
+            Json(JoinPayload {
+                invite_code: invite_code.to_string(),
+            }),
+            Extension(shared_state.clone()),
+            Extension(db.clone()),
+            Extension(config.clone()),
+        )
+    };
+
+    // Without an invite, the session can't take the contribution spot
+    let response = try_contribute(
+        session_id.clone(),
+        ClientVersion(None),
+        Extension(shared_state.clone()),
+        Extension(db.clone()),
+        Extension(transcript.clone()),
+        Extension(config.clone()),
+    )
+    .await;
+    assert!(matches!(response, Err(TryContributeError::InviteRequired)));
+
+    assert!(matches!(
+        join_with(session_id.clone(), "unknown").await,
+        Err(JoinError::InvalidInvite)
+    ));
+    assert!(matches!(
+        join_with(session_id.clone(), "welcome").await,
+        Ok(StatusCode::OK)
+    ));
+    assert!(matches!(
+        join_with(other_session_id.clone(), "welcome").await,
+        Err(JoinError::InvalidInvite)
+    ));
+
+    // Joining again, even concurrently, doesn't use up another code
+    let (first, second) = tokio::join!(
+        join_with(session_id.clone(), "spare"),
+        join_with(session_id.clone(), "spare")
+    );
+    assert!(matches!(first, Ok(StatusCode::OK)));
+    assert!(matches!(second, Ok(StatusCode::OK)));
+    assert!(matches!(
+        join_with(other_session_id, "spare").await,
+        Ok(StatusCode::OK)
+    ));
+
+    // Admission is kept in storage, so it survives a restart
+    let restarted_state = SharedState::default();
+    restarted_state
+        .write()
+        .await
+        .lobby
+        .insert(session_id.clone(), create_test_session_info(100));
+    let response = try_contribute(
+        session_id,
+        ClientVersion(None),
+        Extension(restarted_state),
+        Extension(db.clone()),
+        Extension(transcript.clone()),
+        Extension(config),
+    )
+    .await;
+    assert!(response.is_ok());
+}
+
+#[tokio::test]
+async fn sessions_expire_after_max_lifetime() {
+    use crate::{
+        storage::test_storage_client,
+        test_util::{create_test_session_info, test_config},
+        TestTranscript,
+    };
+
+    let shared_state = SharedState::default();
+    let transcript = SharedTranscript::<TestTranscript>::default();
+    let db = test_storage_client().await;
+    tokio::time::pause();
+    let config = AppConfig {
+        session_max_lifetime: Some(Duration::from_secs(3600)),
+        ..test_config()
+    };
+
+    let fresh_session_id = SessionId::new();
+    let old_session_id = SessionId::new();
+    {
+        let mut state = shared_state.write().await;
+        state
+            .lobby
+            .insert(old_session_id.clone(), create_test_session_info(100));
+    }
+    // The old session keeps checking in, but that doesn't extend its lifetime
+    tokio::time::advance(Duration::from_secs(3601)).await;
+    {
+        let mut state = shared_state.write().await;
+        let mut fresh_session_info = create_test_session_info(100);
+        fresh_session_info.token.as_mut().unwrap().sub = "bar".to_string();
+        state
+            .lobby
+            .insert(fresh_session_id.clone(), fresh_session_info);
+        state.lobby.get_mut(&old_session_id).unwrap().last_ping_time = Instant::now();
+    }
+
+    let response = try_contribute(
+        old_session_id,
+        ClientVersion(None),
+        Extension(shared_state.clone()),
+        Extension(db.clone()),
+        Extension(transcript.clone()),
+        Extension(config.clone()),
+    )
+    .await;
+    assert!(matches!(response, Err(TryContributeError::SessionExpired)));
+
+    let response = try_contribute(
+        fresh_session_id,
+        ClientVersion(None),
+        Extension(shared_state.clone()),
+        Extension(db.clone()),
+        Extension(transcript.clone()),
+        Extension(config),
+    )
+    .await;
+    assert!(response.is_ok());
+}
+
+#[tokio::test]
+async fn lobby_keeps_insertion_order() {
+    use crate::test_util::create_test_session_info;
+
+    let shared_state = SharedState::default();
+    let sessions = (0..5).map(|_| SessionId::new()).collect::<Vec<_>>();
+
+    let mut state = shared_state.write().await;
+    for session_id in &sessions {
+        state
+            .lobby
+            .insert(session_id.clone(), create_test_session_info(100));
+    }
+    assert!(state.try_set_current_contributor(sessions[1].clone(), Duration::from_secs(180)));
+    state
+        .lobby
+        .insert(sessions[1].clone(), create_test_session_info(100));
+    state.clear_current_contributor(SlotOutcome::Completed);
+    assert!(state.try_set_current_contributor(sessions[3].clone(), Duration::from_secs(180)));
+
+    let listed = state.lobby.keys().cloned().collect::<Vec<_>>();
+    assert_eq!(listed, vec![
+        sessions[0].clone(),
+        sessions[2].clone(),
+        sessions[4].clone(),
+        sessions[1].clone(),
+    ]);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn simultaneous_grants_have_one_winner() {
+    use crate::{
+        storage::test_storage_client,
+        test_util::{create_test_session_info, test_config},
+        TestTranscript,
+    };
+    use futures::future::join_all;
+
+    let shared_state = SharedState::default();
+    let transcript = SharedTranscript::<TestTranscript>::default();
+    let db = test_storage_client().await;
+    let sessions = (0..32).map(|_| SessionId::new()).collect::<Vec<_>>();
+    {
+        let mut state = shared_state.write().await;
+        for (i, session_id) in sessions.iter().enumerate() {
+            let mut info = create_test_session_info(100);
+            info.token.as_mut().unwrap().sub = format!("user{}", i);
+            state.lobby.insert(session_id.clone(), info);
+        }
+    }
+
+    let attempts = sessions.into_iter().map(|session_id| {
+        tokio::spawn(try_contribute(
+            session_id,
+            ClientVersion(None),
+            Extension(shared_state.clone()),
+            Extension(db.clone()),
+            Extension(transcript.clone()),
+            Extension(test_config()),
+        ))
+    });
+    let responses = join_all(attempts).await;
+
+    let granted = responses
+        .into_iter()
+        .map(Result::unwrap)
+        .filter(Result::is_ok)
+        .count();
+    assert_eq!(granted, 1);
+    let state = shared_state.read().await;
+    assert!(state.participant.is_some());
+    assert_eq!(state.lobby.len(), 31);
+}
+
+#[tokio::test]
+async fn compute_deadline_scales_with_ceremony_size() {
+    use crate::{
+        storage::test_storage_client,
+        test_util::{create_test_session_info, test_config},
+        TestTranscript,
+    };
+
+    tokio::time::pause();
+    let db = test_storage_client().await;
+
+    let granted_deadline = |ceremony_sizes: Vec<(usize, usize)>| {
+        let db = db.clone();
+        async move {
+            let shared_state = SharedState::default();
+            let session_id = SessionId::new();
+            shared_state
+                .write()
+                .await
+                .lobby
+                .insert(session_id.clone(), create_test_session_info(100));
+            let config = AppConfig {
+                compute_deadline_per_power: Some(Duration::from_millis(10)),
+                ceremony_sizes,
+                ..test_config()
+            };
+            try_contribute(
+                session_id,
+                ClientVersion(None),
+                Extension(shared_state.clone()),
+                Extension(db),
+                Extension(SharedTranscript::<TestTranscript>::default()),
+                Extension(config),
+            )
+            .await
+            .ok()
+            .expect("slot should be granted");
+
+            let state = shared_state.read().await;
+            state.participant_deadline.unwrap() - state.participant_granted_at.unwrap()
+        }
+    };
+
+    let small = granted_deadline(vec![(4096, 65)]).await;
+    let large = granted_deadline(vec![(4096, 65), (8192, 65), (16384, 65), (32768, 65)]).await;
+    assert_eq!(small, Duration::from_millis(10 * 4161));
+    assert_eq!(large, Duration::from_millis(10 * 61700));
+}
+
+#[tokio::test]
+async fn served_transcript_hash_matches_status() {
+    use crate::{
+        api::v1::{contribute::contribute, info::status},
+        data::transcript::transcript_hash,
+        storage::test_storage_client,
+        test_transcript::TestContribution,
+        test_util::{create_test_session_info, init_keys, test_config},
+        verification::{FullVerifier, SharedVerifier, VerificationLimiter},
+        TestTranscript,
+    };
+    use http::HeaderMap;
+    use std::sync::Arc;
+
+    init_keys().await;
+    let db = test_storage_client().await;
+    let shared_state = SharedState::default();
+    let transcript = SharedTranscript::<TestTranscript>::default();
+    shared_state
+        .write()
+        .await
+        .resume_from(&*transcript.read().await);
+
+    let grant = |session_id: SessionId| {
+        try_contribute(
+            session_id,
+            ClientVersion(None),
+            Extension(shared_state.clone()),
+            Extension(db.clone()),
+            Extension(transcript.clone()),
+            Extension(test_config()),
+        )
+    };
+
+    let first = SessionId::new();
+    shared_state
+        .write()
+        .await
+        .lobby
+        .insert(first.clone(), create_test_session_info(100));
+    let served = grant(first.clone()).await.ok().unwrap();
+    let reported = status(Extension(shared_state.clone()), Extension(test_config())).await;
+    assert_eq!(served.transcript_hash, reported.transcript_hash);
+    assert_eq!(
+        served.transcript_hash,
+        transcript_hash(&*transcript.read().await)
+    );
+
+    let verifier: SharedVerifier<TestTranscript> =
+        Arc::new(FullVerifier::new(TestTranscript::default()));
+    contribute::<TestTranscript>(
+        first,
+        HeaderMap::new(),
+        Json(TestContribution::ValidContribution(1)),
+        Extension(shared_state.clone()),
+        Extension(test_config()),
+        Extension(transcript.clone()),
+        Extension(db.clone()),
+        Extension(VerificationLimiter::new(1)),
+        Extension(verifier),
+    )
+    .await
+    .ok()
+    .unwrap();
+
+    // Both move on to the transcript including the contribution
+    let second = SessionId::new();
+    let mut session_info = create_test_session_info(100);
+    session_info.token.as_mut().unwrap().sub = "bar".to_string();
+    shared_state
+        .write()
+        .await
+        .lobby
+        .insert(second.clone(), session_info);
+    let served = grant(second).await.ok().unwrap();
+    let reported = status(Extension(shared_state.clone()), Extension(test_config())).await;
+    assert_eq!(served.transcript_hash, reported.transcript_hash);
+    assert_eq!(
+        served.transcript_hash,
+        transcript_hash(&*transcript.read().await)
+    );
+}
+
+#[tokio::test]
+async fn finished_sessions_are_told_they_contributed() {
+    use crate::{
+        api::v1::contribute::contribute,
+        storage::test_storage_client,
+        test_transcript::TestContribution,
+        test_util::{create_test_session_info, init_keys, test_config},
+        verification::{FullVerifier, SharedVerifier, VerificationLimiter},
+        TestTranscript,
+    };
+    use http::HeaderMap;
+    use std::sync::Arc;
+
+    init_keys().await;
+    let db = test_storage_client().await;
+    let shared_state = SharedState::default();
+    let transcript = SharedTranscript::<TestTranscript>::default();
+
+    let poll = |session_id: SessionId| {
+        try_contribute(
+            session_id,
+            ClientVersion(None),
+            Extension(shared_state.clone()),
+            Extension(db.clone()),
+            Extension(transcript.clone()),
+            Extension(test_config()),
+        )
+    };
+
+    let session_id = SessionId::new();
+    shared_state
+        .write()
+        .await
+        .lobby
+        .insert(session_id.clone(), create_test_session_info(100));
+    poll(session_id.clone()).await.ok().unwrap();
+
+    let verifier: SharedVerifier<TestTranscript> =
+        Arc::new(FullVerifier::new(TestTranscript::default()));
+    contribute::<TestTranscript>(
+        session_id.clone(),
+        HeaderMap::new(),
+        Json(TestContribution::ValidContribution(7)),
+        Extension(shared_state.clone()),
+        Extension(test_config()),
+        Extension(transcript.clone()),
+        Extension(db.clone()),
+        Extension(VerificationLimiter::new(1)),
+        Extension(verifier),
+    )
+    .await
+    .ok()
+    .unwrap();
+
+    let stored_receipt = shared_state.read().await.contribution_bundles["foo"]
+        .receipt
+        .clone();
+    let response = poll(session_id).await;
+    assert!(matches!(
+        response,
+        Err(TryContributeError::AlreadyContributed {
+            contribution_index: 0,
+            ref receipt,
+            ..
+        }) if receipt == &stored_receipt
+    ));
+
+    // Sessions that never joined are still unknown
+    let response = poll(SessionId::new()).await;
+    assert!(matches!(
+        response,
+        Err(TryContributeError::UnknownSessionId)
+    ));
+}
+
+#[tokio::test]
+async fn poll_interval_grows_with_lobby_load() {
+    use crate::{
+        api::v1::info::status,
+        storage::test_storage_client,
+        test_util::{create_test_session_info, test_config},
+        TestTranscript,
+    };
+
+    let config = test_config();
+    let db = test_storage_client().await;
+    let shared_state = SharedState::default();
+    let transcript = SharedTranscript::<TestTranscript>::default();
+    shared_state.write().await.participant =
+        Some((SessionId::new(), create_test_session_info(100)));
+
+    let poll_advice = || async {
+        let session_id = SessionId::new();
+        shared_state
+            .write()
+            .await
+            .lobby
+            .insert(session_id.clone(), create_test_session_info(100));
+        let response = try_contribute(
+            session_id,
+            ClientVersion(None),
+            Extension(shared_state.clone()),
+            Extension(db.clone()),
+            Extension(transcript.clone()),
+            Extension(test_config()),
+        )
+        .await;
+        let lobby_advice = match response {
+            Err(TryContributeError::AnotherContributionInProgress(advice)) => advice,
+            _ => panic!("expected to keep waiting"),
+        };
+        let status_advice = status(Extension(shared_state.clone()), Extension(test_config()))
+            .await
+            .poll_advice;
+        (lobby_advice, status_advice)
+    };
+
+    let (quiet_lobby, quiet_status) = poll_advice().await;
+    for _ in 0..config.overload_lobby_size {
+        shared_state
+            .write()
+            .await
+            .lobby
+            .insert(SessionId::new(), create_test_session_info(100));
+    }
+    let (busy_lobby, busy_status) = poll_advice().await;
+
+    assert!(busy_lobby.poll_after_secs > quiet_lobby.poll_after_secs);
+    assert!(busy_status.poll_after_secs > quiet_status.poll_after_secs);
+    assert!(busy_status.poll_jitter_secs >= quiet_status.poll_jitter_secs);
+
+    // Following the advice keeps lobby members within the check-in window
+    let min_diff = config.lobby_checkin_frequency - config.lobby_checkin_tolerance;
+    let max_diff = config.lobby_checkin_frequency + config.lobby_checkin_tolerance;
+    for advice in [quiet_lobby, busy_lobby] {
+        assert!(Duration::from_secs(advice.poll_after_secs) >= min_diff);
+        assert!(Duration::from_secs(advice.poll_after_secs + advice.poll_jitter_secs) < max_diff);
+    }
+}
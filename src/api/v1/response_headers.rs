@@ -0,0 +1,97 @@
+use std::collections::BTreeMap;
+
+use axum::{http::Request, middleware::Next, response::Response};
+use eyre::{bail, Result as EyreResult};
+use http::{
+    header::{
+        CONNECTION, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, TRANSFER_ENCODING, UPGRADE,
+    },
+    HeaderMap, HeaderName, HeaderValue,
+};
+
+// Headers the sequencer relies on being set by itself, which can't be
+// configured
+static PROTECTED_HEADERS: &[HeaderName] = &[
+    CONNECTION,
+    CONTENT_ENCODING,
+    CONTENT_LENGTH,
+    CONTENT_TYPE,
+    TRANSFER_ENCODING,
+    UPGRADE,
+];
+
+// Parses a JSON object of header names and values, like
+// `{"Strict-Transport-Security": "max-age=63072000"}`
+pub fn parse_response_headers(json: &str) -> EyreResult<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    for (name, value) in serde_json::from_str::<BTreeMap<String, String>>(json)? {
+        let name = HeaderName::from_bytes(name.as_bytes())?;
+        if PROTECTED_HEADERS.contains(&name) {
+            bail!("Header {} can't be configured", name);
+        }
+        headers.insert(name, HeaderValue::from_str(&value)?);
+    }
+    Ok(headers)
+}
+
+// Adds the configured `headers` to every response. Headers the handler set
+// itself are left alone.
+pub async fn add_response_headers<B: Send>(
+    headers: HeaderMap,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let mut response = next.run(request).await;
+    for (name, value) in &headers {
+        response
+            .headers_mut()
+            .entry(name)
+            .or_insert_with(|| value.clone());
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{api::v1::info::status, test_util::test_config, SharedState};
+    use axum::{body::Body, middleware::from_fn, routing::get, Extension, Router};
+    use http::StatusCode;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn configured_headers_are_added_to_responses() {
+        let headers = parse_response_headers(
+            r#"{"Strict-Transport-Security": "max-age=63072000", "X-Trace-Origin": "sequencer"}"#,
+        )
+        .unwrap();
+        let app = Router::new()
+            .route("/info/status", get(status))
+            .layer(from_fn(move |request: Request<Body>, next: Next<Body>| {
+                add_response_headers(headers.clone(), request, next)
+            }))
+            .layer(Extension(SharedState::default()))
+            .layer(Extension(test_config()));
+        let request = Request::builder()
+            .uri("/info/status")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()["strict-transport-security"],
+            "max-age=63072000"
+        );
+        assert_eq!(response.headers()["x-trace-origin"], "sequencer");
+        assert_eq!(response.headers()[CONTENT_TYPE], "application/json");
+    }
+
+    #[test]
+    fn rejects_malformed_and_protected_headers() {
+        assert!(parse_response_headers(r#"{"Bad Name": "value"}"#).is_err());
+        assert!(parse_response_headers(r#"{"X-Value": "line\nbreak"}"#).is_err());
+        assert!(parse_response_headers(r#"{"Content-Type": "text/plain"}"#).is_err());
+        assert!(parse_response_headers("not json").is_err());
+    }
+}
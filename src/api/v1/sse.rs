@@ -0,0 +1,75 @@
+use std::convert::Infallible;
+
+use axum::{
+    response::sse::{Event, KeepAlive, Sse},
+    Extension,
+};
+use futures::{stream, Stream, StreamExt};
+use serde::Serialize;
+use tokio::time::{interval, Duration, MissedTickBehavior};
+
+use crate::{constants::SSE_STATUS_INTERVAL_SEC, SharedState};
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct StatusUpdate {
+    lobby_size:        usize,
+    num_contributions: usize,
+    ceremony_status:   &'static str,
+}
+
+// Emits a status snapshot every `period`. Nothing is spawned, so the stream
+// simply stops when a subscriber disconnects and the response is dropped.
+pub fn status_updates(store: SharedState, period: Duration) -> impl Stream<Item = StatusUpdate> {
+    let mut ticks = interval(period);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    stream::unfold((store, ticks), |(store, mut ticks)| async move {
+        ticks.tick().await;
+        let update = {
+            let app_state = store.read().await;
+            StatusUpdate {
+                lobby_size:        app_state.lobby.len(),
+                num_contributions: app_state.num_contributions,
+                ceremony_status:   app_state.ceremony_status(),
+            }
+        };
+        Some((update, (store, ticks)))
+    })
+}
+
+#[allow(clippy::unused_async)] // Required for axum function signature
+pub async fn sse_status(
+    Extension(store): Extension<SharedState>,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let period = Duration::from_secs(SSE_STATUS_INTERVAL_SEC as u64);
+    let events = status_updates(store, period).map(|update| {
+        Ok(Event::default()
+            .event("status")
+            .json_data(update)
+            .unwrap_or_default())
+    });
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn streams_status_after_contribution() {
+        tokio::time::pause();
+        let store = SharedState::default();
+        let updates = status_updates(store.clone(), Duration::from_secs(1));
+        futures::pin_mut!(updates);
+
+        let first = updates.next().await.unwrap();
+        assert_eq!(first.num_contributions, 0);
+
+        store.write().await.num_contributions += 1;
+        let next = updates.next().await.unwrap();
+        assert_eq!(next, StatusUpdate {
+            lobby_size:        0,
+            num_contributions: 1,
+            ceremony_status:   "waiting_for_participant",
+        });
+    }
+}
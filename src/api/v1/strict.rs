@@ -0,0 +1,218 @@
+use async_session::async_trait;
+use axum::{
+    body::{Body, HttpBody},
+    extract::{FromRequest, RequestParts},
+    response::{IntoResponse, Response},
+    Json,
+};
+use http::{header::CONTENT_TYPE, StatusCode};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Value};
+
+use crate::{data::transcript::max_contribution_size, AppConfig};
+
+// A JSON request body that is read up to the size of a contribution and
+// parsed once. With `STRICT_REQUESTS`, a body with fields that `T` doesn't
+// know is rejected instead of the fields being silently ignored. Unknown
+// fields are found by comparing the body with its round trip through `T`.
+pub struct StrictJson<T>(pub T);
+
+pub enum StrictJsonRejection {
+    NotJson,
+    // Contains the most bytes the body may take
+    TooLarge(usize),
+    Unreadable,
+    Invalid(String),
+    UnknownFields(Vec<String>),
+}
+
+impl IntoResponse for StrictJsonRejection {
+    fn into_response(self) -> Response {
+        let (status, body) = match self {
+            Self::NotJson => {
+                let body = Json(json!({"error": "expected a JSON request body"}));
+                (StatusCode::UNSUPPORTED_MEDIA_TYPE, body)
+            }
+            Self::TooLarge(limit) => {
+                let body = Json(json!({
+                    "error": "request body is too large",
+                    "limit": limit,
+                }));
+                (StatusCode::PAYLOAD_TOO_LARGE, body)
+            }
+            Self::Unreadable => {
+                let body = Json(json!({"error": "request body could not be read"}));
+                (StatusCode::BAD_REQUEST, body)
+            }
+            Self::Invalid(reason) => {
+                let body = Json(json!({ "error": format!("invalid request body: {}", reason) }));
+                (StatusCode::UNPROCESSABLE_ENTITY, body)
+            }
+            Self::UnknownFields(fields) => {
+                let body = Json(json!({
+                    "error": "request contains unknown fields",
+                    "fields": fields,
+                }));
+                (StatusCode::BAD_REQUEST, body)
+            }
+        };
+        (status, body).into_response()
+    }
+}
+
+#[async_trait]
+impl<T> FromRequest<Body> for StrictJson<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    type Rejection = StrictJsonRejection;
+
+    async fn from_request(req: &mut RequestParts<Body>) -> Result<Self, Self::Rejection> {
+        let config = req
+            .extensions()
+            .get::<AppConfig>()
+            .expect("AppConfig extension is missing");
+        let (strict, limit) = (
+            config.strict_requests,
+            max_contribution_size(&config.ceremony_sizes),
+        );
+        let is_json = req
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map_or(false, |value| value.starts_with("application/json"));
+        if !is_json {
+            return Err(StrictJsonRejection::NotJson);
+        }
+
+        let mut body = req.take_body().ok_or(StrictJsonRejection::Unreadable)?;
+        let mut bytes = Vec::new();
+        while let Some(chunk) = body.data().await {
+            let chunk = chunk.map_err(|_| StrictJsonRejection::Unreadable)?;
+            if bytes.len() + chunk.len() > limit {
+                return Err(StrictJsonRejection::TooLarge(limit));
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+
+        let invalid = |error: serde_json::Error| StrictJsonRejection::Invalid(error.to_string());
+        if !strict {
+            return serde_json::from_slice(&bytes).map(Self).map_err(invalid);
+        }
+        let received = serde_json::from_slice::<Value>(&bytes).map_err(invalid)?;
+        let parsed = serde_json::from_value::<T>(received.clone()).map_err(invalid)?;
+        let known = serde_json::to_value(&parsed).map_err(invalid)?;
+        let mut fields = Vec::new();
+        collect_unknown_fields(&received, &known, "", &mut fields);
+        if !fields.is_empty() {
+            fields.sort();
+            return Err(StrictJsonRejection::UnknownFields(fields));
+        }
+        Ok(Self(parsed))
+    }
+}
+
+// Adds the paths like `outer.inner` or `items.0.inner` of the fields in
+// `received` that are lost in `known`, its round trip through a type
+fn collect_unknown_fields(received: &Value, known: &Value, path: &str, fields: &mut Vec<String>) {
+    let child_path = |key: &str| {
+        if path.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}.{}", path, key)
+        }
+    };
+    match (received, known) {
+        (Value::Object(received), Value::Object(known)) => {
+            for (key, value) in received {
+                match known.get(key) {
+                    Some(known) => collect_unknown_fields(value, known, &child_path(key), fields),
+                    None => fields.push(child_path(key)),
+                }
+            }
+        }
+        (Value::Array(received), Value::Array(known)) => {
+            for (index, (value, known)) in received.iter().zip(known).enumerate() {
+                collect_unknown_fields(value, known, &child_path(&index.to_string()), fields);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{response_body, test_config};
+    use axum::{http::Request, routing::post, Extension, Router};
+    use serde::Deserialize;
+    use tower::ServiceExt;
+
+    #[derive(Serialize, Deserialize)]
+    struct Payload {
+        name:   String,
+        points: Vec<Point>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Point {
+        x: u64,
+    }
+
+    #[allow(clippy::unused_async)] // Required for axum function signature
+    async fn accept(StrictJson(payload): StrictJson<Payload>) -> String {
+        payload.name
+    }
+
+    async fn submit(config: AppConfig, body: impl Into<Body>) -> Response {
+        let app = Router::new()
+            .route("/submit", post(accept))
+            .layer(Extension(config));
+        let request = Request::builder()
+            .method("POST")
+            .uri("/submit")
+            .header("content-type", "application/json")
+            .body(body.into())
+            .unwrap();
+        app.oneshot(request).await.unwrap()
+    }
+
+    fn strict(strict_requests: bool) -> AppConfig {
+        AppConfig {
+            strict_requests,
+            ..test_config()
+        }
+    }
+
+    #[tokio::test]
+    async fn unknown_fields_are_only_rejected_in_strict_mode() {
+        let body = r#"{"name": "alice", "points": [{"x": 1, "y": 2}], "extra": true}"#;
+
+        let lenient = submit(strict(false), body).await;
+        assert_eq!(lenient.status(), StatusCode::OK);
+        assert_eq!(response_body(lenient).await, b"alice");
+
+        let rejected = submit(strict(true), body).await;
+        assert_eq!(rejected.status(), StatusCode::BAD_REQUEST);
+        let error: Value = serde_json::from_slice(&response_body(rejected).await).unwrap();
+        assert_eq!(error["fields"], json!(["extra", "points.0.y"]));
+
+        let exact = submit(strict(true), r#"{"name": "bob", "points": []}"#).await;
+        assert_eq!(exact.status(), StatusCode::OK);
+        assert_eq!(response_body(exact).await, b"bob");
+    }
+
+    #[tokio::test]
+    async fn bodies_larger_than_a_contribution_are_not_read() {
+        let config = AppConfig {
+            ceremony_sizes: vec![(1, 1)],
+            ..strict(true)
+        };
+        let limit = max_contribution_size(&config.ceremony_sizes);
+        let padding = " ".repeat(limit);
+        let body = format!(r#"{{"name": "carol", "points": []}}{}"#, padding);
+
+        let response = submit(config, body).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
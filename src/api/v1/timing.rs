@@ -0,0 +1,145 @@
+use axum::{
+    http::Request,
+    middleware::Next,
+    response::{IntoResponse, Response},
+    BoxError, Json,
+};
+use http::StatusCode;
+use serde_json::json;
+use tokio::time::{sleep_until, timeout, Duration, Instant};
+use tracing::warn;
+
+use crate::{storage::PersistentStorage, SessionId, SharedState};
+
+// Holds back error responses until at least `floor` has passed since the
+// request arrived, so that how fast a request fails doesn't reveal why it
+// failed. Successful responses are not delayed.
+pub async fn pad_error_responses<B: Send>(
+    floor: Option<Duration>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let started = Instant::now();
+    let response = next.run(request).await;
+    if let Some(floor) = floor {
+        let status = response.status();
+        if status.is_client_error() || status.is_server_error() {
+            sleep_until(started + floor).await;
+        }
+    }
+    response
+}
+
+// Turns the error of a `tower::timeout` layer into a response. Dropping the
+// timed out handler releases whatever it held, such as a verification slot.
+#[allow(clippy::unused_async)] // Required for axum function signature
+pub async fn timed_out(error: BoxError) -> Response {
+    if error.is::<tower::timeout::error::Elapsed>() {
+        timeout_response()
+    } else {
+        let body = Json(json!({ "error": error.to_string() }));
+        (StatusCode::INTERNAL_SERVER_ERROR, body).into_response()
+    }
+}
+
+fn timeout_response() -> Response {
+    let body = Json(json!({"error": "request timed out"}));
+    (StatusCode::GATEWAY_TIMEOUT, body).into_response()
+}
+
+// Times out requests like a `tower::timeout` layer. Dropping the handler
+// doesn't release the contribution spot, so a timed out request of the
+// current contributor also expires them, and the next participant can go.
+pub async fn release_slot_on_timeout<B: Send>(
+    limit: Duration,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let session_id = SessionId::from_headers(request.headers());
+    let store = request.extensions().get::<SharedState>().cloned();
+    let storage = request.extensions().get::<PersistentStorage>().cloned();
+    if let Ok(response) = timeout(limit, next.run(request)).await {
+        return response;
+    }
+    if let (Some(session_id), Some(store)) = (session_id, store) {
+        let expired = store.write().await.expire_current_contributor(&session_id);
+        if let Some((_, session_info)) = expired {
+            warn!(session = %session_id.public_handle(), "Contribution timed out, expired");
+            if let Some(storage) = storage {
+                storage
+                    .expire_contribution(session_info.unique_identifier())
+                    .await;
+            }
+        }
+    }
+    timeout_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::verification::VerificationLimiter;
+    use axum::{
+        body::Body, error_handling::HandleErrorLayer, middleware::from_fn, routing::get, Router,
+    };
+    use tower::{ServiceBuilder, ServiceExt};
+
+    async fn request(floor: Option<Duration>, uri: &str) -> (StatusCode, Duration) {
+        let app = Router::new()
+            .route("/ok", get(|| async { StatusCode::OK }))
+            .route("/fail", get(|| async { StatusCode::UNAUTHORIZED }))
+            .layer(from_fn(move |request: Request<Body>, next: Next<Body>| {
+                pad_error_responses(floor, request, next)
+            }));
+        let started = Instant::now();
+        let request = Request::builder().uri(uri).body(Body::empty()).unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        (response.status(), started.elapsed())
+    }
+
+    #[tokio::test]
+    async fn pads_error_responses_to_the_floor() {
+        tokio::time::pause();
+        let floor = Duration::from_millis(250);
+
+        let (status, elapsed) = request(Some(floor), "/fail").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert!(elapsed >= floor);
+
+        let (status, elapsed) = request(Some(floor), "/ok").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(elapsed < floor);
+
+        let (_, elapsed) = request(None, "/fail").await;
+        assert!(elapsed < floor);
+    }
+
+    #[tokio::test]
+    async fn stalled_handler_times_out_and_releases_its_slot() {
+        tokio::time::pause();
+        let limiter = VerificationLimiter::new(1);
+        let stalled = limiter.clone();
+        let app = Router::new()
+            .route(
+                "/stalled",
+                get(move || async move {
+                    let _permit = stalled.try_acquire();
+                    std::future::pending::<StatusCode>().await
+                }),
+            )
+            .layer(
+                ServiceBuilder::new()
+                    .layer(HandleErrorLayer::new(timed_out))
+                    .timeout(Duration::from_secs(10)),
+            );
+
+        let request = Request::builder()
+            .uri("/stalled")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+        assert!(!limiter.is_saturated());
+    }
+}
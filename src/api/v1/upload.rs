@@ -0,0 +1,622 @@
+use std::{
+    io,
+    path::{Path as FilePath, PathBuf},
+    sync::Arc,
+};
+
+use axum::{
+    extract::{BodyStream, Path},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use futures::StreamExt;
+use http::{HeaderMap, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::{fs::OpenOptions, io::AsyncWriteExt, sync::Mutex};
+use uuid::Uuid;
+
+use crate::{
+    api::v1::contribute::{contribute, ContributeError, ContributeReceipt},
+    data::transcript::max_contribution_size,
+    framing::FramingError,
+    storage::PersistentStorage,
+    verification::{SharedVerifier, VerificationLimiter},
+    AppConfig, Contribution, SessionId, SharedState, SharedTranscript, Transcript,
+};
+
+// The number of bytes of the upload received so far. Appended chunks have to
+// start right there.
+pub const UPLOAD_OFFSET: &str = "upload-offset";
+
+// A contribution that is uploaded in chunks, so that an upload interrupted by
+// a dropped connection can be resumed where it stopped. Only the current
+// participant uploads, so there is at most one.
+#[derive(Serialize, Deserialize)]
+struct Upload {
+    id:         String,
+    session_id: SessionId,
+    // The chunks received so far are kept in this file
+    path:       PathBuf,
+    offset:     u64,
+}
+
+impl Upload {
+    // Kept next to the chunks after every change, so the upload can be resumed
+    // after a restart
+    async fn persist(&self) -> io::Result<()> {
+        let state = state_path(&self.path);
+        let mut temp = state.clone().into_os_string();
+        temp.push(".tmp");
+        let encoded = serde_json::to_vec(self).expect("Cannot serialize upload");
+        tokio::fs::write(&temp, encoded).await?;
+        tokio::fs::rename(&temp, &state).await
+    }
+}
+
+// Where the chunks of an upload are kept, next to the transcript
+pub fn upload_path(transcript_file: &FilePath) -> PathBuf {
+    let mut path = transcript_file.as_os_str().to_owned();
+    path.push(".upload");
+    PathBuf::from(path)
+}
+
+// Where the id and offset of the upload at `path` are kept
+fn state_path(path: &FilePath) -> PathBuf {
+    let mut state = path.as_os_str().to_owned();
+    state.push(".state");
+    PathBuf::from(state)
+}
+
+// Deletes the files of the upload at `path`. This is quick enough to be done
+// under the state lock when the contribution spot is released, so it can't
+// race with the next participant's upload.
+pub fn remove_upload_files(path: &FilePath) {
+    std::fs::remove_file(state_path(path)).ok();
+    std::fs::remove_file(path).ok();
+}
+
+#[derive(Clone, Default)]
+pub struct Uploads(Arc<Mutex<Option<Upload>>>);
+
+impl Uploads {
+    // Picks up the upload that was in progress when the sequencer stopped, if
+    // any. It can be resumed once its session holds the contribution spot.
+    pub async fn restore(transcript_file: &FilePath) -> Self {
+        let path = upload_path(transcript_file);
+        let upload = async {
+            let state = tokio::fs::read(state_path(&path)).await.ok()?;
+            let mut upload = serde_json::from_slice::<Upload>(&state).ok()?;
+            // The state is written after the chunk is synced, so the file is
+            // never shorter unless it was tampered with
+            let written = tokio::fs::metadata(&upload.path).await.ok()?.len();
+            upload.offset = upload.offset.min(written);
+            Some(upload)
+        }
+        .await;
+        Self(Arc::new(Mutex::new(upload)))
+    }
+}
+
+pub enum UploadError {
+    Contribute(ContributeError),
+    UnknownUpload,
+    MissingOffset,
+    // Contains the offset the upload is at
+    OffsetMismatch(u64),
+    // Contains the most bytes a contribution can take
+    TooLarge(u64),
+    Io(io::Error),
+}
+
+impl IntoResponse for UploadError {
+    fn into_response(self) -> Response {
+        let (status, body) = match self {
+            Self::Contribute(error) => return error.into_response(),
+            Self::UnknownUpload => {
+                let body = Json(json!({"error": "unknown upload"}));
+                (StatusCode::NOT_FOUND, body)
+            }
+            Self::MissingOffset => {
+                let body = Json(json!({"error": "missing or invalid upload-offset header"}));
+                (StatusCode::BAD_REQUEST, body)
+            }
+            Self::OffsetMismatch(offset) => {
+                let body = Json(json!({
+                    "error": "chunk does not start at the upload offset",
+                    "offset": offset,
+                }));
+                return (
+                    StatusCode::CONFLICT,
+                    [(UPLOAD_OFFSET, offset.to_string())],
+                    body,
+                )
+                    .into_response();
+            }
+            Self::TooLarge(limit) => {
+                let body = Json(json!({
+                    "error": "upload exceeds the size of a contribution",
+                    "limit": limit,
+                }));
+                (StatusCode::PAYLOAD_TOO_LARGE, body)
+            }
+            Self::Io(error) => {
+                let body = Json(json!({ "error": format!("could not store upload: {}", error) }));
+                (StatusCode::INTERNAL_SERVER_ERROR, body)
+            }
+        };
+        (status, body).into_response()
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct UploadCreated {
+    upload_id: String,
+}
+
+impl IntoResponse for UploadCreated {
+    fn into_response(self) -> Response {
+        (StatusCode::CREATED, Json(self)).into_response()
+    }
+}
+
+pub struct UploadOffset(u64);
+
+impl IntoResponse for UploadOffset {
+    fn into_response(self) -> Response {
+        (StatusCode::NO_CONTENT, [(
+            UPLOAD_OFFSET,
+            self.0.to_string(),
+        )])
+            .into_response()
+    }
+}
+
+async fn ensure_participant(
+    store: &SharedState,
+    session_id: &SessionId,
+) -> Result<(), UploadError> {
+    match &store.read().await.participant {
+        Some((id, _)) if id == session_id => Ok(()),
+        _ => Err(UploadError::Contribute(ContributeError::NotUsersTurn)),
+    }
+}
+
+// Returns the upload with `id` if it belongs to the current participant. Once
+// the participant's spot is released, their upload can't be used anymore.
+async fn current_upload<'a>(
+    upload: &'a mut Option<Upload>,
+    id: &str,
+    session_id: &SessionId,
+    store: &SharedState,
+) -> Result<&'a mut Upload, UploadError> {
+    ensure_participant(store, session_id).await?;
+    upload
+        .as_mut()
+        .filter(|upload| upload.id == id && &upload.session_id == session_id)
+        .ok_or(UploadError::UnknownUpload)
+}
+
+// Has the files of `upload` deleted once its session's spot is released,
+// however that happens. If it already was, they are deleted right away.
+async fn track_upload_files(store: &SharedState, upload: &Upload) -> Result<(), UploadError> {
+    let mut app_state = store.write().await;
+    if !app_state.is_participant(&upload.session_id) {
+        remove_upload_files(&upload.path);
+        return Err(UploadError::Contribute(ContributeError::NotUsersTurn));
+    }
+    app_state.participant_upload = Some(upload.path.clone());
+    Ok(())
+}
+
+// Starts an upload for the current participant, replacing any earlier one
+pub async fn create_upload(
+    session_id: SessionId,
+    Extension(store): Extension<SharedState>,
+    Extension(config): Extension<AppConfig>,
+    Extension(uploads): Extension<Uploads>,
+) -> Result<UploadCreated, UploadError> {
+    ensure_participant(&store, &session_id).await?;
+    let mut upload = uploads.0.lock().await;
+    let path = upload_path(&config.transcript_file);
+    tokio::fs::File::create(&path)
+        .await
+        .map_err(UploadError::Io)?;
+    let id = Uuid::new_v4().to_string();
+    let created = Upload {
+        id: id.clone(),
+        session_id,
+        path,
+        offset: 0,
+    };
+    created.persist().await.map_err(UploadError::Io)?;
+    track_upload_files(&store, &created).await?;
+    *upload = Some(created);
+    Ok(UploadCreated { upload_id: id })
+}
+
+// Tells a client resuming an upload where to continue
+pub async fn upload_offset(
+    session_id: SessionId,
+    Path(id): Path<String>,
+    Extension(store): Extension<SharedState>,
+    Extension(uploads): Extension<Uploads>,
+) -> Result<UploadOffset, UploadError> {
+    let mut upload = uploads.0.lock().await;
+    let upload = current_upload(&mut upload, &id, &session_id, &store).await?;
+    Ok(UploadOffset(upload.offset))
+}
+
+// Appends the body to the upload. It is written to the file as it arrives, and
+// an upload can't grow beyond the size of a contribution.
+pub async fn append_chunk(
+    session_id: SessionId,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    Extension(store): Extension<SharedState>,
+    Extension(config): Extension<AppConfig>,
+    Extension(uploads): Extension<Uploads>,
+    mut chunk: BodyStream,
+) -> Result<UploadOffset, UploadError> {
+    let offset = headers
+        .get(UPLOAD_OFFSET)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok())
+        .ok_or(UploadError::MissingOffset)?;
+    let mut upload = uploads.0.lock().await;
+    let upload = current_upload(&mut upload, &id, &session_id, &store).await?;
+    if offset != upload.offset {
+        return Err(UploadError::OffsetMismatch(upload.offset));
+    }
+
+    let mut file = OpenOptions::new()
+        .append(true)
+        .open(&upload.path)
+        .await
+        .map_err(UploadError::Io)?;
+    // Drop whatever a failed earlier append may have left behind
+    file.set_len(upload.offset).await.map_err(UploadError::Io)?;
+    let limit = max_contribution_size(&config.ceremony_sizes) as u64;
+    let mut offset = upload.offset;
+    while let Some(bytes) = chunk.next().await {
+        let bytes = bytes.map_err(|error| {
+            UploadError::Io(io::Error::new(io::ErrorKind::UnexpectedEof, error))
+        })?;
+        offset += bytes.len() as u64;
+        if offset > limit {
+            return Err(UploadError::TooLarge(limit));
+        }
+        file.write_all(&bytes).await.map_err(UploadError::Io)?;
+    }
+    file.sync_data().await.map_err(UploadError::Io)?;
+    upload.offset = offset;
+    upload.persist().await.map_err(UploadError::Io)?;
+    // Also covers an upload restored at startup, which the state doesn't know
+    track_upload_files(&store, upload).await?;
+    Ok(UploadOffset(upload.offset))
+}
+
+// Submits the uploaded contribution, which is then handled like one sent to
+// `/contribute`
+pub async fn commit_upload<T>(
+    session_id: SessionId,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    Extension(store): Extension<SharedState>,
+    Extension(config): Extension<AppConfig>,
+    Extension(shared_transcript): Extension<SharedTranscript<T>>,
+    Extension(storage): Extension<PersistentStorage>,
+    Extension(verification_limiter): Extension<VerificationLimiter>,
+    Extension(verifier): Extension<SharedVerifier<T>>,
+    Extension(uploads): Extension<Uploads>,
+) -> Result<ContributeReceipt, UploadError>
+where
+    T: Transcript + Send + Sync + 'static,
+    T::ContributionType: Send + 'static,
+    <<T as Transcript>::ContributionType as Contribution>::Receipt: Send,
+{
+    let path = {
+        let mut upload = uploads.0.lock().await;
+        current_upload(&mut upload, &id, &session_id, &store).await?;
+        upload.take().expect("upload was found above").path
+    };
+    // Appends keep the upload within the limit, this only guards against the
+    // file having been replaced
+    let limit = max_contribution_size(&config.ceremony_sizes) as u64;
+    let file = std::fs::File::open(&path).map_err(UploadError::Io)?;
+    if file.metadata().map_err(UploadError::Io)?.len() > limit {
+        remove_upload_files(&path);
+        return Err(UploadError::TooLarge(limit));
+    }
+    let decoded = tokio::task::spawn_blocking(move || {
+        serde_json::from_reader::<_, T::ContributionType>(io::BufReader::new(file))
+    })
+    .await
+    .expect("upload decoder panicked");
+    remove_upload_files(&path);
+    let contribution = decoded.map_err(|_| {
+        UploadError::Contribute(ContributeError::MalformedStream(
+            FramingError::InvalidEncoding,
+        ))
+    })?;
+    contribute::<T>(
+        session_id,
+        headers,
+        Json(contribution),
+        Extension(store),
+        Extension(config),
+        Extension(shared_transcript),
+        Extension(storage),
+        Extension(verification_limiter),
+        Extension(verifier),
+    )
+    .await
+    .map_err(UploadError::Contribute)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        api::v1::lobby::SlotOutcome,
+        test_transcript::TestContribution::ValidContribution,
+        test_util::{create_test_session_info, init_keys, test_config, TestCeremony},
+        verification::FullVerifier,
+        TestTranscript,
+    };
+    use axum::{
+        body::{Body, Bytes},
+        extract::{FromRequest, RequestParts},
+    };
+
+    async fn append(
+        config: &AppConfig,
+        uploads: &Uploads,
+        store: &SharedState,
+        session_id: &SessionId,
+        upload_id: &str,
+        offset: u64,
+        chunk: impl Into<Bytes>,
+    ) -> Result<UploadOffset, UploadError> {
+        let mut headers = HeaderMap::new();
+        headers.insert(UPLOAD_OFFSET, offset.into());
+        let request = http::Request::new(Body::from(chunk.into()));
+        let body = BodyStream::from_request(&mut RequestParts::new(request))
+            .await
+            .unwrap();
+        append_chunk(
+            session_id.clone(),
+            Path(upload_id.to_string()),
+            headers,
+            Extension(store.clone()),
+            Extension(config.clone()),
+            Extension(uploads.clone()),
+            body,
+        )
+        .await
+    }
+
+    async fn start_upload(config: &AppConfig, store: &SharedState, uploads: &Uploads) -> String {
+        let participant = SessionId::new();
+        store.write().await.participant =
+            Some((participant.clone(), create_test_session_info(100)));
+        create_upload(
+            participant,
+            Extension(store.clone()),
+            Extension(config.clone()),
+            Extension(uploads.clone()),
+        )
+        .await
+        .ok()
+        .unwrap()
+        .upload_id
+    }
+
+    async fn current_session(store: &SharedState) -> SessionId {
+        store.read().await.participant.clone().unwrap().0
+    }
+
+    #[tokio::test]
+    async fn resumes_and_commits_an_interrupted_upload() {
+        init_keys().await;
+        let ceremony = TestCeremony::new("upload").await;
+        let (store, config) = (&ceremony.store, &ceremony.config);
+        let uploads = Uploads::default();
+        let participant = SessionId::new();
+        store.write().await.participant =
+            Some((participant.clone(), create_test_session_info(100)));
+
+        let contribution = serde_json::to_vec(&ValidContribution(7)).unwrap();
+        assert_eq!(contribution, br#"{"ValidContribution":7}"#);
+        let upload_id = create_upload(
+            participant.clone(),
+            Extension(store.clone()),
+            Extension(config.clone()),
+            Extension(uploads.clone()),
+        )
+        .await
+        .ok()
+        .unwrap()
+        .upload_id;
+
+        assert!(append(
+            config,
+            &uploads,
+            store,
+            &participant,
+            &upload_id,
+            0,
+            "{\"Valid"
+        )
+        .await
+        .is_ok());
+        // The connection dropped after this chunk was sent, so the client
+        // doesn't know whether it arrived and sends it again
+        let resent = append(
+            config,
+            &uploads,
+            store,
+            &participant,
+            &upload_id,
+            0,
+            "{\"Valid",
+        )
+        .await;
+        assert!(matches!(resent, Err(UploadError::OffsetMismatch(8))));
+        let offset = upload_offset(
+            participant.clone(),
+            Path(upload_id.clone()),
+            Extension(store.clone()),
+            Extension(uploads.clone()),
+        )
+        .await
+        .ok()
+        .unwrap();
+        assert_eq!(offset.0, 8);
+        let completed = append(
+            config,
+            &uploads,
+            store,
+            &participant,
+            &upload_id,
+            offset.0,
+            "Contribution\":7}",
+        )
+        .await
+        .ok()
+        .unwrap();
+        assert_eq!(completed.0, contribution.len() as u64);
+
+        let verifier: SharedVerifier<TestTranscript> =
+            Arc::new(FullVerifier::new(TestTranscript::default()));
+        let receipt = commit_upload::<TestTranscript>(
+            participant,
+            Path(upload_id),
+            HeaderMap::new(),
+            Extension(store.clone()),
+            Extension(config.clone()),
+            Extension(ceremony.transcript.clone()),
+            Extension(ceremony.storage.clone()),
+            Extension(VerificationLimiter::new(1)),
+            Extension(verifier),
+            Extension(uploads),
+        )
+        .await;
+        assert!(receipt.is_ok());
+        assert_eq!(ceremony.transcript.read().await.contributions, vec![
+            ValidContribution(7)
+        ]);
+        assert!(store.read().await.participant.is_none());
+    }
+
+    #[tokio::test]
+    async fn only_the_participant_can_upload() {
+        let store = SharedState::default();
+        store.write().await.participant = Some((SessionId::new(), create_test_session_info(100)));
+        let created = create_upload(
+            SessionId::new(),
+            Extension(store),
+            Extension(test_config()),
+            Extension(Uploads::default()),
+        )
+        .await;
+        assert!(matches!(
+            created,
+            Err(UploadError::Contribute(ContributeError::NotUsersTurn))
+        ));
+    }
+
+    #[tokio::test]
+    async fn upload_resumes_after_a_restart() {
+        let ceremony = TestCeremony::new("upload_restart").await;
+        let (store, config) = (&ceremony.store, &ceremony.config);
+        let uploads = Uploads::default();
+        let upload_id = start_upload(config, store, &uploads).await;
+        let participant = current_session(store).await;
+        assert!(append(
+            config,
+            &uploads,
+            store,
+            &participant,
+            &upload_id,
+            0,
+            "{\"Valid"
+        )
+        .await
+        .is_ok());
+
+        let restored = Uploads::restore(&config.transcript_file).await;
+        let offset = upload_offset(
+            participant.clone(),
+            Path(upload_id.clone()),
+            Extension(store.clone()),
+            Extension(restored.clone()),
+        )
+        .await
+        .ok()
+        .unwrap();
+        assert_eq!(offset.0, 8);
+        let completed = append(
+            config,
+            &restored,
+            store,
+            &participant,
+            &upload_id,
+            offset.0,
+            "Contribution\":7}",
+        )
+        .await
+        .ok()
+        .unwrap();
+        assert_eq!(completed.0, 23);
+        let uploaded = std::fs::read(upload_path(&config.transcript_file)).unwrap();
+        assert_eq!(uploaded, br#"{"ValidContribution":7}"#);
+    }
+
+    #[tokio::test]
+    async fn upload_cannot_exceed_a_contribution() {
+        let mut ceremony = TestCeremony::new("upload_limit").await;
+        ceremony.config.ceremony_sizes = vec![(1, 1)];
+        let (store, config) = (&ceremony.store, &ceremony.config);
+        let uploads = Uploads::default();
+        let limit = max_contribution_size(&config.ceremony_sizes);
+        let upload_id = start_upload(config, store, &uploads).await;
+        let participant = current_session(store).await;
+
+        let first = vec![b' '; limit - 1];
+        assert!(
+            append(config, &uploads, store, &participant, &upload_id, 0, first)
+                .await
+                .is_ok()
+        );
+        let second = append(
+            config,
+            &uploads,
+            store,
+            &participant,
+            &upload_id,
+            limit as u64 - 1,
+            vec![b' '; 2],
+        )
+        .await;
+        assert!(matches!(second, Err(UploadError::TooLarge(l)) if l == limit as u64));
+    }
+
+    #[tokio::test]
+    async fn upload_is_deleted_when_the_spot_is_released() {
+        let ceremony = TestCeremony::new("upload_release").await;
+        let (store, config) = (&ceremony.store, &ceremony.config);
+        let uploads = Uploads::default();
+        let path = upload_path(&config.transcript_file);
+        start_upload(config, store, &uploads).await;
+        assert!(path.exists());
+        assert!(state_path(&path).exists());
+
+        store
+            .write()
+            .await
+            .clear_current_contributor(SlotOutcome::Expired);
+        assert!(!path.exists());
+        assert!(!state_path(&path).exists());
+    }
+}
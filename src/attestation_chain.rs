@@ -0,0 +1,114 @@
+use chrono::{DateTime, Utc};
+use rand::RngCore;
+use ring::digest::{Context, SHA256};
+use serde::Serialize;
+
+use crate::storage::Attestation;
+
+// What the first attestation links to
+pub const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+// Makes every attestation unique, even if the same identity contributed the
+// same pubkeys twice
+pub fn nonce() -> String {
+    let mut nonce = [0_u8; 32];
+    rand::thread_rng().fill_bytes(&mut nonce);
+    hex::encode(nonce)
+}
+
+// What a link covers, encoded as attestations were when they were first
+// chained. The phase was recorded later and is left out, the link to the
+// previous attestation already pins an attestation's place in the chain.
+#[derive(Serialize)]
+struct LinkedRecord<'a> {
+    contribution_index: i64,
+    identity_hash:      &'a str,
+    attested_at:        DateTime<Utc>,
+    provider:           &'a str,
+    pubkeys:            &'a [String],
+    identity_signature: Option<&'a str>,
+    nonce:              Option<&'a str>,
+    // Always empty, a link can't cover itself
+    chain_hash:         Option<&'a str>,
+}
+
+// Hex encoded hash linking `attestation` to the chain ending in `prev`. It
+// covers everything recorded about the contribution, so an attestation can't
+// be changed or moved to another position without breaking every later link.
+pub fn link(prev: &str, attestation: &Attestation) -> String {
+    let record = LinkedRecord {
+        contribution_index: attestation.contribution_index,
+        identity_hash:      &attestation.identity_hash,
+        attested_at:        attestation.attested_at,
+        provider:           &attestation.provider,
+        pubkeys:            &attestation.pubkeys,
+        identity_signature: attestation.identity_signature.as_deref(),
+        nonce:              attestation.nonce.as_deref(),
+        chain_hash:         None,
+    };
+    let mut context = Context::new(&SHA256);
+    context.update(prev.as_bytes());
+    context.update(&serde_json::to_vec(&record).expect("Cannot serialize attestation"));
+    hex::encode(context.finish())
+}
+
+// Checks that the attestations, in contribution order, form an unbroken
+// chain from the genesis. Fails with the position of the first one that
+// doesn't link to those before it.
+pub fn verify(attestations: &[Attestation]) -> Result<(), usize> {
+    let mut prev = GENESIS.to_string();
+    for (position, attestation) in attestations.iter().enumerate() {
+        let expected = link(&prev, attestation);
+        if attestation.chain_hash.as_ref() != Some(&expected) {
+            return Err(position);
+        }
+        prev = expected;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::test_storage_client;
+
+    #[tokio::test]
+    async fn stored_attestations_form_a_chain() {
+        let storage = test_storage_client().await;
+        let mut prev = GENESIS.to_string();
+        for index in 0..4 {
+            let attestation = Attestation::new(
+                0,
+                index,
+                storage.identity_hash(&format!("github | {}", index)),
+                "github",
+                vec![format!("0x{:02x}", index)],
+                None,
+                &prev,
+            );
+            prev = attestation.chain_hash.clone().unwrap();
+            storage
+                .insert_attestation(&attestation, None, None)
+                .await
+                .unwrap();
+        }
+        assert_eq!(storage.attestation_chain_head().await.unwrap(), Some(prev));
+
+        let attestations = storage.attestations_page(None, 10).await.unwrap();
+        assert_eq!(verify(&attestations), Ok(()));
+
+        let mut reordered = attestations.clone();
+        reordered.swap(1, 2);
+        assert_eq!(verify(&reordered), Err(1));
+
+        // Moving the records along with their positions doesn't help either
+        let mut renumbered = reordered;
+        renumbered[1].contribution_index = 1;
+        renumbered[2].contribution_index = 2;
+        assert_eq!(verify(&renumbered), Err(1));
+
+        let mut tampered = attestations;
+        tampered[3].nonce = Some(nonce());
+        assert_eq!(verify(&tampered), Err(3));
+    }
+}
@@ -0,0 +1,186 @@
+use std::{
+    str::FromStr,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use axum::BoxError;
+use futures::future::BoxFuture;
+use once_cell::sync::Lazy;
+use prometheus::{register_int_counter, register_int_gauge, IntCounter, IntGauge};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tower::Service;
+
+static OPEN_CONNECTIONS: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!(
+        "open_connections",
+        "Number of client connections currently served"
+    )
+    .unwrap()
+});
+
+static REFUSED_CONNECTIONS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "refused_connections_total",
+        "Number of client connections dropped because too many were open"
+    )
+    .unwrap()
+});
+
+// What happens to connections beyond the limit
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ExcessConnections {
+    // Closed right away
+    #[default]
+    Refuse,
+    // Accepted, but not read from until another connection closes
+    Queue,
+}
+
+impl FromStr for ExcessConnections {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_ascii_lowercase().as_str() {
+            "refuse" => Ok(Self::Refuse),
+            "queue" => Ok(Self::Queue),
+            other => Err(format!("unknown excess connection policy {}", other)),
+        }
+    }
+}
+
+// Wraps the service that makes a service per accepted connection, so that at
+// most `max_connections` connections are served at once. Unlike request rate
+// limits, this also holds up against clients that open connections and then
+// send nothing. Each connection's service holds a permit until the
+// connection is closed.
+#[derive(Clone)]
+pub struct ConnectionLimit<M> {
+    inner:   M,
+    permits: Arc<Semaphore>,
+    excess:  ExcessConnections,
+}
+
+impl<M> ConnectionLimit<M> {
+    pub fn new(inner: M, max_connections: usize, excess: ExcessConnections) -> Self {
+        Self {
+            inner,
+            permits: Arc::new(Semaphore::new(max_connections)),
+            excess,
+        }
+    }
+}
+
+impl<M, T> Service<T> for ConnectionLimit<M>
+where
+    M: Service<T>,
+    M::Future: Send + 'static,
+    M::Error: Into<BoxError>,
+{
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+    type Response = Connection<M::Response>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        let permits = self.permits.clone();
+        let excess = self.excess;
+        let service = self.inner.call(target);
+        Box::pin(async move {
+            let permit = match excess {
+                ExcessConnections::Refuse => permits.try_acquire_owned().map_err(|_| {
+                    REFUSED_CONNECTIONS.inc();
+                    "too many open connections"
+                })?,
+                ExcessConnections::Queue => permits.acquire_owned().await?,
+            };
+            let service = service.await.map_err(Into::into)?;
+            OPEN_CONNECTIONS.inc();
+            Ok(Connection {
+                service,
+                _permit: permit,
+            })
+        })
+    }
+}
+
+// The service of a single connection, dropped once it is closed
+pub struct Connection<S> {
+    service: S,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl<S> Drop for Connection<S> {
+    fn drop(&mut self) {
+        OPEN_CONNECTIONS.dec();
+    }
+}
+
+impl<S, R> Service<R> for Connection<S>
+where
+    S: Service<R>,
+{
+    type Error = S::Error;
+    type Future = S::Future;
+    type Response = S::Response;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: R) -> Self::Future {
+        self.service.call(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{routing::get, Router, Server};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+        time::{sleep, timeout, Duration},
+    };
+
+    // Sends a request and reads the start of the response. Returns `None` if
+    // the connection was closed instead.
+    async fn request(connection: &mut TcpStream) -> Option<String> {
+        connection
+            .write_all(b"GET / HTTP/1.1\r\nhost: localhost\r\n\r\n")
+            .await
+            .ok()?;
+        let mut response = [0; 12];
+        match timeout(Duration::from_secs(5), connection.read(&mut response)).await {
+            Ok(Ok(read)) if read > 0 => Some(String::from_utf8_lossy(&response[..read]).into()),
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn connections_beyond_the_limit_are_shed() {
+        let app = Router::new().route("/", get(|| async { "ok" }));
+        let server = Server::bind(&"127.0.0.1:0".parse().unwrap()).serve(ConnectionLimit::new(
+            app.into_make_service(),
+            1,
+            ExcessConnections::Refuse,
+        ));
+        let addr = server.local_addr();
+        tokio::spawn(server);
+
+        let mut first = TcpStream::connect(addr).await.unwrap();
+        assert_eq!(request(&mut first).await.unwrap(), "HTTP/1.1 200");
+
+        // The first connection is kept alive and holds the only permit
+        let mut second = TcpStream::connect(addr).await.unwrap();
+        assert_eq!(request(&mut second).await, None);
+
+        drop(first);
+        sleep(Duration::from_millis(100)).await;
+        let mut third = TcpStream::connect(addr).await.unwrap();
+        assert_eq!(request(&mut third).await.unwrap(), "HTTP/1.1 200");
+    }
+}
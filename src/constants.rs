@@ -2,6 +2,10 @@
 // a contributor has to complete their contribution
 pub const COMPUTE_DEADLINE: usize = 180;
 
+// In seconds, the most an operator may extend a contributor's deadline by,
+// in total, see `/admin/extend_deadline`
+pub const MAX_DEADLINE_EXTENSION_SEC: usize = 600;
+
 // In seconds, This is the expected amout of time
 // between calls to /lobby/try_contribute
 // Contributors will be kicked from the lobby if they
@@ -14,15 +18,81 @@ pub const LOBBY_CHECKIN_FREQUENCY_SEC: usize = 30;
 // participants MUST call every 28-32 seconds).
 pub const LOBBY_CHECKIN_TOLERANCE_SEC: usize = 2;
 
+// How many check-ins are allowed within as many check-in intervals, see
+// `CheckinPacer`. One means every check-in has to wait for the full interval
+// since the previous one. Two lets an early check-in follow a late one, but
+// also two check-ins back to back, so it is left to operators to opt in.
+pub const CHECKIN_BURST: u32 = 1;
+
+// How long after joining the first check-in is exempt from rate limiting, in
+// seconds
+pub const FIRST_CHECKIN_GRACE_SEC: usize = 10;
+
 // This is the maximum amount of people that can be held in the
 // lobby. Users in the lobby are allowed to ping to contribute
 pub const MAX_LOBBY_SIZE: usize = 1_000;
 
+// While all verification slots are busy, new participants are turned away
+// once the lobby holds this many sessions, as they would likely time out
+pub const OVERLOAD_LOBBY_SIZE: usize = 100;
+
+// This is the maximum number of contribution verifications
+// that are allowed to run at the same time. Requests over this
+// limit are rejected as busy
+pub const MAX_CONCURRENT_VERIFICATIONS: usize = 4;
+
+// This is the maximum number of database connections storage calls can use
+// at the same time
+pub const STORAGE_POOL_SIZE: usize = 8;
+
+// How long a storage call waits for a free database connection before it
+// fails, in seconds
+pub const STORAGE_ACQUIRE_TIMEOUT_SEC: usize = 5;
+
+// How long a resume token handed out at join time stays valid, in seconds.
+// Clients that lose their session id can use it to reclaim their lobby entry
+pub const RESUME_TOKEN_LIFETIME_SEC: usize = 600;
+
+// How often status updates are pushed to `/sse/status` subscribers, in seconds
+pub const SSE_STATUS_INTERVAL_SEC: usize = 1;
+
+// How long verifying a single contribution may take before it is rejected,
+// in seconds
+pub const VERIFY_TIMEOUT_SEC: usize = 60;
+
+// How long the contribute endpoints may take to respond, in seconds. This is
+// longer than `VERIFY_TIMEOUT_SEC`, so a slow verification is rejected by the
+// handler itself, which also frees the contribution spot
+pub const CONTRIBUTE_TIMEOUT_SEC: usize = 120;
+
+// How long the status and info endpoints may take to respond, in seconds
+pub const INFO_TIMEOUT_SEC: usize = 10;
+
+// The largest total size of request headers that is accepted, in bytes
+pub const MAX_HEADER_BYTES: usize = 16 * 1024;
+
+// The number of client connections served at once
+pub const MAX_CONNECTIONS: usize = 1024;
+
+// How long serving the transcript file waits for a write in progress to be
+// committed before clients are told to retry, in milliseconds
+pub const TRANSCRIPT_SNAPSHOT_WAIT_MS: usize = 500;
+
+// How long the outcome of verifying a contribution is reused for identical
+// resubmissions, in seconds
+pub const VERIFICATION_CACHE_TTL_SEC: usize = 60;
+
+// How far a client's clock may be off when we validate token expiry, in seconds
+pub const CLOCK_SKEW_SEC: usize = 60;
+
 // Periodically, we check whether the participants
 // have not pinged the sequencer on time.
 // This constant defines how often we check, In seconds
 pub const LOBBY_FLUSH_INTERVAL: usize = 5;
 
+// How often attestations that couldn't be stored are retried, in seconds
+pub const ATTESTATION_RETRY_INTERVAL_SEC: usize = 30;
+
 pub const SIWE_OAUTH_REDIRECT_URL: &str = "http://127.0.0.1:3000/auth/callback/siwe";
 pub const SIWE_OAUTH_AUTH_URL: &str = "https://oidc.signinwithethereum.org/authorize";
 pub const SIWE_OAUTH_TOKEN_URL: &str = "https://oidc.signinwithethereum.org/token";
@@ -39,3 +109,44 @@ pub const ETH_CHECK_NONCE_AT_BLOCK: &str = "0xE4D540";
 
 // The minimum nonce we require from eligible participants
 pub const ETH_MIN_NONCE: i64 = 4;
+
+// How points are serialized in contributions and transcripts
+pub const POINT_ENCODING: &str =
+    "BLS12-381 compressed points (ZCash format), hex encoded with 0x prefix";
+
+// Byte order of field elements within encoded points. Points in the other
+// order are rejected rather than reinterpreted
+pub const POINT_ENDIANNESS: &str = "big-endian";
+
+// How the next contributor is chosen from the lobby
+pub const SELECTION_POLICY: &str = "first lobby member to check in once the slot is free";
+
+// Number of upstream failures in a row after which an identity provider is
+// considered down
+pub const AUTH_PROVIDER_FAILURE_THRESHOLD: usize = 5;
+
+// How long sign-ins through a provider that is considered down are refused,
+// in seconds, before it is tried again
+pub const AUTH_PROVIDER_COOLDOWN_SEC: usize = 30;
+
+// How long the admission hook may take to decide, in seconds
+pub const ADMISSION_TIMEOUT_SEC: usize = 5;
+
+// Anonymous sessions a single client address can start per minute
+pub const ANONYMOUS_JOINS_PER_MINUTE: u32 = 5;
+
+// How long sessions that contributed are remembered, in seconds, so clients
+// still polling with them are told to stop
+pub const FINISHED_SESSION_RETENTION_SEC: usize = 3600;
+
+// Compute times kept for the tuning suggestion and the average compute time.
+// Only the latest are kept, so they follow how contributors do now.
+pub const COMPUTE_TIMES_WINDOW: usize = 1000;
+
+// How long a session has to sign in with the rest of the required auth
+// providers, in seconds, before it is forgotten
+pub const PENDING_SESSION_TTL_SEC: usize = 600;
+
+// Largest JSON response that is re-encoded to match `PRETTY_RESPONSES`, in
+// bytes. Larger ones, such as the transcript, are passed through as they are.
+pub const MAX_FORMATTED_RESPONSE_BYTES: u64 = 1 << 20;
@@ -1 +1,3 @@
+pub mod hash;
+pub mod objects;
 pub mod transcript;
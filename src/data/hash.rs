@@ -0,0 +1,191 @@
+use std::{fmt, str::FromStr};
+
+use once_cell::sync::OnceCell;
+use ring::digest::{Context, SHA256};
+use serde::{Deserialize, Deserializer, Serialize};
+use sha3::{Digest, Keccak256};
+
+// The algorithm transcript hashes are computed with, set once at startup.
+// SHA256 is used until then.
+pub static HASH_ALGORITHM: OnceCell<HashAlgorithm> = OnceCell::new();
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    #[default]
+    Sha256,
+    Blake3,
+    // What Ethereum tooling uses, e.g. for `keccak256` in Solidity
+    Keccak256,
+}
+
+impl HashAlgorithm {
+    pub fn configured() -> Self {
+        HASH_ALGORITHM.get().copied().unwrap_or(Self::Sha256)
+    }
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Sha256 => "sha256",
+            Self::Blake3 => "blake3",
+            Self::Keccak256 => "keccak256",
+        }
+    }
+
+    pub fn hasher(self) -> Hasher {
+        match self {
+            Self::Sha256 => Hasher::Sha256(Context::new(&SHA256)),
+            Self::Blake3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+            Self::Keccak256 => Hasher::Keccak256(Keccak256::new()),
+        }
+    }
+
+    pub fn digest(self, data: &[u8]) -> Vec<u8> {
+        let mut hasher = self.hasher();
+        hasher.update(data);
+        hasher.finish()
+    }
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for HashAlgorithm {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_ascii_lowercase().as_str() {
+            "sha256" => Ok(Self::Sha256),
+            "blake3" => Ok(Self::Blake3),
+            "keccak256" => Ok(Self::Keccak256),
+            other => Err(format!("unknown hash algorithm {}", other)),
+        }
+    }
+}
+
+// Incremental hashing, for inputs that are not held in memory as a whole
+pub enum Hasher {
+    Sha256(Context),
+    Blake3(Box<blake3::Hasher>),
+    Keccak256(Keccak256),
+}
+
+impl Hasher {
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Sha256(context) => context.update(data),
+            Self::Blake3(hasher) => {
+                hasher.update(data);
+            }
+            Self::Keccak256(hasher) => hasher.update(data),
+        }
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        match self {
+            Self::Sha256(context) => context.finish().as_ref().to_vec(),
+            Self::Blake3(hasher) => hasher.finalize().as_bytes().to_vec(),
+            Self::Keccak256(hasher) => hasher.finalize().to_vec(),
+        }
+    }
+}
+
+// A 0x prefixed, hex encoded hash together with the algorithm that produced
+// it, so downstream verifiers know how to recompute it
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct TranscriptHash {
+    pub alg:  HashAlgorithm,
+    pub hash: String,
+}
+
+impl TranscriptHash {
+    pub fn new(alg: HashAlgorithm, digest: &[u8]) -> Self {
+        Self {
+            alg,
+            hash: format!("0x{}", hex::encode(digest)),
+        }
+    }
+}
+
+impl fmt::Display for TranscriptHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.alg, self.hash)
+    }
+}
+
+// Hashes written before the algorithm was configurable are bare SHA256 strings
+impl<'de> Deserialize<'de> for TranscriptHash {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Encoded {
+            Bare(String),
+            Tagged { alg: HashAlgorithm, hash: String },
+        }
+
+        Ok(match Encoded::deserialize(deserializer)? {
+            Encoded::Bare(hash) => Self {
+                alg: HashAlgorithm::Sha256,
+                hash,
+            },
+            Encoded::Tagged { alg, hash } => Self { alg, hash },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn digests_match_known_vectors() {
+        let fixtures = [
+            (
+                HashAlgorithm::Sha256,
+                "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            ),
+            (
+                HashAlgorithm::Blake3,
+                "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262",
+                "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85",
+            ),
+            (
+                HashAlgorithm::Keccak256,
+                "c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470",
+                "4e03657aea45a94fc7d47ba826c8d667c0d1e6e33a64a036ec44f58fa12d6c45",
+            ),
+        ];
+        for (alg, empty, abc) in fixtures {
+            assert_eq!(hex::encode(alg.digest(b"")), empty, "{}", alg);
+            assert_eq!(hex::encode(alg.digest(b"abc")), abc, "{}", alg);
+
+            // Hashing in pieces gives the same result
+            let mut hasher = alg.hasher();
+            hasher.update(b"a");
+            hasher.update(b"bc");
+            assert_eq!(hex::encode(hasher.finish()), abc, "{}", alg);
+        }
+    }
+
+    #[test]
+    fn reads_tagged_and_legacy_hashes() {
+        let tagged: TranscriptHash =
+            serde_json::from_str(r#"{"alg":"keccak256","hash":"0x01"}"#).unwrap();
+        assert_eq!(tagged, TranscriptHash {
+            alg:  HashAlgorithm::Keccak256,
+            hash: "0x01".to_string(),
+        });
+        let legacy: TranscriptHash = serde_json::from_str(r#""0x02""#).unwrap();
+        assert_eq!(legacy.alg, HashAlgorithm::Sha256);
+        assert_eq!(
+            serde_json::to_value(&tagged).unwrap(),
+            serde_json::json!({"alg": "keccak256", "hash": "0x01"})
+        );
+        assert_eq!("Keccak256".parse(), Ok(HashAlgorithm::Keccak256));
+        assert!("md5".parse::<HashAlgorithm>().is_err());
+    }
+}
@@ -0,0 +1,209 @@
+use std::{
+    ops::Range,
+    path::Path,
+    sync::{Arc, RwLock},
+};
+
+use bytes::Bytes;
+use eyre::{bail, eyre, Result as EyreResult};
+use futures::stream::BoxStream;
+use object_store::{aws::AmazonS3Builder, path::Path as ObjectPath, ObjectStore};
+use tokio::io::AsyncWriteExt;
+use url::Url;
+
+use crate::data::hash::TranscriptHash;
+
+// The transcript kept as an object in an object store such as S3, for
+// ceremonies whose transcript is served from there rather than local disk
+#[derive(Clone)]
+pub struct TranscriptObjects {
+    store:   Arc<dyn ObjectStore>,
+    path:    ObjectPath,
+    // The version matching the local transcript file. It is unset until an
+    // upload succeeds, and while the next one is in progress or after it
+    // failed, so a stale object is never served.
+    current: Arc<RwLock<Option<TranscriptVersion>>>,
+}
+
+// An uploaded transcript, stored under a name of its own that is never
+// overwritten, so its size and contents can't change between two requests
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TranscriptVersion {
+    path:                ObjectPath,
+    pub size:            usize,
+    pub transcript_hash: TranscriptHash,
+}
+
+impl TranscriptObjects {
+    pub fn new(store: Arc<dyn ObjectStore>, path: ObjectPath) -> Self {
+        Self {
+            store,
+            path,
+            current: Arc::default(),
+        }
+    }
+
+    // Connects to the object at a uri like `s3://bucket/path/transcript.json`.
+    // Credentials and region are taken from the usual `AWS_*` variables.
+    pub fn from_uri(uri: &str) -> EyreResult<Self> {
+        let url = Url::parse(uri)?;
+        let bucket = url
+            .host_str()
+            .ok_or_else(|| eyre!("Transcript uri {} has no bucket", uri))?;
+        let path = ObjectPath::parse(url.path().trim_start_matches('/'))?;
+        let store: Arc<dyn ObjectStore> = match url.scheme() {
+            "s3" => Arc::new(
+                AmazonS3Builder::from_env()
+                    .with_bucket_name(bucket)
+                    .build()?,
+            ),
+            scheme => bail!("Unsupported transcript uri scheme {}", scheme),
+        };
+        Ok(Self::new(store, path))
+    }
+
+    // Uploads the transcript file, whose hash is `transcript_hash`. It is
+    // streamed from disk into a version named after the hash, which is then
+    // copied over the object at the configured path for readers of the
+    // bucket. The sequencer serves the version itself.
+    pub async fn put_file(
+        &self,
+        file: &Path,
+        transcript_hash: &TranscriptHash,
+    ) -> object_store::Result<()> {
+        *self.current.write().unwrap() = None;
+        let version = self.version_path(transcript_hash);
+        let (upload_id, mut upload) = self.store.put_multipart(&version).await?;
+        let uploaded = async {
+            let mut source = tokio::fs::File::open(file).await?;
+            tokio::io::copy(&mut source, &mut upload).await?;
+            upload.shutdown().await
+        }
+        .await;
+        if let Err(error) = uploaded {
+            self.store.abort_multipart(&version, &upload_id).await.ok();
+            return Err(object_store::Error::Generic {
+                store:  "transcript",
+                source: Box::new(error),
+            });
+        }
+        self.store.copy(&version, &self.path).await?;
+        let size = self.store.head(&version).await?.size;
+        *self.current.write().unwrap() = Some(TranscriptVersion {
+            path: version,
+            size,
+            transcript_hash: transcript_hash.clone(),
+        });
+        Ok(())
+    }
+
+    fn version_path(&self, transcript_hash: &TranscriptHash) -> ObjectPath {
+        let hash = transcript_hash.hash.trim_start_matches("0x");
+        ObjectPath::from(format!("{}.{}", self.path, hash))
+    }
+
+    // The uploaded version of the current transcript, if there is one
+    pub fn current(&self) -> Option<TranscriptVersion> {
+        self.current.read().unwrap().clone()
+    }
+
+    pub async fn stream(
+        &self,
+        version: &TranscriptVersion,
+    ) -> object_store::Result<BoxStream<'static, object_store::Result<Bytes>>> {
+        Ok(self.store.get(&version.path).await?.into_stream())
+    }
+
+    pub async fn range(
+        &self,
+        version: &TranscriptVersion,
+        range: Range<usize>,
+    ) -> object_store::Result<Bytes> {
+        self.store.get_range(&version.path, range).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::transcript::transcript_file_digest;
+    use futures::TryStreamExt;
+    use object_store::memory::InMemory;
+
+    async fn put(objects: &TranscriptObjects, file: &Path, contents: &str) -> TranscriptHash {
+        std::fs::write(file, contents).unwrap();
+        let (_, hash) = transcript_file_digest(file.to_path_buf()).await.unwrap();
+        objects.put_file(file, &hash).await.unwrap();
+        hash
+    }
+
+    async fn served(objects: &TranscriptObjects) -> Vec<u8> {
+        let version = objects.current().unwrap();
+        objects
+            .stream(&version)
+            .await
+            .unwrap()
+            .map_ok(|chunk| chunk.to_vec())
+            .try_concat()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn keeps_every_version_of_the_transcript() {
+        let store = Arc::new(InMemory::new());
+        let objects = TranscriptObjects::new(store.clone(), ObjectPath::from("transcript.json"));
+        let file = std::env::temp_dir().join("object_versions_transcript.json");
+        assert_eq!(objects.current(), None);
+
+        let first = put(&objects, &file, "first").await;
+        put(&objects, &file, "second").await;
+
+        let version = objects.current().unwrap();
+        assert_eq!(version.size, 6);
+        assert_eq!(served(&objects).await, b"second".to_vec());
+        assert_eq!(
+            objects.range(&version, 1..4).await.unwrap(),
+            Bytes::from_static(b"eco")
+        );
+        let latest = store
+            .get(&objects.path)
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        assert_eq!(latest, Bytes::from_static(b"second"));
+
+        let first = store
+            .get(&objects.version_path(&first))
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        assert_eq!(first, Bytes::from_static(b"first"));
+    }
+
+    #[tokio::test]
+    async fn failed_upload_is_not_served() {
+        let objects = TranscriptObjects::new(
+            Arc::new(InMemory::new()),
+            ObjectPath::from("transcript.json"),
+        );
+        let file = std::env::temp_dir().join("object_failed_transcript.json");
+        let hash = put(&objects, &file, "uploaded").await;
+        assert_eq!(served(&objects).await, b"uploaded".to_vec());
+
+        // The local transcript moved on, but its upload didn't make it
+        let missing = std::env::temp_dir().join("object_failed_missing.json");
+        assert!(objects.put_file(&missing, &hash).await.is_err());
+        assert_eq!(objects.current(), None);
+    }
+
+    #[test]
+    fn rejects_unsupported_uris() {
+        assert!(TranscriptObjects::from_uri("ftp://bucket/transcript.json").is_err());
+        assert!(TranscriptObjects::from_uri("not a uri").is_err());
+    }
+}
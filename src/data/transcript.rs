@@ -1,26 +1,279 @@
 use core::result::Result;
-use std::path::PathBuf;
+use std::{
+    fs::File,
+    io::{BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
-use crate::SharedTranscript;
-use serde::{de::DeserializeOwned, ser::Serialize};
+use crate::{
+    data::{
+        hash::{HashAlgorithm, Hasher, TranscriptHash},
+        objects::TranscriptObjects,
+    },
+    keys::{Keys, KEYS},
+    AppConfig, SharedTranscript,
+};
+use eyre::{bail, ensure, eyre, Result as EyreResult};
+use once_cell::sync::Lazy;
+use serde::{de::DeserializeOwned, ser::Serialize, Deserialize};
+use tokio::sync::{Mutex, RwLock, RwLockReadGuard};
+
+// Held for writing while the transcript file and its signature are being
+// replaced, so readers only ever open a committed snapshot of the file
+pub(crate) static TRANSCRIPT_COMMIT: Lazy<RwLock<()>> = Lazy::new(|| RwLock::new(()));
+
+// Held by a transcript write from taking its snapshot until it is committed
+// and uploaded, so writes are committed in the order their snapshots were
+// taken
+static TRANSCRIPT_WRITE: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
 
 pub trait Contribution: Serialize + DeserializeOwned {
     type Receipt: Serialize;
     fn get_receipt(&self) -> Self::Receipt;
+
+    // The encoded proof of knowledge pubkeys this contribution introduces
+    fn pubkeys(&self) -> Vec<String>;
+}
+
+// The amount of work a single contribution verification performs
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VerificationWork {
+    pub powers:   usize,
+    pub pairings: usize,
+}
+
+// Why a transcript doesn't verify from its initial state
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum VerifyAllError<E> {
+    // The transcript doesn't start from the initial state of the ceremony
+    TamperedInitialState,
+    // The contribution at the index doesn't verify
+    InvalidContribution(usize, E),
 }
 
 pub trait Transcript: Serialize + DeserializeOwned {
     type ContributionType: Contribution;
-    type ValidationError: Serialize;
+    type ValidationError: Serialize + Clone;
+
+    // The state of a ceremony without contributions generated for the given
+    // number of G1 and G2 powers of each sub-ceremony
+    fn generate(ceremony_sizes: &[(usize, usize)]) -> Self;
+
+    // The state a ceremony starts from. This is the configured fixture if
+    // there is one, otherwise it is generated for the configured sizes, so
+    // sequencers with the same config always start from the same transcript.
+    fn initial(config: &AppConfig) -> EyreResult<Self> {
+        let path = match &config.initial_transcript_file {
+            Some(path) => path,
+            None => return Ok(Self::generate(&config.ceremony_sizes)),
+        };
+        let reader = std::io::BufReader::new(std::fs::File::open(path)?);
+        let transcript = serde_json::from_reader::<_, Self>(reader)?;
+        ensure!(
+            transcript.num_contributions() == 0,
+            "Initial transcript {} already has {} contributions",
+            path.display(),
+            transcript.num_contributions()
+        );
+        Ok(transcript)
+    }
 
+    // Implementations must reject a first contribution unless the transcript
+    // is in the `initial` state the ceremony started from
     fn verify_contribution(
         &self,
+        initial: &Self,
         contribution: &Self::ContributionType,
     ) -> Result<(), Self::ValidationError>;
 
+    fn verification_work(&self, contribution: &Self::ContributionType) -> VerificationWork;
+
+    // Checks a single encoded point of a contribution that is still being
+    // uploaded, such as whether it is in the subgroup. This lets streamed
+    // uploads be rejected on the first bad point. Strings that are not points
+    // must be accepted. It doesn't depend on the transcript, so the transcript
+    // isn't locked while an upload is still arriving.
+    fn check_point(point: &str) -> Result<(), Self::ValidationError>;
+
+    // Names of the checks `verify_contribution` performs, listed in receipts
+    fn verification_checks(&self) -> &'static [&'static str];
+
+    // Best-effort detection of valid but degenerate contributions, such as
+    // consecutive powers being equal, or a delta that is a small known
+    // scalar. Returns the name of the pattern found.
+    fn suspicious_pattern(&self, contribution: &Self::ContributionType) -> Option<&'static str>;
+
     fn update(&self, contribution: &Self::ContributionType) -> Self;
 
     fn get_contribution(&self) -> Self::ContributionType;
+
+    fn num_contributions(&self) -> usize;
+
+    // The number of G1 and G2 powers of each sub-ceremony
+    fn dimensions(&self) -> Vec<(usize, usize)>;
+
+    // Checks every contribution in the transcript, in order, starting from
+    // `initial`. Fails with the index of the first contribution that doesn't
+    // verify, or because the transcript doesn't start from `initial`.
+    fn verify_all(&self, initial: &Self) -> Result<(), VerifyAllError<Self::ValidationError>> {
+        self.verify_all_with_progress(initial, &mut |_| ())
+    }
+
+    // Like `verify_all`, and calls `progress` with the number of contributions
+    // checked so far
+    fn verify_all_with_progress(
+        &self,
+        initial: &Self,
+        progress: &mut dyn FnMut(usize),
+    ) -> Result<(), VerifyAllError<Self::ValidationError>>;
+
+    // The encoded pubkeys of all contributions recorded in the transcript
+    fn pubkeys(&self) -> Vec<String>;
+
+    // Signs the hash of the transcript as it is written to disk and served
+    fn sign(&self, keys: &Keys) -> Result<TranscriptSignature, jsonwebtoken::errors::Error> {
+        TranscriptSignature::new(keys, transcript_hash(self))
+    }
+
+    fn verify_signature(&self, keys: &Keys, signature: &TranscriptSignature) -> bool {
+        // Signatures made before a change of the algorithm stay valid
+        signature.transcript_hash == transcript_hash_with(signature.transcript_hash.alg, self)
+            && keys.verify(
+                &signature.signature,
+                signature.transcript_hash.hash.as_bytes(),
+            )
+    }
+}
+
+// Length of a hex encoded compressed BLS12-381 point with its `0x` prefix,
+// in G1 and G2
+const G1_POINT_HEX_LEN: usize = 2 + 2 * 48;
+const G2_POINT_HEX_LEN: usize = 2 + 2 * 96;
+
+// Room for the keys and punctuation of a sub-ceremony, and for its pubkey and
+// signature
+const SUB_CEREMONY_OVERHEAD: usize = 2048;
+
+// The most bytes a JSON encoded contribution for `ceremony_sizes` can
+// reasonably take. Every point is counted with its quotes and separator, and
+// the total is doubled to leave room for whitespace.
+pub fn max_contribution_size(ceremony_sizes: &[(usize, usize)]) -> usize {
+    ceremony_sizes
+        .iter()
+        .map(|(g1_powers, g2_powers)| {
+            g1_powers * (G1_POINT_HEX_LEN + 3)
+                + g2_powers * (G2_POINT_HEX_LEN + 3)
+                + SUB_CEREMONY_OVERHEAD
+        })
+        .sum::<usize>()
+        .saturating_mul(2)
+}
+
+// Whether going from `before` to `after` dimensions drops a sub-ceremony, or
+// powers of one
+pub fn dimensions_shrank(before: &[(usize, usize)], after: &[(usize, usize)]) -> bool {
+    after.len() < before.len()
+        || before
+            .iter()
+            .zip(after)
+            .any(|((g1_before, g2_before), (g1_after, g2_after))| {
+                g1_after < g1_before || g2_after < g2_before
+            })
+}
+
+// The sequencer's signature over a transcript, published next to it so
+// downstream verifiers can check where the transcript came from
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TranscriptSignature {
+    // Hash of the transcript file
+    pub transcript_hash: TranscriptHash,
+    pub signature:       String,
+}
+
+impl TranscriptSignature {
+    // Signs a transcript hash computed elsewhere, such as while writing it
+    pub fn new(
+        keys: &Keys,
+        transcript_hash: TranscriptHash,
+    ) -> Result<Self, jsonwebtoken::errors::Error> {
+        let signature = keys.sign(transcript_hash.hash.as_bytes())?;
+        Ok(Self {
+            transcript_hash,
+            signature,
+        })
+    }
+}
+
+// Hashes the transcript as it is written to disk, with the configured
+// algorithm
+pub fn transcript_hash<T: Serialize + ?Sized>(transcript: &T) -> TranscriptHash {
+    transcript_hash_with(HashAlgorithm::configured(), transcript)
+}
+
+pub fn transcript_hash_with<T: Serialize + ?Sized>(
+    alg: HashAlgorithm,
+    transcript: &T,
+) -> TranscriptHash {
+    let json = serde_json::to_vec_pretty(transcript).expect("Cannot serialize transcript");
+    TranscriptHash::new(alg, &alg.digest(&json))
+}
+
+pub async fn read_transcript_signature(path: PathBuf) -> std::io::Result<TranscriptSignature> {
+    let json = tokio::fs::read(path).await?;
+    serde_json::from_slice(&json).map_err(std::io::Error::from)
+}
+
+// Resolves the transcript path to an absolute one and checks that its
+// directory is writable, as transcript updates are written next to it and
+// then renamed into place
+pub fn validate_transcript_path(path: &Path) -> EyreResult<PathBuf> {
+    let resolved = resolve_transcript_path(path)?;
+    let parent = resolved.parent().unwrap_or_else(|| Path::new("."));
+    let mut probe = resolved.clone();
+    probe.set_extension("probe");
+    std::fs::write(&probe, b"").map_err(|e| {
+        eyre!(
+            "Transcript directory {} is not writable: {}",
+            parent.display(),
+            e
+        )
+    })?;
+    std::fs::remove_file(&probe)?;
+
+    Ok(resolved)
+}
+
+// Like `validate_transcript_path`, but only looks at the permissions of the
+// directory instead of writing to it, for checks that must not change
+// anything on disk
+pub fn resolve_transcript_path(path: &Path) -> EyreResult<PathBuf> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| eyre!("Transcript path {} has no file name", path.display()))?;
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let parent = parent.canonicalize().map_err(|e| {
+        eyre!(
+            "Transcript directory {} is not accessible: {}",
+            parent.display(),
+            e
+        )
+    })?;
+    let metadata = parent.metadata()?;
+    if !metadata.is_dir() {
+        bail!(
+            "Transcript directory {} is not a directory",
+            parent.display()
+        );
+    }
+    if metadata.permissions().readonly() {
+        bail!("Transcript directory {} is not writable", parent.display());
+    }
+
+    Ok(parent.join(file_name))
 }
 
 pub async fn read_transcript_file<T: DeserializeOwned + Send + 'static>(path: PathBuf) -> T {
@@ -32,20 +285,362 @@ pub async fn read_transcript_file<T: DeserializeOwned + Send + 'static>(path: Pa
     handle.await.expect("can't read transcript")
 }
 
+// Reads a transcript from a file or an http(s) URL and checks that it has the
+// configured powers, and that all of it is valid, starting from `initial`,
+// before it is accepted
+pub async fn import_transcript<T: Transcript>(
+    source: &str,
+    initial: &T,
+    ceremony_sizes: &[(usize, usize)],
+) -> EyreResult<T> {
+    let json = if source.starts_with("http://") || source.starts_with("https://") {
+        reqwest::get(source)
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?
+            .to_vec()
+    } else {
+        tokio::fs::read(source).await?
+    };
+    let transcript = serde_json::from_slice::<T>(&json)?;
+    let dimensions = transcript.dimensions();
+    ensure!(
+        dimensions == ceremony_sizes,
+        "Transcript at {} has powers {:?}, but CEREMONY_SIZES is {:?}",
+        source,
+        dimensions,
+        ceremony_sizes
+    );
+    transcript
+        .verify_all(initial)
+        .map_err(|_| eyre!("Transcript at {} does not verify", source))?;
+    Ok(transcript)
+}
+
+// Writes the transcript and its signature next to it. If the transcript is
+// served from an object store, it is then uploaded there too.
+//
+// The transcript is serialized and hashed in one pass before the commit lock
+// is taken, so readers of the file only wait for the two renames.
 pub async fn write_transcript_file<T: Transcript + Send + Sync + 'static>(
     target_path: PathBuf,
     work_path: PathBuf,
+    signature_path: PathBuf,
+    objects: Option<TranscriptObjects>,
     transcript: SharedTranscript<T>,
 ) {
+    // Held until the upload is done too, so an older transcript is never
+    // uploaded after a newer one
+    let _write = TRANSCRIPT_WRITE.lock().await;
+    let uploaded_path = target_path.clone();
     let handle = tokio::task::spawn_blocking(move || {
-        let f = std::fs::OpenOptions::new()
-            .write(true)
-            .create(true)
-            .open(&work_path)
-            .expect("Can't access transcript file.");
-        let transcript = transcript.blocking_read();
-        serde_json::to_writer_pretty(&f, &*transcript).expect("Cannot write transcript");
+        let alg = HashAlgorithm::configured();
+        let mut writer = HashingWriter {
+            inner:  BufWriter::new(
+                File::create(&work_path).expect("Can't access transcript file."),
+            ),
+            hasher: alg.hasher(),
+        };
+        serde_json::to_writer_pretty(&mut writer, &*transcript.blocking_read())
+            .expect("Cannot write transcript");
+        writer.inner.flush().expect("Cannot write transcript");
+        let transcript_hash = TranscriptHash::new(alg, &writer.hasher.finish());
+
+        let signature = TranscriptSignature::new(KEYS.get().unwrap(), transcript_hash.clone())
+            .expect("Cannot sign transcript");
+        let signature = serde_json::to_vec_pretty(&signature).expect("Cannot encode signature");
+        let mut signature_work_path = signature_path.clone().into_os_string();
+        signature_work_path.push(".next");
+        std::fs::write(&signature_work_path, signature).expect("Cannot write transcript signature");
+
+        let _commit = TRANSCRIPT_COMMIT.blocking_write();
         std::fs::rename(&work_path, &target_path).unwrap();
+        std::fs::rename(&signature_work_path, &signature_path)
+            .expect("Cannot write transcript signature");
+        transcript_hash
+    });
+    let transcript_hash = handle.await.expect("Cannot write transcript");
+
+    if let Some(objects) = objects {
+        // The local file stays authoritative and is served until an upload
+        // succeeds, the next write retries
+        if let Err(error) = objects.put_file(&uploaded_path, &transcript_hash).await {
+            tracing::error!(?error, "Cannot upload transcript to object storage");
+        }
+    }
+}
+
+// Waits up to `wait` for a transcript write in progress to be committed.
+// While the returned guard is held, the transcript file is not replaced.
+pub async fn committed_transcript(wait: Duration) -> Option<RwLockReadGuard<'static, ()>> {
+    tokio::time::timeout(wait, TRANSCRIPT_COMMIT.read())
+        .await
+        .ok()
+}
+
+// Passes writes through to `inner`, hashing what was written
+struct HashingWriter<W> {
+    inner:  W,
+    hasher: Hasher,
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// Returns the size in bytes and the hash of the transcript file, without
+// holding the whole file in memory
+pub async fn transcript_file_digest(path: PathBuf) -> std::io::Result<(u64, TranscriptHash)> {
+    let alg = HashAlgorithm::configured();
+    let handle = tokio::task::spawn_blocking(move || {
+        let mut file = std::fs::File::open(path)?;
+        let mut hasher = alg.hasher();
+        let mut buffer = [0_u8; 8192];
+        let mut size = 0_u64;
+        loop {
+            let read = file.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+            size += read as u64;
+        }
+        Ok((size, TranscriptHash::new(alg, &hasher.finish())))
     });
-    handle.await.expect("Cannot write transcript");
+    handle.await.expect("can't hash transcript")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_transcript_path() {
+        let dir = std::env::temp_dir().canonicalize().unwrap();
+        let path = dir.join(".").join("transcript.json");
+        assert_eq!(
+            validate_transcript_path(&path).unwrap(),
+            dir.join("transcript.json")
+        );
+    }
+
+    #[test]
+    fn rejects_unwritable_transcript_path() {
+        // A regular file can't hold the transcript, even when running as root
+        let mut not_a_dir = std::env::temp_dir();
+        not_a_dir.push("transcript_parent_is_a_file");
+        std::fs::write(&not_a_dir, b"").unwrap();
+
+        let error = validate_transcript_path(&not_a_dir.join("transcript.json")).unwrap_err();
+        assert!(error.to_string().contains("is not a directory"));
+
+        let error =
+            validate_transcript_path(Path::new("/nonexistent/transcript.json")).unwrap_err();
+        assert!(error.to_string().contains("is not accessible"));
+    }
+
+    #[test]
+    fn same_config_gives_same_initial_transcript() {
+        use crate::{
+            test_transcript::{TestContribution, TestTranscript},
+            test_util::test_config,
+        };
+
+        let generated = AppConfig {
+            ceremony_sizes: vec![(16, 4)],
+            ..test_config()
+        };
+        assert_eq!(
+            transcript_hash(&TestTranscript::initial(&generated).unwrap()),
+            transcript_hash(&TestTranscript::initial(&generated.clone()).unwrap())
+        );
+
+        let path = std::env::temp_dir().join("initial_transcript_fixture.json");
+        let fixture = TestTranscript::generate(&[]);
+        std::fs::write(&path, serde_json::to_vec(&fixture).unwrap()).unwrap();
+        let from_fixture = AppConfig {
+            initial_transcript_file: Some(path.clone()),
+            ..test_config()
+        };
+        assert_eq!(
+            transcript_hash(&TestTranscript::initial(&from_fixture).unwrap()),
+            transcript_hash(&fixture)
+        );
+
+        // A fixture has to be a starting point, not a ceremony in progress
+        let contributed = fixture.update(&TestContribution::ValidContribution(1));
+        std::fs::write(&path, serde_json::to_vec(&contributed).unwrap()).unwrap();
+        assert!(TestTranscript::initial(&from_fixture).is_err());
+    }
+
+    #[tokio::test]
+    async fn ceremony_starts_from_a_differing_fixture() {
+        use crate::{
+            test_transcript::{TestContribution, TestTranscript},
+            test_util::test_config,
+            verification::{FullVerifier, Verifier},
+        };
+
+        let path = std::env::temp_dir().join("differing_initial_transcript_fixture.json");
+        let fixture = TestTranscript {
+            initial:       TestContribution::ValidContribution(42),
+            contributions: vec![],
+        };
+        assert_ne!(fixture, TestTranscript::generate(&[]));
+        std::fs::write(&path, serde_json::to_vec(&fixture).unwrap()).unwrap();
+        let config = AppConfig {
+            initial_transcript_file: Some(path.clone()),
+            ..test_config()
+        };
+        let initial = TestTranscript::initial(&config).unwrap();
+
+        // The first contribution builds on the fixture, not the generated state
+        let verifier = FullVerifier::new(initial.clone());
+        let contribution = TestContribution::ValidContribution(1);
+        let hash = transcript_hash(&initial);
+        assert!(verifier
+            .verify(&initial, &hash, &contribution, None)
+            .is_ok());
+        let generated = TestTranscript::generate(&[]);
+        assert!(verifier
+            .verify(
+                &generated,
+                &transcript_hash(&generated),
+                &contribution,
+                None
+            )
+            .is_err());
+
+        let contributed = initial.update(&contribution);
+        assert_eq!(contributed.verify_all(&initial), Ok(()));
+        assert_eq!(
+            contributed.verify_all(&TestTranscript::generate(&[])),
+            Err(VerifyAllError::TamperedInitialState)
+        );
+        std::fs::write(&path, serde_json::to_vec(&contributed).unwrap()).unwrap();
+        let source = path.to_str().unwrap();
+        assert!(import_transcript(source, &initial, &[(1, 1)]).await.is_ok());
+        assert!(
+            import_transcript(source, &TestTranscript::generate(&[]), &[(1, 1)])
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn imports_transcript_and_continues_the_ceremony() {
+        use crate::{
+            api::v1::contribute::contribute,
+            storage::test_storage_client,
+            test_transcript::{TestContribution, TestTranscript},
+            test_util::{create_test_session_info, init_keys, test_config},
+            verification::{FullVerifier, SharedVerifier, VerificationLimiter},
+            SessionId, SharedState,
+        };
+        use axum::{Extension, Json};
+        use http::HeaderMap;
+        use std::sync::Arc;
+        use tokio::sync::RwLock;
+
+        init_keys().await;
+        let path = std::env::temp_dir().join("imported_transcript.json");
+        let invalid_path = std::env::temp_dir().join("invalid_imported_transcript.json");
+        let exported = TestTranscript::generate(&[])
+            .update(&TestContribution::ValidContribution(1))
+            .update(&TestContribution::ValidContribution(2));
+        std::fs::write(&path, serde_json::to_vec(&exported).unwrap()).unwrap();
+        let tampered = exported.update(&TestContribution::InvalidContribution(3));
+        std::fs::write(&invalid_path, serde_json::to_vec(&tampered).unwrap()).unwrap();
+
+        let initial = TestTranscript::default();
+        assert!(
+            import_transcript(invalid_path.to_str().unwrap(), &initial, &[(1, 1)])
+                .await
+                .is_err()
+        );
+        // The transcript is of a ceremony with other powers
+        let error = import_transcript(path.to_str().unwrap(), &initial, &[(1, 1), (2, 2)])
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("CEREMONY_SIZES"));
+        let imported = import_transcript(path.to_str().unwrap(), &initial, &[(1, 1)])
+            .await
+            .unwrap();
+        assert_eq!(imported, exported);
+
+        let state = SharedState::default();
+        state.write().await.resume_from(&imported);
+        assert_eq!(state.read().await.num_contributions, 2);
+
+        // A reused pubkey from the imported transcript is still caught
+        let participant = SessionId::new();
+        let transcript = Arc::new(RwLock::new(imported));
+        let db = test_storage_client().await;
+        let verifier: SharedVerifier<TestTranscript> =
+            Arc::new(FullVerifier::new(TestTranscript::default()));
+        let submit = |contribution| {
+            contribute::<TestTranscript>(
+                participant.clone(),
+                HeaderMap::new(),
+                Json(contribution),
+                Extension(state.clone()),
+                Extension(test_config()),
+                Extension(transcript.clone()),
+                Extension(db.clone()),
+                Extension(VerificationLimiter::new(1)),
+                Extension(verifier.clone()),
+            )
+        };
+        state.write().await.participant =
+            Some((participant.clone(), create_test_session_info(100)));
+        assert!(submit(TestContribution::ValidContribution(2))
+            .await
+            .is_err());
+        state.write().await.participant =
+            Some((participant.clone(), create_test_session_info(100)));
+        assert!(submit(TestContribution::ValidContribution(3)).await.is_ok());
+        assert_eq!(state.read().await.num_contributions, 3);
+        assert_eq!(transcript.read().await.contributions.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn transcript_stays_readable_while_a_write_is_serialized() {
+        use crate::{test_transcript::TestTranscript, test_util::init_keys};
+
+        init_keys().await;
+        let dir = std::env::temp_dir();
+        let transcript = SharedTranscript::<TestTranscript>::default();
+        // The write can't serialize the transcript while it is locked
+        let locked = transcript.write().await;
+        let write = tokio::spawn(write_transcript_file(
+            dir.join("serializing_transcript.json"),
+            dir.join("serializing_transcript.json.new"),
+            dir.join("serializing_transcript.json.sig"),
+            None,
+            transcript.clone(),
+        ));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(committed_transcript(Duration::from_millis(50))
+            .await
+            .is_some());
+
+        drop(locked);
+        write.await.unwrap();
+        let signature = read_transcript_signature(dir.join("serializing_transcript.json.sig"))
+            .await
+            .unwrap();
+        let (_, file_hash) = transcript_file_digest(dir.join("serializing_transcript.json"))
+            .await
+            .unwrap();
+        assert_eq!(signature.transcript_hash, file_hash);
+    }
 }
@@ -0,0 +1,71 @@
+use k256::{
+    ecdsa::{recoverable, VerifyingKey},
+    elliptic_curve::sec1::ToEncodedPoint,
+};
+use sha3::{Digest, Keccak256};
+
+// Prefix identifying the uid of identities authenticated through SIWE
+pub const ETH_UID_PREFIX: &str = "eth | ";
+
+// Wraps the message like `personal_sign`, so wallets can produce the signature
+fn personal_message(message: &[u8]) -> Vec<u8> {
+    let mut prefixed = format!("\x19Ethereum Signed Message:\n{}", message.len()).into_bytes();
+    prefixed.extend_from_slice(message);
+    prefixed
+}
+
+// Returns the 0x prefixed, lowercase address of the key
+pub fn address(key: &VerifyingKey) -> String {
+    let point = key.to_encoded_point(false);
+    let hash = Keccak256::digest(&point.as_bytes()[1..]);
+    format!("0x{}", hex::encode(&hash[12..]))
+}
+
+// Recovers the address that produced a hex encoded, 65 byte `personal_sign`
+// signature over `message`
+pub fn recover_address(message: &[u8], signature: &str) -> Option<String> {
+    let mut bytes = hex::decode(signature.trim_start_matches("0x")).ok()?;
+    if bytes.len() != 65 {
+        return None;
+    }
+    // Wallets encode the recovery id as 27 or 28
+    if bytes[64] >= 27 {
+        bytes[64] -= 27;
+    }
+    let signature = recoverable::Signature::try_from(bytes.as_slice()).ok()?;
+    let key = signature
+        .recover_verifying_key(&personal_message(message))
+        .ok()?;
+    Some(address(&key))
+}
+
+// Signs like a wallet's `personal_sign`
+#[cfg(test)]
+pub fn personal_sign(key: &k256::ecdsa::SigningKey, message: &[u8]) -> String {
+    use k256::ecdsa::signature::Signer;
+
+    let signature: recoverable::Signature = key.sign(&personal_message(message));
+    let mut bytes = signature.as_ref().to_vec();
+    bytes[64] += 27;
+    format!("0x{}", hex::encode(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k256::ecdsa::SigningKey;
+
+    #[test]
+    fn recovers_signing_address() {
+        let key = SigningKey::from_bytes(&[1; 32]).unwrap();
+        let signature = personal_sign(&key, b"hello");
+
+        let expected = address(&key.verifying_key());
+        assert_eq!(
+            recover_address(b"hello", &signature),
+            Some(expected.clone())
+        );
+        assert_ne!(recover_address(b"other", &signature), Some(expected));
+        assert_eq!(recover_address(b"hello", "0x1234"), None);
+    }
+}
@@ -0,0 +1,17 @@
+use crate::{
+    test_transcript::{TestContribution, TestTranscript},
+    AppState, Transcript,
+};
+
+// Appends `count` valid dummy contributions to `transcript` and catches the
+// ceremony state up with it, so tests can start mid-ceremony or close to the
+// end without driving every contribution through the sequencer. The dummy
+// contributions have negative receipts, so they don't collide with the pubkeys
+// of contributions a test submits itself.
+pub fn fast_forward(app_state: &mut AppState, transcript: &mut TestTranscript, count: usize) {
+    for _ in 0..count {
+        let index = i64::try_from(transcript.contributions.len()).expect("too many contributions");
+        *transcript = transcript.update(&TestContribution::ValidContribution(-index - 1));
+    }
+    app_state.resume_from(transcript);
+}
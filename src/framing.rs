@@ -0,0 +1,349 @@
+use std::{
+    convert::Infallible,
+    io::{self, Read},
+};
+
+use axum::body::Bytes;
+use futures::{Stream, StreamExt};
+use serde::de::DeserializeOwned;
+use tokio::sync::mpsc;
+
+// Largest frame a client may send. Frames are forwarded to the decoder as they
+// arrive, so this only bounds how much a single frame header can announce.
+pub const MAX_FRAME_SIZE: usize = 1 << 20;
+
+// Number of body chunks buffered between the request and the decoder
+const FRAME_CHANNEL_CAPACITY: usize = 4;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum FramingError {
+    // The body ended in the middle of a frame, or could not be read
+    MalformedFrame,
+    // Contains the announced frame size
+    FrameTooLarge(usize),
+    // The frames together exceed the size limit, which it contains
+    BodyTooLarge(usize),
+    // The frames did not contain a valid encoding
+    InvalidEncoding,
+}
+
+// Decodes a JSON value from a body made of frames, each a big-endian `u32`
+// length followed by that many bytes. The JSON is parsed while the body is
+// still arriving, so the raw body is never held in memory as a whole, and
+// decoding stops on the first malformed frame, or once the payloads exceed
+// `max_size` bytes.
+pub async fn decode_framed<T, S, E>(body: S, max_size: usize) -> Result<T, FramingError>
+where
+    T: DeserializeOwned + Send + 'static,
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+{
+    let decoded = decode_framed_checked(body, max_size, |_| Ok::<_, Infallible>(())).await?;
+    Ok(decoded.unwrap_or_else(|never| match never {}))
+}
+
+// Like `decode_framed`, but every JSON string is passed to `check` as soon as
+// it has been read. The first string `check` rejects stops decoding, and no
+// more of the body is read. The rejection is returned as the inner error.
+pub async fn decode_framed_checked<T, S, E, C, V>(
+    mut body: S,
+    max_size: usize,
+    check: C,
+) -> Result<Result<T, V>, FramingError>
+where
+    T: DeserializeOwned + Send + 'static,
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+    C: FnMut(&str) -> Result<(), V> + Send + 'static,
+    V: Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel(FRAME_CHANNEL_CAPACITY);
+    let decoder = tokio::task::spawn_blocking(move || {
+        let mut reader = FrameReader {
+            receiver,
+            current: Bytes::new(),
+            strings: StringScanner::default(),
+            check,
+            rejection: None,
+        };
+        let decoded = serde_json::from_reader::<_, T>(&mut reader);
+        (decoded, reader.rejection)
+    });
+
+    let framing = async {
+        let mut header = Vec::with_capacity(4);
+        let mut remaining = 0_usize;
+        let mut received = 0_usize;
+        loop {
+            let chunk = tokio::select! {
+                chunk = body.next() => chunk,
+                // The decoder already stopped, it reports why
+                () = sender.closed() => return Ok(()),
+            };
+            let mut chunk = match chunk {
+                Some(chunk) => chunk.map_err(|_| FramingError::MalformedFrame)?,
+                None => break,
+            };
+            while !chunk.is_empty() {
+                if remaining == 0 {
+                    let needed = 4 - header.len();
+                    let taken = chunk.split_to(needed.min(chunk.len()));
+                    header.extend_from_slice(&taken);
+                    if header.len() < 4 {
+                        continue;
+                    }
+                    let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+                    let length = usize::try_from(length).unwrap_or(usize::MAX);
+                    header.clear();
+                    if length > MAX_FRAME_SIZE {
+                        return Err(FramingError::FrameTooLarge(length));
+                    }
+                    remaining = length;
+                    continue;
+                }
+                let payload = chunk.split_to(remaining.min(chunk.len()));
+                remaining -= payload.len();
+                received += payload.len();
+                if received > max_size {
+                    return Err(FramingError::BodyTooLarge(max_size));
+                }
+                if sender.send(Ok(payload)).await.is_err() {
+                    return Ok(());
+                }
+            }
+        }
+        if remaining > 0 || !header.is_empty() {
+            return Err(FramingError::MalformedFrame);
+        }
+        Ok(())
+    }
+    .await;
+
+    if framing.is_err() {
+        sender
+            .send(Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "malformed frame",
+            )))
+            .await
+            .ok();
+    }
+    drop(sender);
+    let (decoded, rejection) = decoder.await.expect("frame decoder panicked");
+    if let Some(rejection) = rejection {
+        return Ok(Err(rejection));
+    }
+    framing?;
+    decoded.map(Ok).map_err(|_| FramingError::InvalidEncoding)
+}
+
+// Blocking reader over the frame payloads forwarded by `decode_framed`, which
+// checks the strings in the payloads as they pass through
+struct FrameReader<C, V> {
+    receiver:  mpsc::Receiver<io::Result<Bytes>>,
+    current:   Bytes,
+    strings:   StringScanner,
+    check:     C,
+    rejection: Option<V>,
+}
+
+impl<C, V> Read for FrameReader<C, V>
+where
+    C: FnMut(&str) -> Result<(), V>,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.current.is_empty() {
+            match self.receiver.blocking_recv() {
+                Some(payload) => self.current = payload?,
+                None => return Ok(0),
+            }
+        }
+        let read = self.current.split_to(buf.len().min(self.current.len()));
+        for &byte in &read {
+            if let Some(string) = self.strings.push(byte) {
+                if let Err(rejection) = (self.check)(&string) {
+                    self.rejection = Some(rejection);
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "rejected by check",
+                    ));
+                }
+            }
+        }
+        buf[..read.len()].copy_from_slice(&read);
+        Ok(read.len())
+    }
+}
+
+// Picks the string literals out of JSON fed to it byte by byte. Escapes are
+// kept as they are, as the strings of interest are hex encoded.
+#[derive(Default)]
+struct StringScanner {
+    in_string: bool,
+    escaped:   bool,
+    current:   Vec<u8>,
+}
+
+impl StringScanner {
+    // Returns the string that `byte` completes, if any
+    fn push(&mut self, byte: u8) -> Option<String> {
+        if !self.in_string {
+            self.in_string = byte == b'"';
+            return None;
+        }
+        if self.escaped {
+            self.escaped = false;
+        } else if byte == b'\\' {
+            self.escaped = true;
+        } else if byte == b'"' {
+            self.in_string = false;
+            let string = std::mem::take(&mut self.current);
+            // Invalid UTF-8 fails decoding anyway
+            return String::from_utf8(string).ok();
+        }
+        self.current.push(byte);
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::stream;
+    use serde_json::{json, Value};
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+    use tokio::time::{timeout, Duration};
+
+    fn frames(payload: &[u8], frame_size: usize) -> Vec<u8> {
+        payload
+            .chunks(frame_size)
+            .flat_map(|frame| {
+                let length = u32::try_from(frame.len()).unwrap().to_be_bytes();
+                length.into_iter().chain(frame.iter().copied())
+            })
+            .collect()
+    }
+
+    // Splits the body at arbitrary points, unrelated to the frame boundaries
+    fn body(bytes: Vec<u8>, chunk_size: usize) -> impl Stream<Item = Result<Bytes, Infallible>> {
+        let chunks = bytes
+            .chunks(chunk_size)
+            .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+            .collect::<Vec<_>>();
+        stream::iter(chunks)
+    }
+
+    #[tokio::test]
+    async fn streaming_decode_matches_buffered_decode() {
+        let powers = (0..20_000)
+            .map(|i| format!("0x{:096x}", i))
+            .collect::<Vec<_>>();
+        let contribution = json!({ "powers": powers, "pubkey": "0x1234" });
+        let encoded = serde_json::to_vec(&contribution).unwrap();
+
+        let buffered = serde_json::from_slice::<Value>(&encoded).unwrap();
+        let streamed =
+            decode_framed::<Value, _, _>(body(frames(&encoded, 65_536), 7_919), usize::MAX)
+                .await
+                .unwrap();
+        assert_eq!(streamed, buffered);
+    }
+
+    #[tokio::test]
+    async fn rejects_malformed_frames() {
+        let encoded = serde_json::to_vec(&json!({ "powers": ["0x00", "0x01"] })).unwrap();
+
+        let mut truncated = frames(&encoded, 8);
+        truncated.truncate(truncated.len() - 3);
+        assert_eq!(
+            decode_framed::<Value, _, _>(body(truncated, 5), usize::MAX).await,
+            Err(FramingError::MalformedFrame)
+        );
+
+        let oversized = u32::MAX.to_be_bytes().to_vec();
+        assert_eq!(
+            decode_framed::<Value, _, _>(body(oversized, 2), usize::MAX).await,
+            Err(FramingError::FrameTooLarge(u32::MAX as usize))
+        );
+
+        assert_eq!(
+            decode_framed::<Value, _, _>(body(frames(b"[1, 2", 2), 3), usize::MAX).await,
+            Err(FramingError::InvalidEncoding)
+        );
+
+        // Small frames can't add up to more than the limit either
+        assert_eq!(
+            decode_framed::<Value, _, _>(body(frames(&encoded, 4), 3), encoded.len() - 1).await,
+            Err(FramingError::BodyTooLarge(encoded.len() - 1))
+        );
+        assert!(
+            decode_framed::<Value, _, _>(body(frames(&encoded, 4), 3), encoded.len())
+                .await
+                .is_ok()
+        );
+    }
+
+    #[tokio::test]
+    async fn bad_point_aborts_upload_midway() {
+        let powers = (0..1_000)
+            .map(|i| {
+                if i == 10 {
+                    "0xbad".to_string()
+                } else {
+                    format!("0x{:096x}", i)
+                }
+            })
+            .collect::<Vec<_>>();
+        let encoded = serde_json::to_vec(&json!({ "powers": powers })).unwrap();
+
+        // The client never finishes sending the body
+        let pulled = Arc::new(AtomicUsize::new(0));
+        let counter = pulled.clone();
+        let chunks = body(frames(&encoded, 512), 256)
+            .inspect(move |_| {
+                counter.fetch_add(1, Ordering::SeqCst);
+            })
+            .chain(stream::pending());
+        let chunk_count = frames(&encoded, 512).len() / 256;
+
+        let decoded = timeout(
+            Duration::from_secs(5),
+            decode_framed_checked::<Value, _, _, _, _>(Box::pin(chunks), usize::MAX, |point| {
+                if point == "0xbad" {
+                    Err(point.to_string())
+                } else {
+                    Ok(())
+                }
+            }),
+        )
+        .await
+        .expect("decoding should stop at the bad point");
+        assert_eq!(decoded, Ok(Err("0xbad".to_string())));
+        assert!(pulled.load(Ordering::SeqCst) < chunk_count / 2);
+    }
+
+    #[tokio::test]
+    async fn checked_decode_sees_every_string() {
+        let encoded = serde_json::to_vec(&json!({ "powers": ["0x00", "0x\"01"] })).unwrap();
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = seen.clone();
+        let decoded = decode_framed_checked::<Value, _, _, _, _>(
+            body(frames(&encoded, 3), 2),
+            usize::MAX,
+            move |string| {
+                recorded.lock().unwrap().push(string.to_string());
+                Ok::<_, Infallible>(())
+            },
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(decoded, json!({ "powers": ["0x00", "0x\"01"] }));
+        assert_eq!(*seen.lock().unwrap(), vec![
+            "powers".to_string(),
+            "0x00".to_string(),
+            "0x\\\"01".to_string()
+        ]);
+    }
+}
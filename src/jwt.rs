@@ -1,16 +1,32 @@
 pub mod errors;
 use errors::JwtError;
 
-use crate::keys::KEYS;
-use serde::{Deserialize, Serialize};
+use crate::{
+    constants::RESUME_TOKEN_LIFETIME_SEC, data::hash::TranscriptHash, keys::KEYS, SessionId,
+};
+use chrono::Utc;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::time::Duration;
 
 // Receipt for contributor that sequencer has
-// included their contribution
-#[derive(Serialize)]
+// included their contribution. It pins the state the contribution was
+// verified against, so anyone holding the prior transcript and the
+// contribution can re-derive `transcript_hash_after`.
+#[derive(Serialize, Deserialize)]
 pub struct Receipt<T: Serialize> {
-    pub(crate) id_token: IdToken,
+    // Unset for anonymous participants
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) id_token: Option<IdToken>,
 
     pub witness: T,
+
+    // Hashes of the transcript right before and after the contribution was
+    // applied, see `transcript_hash`
+    pub transcript_hash_before: TranscriptHash,
+    pub transcript_hash_after:  TranscriptHash,
+
+    // The verification checks the contribution passed
+    pub checks: Vec<String>,
 }
 
 impl<T: Serialize> Receipt<T> {
@@ -22,6 +38,18 @@ impl<T: Serialize> Receipt<T> {
     }
 }
 
+impl<T: Serialize + DeserializeOwned> Receipt<T> {
+    // Fails unless the receipt is exactly as signed by the sequencer
+    pub fn decode(token: &str) -> Result<Self, JwtError> {
+        let token_data = KEYS
+            .get()
+            .unwrap()
+            .decode_unexpiring(token)
+            .map_err(|_| JwtError::InvalidToken)?;
+        Ok(token_data.claims)
+    }
+}
+
 // This is the JWT token that the sequencer will hand out to contributors
 // after they have authenticated through oAUTH
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -60,3 +88,44 @@ impl IdToken {
         Ok(token_data.claims)
     }
 }
+
+// Short-lived token handed out when joining the lobby. It lets a client that
+// lost its session id reclaim its lobby entry, as long as it was not evicted
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResumeToken {
+    pub session_id: SessionId,
+    // Place in the lobby, counting from 0, when the token was issued. Anyone
+    // ahead leaving moves the session up, so it is only a snapshot, and
+    // resuming hands out a token with the place at that time.
+    pub position:   usize,
+    pub exp:        u64,
+}
+
+impl ResumeToken {
+    pub fn new(session_id: SessionId, position: usize) -> Self {
+        let exp = Utc::now().timestamp().unsigned_abs() + RESUME_TOKEN_LIFETIME_SEC as u64;
+        Self {
+            session_id,
+            position,
+            exp,
+        }
+    }
+
+    pub fn encode(&self) -> Result<String, JwtError> {
+        KEYS.get()
+            .unwrap()
+            .encode(self)
+            .map_err(|_| JwtError::TokenCreation)
+    }
+
+    // Fails for tokens that were not signed by us or that have expired more
+    // than `clock_skew` ago
+    pub fn decode(token: &str, clock_skew: Duration) -> Result<Self, JwtError> {
+        let token_data = KEYS
+            .get()
+            .unwrap()
+            .decode_with_leeway(token, clock_skew)
+            .map_err(|_| JwtError::InvalidToken)?;
+        Ok(token_data.claims)
+    }
+}
@@ -1,11 +1,12 @@
 use clap::Parser;
 use eyre::Result;
 use jsonwebtoken::{
-    decode, encode, Algorithm, DecodingKey, EncodingKey, Header, TokenData, Validation,
+    crypto, decode, encode, Algorithm, DecodingKey, EncodingKey, Header, TokenData, Validation,
 };
 use once_cell::sync::OnceCell;
+use ring::digest::{digest, SHA256};
 use serde::{de::DeserializeOwned, Serialize};
-use std::{path::PathBuf, str::FromStr};
+use std::{path::PathBuf, str::FromStr, time::Duration};
 use tokio::try_join;
 use tracing::info;
 
@@ -55,6 +56,47 @@ impl Keys {
         decode::<T>(token, &self.decoding, &Validation::new(Self::alg()))
     }
 
+    // Like `decode`, but also checks `nbf`, and tolerates clients whose clock
+    // is off by up to `leeway` when checking `exp` and `nbf`
+    pub fn decode_with_leeway<T: DeserializeOwned>(
+        &self,
+        token: &str,
+        leeway: Duration,
+    ) -> Result<TokenData<T>, jsonwebtoken::errors::Error> {
+        let mut validation = Validation::new(Self::alg());
+        validation.leeway = leeway.as_secs();
+        validation.validate_nbf = true;
+        decode::<T>(token, &self.decoding, &validation)
+    }
+
+    // Like `decode`, for tokens such as receipts that don't expire
+    pub fn decode_unexpiring<T: DeserializeOwned>(
+        &self,
+        token: &str,
+    ) -> Result<TokenData<T>, jsonwebtoken::errors::Error> {
+        let mut validation = Validation::new(Self::alg());
+        validation.required_spec_claims.clear();
+        validation.validate_exp = false;
+        decode::<T>(token, &self.decoding, &validation)
+    }
+
+    // Returns the base64 encoded signature of `message`
+    pub fn sign(&self, message: &[u8]) -> Result<String, jsonwebtoken::errors::Error> {
+        crypto::sign(message, &self.encoding, Self::alg())
+    }
+
+    pub fn verify(&self, signature: &str, message: &[u8]) -> bool {
+        crypto::verify(signature, message, &self.decoding, Self::alg()).unwrap_or(false)
+    }
+
+    // Signs the canonical encoding of `value`, see `canonical_json`
+    pub fn sign_canonical<T: Serialize>(
+        &self,
+        value: &T,
+    ) -> Result<String, jsonwebtoken::errors::Error> {
+        self.sign(&canonical_json(value))
+    }
+
     pub const fn alg_str() -> &'static str {
         "PS256"
     }
@@ -66,6 +108,22 @@ impl Keys {
     pub fn decode_key_to_string(&self) -> String {
         self.pubkey.clone()
     }
+
+    // Identifies the signing key, as the hex encoded SHA256 of its public key
+    pub fn key_id(&self) -> String {
+        hex::encode(digest(&SHA256, self.pubkey.as_bytes()))
+    }
+}
+
+// The encoding signed documents are signed in, which clients reproduce from
+// the JSON they received: no whitespace, the keys of every object sorted
+// bytewise, and strings escaped only where JSON requires it. For the ASCII
+// keys, strings and integers the sequencer signs, this is the JSON
+// Canonicalization Scheme of RFC 8785.
+pub fn canonical_json<T: Serialize>(value: &T) -> Vec<u8> {
+    // Objects are collected in sorted maps, as `preserve_order` is off
+    let value = serde_json::to_value(value).expect("Cannot serialize signed value");
+    serde_json::to_vec(&value).expect("Cannot serialize signed value")
 }
 
 #[cfg(test)]
@@ -73,6 +131,23 @@ mod tests {
     use super::*;
     use serde::Deserialize;
 
+    #[test]
+    fn canonical_json_sorts_keys_without_whitespace() {
+        #[derive(Serialize)]
+        struct Signed {
+            zeta:  u64,
+            alpha: serde_json::Value,
+        }
+        let value = Signed {
+            zeta:  1,
+            alpha: serde_json::json!({ "b": "x\ny", "a": [2, 1] }),
+        };
+        assert_eq!(
+            String::from_utf8(canonical_json(&value)).unwrap(),
+            r#"{"alpha":{"a":[2,1],"b":"x\ny"},"zeta":1}"#
+        );
+    }
+
     #[ignore] // Do not run this test by default due to dependency on files.
     #[tokio::test]
     async fn encode_decode_pem() {
@@ -9,62 +9,134 @@
 use std::{
     collections::{BTreeMap, BTreeSet},
     env,
+    fmt::Display,
+    io::ErrorKind,
     net::{IpAddr, Ipv4Addr, SocketAddr},
     ops::Deref,
     path::PathBuf,
+    str::FromStr,
     sync::Arc,
     time::Duration,
 };
 
-use crate::data::transcript::read_transcript_file;
+use crate::data::{
+    hash::{HashAlgorithm, TranscriptHash, HASH_ALGORITHM},
+    objects::TranscriptObjects,
+    transcript::{
+        import_transcript, read_transcript_file, read_transcript_signature, transcript_hash,
+        validate_transcript_path, write_transcript_file,
+    },
+};
 use axum::{
+    body::Body,
+    error_handling::HandleErrorLayer,
     extract::Extension,
+    http::{HeaderMap, Request},
+    middleware::{from_fn, Next},
     response::Html,
-    routing::{get, post},
+    routing::{get, patch, post},
     Router, Server,
 };
-use chrono::{DateTime, FixedOffset};
+use chrono::{DateTime, FixedOffset, Utc};
 use clap::Parser;
-use cli_batteries::{await_shutdown, version};
+use cli_batteries::{await_shutdown, shutdown, version};
 use eyre::{bail, ensure, eyre, Result as EyreResult};
+use indexmap::IndexMap;
+use merkle::OrderCommitment;
 use oauth2::{basic::BasicClient, AuthUrl, ClientId, ClientSecret, RedirectUrl, TokenUrl};
-use sessions::{SessionId, SessionInfo};
-use storage::persistent_storage_client;
+use preflight::{preflight, ConfigProblems};
+use semver::VersionReq;
+use sessions::{CheckinPacer, SessionId, SessionInfo};
+use storage::{persistent_storage_client, CeremonyPhase};
 use tokio::{
-    sync::RwLock,
+    sync::{oneshot, Notify, RwLock},
+    task::JoinHandle,
     time::{Instant, Interval},
 };
+use tower::ServiceBuilder;
 use tower_http::trace::TraceLayer;
-use tracing::info;
+use tracing::{info, warn};
 use url::{Host, Url};
 
 use crate::{
+    admission::AdmissionHook,
+    allowlist::{read_allowlist, read_denylist},
     api::v1::{
-        auth::{auth_client_link, github_callback, siwe_callback},
-        contribute::contribute,
-        info::{current_state, jwt_info, status},
-        lobby::try_contribute,
+        admin::{
+            await_drained, caches, clear_caches, drain, drain_status, expire_current,
+            export_attestations, extend_deadline, lobby_stats, mint_invite_codes, reload_allowlist,
+            reload_denylist, reverify, reverify_status, transition_phase, tuning, Drain,
+            LobbyStats, Reverification,
+        },
+        auth::{
+            auth_client_link, callback, join_anonymously, AnonymousJoinLimiter, PendingSession,
+        },
+        contribute::{
+            attestation_journal_path, challenge, contribute_json, contribute_stream,
+            contribution_bundle, contribution_receipt, heartbeat, read_attestation_journal,
+            retry_attestations_on_interval, ContributionBundle, PendingAttestation,
+        },
+        format::format_json,
+        identity::IdentityProviders,
+        info::{
+            attestation_chain_head, counters, current_state, current_state_head, dashboard,
+            has_contributed, jwt_info, manifest, order_commitment, order_proof, parameters, ready,
+            status, transcript_signature, Counters, LookupLimiter,
+        },
+        limits::{allow_methods, limit_header_size, READ_METHODS, SUBMIT_METHODS, UPLOAD_METHODS},
+        lobby::{join, resume, try_contribute, SlotOutcome},
+        response_headers::{add_response_headers, parse_response_headers},
+        sse::sse_status,
+        timing::{pad_error_responses, release_slot_on_timeout, timed_out},
+        upload::{
+            append_chunk, commit_upload, create_upload, remove_upload_files, upload_offset, Uploads,
+        },
     },
+    connections::{ConnectionLimit, ExcessConnections},
     constants::{
+        ATTESTATION_RETRY_INTERVAL_SEC, COMPUTE_TIMES_WINDOW, FINISHED_SESSION_RETENTION_SEC,
         GITHUB_OAUTH_AUTH_URL, GITHUB_OAUTH_REDIRECT_URL, GITHUB_OAUTH_TOKEN_URL,
-        LOBBY_CHECKIN_FREQUENCY_SEC, LOBBY_CHECKIN_TOLERANCE_SEC, LOBBY_FLUSH_INTERVAL,
-        SIWE_OAUTH_AUTH_URL, SIWE_OAUTH_REDIRECT_URL, SIWE_OAUTH_TOKEN_URL,
+        LOBBY_FLUSH_INTERVAL, PENDING_SESSION_TTL_SEC, SIWE_OAUTH_AUTH_URL,
+        SIWE_OAUTH_REDIRECT_URL, SIWE_OAUTH_TOKEN_URL, VERIFICATION_CACHE_TTL_SEC,
     },
-    data::transcript::{Contribution, Transcript},
+    data::transcript::{Contribution, Transcript, VerifyAllError},
     keys::Keys,
-    test_transcript::TestTranscript,
+    verification::{
+        CachedVerifier, FullVerifier, PreverifiedVerifier, SharedCache, SharedVerifier,
+        VerificationLimiter,
+    },
 };
 
+mod admission;
+mod allowlist;
 mod api;
+mod attestation_chain;
+mod connections;
 mod constants;
 mod data;
+mod ethereum;
+#[cfg(any(test, feature = "test-util"))]
+pub mod fast_forward;
+mod framing;
 mod jwt;
 mod keys;
+mod merkle;
+mod preflight;
 mod sessions;
 mod storage;
+#[cfg(feature = "test-util")]
+pub mod test_transcript;
+#[cfg(not(feature = "test-util"))]
 mod test_transcript;
 #[cfg(test)]
 mod test_util;
+mod verification;
+
+// Transcripts for `fast_forward` in tests of downstream crates
+#[cfg(not(feature = "test-util"))]
+use test_transcript::TestTranscript;
+#[cfg(feature = "test-util")]
+pub use test_transcript::{TestContribution, TestTranscript};
 
 pub type SharedTranscript<T> = Arc<RwLock<T>>;
 pub(crate) type SharedState = Arc<RwLock<AppState>>;
@@ -77,6 +149,20 @@ pub struct Options {
 
     #[clap(flatten)]
     pub keys: keys::Options,
+
+    /// Transcript file or URL to continue the ceremony from, for example when
+    /// migrating from another sequencer
+    #[clap(long, env)]
+    pub import_transcript: Option<String>,
+
+    /// Import even if the local transcript already has contributions
+    #[clap(long)]
+    pub force: bool,
+
+    /// Validate the configuration, keys, storage and transcript, then exit
+    /// without starting the server
+    #[clap(long)]
+    pub dry_run: bool,
 }
 
 #[allow(dead_code)] // Entry point
@@ -89,8 +175,9 @@ fn main() {
 
 async fn async_main<T>(options: Options) -> EyreResult<()>
 where
-    T: Transcript + Send + Sync + 'static,
-    T::ContributionType: Send,
+    T: Transcript + Clone + Send + Sync + 'static,
+    T::ContributionType: Send + 'static,
+    T::ValidationError: Send,
     <<T as Transcript>::ContributionType as Contribution>::Receipt: Send,
 {
     // Load JWT keys
@@ -99,43 +186,385 @@ where
         .map_err(|_e| eyre!("KEYS was already set."))?;
 
     let shared_state = SharedState::default();
-    let config = AppConfig::default();
-    let transcript_data = read_transcript_file::<T>(config.transcript_file.clone()).await;
+    // Report every problem with the config before touching anything
+    let mut config = AppConfig::from_env()?;
+    let verification_limiter = VerificationLimiter::new(config.max_concurrent_verifications);
+    HASH_ALGORITHM
+        .set(config.transcript_hash_algorithm)
+        .map_err(|_e| eyre!("HASH_ALGORITHM was already set."))?;
+    if options.dry_run {
+        let database_url = env::var("DATABASE_URL").map_err(|_| eyre!("Missing DATABASE_URL"))?;
+        let report = preflight::<T>(
+            &config,
+            keys::KEYS.get().unwrap(),
+            &verification_limiter,
+            &database_url,
+        )
+        .await?;
+        println!("{}", report);
+        return Ok(());
+    }
+    // Fail at startup rather than on the first transcript request
+    config.transcript_file = validate_transcript_path(&config.transcript_file)?;
+    // A new ceremony starts from the configured initial transcript, and
+    // transcripts that are not signed by us are verified from it
+    let initial = T::initial(&config)?;
+    let transcript_exists = tokio::fs::metadata(&config.transcript_file).await.is_ok();
+    let transcript_data = if let Some(source) = &options.import_transcript {
+        if transcript_exists && !options.force {
+            let local = read_transcript_file::<T>(config.transcript_file.clone()).await;
+            ensure!(
+                local.num_contributions() == 0,
+                "Refusing to import over a transcript with {} contributions, pass --force to \
+                 replace it",
+                local.num_contributions()
+            );
+        }
+        info!(source = %source, "Importing transcript");
+        import_transcript::<T>(source, &initial, &config.ceremony_sizes).await?
+    } else if transcript_exists {
+        read_transcript_file::<T>(config.transcript_file.clone()).await
+    } else {
+        info!(path = ?config.transcript_file, "Creating initial transcript");
+        initial.clone()
+    };
+    shared_state.write().await.resume_from(&transcript_data);
     let transcript = Arc::new(RwLock::new(transcript_data));
+    if !transcript_exists || options.import_transcript.is_some() {
+        write_transcript_file(
+            config.transcript_file.clone(),
+            config.transcript_in_progress_file.clone(),
+            config.transcript_signature_file.clone(),
+            config.transcript_objects.clone(),
+            transcript.clone(),
+        )
+        .await;
+    } else {
+        match read_transcript_signature(config.transcript_signature_file.clone()).await {
+            // Refuse to serve a transcript that was modified behind our back
+            Ok(signature) => ensure!(
+                transcript
+                    .read()
+                    .await
+                    .verify_signature(keys::KEYS.get().unwrap(), &signature),
+                "Transcript signature does not match the transcript"
+            ),
+            // Transcripts written before they were signed are checked in
+            // full instead, and signed from then on
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                warn!(path = ?config.transcript_file, "Transcript is not signed, verifying it");
+                let verified = verification_limiter
+                    .verify_all(transcript.clone().read_owned().await, initial.clone())
+                    .await;
+                match verified {
+                    Ok(()) => {}
+                    Err(VerifyAllError::TamperedInitialState) => {
+                        bail!("Unsigned transcript does not start from the initial state")
+                    }
+                    Err(VerifyAllError::InvalidContribution(index, _)) => bail!(
+                        "Unsigned transcript does not verify at contribution {}",
+                        index
+                    ),
+                }
+                write_transcript_file(
+                    config.transcript_file.clone(),
+                    config.transcript_in_progress_file.clone(),
+                    config.transcript_signature_file.clone(),
+                    config.transcript_objects.clone(),
+                    transcript.clone(),
+                )
+                .await;
+            }
+            Err(e) => bail!("Cannot read transcript signature: {}", e),
+        }
+    }
+    let storage = persistent_storage_client(&config).await;
+    if !storage.has_identity_key() {
+        warn!(
+            "IDENTITY_ENCRYPTION_KEY is not set, attestations refer to identities by their \
+             unkeyed hash"
+        );
+    }
+    shared_state.write().await.phase = storage
+        .current_phase()
+        .await
+        .map_err(|e| eyre!("Cannot read ceremony phase: {:?}", e))?;
+    let relinked = storage
+        .chain_legacy_attestations()
+        .await
+        .map_err(|e| eyre!("Cannot chain attestations: {:?}", e))?;
+    if relinked > 0 {
+        warn!(
+            relinked,
+            "Linked attestations stored before they were chained"
+        );
+    }
+    // Attestations of acknowledged contributions that were not stored yet
+    // are replayed by the first retry
+    let attestation_journal = attestation_journal_path(&config.transcript_file);
+    let journal = read_attestation_journal(&attestation_journal)
+        .await
+        .map_err(|e| eyre!("Cannot read attestation journal: {}", e))?;
+    let chain_head = match journal.chain_head {
+        Some(head) if relinked == 0 => Some(head),
+        _ => storage
+            .attestation_chain_head()
+            .await
+            .map_err(|e| eyre!("Cannot read attestation chain: {:?}", e))?,
+    };
+    shared_state.write().await.attestation_chain_head = chain_head;
+    shared_state.write().await.pending_attestations = journal.pending;
+    if config.order_commitment {
+        let mut app_state = shared_state.write().await;
+        let phase = app_state.phase.as_ref().map_or(0, |phase| phase.phase);
+        let stored = storage
+            .receipts_of_phase(phase)
+            .await
+            .map_err(|e| eyre!("Cannot read receipts: {:?}", e))?;
+        app_state.restore_order_commitment(stored);
+        if app_state.order_commitment.len() != app_state.num_contributions {
+            warn!(
+                committed = app_state.order_commitment.len(),
+                contributions = app_state.num_contributions,
+                "Order commitment does not cover every contribution, as some receipts were not \
+                 stored"
+            );
+        }
+    }
+    let recovered = {
+        let app_state = shared_state.read().await;
+        let pending = app_state
+            .pending_attestations
+            .iter()
+            .map(|pending| pending.attestation().clone())
+            .collect::<Vec<_>>();
+        storage
+            .recover_orphaned_reservations(
+                &app_state.seen_pubkeys,
+                app_state.num_contributions,
+                &pending,
+            )
+            .await
+            .map_err(|e| eyre!("Cannot recover reservations: {:?}", e))?
+    };
+    if recovered.released > 0 {
+        info!(
+            released = recovered.released,
+            "Released reservations left open by a previous run"
+        );
+    }
+    if recovered.kept > 0 {
+        warn!(
+            kept = recovered.kept,
+            "Kept reservations left open by a previous run, as the transcript has contributions \
+             without an attestation"
+        );
+    }
+    let lookup_limiter = LookupLimiter::new(config.identity_lookups_per_minute);
+    let anonymous_join_limiter = AnonymousJoinLimiter::new(config.anonymous_joins_per_minute);
+    let verifier: SharedVerifier<T> = match &config.preverification_key {
+        Some(key) => Arc::new(PreverifiedVerifier::new(key, initial)),
+        None => Arc::new(FullVerifier::new(initial)),
+    };
+    let verifier = Arc::new(CachedVerifier::new(
+        verifier,
+        Duration::from_secs(VERIFICATION_CACHE_TTL_SEC as u64),
+    ));
+    let verification_cache: SharedCache = verifier.clone();
+    let uploads = Uploads::restore(&config.transcript_file).await;
+    let verifier: SharedVerifier<T> = verifier;
+
+    if let Some(allowlist_file) = &config.allowlist_file {
+        shared_state.write().await.allowlist = Some(read_allowlist(allowlist_file).await?);
+    }
+    if let Some(denylist_file) = &config.denylist_file {
+        shared_state.write().await.denylist = read_denylist(denylist_file).await?;
+    }
 
     let shared_state_clone = shared_state.clone();
 
     // Spawn automatic queue flusher -- flushes those in the lobby whom have not
     // pinged in a considerable amount of time
     let interval = tokio::time::interval(Duration::from_secs(LOBBY_FLUSH_INTERVAL as u64));
-    tokio::spawn(clear_lobby_on_interval(shared_state_clone, interval));
+    tokio::spawn(clear_lobby_on_interval(
+        shared_state_clone,
+        interval,
+        config.clone(),
+    ));
+
+    let interval =
+        tokio::time::interval(Duration::from_secs(ATTESTATION_RETRY_INTERVAL_SEC as u64));
+    tokio::spawn(retry_attestations_on_interval(
+        shared_state.clone(),
+        storage.clone(),
+        attestation_journal,
+        interval,
+    ));
+
+    if config.shutdown_when_drained {
+        let shared_state = shared_state.clone();
+        tokio::spawn(async move {
+            await_drained(&shared_state).await;
+            info!("Drained, shutting down");
+            shutdown();
+        });
+    }
+
+    let siwe_client = siwe_oauth_client();
+    let github_client = github_oauth_client();
+    let http_client = reqwest::Client::new();
+    let identity_providers = IdentityProviders::from_config(
+        &config,
+        github_client.clone(),
+        siwe_client.clone(),
+        http_client.clone(),
+    );
 
+    let pretty_responses = config.pretty_responses;
+    let error_response_floor = config.error_response_floor;
+    let max_header_bytes = config.max_header_bytes;
+    let contribute_timeout = config.contribute_timeout;
+    let response_headers = config.response_headers.clone();
+    let (max_connections, excess_connections) = (config.max_connections, config.excess_connections);
+    // Failures of these endpoints must not reveal their cause through timing
+    let padded = Router::new()
+        .route("/auth/callback/:provider", get(callback))
+        .route("/lobby/try_contribute", post(try_contribute::<T>))
+        .route("/lobby/resume", post(resume))
+        .route("/lobby/join", post(join))
+        .layer(from_fn(move |request: Request<Body>, next: Next<Body>| {
+            pad_error_responses(error_response_floor, request, next)
+        }));
+    // Verifying contributions takes a while, so these get more time than the
+    // rest of the endpoints before timing out. The challenge is part of
+    // contributing, and times out like a submission does.
+    let challenges = Router::new()
+        .route("/contribute/challenge", get(challenge::<T>))
+        .layer(from_fn(|request: Request<Body>, next: Next<Body>| {
+            allow_methods(READ_METHODS, request, next)
+        }));
+    let contributions = Router::new()
+        .route("/contribute", post(contribute_json::<T>))
+        .route("/contribute/stream", post(contribute_stream::<T>))
+        .layer(from_fn(|request: Request<Body>, next: Next<Body>| {
+            allow_methods(SUBMIT_METHODS, request, next)
+        }))
+        .merge(challenges)
+        .layer(from_fn(move |request: Request<Body>, next: Next<Body>| {
+            release_slot_on_timeout(contribute_timeout, request, next)
+        }));
+    let uploads = Router::new()
+        .route("/contribute/upload", post(create_upload))
+        .route(
+            "/contribute/upload/:id",
+            patch(append_chunk).head(upload_offset),
+        )
+        .route("/contribute/upload/:id/commit", post(commit_upload::<T>))
+        .layer(from_fn(move |request: Request<Body>, next: Next<Body>| {
+            release_slot_on_timeout(contribute_timeout, request, next)
+        }))
+        .layer(from_fn(|request: Request<Body>, next: Next<Body>| {
+            allow_methods(UPLOAD_METHODS, request, next)
+        }));
+    let info = Router::new()
+        .route("/info/status", get(status))
+        .route("/info/jwt", get(jwt_info))
+        .route("/info/ready", get(ready))
+        .route("/info/parameters", get(parameters))
+        .route("/info/dashboard", get(dashboard))
+        .route("/info/has_contributed", get(has_contributed))
+        .route("/info/order_commitment", get(order_commitment))
+        .route("/info/attestation_chain", get(attestation_chain_head))
+        .route("/info/order_proof/:index", get(order_proof))
+        .route("/info/transcript_signature", get(transcript_signature))
+        .route("/info/manifest", get(manifest))
+        .route("/info/counters", get(counters))
+        .route(
+            "/info/current_state",
+            get(current_state::<T>).head(current_state_head),
+        )
+        .layer(
+            ServiceBuilder::new()
+                .layer(HandleErrorLayer::new(timed_out))
+                .timeout(config.info_timeout),
+        )
+        .layer(from_fn(|request: Request<Body>, next: Next<Body>| {
+            allow_methods(READ_METHODS, request, next)
+        }));
     let app = Router::new()
         .layer(TraceLayer::new_for_http())
         .route("/hello_world", get(hello_world))
         .route("/auth/request_link", get(auth_client_link))
-        .route("/auth/callback/github", get(github_callback))
-        .route("/auth/callback/siwe", get(siwe_callback))
-        .route("/lobby/try_contribute", post(try_contribute::<T>))
-        .route("/contribute", post(contribute::<T>))
-        .route("/info/status", get(status))
-        .route("/info/jwt", get(jwt_info))
-        .route("/info/current_state", get(current_state))
-        .layer(Extension(shared_state))
-        .layer(Extension(siwe_oauth_client()))
-        .layer(Extension(github_oauth_client()))
-        .layer(Extension(reqwest::Client::new()))
-        .layer(Extension(persistent_storage_client().await))
+        .route("/auth/anonymous", post(join_anonymously))
+        .merge(padded)
+        .merge(contributions)
+        .merge(uploads)
+        .merge(info)
+        .route("/contribute/heartbeat", post(heartbeat))
+        .route("/contribution/:uid/bundle", get(contribution_bundle))
+        .route("/contribution/:uid/receipt", get(contribution_receipt))
+        .route("/sse/status", get(sse_status))
+        .route("/admin/lobby_stats", get(lobby_stats))
+        .route("/admin/allowlist/reload", post(reload_allowlist))
+        .route("/admin/denylist/reload", post(reload_denylist))
+        .route("/admin/tuning", get(tuning))
+        .route("/admin/drain", post(drain))
+        .route("/admin/drain_status", get(drain_status))
+        .route("/admin/invite_codes", post(mint_invite_codes))
+        .route("/admin/expire_current", post(expire_current))
+        .route("/admin/extend_deadline", post(extend_deadline))
+        .route("/admin/phase", post(transition_phase::<T>))
+        .route("/admin/attestations/export", get(export_attestations))
+        .route("/admin/caches", get(caches))
+        .route("/admin/caches/clear", post(clear_caches))
+        .route("/admin/reverify", post(reverify::<T>))
+        .route("/admin/reverify/status", get(reverify_status))
+        .layer(from_fn(move |request: Request<Body>, next: Next<Body>| {
+            format_json(pretty_responses, request, next)
+        }))
+        .layer(from_fn(move |request: Request<Body>, next: Next<Body>| {
+            limit_header_size(max_header_bytes, request, next)
+        }))
+        // Outermost, so the headers are also added to rejected requests
+        .layer(from_fn(move |request: Request<Body>, next: Next<Body>| {
+            add_response_headers(response_headers.clone(), request, next)
+        }))
+        .layer(Extension(shared_state.clone()))
+        .layer(Extension(siwe_client))
+        .layer(Extension(github_client))
+        .layer(Extension(identity_providers))
+        .layer(Extension(http_client))
+        .layer(Extension(storage))
+        .layer(Extension(verification_limiter))
+        .layer(Extension(lookup_limiter))
+        .layer(Extension(anonymous_join_limiter))
+        .layer(Extension(uploads))
+        .layer(Extension(Reverification::default()))
+        .layer(Extension(verifier))
+        .layer(Extension(verification_cache))
         .layer(Extension(config))
         .layer(Extension(transcript));
 
     // Run the server
     let (addr, prefix) = parse_url(&options.server)?;
     let app = Router::new().nest(prefix, app);
-    let server = Server::try_bind(&addr)?.serve(app.into_make_service());
+    let server = Server::try_bind(&addr)?.serve(ConnectionLimit::new(
+        // Rate limits tell clients apart by their address
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+        max_connections,
+        excess_connections,
+    ));
     info!("Listening on http://{}{}", server.local_addr(), prefix);
     server.with_graceful_shutdown(await_shutdown()).await?;
 
+    let app_state = shared_state.read().await;
+    match &app_state.drain {
+        Some(drain) => {
+            info!(reason = %drain.reason, since = %drain.since, "Shut down after draining")
+        }
+        None => info!(reason = "signal", "Shut down"),
+    }
+
     Ok(())
 }
 
@@ -153,8 +582,10 @@ impl Deref for SiweOAuthClient {
 }
 
 fn siwe_oauth_client() -> SiweOAuthClient {
-    let client_id = env::var("SIWE_CLIENT_ID").expect("Missing SIWE_CLIENT_ID!");
-    let client_secret = env::var("SIWE_CLIENT_SECRET").expect("Missing SIWE_CLIENT_SECRET!");
+    // Required if the provider is enabled, see `missing_provider_credentials`.
+    // The client of a disabled provider never completes a sign in.
+    let client_id = env::var("SIWE_CLIENT_ID").unwrap_or_default();
+    let client_secret = env::var("SIWE_CLIENT_SECRET").unwrap_or_default();
 
     let redirect_url =
         env::var("SIWE_REDIRECT_URL").unwrap_or_else(|_| SIWE_OAUTH_REDIRECT_URL.to_string());
@@ -186,8 +617,9 @@ impl Deref for GithubOAuthClient {
 }
 
 fn github_oauth_client() -> GithubOAuthClient {
-    let client_id = env::var("GITHUB_CLIENT_ID").expect("Missing GITHUB_CLIENT_ID!");
-    let client_secret = env::var("GITHUB_CLIENT_SECRET").expect("Missing GITHUB_CLIENT_SECRET!");
+    // Required if the provider is enabled, see `missing_provider_credentials`
+    let client_id = env::var("GITHUB_CLIENT_ID").unwrap_or_default();
+    let client_secret = env::var("GITHUB_CLIENT_SECRET").unwrap_or_default();
     let redirect_url =
         env::var("GITHUB_REDIRECT_URL").unwrap_or_else(|_| GITHUB_OAUTH_REDIRECT_URL.to_string());
     let auth_url =
@@ -212,30 +644,375 @@ async fn hello_world() -> Html<&'static str> {
 
 #[derive(Clone)]
 pub struct AppConfig {
-    github_max_creation_time:    DateTime<FixedOffset>,
-    eth_check_nonce_at_block:    String,
-    eth_min_nonce:               i64,
-    eth_rpc_url:                 String,
-    transcript_file:             PathBuf,
-    transcript_in_progress_file: PathBuf,
+    github_max_creation_time:     DateTime<FixedOffset>,
+    eth_check_nonce_at_block:     String,
+    eth_min_nonce:                i64,
+    eth_rpc_url:                  String,
+    identity_providers:           Vec<String>,
+    required_auth_providers:      Vec<String>,
+    transcript_file:              PathBuf,
+    transcript_in_progress_file:  PathBuf,
+    transcript_signature_file:    PathBuf,
+    transcript_objects:           Option<TranscriptObjects>,
+    initial_transcript_file:      Option<PathBuf>,
+    identity_encryption_key:      Option<Vec<u8>>,
+    max_concurrent_verifications: usize,
+    storage_pool_size:            u32,
+    storage_acquire_timeout:      Duration,
+    verify_timeout:               Duration,
+    contribute_timeout:           Duration,
+    info_timeout:                 Duration,
+    max_header_bytes:             usize,
+    max_connections:              usize,
+    excess_connections:           ExcessConnections,
+    response_headers:             HeaderMap,
+    compute_deadline:             Duration,
+    compute_deadline_per_power:   Option<Duration>,
+    compute_heartbeat_timeout:    Option<Duration>,
+    min_compute_time:             Option<Duration>,
+    max_deadline_extension:       Duration,
+    lobby_checkin_frequency:      Duration,
+    lobby_checkin_tolerance:      Duration,
+    checkin_burst:                u32,
+    first_checkin_grace:          Duration,
+    ceremony_sizes:               Vec<(usize, usize)>,
+    denied_client_versions:       Vec<VersionReq>,
+    client_upgrade_url:           Option<String>,
+    admin_token:                  Option<String>,
+    allowlist_file:               Option<PathBuf>,
+    denylist_file:                Option<PathBuf>,
+    admission_hook:               Option<AdmissionHook>,
+    rejoin_cooldown:              Option<Duration>,
+    order_commitment:             bool,
+    clock_skew:                   Duration,
+    preverification_key:          Option<Vec<u8>>,
+    pretty_responses:             bool,
+    public_contribution_bundles:  bool,
+    identity_lookups_per_minute:  u32,
+    transcript_hash_algorithm:    HashAlgorithm,
+    missing_transcript:           MissingTranscript,
+    contributed_message:          String,
+    require_invite_code:          bool,
+    anonymous_participants:       bool,
+    anonymous_joins_per_minute:   u32,
+    shutdown_when_drained:        bool,
+    strict_requests:              bool,
+    overload_lobby_size:          usize,
+    max_contributions:            Option<usize>,
+    drain_at_max_contributions:   bool,
+    session_max_lifetime:         Option<Duration>,
+    log_rejected_contributions:   bool,
+    quarantine_contributions:     bool,
+    check_contribution_entropy:   bool,
+    require_identity_signature:   bool,
+    error_response_floor:         Option<Duration>,
 }
 
-impl Default for AppConfig {
-    fn default() -> Self {
+impl AppConfig {
+    // Reads the config from the environment. Every variable that doesn't
+    // parse is reported, along with the problems `validate` finds, instead of
+    // stopping at the first one.
+    pub fn from_env() -> Result<Self, ConfigProblems> {
+        let mut vars = EnvVars::default();
         let transcript =
             env::var("TRANSCRIPT_FILE").unwrap_or_else(|_| "./transcript.json".to_string());
         let transcript_progress = format!("{}.new", transcript);
-        Self {
-            github_max_creation_time:    DateTime::parse_from_rfc3339(
+        let transcript_signature =
+            env::var("TRANSCRIPT_SIGNATURE_FILE").unwrap_or_else(|_| format!("{}.sig", transcript));
+        let config = Self {
+            github_max_creation_time:     DateTime::parse_from_rfc3339(
                 constants::GITHUB_ACCOUNT_CREATION_DEADLINE,
             )
             .unwrap(),
-            eth_check_nonce_at_block:    constants::ETH_CHECK_NONCE_AT_BLOCK.to_string(),
-            eth_min_nonce:               constants::ETH_MIN_NONCE,
-            eth_rpc_url:                 env::var("ETH_RPC_URL").expect("Missing ETH_RPC_URL"),
-            transcript_file:             PathBuf::from(transcript),
-            transcript_in_progress_file: PathBuf::from(transcript_progress),
+            eth_check_nonce_at_block:     constants::ETH_CHECK_NONCE_AT_BLOCK.to_string(),
+            eth_min_nonce:                constants::ETH_MIN_NONCE,
+            eth_rpc_url:                  vars.required("ETH_RPC_URL"),
+            // Callback routes of the enabled providers, separated by `,`
+            identity_providers:           vars
+                .or("IDENTITY_PROVIDERS", "github,siwe".to_string())
+                .split(',')
+                .map(|provider| provider.trim().to_string())
+                .collect(),
+            // Providers every participant must authenticate with before
+            // joining the lobby, separated by `,`. Any one suffices if unset.
+            required_auth_providers:      env::var("REQUIRED_AUTH_PROVIDERS").ok().map_or_else(
+                Vec::new,
+                |providers| {
+                    providers
+                        .split(',')
+                        .map(|provider| provider.trim().to_string())
+                        .collect()
+                },
+            ),
+            transcript_file:              PathBuf::from(transcript),
+            transcript_in_progress_file:  PathBuf::from(transcript_progress),
+            transcript_signature_file:    PathBuf::from(transcript_signature),
+            // If set, e.g. to `s3://bucket/transcript.json`, the transcript is
+            // also kept in and served from this object
+            transcript_objects:           vars
+                .parse_with("TRANSCRIPT_URI", |uri| TranscriptObjects::from_uri(&uri)),
+            // A transcript without contributions to start new ceremonies from,
+            // instead of one generated for the ceremony sizes
+            initial_transcript_file:      env::var("INITIAL_TRANSCRIPT_FILE")
+                .ok()
+                .map(PathBuf::from),
+            identity_encryption_key:      vars.parse_with("IDENTITY_ENCRYPTION_KEY", hex::decode),
+            max_concurrent_verifications: vars.or(
+                "MAX_CONCURRENT_VERIFICATIONS",
+                constants::MAX_CONCURRENT_VERIFICATIONS,
+            ),
+            storage_pool_size:            vars
+                .or("STORAGE_POOL_SIZE", constants::STORAGE_POOL_SIZE as u32),
+            storage_acquire_timeout:      Duration::from_secs(vars.or(
+                "STORAGE_ACQUIRE_TIMEOUT_SECS",
+                constants::STORAGE_ACQUIRE_TIMEOUT_SEC as u64,
+            )),
+            verify_timeout:               Duration::from_secs(
+                vars.or("VERIFY_TIMEOUT_SECS", constants::VERIFY_TIMEOUT_SEC as u64),
+            ),
+            contribute_timeout:           Duration::from_secs(vars.or(
+                "CONTRIBUTE_TIMEOUT_SECS",
+                constants::CONTRIBUTE_TIMEOUT_SEC as u64,
+            )),
+            info_timeout:                 Duration::from_secs(
+                vars.or("INFO_TIMEOUT_SECS", constants::INFO_TIMEOUT_SEC as u64),
+            ),
+            max_header_bytes:             vars.or("MAX_HEADER_BYTES", constants::MAX_HEADER_BYTES),
+            max_connections:              vars.or("MAX_CONNECTIONS", constants::MAX_CONNECTIONS),
+            // `refuse` or `queue`, see `ExcessConnections`
+            excess_connections:           vars
+                .or("EXCESS_CONNECTIONS", ExcessConnections::default()),
+            // A JSON object of headers to add to every response, such as
+            // HSTS or cache-control set by the operator's infrastructure
+            response_headers:             vars
+                .parse_with("RESPONSE_HEADERS", |headers| {
+                    parse_response_headers(&headers)
+                })
+                .unwrap_or_default(),
+            compute_deadline:             Duration::from_secs(
+                vars.or("COMPUTE_DEADLINE", constants::COMPUTE_DEADLINE as u64),
+            ),
+            // If set, replaces `compute_deadline` with a deadline that scales
+            // with the number of powers a participant has to update
+            compute_deadline_per_power:   vars
+                .optional("COMPUTE_DEADLINE_PER_POWER_MS")
+                .map(Duration::from_millis),
+            compute_heartbeat_timeout:    vars
+                .optional("COMPUTE_HEARTBEAT_TIMEOUT")
+                .map(Duration::from_secs),
+            // If set, contributions submitted sooner than this after the spot
+            // was granted are rejected, as they were likely precomputed
+            min_compute_time:             vars
+                .optional("MIN_COMPUTE_SECS")
+                .map(Duration::from_secs),
+            max_deadline_extension:       Duration::from_secs(vars.or(
+                "MAX_DEADLINE_EXTENSION_SECS",
+                constants::MAX_DEADLINE_EXTENSION_SEC as u64,
+            )),
+            lobby_checkin_frequency:      Duration::from_secs(vars.or(
+                "LOBBY_CHECKIN_FREQUENCY",
+                constants::LOBBY_CHECKIN_FREQUENCY_SEC as u64,
+            )),
+            lobby_checkin_tolerance:      Duration::from_secs(vars.or(
+                "LOBBY_CHECKIN_TOLERANCE",
+                constants::LOBBY_CHECKIN_TOLERANCE_SEC as u64,
+            )),
+            // How many check-ins a session can make within as many check-in
+            // intervals, the frequency minus tolerance. A window of more than
+            // one lets an early check-in follow a late one
+            checkin_burst:                vars.or("CHECKIN_BURST", constants::CHECKIN_BURST),
+            first_checkin_grace:          Duration::from_secs(vars.or(
+                "FIRST_CHECKIN_GRACE_SECS",
+                constants::FIRST_CHECKIN_GRACE_SEC as u64,
+            )),
+            ceremony_sizes:               vars
+                .parse_with("CEREMONY_SIZES", |sizes| parse_ceremony_sizes(&sizes))
+                .unwrap_or_else(|| kzg_ceremony_crypto::SIZES.to_vec()),
+            // Semver requirements separated by `;`, e.g. `<1.2.0;=1.3.1`
+            denied_client_versions:       vars
+                .parse_with("DENIED_CLIENT_VERSIONS", |versions| {
+                    versions
+                        .split(';')
+                        .map(|version| VersionReq::parse(version.trim()))
+                        .collect::<Result<Vec<_>, _>>()
+                })
+                .unwrap_or_default(),
+            client_upgrade_url:           env::var("CLIENT_UPGRADE_URL").ok(),
+            admin_token:                  env::var("ADMIN_TOKEN").ok(),
+            allowlist_file:               env::var("ALLOWLIST_FILE").ok().map(PathBuf::from),
+            // Hashes of contributions to reject even if they verify, such as
+            // leaked precomputed ones
+            denylist_file:                env::var("DENYLIST_FILE").ok().map(PathBuf::from),
+            // A command that can veto lobby joins and contributions, see
+            // `AdmissionHook`. A JSON array of the program and its arguments,
+            // such as `["/usr/bin/check", "--org", "Some Org"]`
+            admission_hook:               vars
+                .parse_with("ADMISSION_COMMAND", |command| {
+                    serde_json::from_str(&command)
+                })
+                .map(|command| {
+                    AdmissionHook::new(
+                        command,
+                        Duration::from_secs(vars.or(
+                            "ADMISSION_TIMEOUT_SECS",
+                            constants::ADMISSION_TIMEOUT_SEC as u64,
+                        )),
+                        vars.or("ADMISSION_FAIL_OPEN", false),
+                    )
+                }),
+            order_commitment:             vars.or("ORDER_COMMITMENT", false),
+            pretty_responses:             vars.or("PRETTY_RESPONSES", false),
+            public_contribution_bundles:  vars.or("PUBLIC_CONTRIBUTION_BUNDLES", false),
+            identity_lookups_per_minute:  vars.or("IDENTITY_LOOKUPS_PER_MINUTE", 60),
+            // One of `sha256`, `blake3` or `keccak256`
+            transcript_hash_algorithm:    vars
+                .or("TRANSCRIPT_HASH_ALGORITHM", HashAlgorithm::Sha256),
+            // `initial` or `no_content`, see `MissingTranscript`
+            missing_transcript:           vars
+                .or("MISSING_TRANSCRIPT_RESPONSE", MissingTranscript::default()),
+            // Shown to sessions that poll `try_contribute` after contributing
+            contributed_message:          vars.or(
+                "CONTRIBUTED_MESSAGE",
+                "Thank you for contributing to the ceremony!".to_string(),
+            ),
+            require_invite_code:          vars.or("REQUIRE_INVITE_CODE", false),
+            // Let participants join through `/auth/anonymous` without signing
+            // in. Only the per-client limit below keeps one person from
+            // joining many times then.
+            anonymous_participants:       vars.or("ANONYMOUS_PARTICIPANTS", false),
+            // Anonymous sessions each client address can start per minute
+            anonymous_joins_per_minute:   vars.or(
+                "ANONYMOUS_JOINS_PER_MINUTE",
+                constants::ANONYMOUS_JOINS_PER_MINUTE,
+            ),
+            // Shut down as soon as draining is done, instead of waiting for a
+            // signal. Leave disabled to move to a next phase after draining.
+            shutdown_when_drained:        vars.or("SHUTDOWN_WHEN_DRAINED", false),
+            // Reject contributions with fields the sequencer doesn't know
+            strict_requests:              vars.or("STRICT_REQUESTS", false),
+            log_rejected_contributions:   vars.or("LOG_REJECTED_CONTRIBUTIONS", false),
+            // Keep rejected contributions in full, so rejections can be
+            // reproduced. Contributions are large, so this is off by default
+            quarantine_contributions:     vars.or("QUARANTINE_CONTRIBUTIONS", false),
+            // Reject contributions whose secrets look degenerate, such as a
+            // small scalar. Opt in, as it rejects otherwise valid updates.
+            check_contribution_entropy:   vars.or("CHECK_CONTRIBUTION_ENTROPY", false),
+            require_identity_signature:   vars.or("REQUIRE_IDENTITY_SIGNATURE", true),
+            overload_lobby_size:          vars
+                .or("OVERLOAD_LOBBY_SIZE", constants::OVERLOAD_LOBBY_SIZE),
+            // The number of contributions the ceremony is planned to end with,
+            // used to estimate when it completes
+            max_contributions:            vars.optional("MAX_CONTRIBUTIONS"),
+            // Start draining once MAX_CONTRIBUTIONS are in, which finalizes
+            // the ceremony when the last contributor is done. Off by default,
+            // the planned end is then only used for the estimate.
+            drain_at_max_contributions:   vars.or("DRAIN_AT_MAX_CONTRIBUTIONS", false),
+            // Hex encoded key shared with a trusted out-of-band verifier.
+            // Contributions with its MAC are accepted without verification,
+            // so whoever holds the key can get any contribution accepted.
+            preverification_key:          vars.parse_with("PREVERIFICATION_KEY", hex::decode),
+            clock_skew:                   Duration::from_secs(
+                vars.or("CLOCK_SKEW_SECS", constants::CLOCK_SKEW_SEC as u64),
+            ),
+            session_max_lifetime:         vars
+                .optional("SESSION_MAX_LIFETIME_SECS")
+                .map(Duration::from_secs),
+            rejoin_cooldown:              vars
+                .optional("REJOIN_COOLDOWN_SECS")
+                .map(Duration::from_secs),
+            error_response_floor:         vars
+                .optional("ERROR_RESPONSE_FLOOR_MS")
+                .map(Duration::from_millis),
+        };
+
+        let mut problems = vars.problems;
+        problems.extend(config.missing_provider_credentials());
+        if let Err(ConfigProblems(invalid)) = config.validate() {
+            problems.extend(invalid);
         }
+        if problems.is_empty() {
+            Ok(config)
+        } else {
+            Err(ConfigProblems(problems))
+        }
+    }
+}
+
+impl AppConfig {
+    // How long a participant granted the contribution spot has to contribute.
+    // Every participant updates all sub-ceremonies, so this is the same for
+    // everyone in a ceremony.
+    pub fn effective_compute_deadline(&self) -> Duration {
+        self.compute_deadline_per_power
+            .map_or(self.compute_deadline, |factor| {
+                let num_powers: usize = self
+                    .ceremony_sizes
+                    .iter()
+                    .map(|(num_g1, num_g2)| num_g1 + num_g2)
+                    .sum();
+                factor * u32::try_from(num_powers).unwrap_or(u32::MAX)
+            })
+    }
+}
+
+// Parses a list of sizes such as `4096:65,8192:65`, where each entry gives
+// the number of G1 and G2 powers of a sub-ceremony
+fn parse_ceremony_sizes(sizes: &str) -> EyreResult<Vec<(usize, usize)>> {
+    sizes
+        .split(',')
+        .map(|size| -> EyreResult<(usize, usize)> {
+            let (num_g1, num_g2) = size
+                .split_once(':')
+                .ok_or_else(|| eyre!("Expected <g1 powers>:<g2 powers> in {}", size))?;
+            Ok((num_g1.trim().parse()?, num_g2.trim().parse()?))
+        })
+        .collect()
+}
+
+// Parses environment variables, noting each one that doesn't parse instead of
+// panicking, so they can all be reported at once
+#[derive(Default)]
+struct EnvVars {
+    problems: Vec<String>,
+}
+
+impl EnvVars {
+    // Parses `name`, falling back to `default` if it is unset or invalid
+    fn or<T>(&mut self, name: &str, default: T) -> T
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        self.optional(name).unwrap_or(default)
+    }
+
+    // Parses `name`, if it is set
+    fn optional<T>(&mut self, name: &str) -> Option<T>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        self.parse_with(name, |value| value.parse())
+    }
+
+    // Parses `name` with `parse`, if it is set. An invalid value is noted and
+    // treated as unset.
+    fn parse_with<T, E: Display>(
+        &mut self,
+        name: &str,
+        parse: impl FnOnce(String) -> Result<T, E>,
+    ) -> Option<T> {
+        let value = env::var(name).ok()?;
+        parse(value)
+            .map_err(|error| self.problems.push(format!("Invalid {}: {}", name, error)))
+            .ok()
+    }
+
+    fn required(&mut self, name: &str) -> String {
+        env::var(name).unwrap_or_else(|_| {
+            self.problems.push(format!("Missing {}", name));
+            String::new()
+        })
     }
 }
 
@@ -245,8 +1022,9 @@ type CsrfToken = String;
 #[derive(Default)]
 pub struct AppState {
     // Use can now be in the lobby and only those who are in
-    // the lobby can ping to start participating
-    lobby: BTreeMap<SessionId, SessionInfo>,
+    // the lobby can ping to start participating.
+    // Kept in insertion order, so that listings and positions are stable
+    lobby: IndexMap<SessionId, SessionInfo>,
 
     // CSRF tokens for oAUTH
     csrf_tokens: BTreeSet<CsrfToken>,
@@ -256,46 +1034,358 @@ pub struct AppState {
     // We use this to check if a user has already entered the lobby
     unique_id_session: BTreeMap<IdTokenSub, SessionId>,
 
+    // When each identity last checked in, across all of its sessions
+    identity_checkins: BTreeMap<IdTokenSub, CheckinPacer>,
+
+    // If set, only these identities are allowed to contribute
+    allowlist: Option<BTreeSet<IdTokenSub>>,
+
+    // Hex encoded hashes of contributions that are rejected even if valid
+    denylist: BTreeSet<String>,
+
+    // Sessions still missing some of the required auth providers
+    pending_sessions: BTreeMap<SessionId, PendingSession>,
+
+    // The session each identity vouched for as an auth factor, so it can't
+    // vouch for another one
+    auth_factor_sessions: BTreeMap<IdTokenSub, SessionId>,
+
     num_contributions: usize,
 
+    // Hash of the transcript as currently served, see `transcript_hash`
+    transcript_hash: TranscriptHash,
+
+    // Number of contribution spots lost to the compute deadline
+    num_expired: usize,
+
+    // Counters served by `/info/counters`
+    counters: Counters,
+
+    // Attestations of accepted contributions that couldn't be stored yet, in
+    // contribution order
+    pending_attestations: Vec<PendingAttestation>,
+
+    // Merkle commitment over the receipts, in contribution order, if enabled
+    order_commitment: OrderCommitment,
+
+    // Chain hash of the latest attestation, `None` before the first one
+    attestation_chain_head: Option<String>,
+
     // This is the Id of the current participant
     // Only they are allowed to call /contribute
     participant: Option<(SessionId, SessionInfo)>,
+
+    // How the previous contribution spot ended
+    last_slot_outcome: Option<SlotOutcome>,
+
+    // When the current participant was given the contribution spot
+    participant_granted_at: Option<Instant>,
+
+    // When the current participant's compute deadline passes
+    participant_deadline: Option<Instant>,
+
+    // Timer that frees the current participant's spot on their deadline
+    deadline_task: Option<JoinHandle<()>>,
+
+    // Dropped when the current participant's spot is released, which wakes
+    // up their deadline timer
+    slot_release: Option<oneshot::Sender<()>>,
+
+    // Chunks of the current participant's upload, see `upload`. They are
+    // deleted once the spot is released.
+    participant_upload: Option<PathBuf>,
+
+    // How long each of the latest successful participants took to
+    // contribute, oldest first, see `COMPUTE_TIMES_WINDOW`
+    compute_times: Vec<Duration>,
+
+    // When each identity last contributed, used to enforce a rejoin cooldown
+    last_contributed_at: BTreeMap<IdTokenSub, Instant>,
+
+    // Pubkeys of all accepted contributions, so that none can be reused
+    seen_pubkeys: BTreeSet<String>,
+
+    // What each identity contributed, served back to them as a proof bundle
+    contribution_bundles: BTreeMap<IdTokenSub, ContributionBundle>,
+
+    // The identity each session that already contributed belongs to, and
+    // when it contributed, so clients still polling with it get a terminal
+    // response. Pruned after `FINISHED_SESSION_RETENTION_SEC`.
+    finished_sessions: BTreeMap<SessionId, (IdTokenSub, Instant)>,
+
+    // The phase the ceremony is in, `None` while it is in its first
+    phase: Option<CeremonyPhase>,
+
+    // Set once an operator starts draining the sequencer before shutdown.
+    // No new contribution spots are granted after this.
+    drain: Option<Drain>,
+
+    // Notified once draining and the contribution spot is free, see
+    // `await_drained`
+    drained: Arc<Notify>,
+
+    // When the ceremony was finalized, which is once it is drained
+    finalized_at: Option<DateTime<Utc>>,
 }
 
 impl AppState {
-    pub fn clear_current_contributor(&mut self) {
+    pub fn is_allowed(&self, uid: &str) -> bool {
+        self.allowlist
+            .as_ref()
+            .map_or(true, |allowlist| allowlist.contains(uid))
+    }
+
+    pub const fn ceremony_status(&self) -> &'static str {
+        if self.drain.is_some() && self.participant.is_none() {
+            // Nobody contributes anymore, but receipts and bundles are still
+            // served
+            "finalized"
+        } else if self.drain.is_some() {
+            "draining"
+        } else if self.participant.is_some() {
+            "contribution_in_progress"
+        } else {
+            "waiting_for_participant"
+        }
+    }
+
+    pub fn clear_current_contributor(&mut self, outcome: SlotOutcome) {
         // Note: when reserving a contribution spot
         // we remove the user from the lobby
         // So simply setting this to None, will forget them
         self.participant = None;
+        self.participant_granted_at = None;
+        self.participant_deadline = None;
+        self.deadline_task = None;
+        self.slot_release = None;
+        if let Some(path) = self.participant_upload.take() {
+            remove_upload_files(&path);
+        }
+        self.last_slot_outcome = Some(outcome);
+        // However the spot was released, nobody takes it while draining
+        if self.drain.is_some() {
+            self.finalize();
+        }
+    }
+
+    // Frees the contribution spot if `session_id` still holds it, returning
+    // whether it did. Handlers that awaited anything since checking the
+    // participant use this, so they can't free a spot that was expired and
+    // granted to someone else in the meantime.
+    pub fn clear_contributor_if_current(
+        &mut self,
+        session_id: &SessionId,
+        outcome: SlotOutcome,
+    ) -> bool {
+        if !self.is_participant(session_id) {
+            return false;
+        }
+        self.clear_current_contributor(outcome);
+        true
+    }
+
+    pub fn start_drain(&mut self, drain: Drain) {
+        self.drain = Some(drain);
+        if self.participant.is_none() {
+            self.finalize();
+        }
+    }
+
+    // Starts draining once `max_contributions` are in, so the ceremony is
+    // finalized as soon as the contribution spot is free
+    pub fn drain_at_cap(&mut self, max_contributions: Option<usize>) {
+        let at_cap = max_contributions.map_or(false, |max| self.num_contributions >= max);
+        if at_cap && self.drain.is_none() {
+            info!(max_contributions, "Contribution cap reached, draining");
+            self.start_drain(Drain {
+                reason: "contribution cap reached".to_string(),
+                since:  Utc::now(),
+            });
+        }
+    }
+
+    fn finalize(&mut self) {
+        self.finalized_at = Some(Utc::now());
+        self.drained.notify_one();
+    }
+
+    // Picks up the ceremony where the transcript left off
+    pub fn resume_from<T: Transcript>(&mut self, transcript: &T) {
+        self.num_contributions = transcript.num_contributions();
+        self.transcript_hash = transcript_hash(transcript);
+        self.seen_pubkeys = transcript.pubkeys().into_iter().collect();
+    }
+
+    // Rebuilds the order commitment over the receipts of the current phase,
+    // both those that were stored and those still waiting in the journal, so
+    // that it keeps covering contributions made before a restart
+    pub fn restore_order_commitment(&mut self, stored: Vec<(i64, String)>) {
+        let phase = self.phase.as_ref().map_or(0, |phase| phase.phase);
+        let mut receipts = stored.into_iter().collect::<BTreeMap<_, _>>();
+        receipts.extend(
+            self.pending_attestations
+                .iter()
+                .filter(|pending| pending.attestation().phase == phase)
+                .map(|pending| {
+                    (
+                        pending.attestation().contribution_index,
+                        pending.receipt().to_string(),
+                    )
+                }),
+        );
+        self.order_commitment = OrderCommitment::default();
+        for receipt in receipts.values() {
+            self.order_commitment.push(receipt.as_bytes());
+        }
     }
 
-    /// # Panics
-    ///
-    /// Panics if the user is not in the lobby.
-    pub fn set_current_contributor(&mut self, session_id: SessionId) {
-        let session_info = self.lobby.remove(&session_id).unwrap();
+    // Whether `session_id` holds the contribution spot
+    pub fn is_participant(&self, session_id: &SessionId) -> bool {
+        matches!(&self.participant, Some((id, _)) if id == session_id)
+    }
+
+    // Frees the contribution spot of a participant that ran out of time,
+    // returning who held it. Does nothing unless `session_id` still holds the
+    // spot, so a late expiry can't free it for whoever was granted it next.
+    pub fn expire_current_contributor(
+        &mut self,
+        session_id: &SessionId,
+    ) -> Option<(SessionId, SessionInfo)> {
+        if !self.is_participant(session_id) {
+            return None;
+        }
+        let participant = self.participant.take()?;
+        self.num_expired += 1;
+        Counters::count(&self.counters.expirations);
+        self.clear_current_contributor(SlotOutcome::Expired);
+        Some(participant)
+    }
+
+    // Forgets sessions that contributed more than `retention` before `now`
+    pub fn prune_finished_sessions(&mut self, now: Instant, retention: Duration) {
+        self.finished_sessions
+            .retain(|_, (_, finished_at)| now.saturating_duration_since(*finished_at) < retention);
+    }
+
+    // Forgets the check-ins of identities that no longer count against them,
+    // including those of identities that were turned away
+    pub fn prune_identity_checkins(&mut self, now: Instant, interval: Duration, burst: u32) {
+        self.identity_checkins
+            .retain(|_, checkins| !checkins.is_idle(now, interval, burst));
+    }
+
+    // Forgets sessions still missing auth providers `ttl` after they started
+    // signing in. Identities are free to vouch again once the session they
+    // vouched for is gone, whether it expired here or left the lobby.
+    pub fn prune_pending_sessions(&mut self, now: Instant, ttl: Duration) {
+        self.pending_sessions
+            .retain(|_, pending| !pending.is_expired(now, ttl));
+        let (pending, lobby, participant) =
+            (&self.pending_sessions, &self.lobby, &self.participant);
+        self.auth_factor_sessions.retain(|_, session_id| {
+            pending.contains_key(session_id)
+                || lobby.contains_key(session_id)
+                || participant
+                    .as_ref()
+                    .map_or(false, |(id, _)| id == session_id)
+        });
+    }
+
+    // Resolves once the current participant's spot is released
+    pub fn slot_released(&mut self) -> oneshot::Receiver<()> {
+        let (release, released) = oneshot::channel();
+        self.slot_release = Some(release);
+        released
+    }
+
+    // Records how long the current participant took to contribute, dropping
+    // the oldest compute time once the window is full
+    pub fn record_compute_time(&mut self) {
+        if let Some(granted_at) = self.participant_granted_at {
+            if self.compute_times.len() >= COMPUTE_TIMES_WINDOW {
+                self.compute_times.remove(0);
+            }
+            self.compute_times
+                .push(Instant::now().saturating_duration_since(granted_at));
+        }
+    }
+
+    pub fn record_contributor(&mut self, uid: &str) {
+        self.last_contributed_at
+            .insert(uid.to_owned(), Instant::now());
+    }
+
+    // Returns how long the identity still has to wait before rejoining
+    pub fn rejoin_cooldown_remaining(&self, uid: &str, cooldown: Duration) -> Option<Duration> {
+        let contributed_at = self.last_contributed_at.get(uid)?;
+        let remaining = cooldown.checked_sub(contributed_at.elapsed())?;
+        (!remaining.is_zero()).then_some(remaining)
+    }
+
+    // Grants the contribution spot to a session in the lobby, unless the spot
+    // is taken. Checking and granting in this one call, made while holding
+    // the write lock, keeps the spot exclusive. Returns whether it was granted.
+    #[must_use]
+    pub fn try_set_current_contributor(
+        &mut self,
+        session_id: SessionId,
+        compute_deadline: Duration,
+    ) -> bool {
+        if self.participant.is_some() {
+            return false;
+        }
+        let session_info = match self.lobby.shift_remove(&session_id) {
+            Some(session_info) => session_info,
+            None => return false,
+        };
 
+        let now = Instant::now();
         self.participant = Some((session_id, session_info));
+        self.participant_granted_at = Some(now);
+        self.participant_deadline = Some(now + compute_deadline);
+        true
     }
 }
 
-pub async fn clear_lobby_on_interval(state: SharedState, mut interval: Interval) {
-    let max_diff =
-        Duration::from_secs((LOBBY_CHECKIN_FREQUENCY_SEC + LOBBY_CHECKIN_TOLERANCE_SEC) as u64);
+pub async fn clear_lobby_on_interval(
+    state: SharedState,
+    mut interval: Interval,
+    config: AppConfig,
+) {
+    let max_diff = config.lobby_checkin_frequency + config.lobby_checkin_tolerance;
+    let min_diff = config.lobby_checkin_frequency - config.lobby_checkin_tolerance;
     loop {
         interval.tick().await;
 
         let now = Instant::now();
-        // Predicate that returns true whenever users go over the ping deadline
+        // Predicate that returns true whenever users go over the ping deadline,
+        // or their session outlived its maximum lifetime
         let predicate = |session_info: &SessionInfo| -> bool {
             let time_diff = now - session_info.last_ping_time;
-            time_diff > max_diff
+            time_diff > max_diff || session_info.is_past_lifetime(config.session_max_lifetime, now)
         };
 
         let clone = state.clone();
         clear_lobby(clone, predicate).await;
+        {
+            let mut app_state = state.write().await;
+            app_state.prune_finished_sessions(
+                now,
+                Duration::from_secs(FINISHED_SESSION_RETENTION_SEC as u64),
+            );
+            app_state
+                .prune_pending_sessions(now, Duration::from_secs(PENDING_SESSION_TTL_SEC as u64));
+            app_state.prune_identity_checkins(now, min_diff, config.checkin_burst);
+        }
+
+        // Keep the lobby gauges up to date
+        let app_state = state.read().await;
+        LobbyStats::compute(
+            app_state.lobby.values(),
+            Instant::now(),
+            config.lobby_checkin_frequency,
+        )
+        .record();
     }
 }
 
@@ -320,7 +1410,7 @@ async fn clear_lobby(state: SharedState, predicate: impl Fn(&SessionInfo) -> boo
         );
     }
     for session_id in sessions_to_kick {
-        app_state.lobby.remove(&session_id);
+        app_state.lobby.shift_remove(&session_id);
     }
 }
 
@@ -354,7 +1444,8 @@ async fn flush_on_predicate() {
 
     // Now we are going to kick all of the participants whom have an
     // expiry which is an even number
-    let predicate = |session_info: &SessionInfo| -> bool { session_info.token.exp % 2 == 0 };
+    let predicate =
+        |session_info: &SessionInfo| -> bool { session_info.token.as_ref().unwrap().exp % 2 == 0 };
 
     clear_lobby(arc_state.clone(), predicate).await;
 
@@ -367,8 +1458,65 @@ async fn flush_on_predicate() {
     for id in session_ids {
         let info = state.lobby.get(&id).unwrap();
         // We should just be left with `exp` numbers which are odd
-        assert_eq!(info.token.exp % 2, 1);
+        assert_eq!(info.token.as_ref().unwrap().exp % 2, 1);
+    }
+}
+
+#[tokio::test]
+async fn prunes_finished_sessions_by_age() {
+    let mut state = AppState::default();
+    let start = Instant::now();
+    let old = SessionId::new();
+    let recent = SessionId::new();
+    state
+        .finished_sessions
+        .insert(old.clone(), ("github | old".to_string(), start));
+    state.finished_sessions.insert(
+        recent.clone(),
+        (
+            "github | recent".to_string(),
+            start + Duration::from_secs(30),
+        ),
+    );
+
+    state.prune_finished_sessions(start + Duration::from_secs(60), Duration::from_secs(60));
+    assert!(!state.finished_sessions.contains_key(&old));
+    assert!(state.finished_sessions.contains_key(&recent));
+}
+
+#[tokio::test]
+async fn keeps_a_window_of_compute_times() {
+    let mut state = AppState::default();
+    state.participant_granted_at = Some(Instant::now());
+    state.compute_times = vec![Duration::from_secs(1); COMPUTE_TIMES_WINDOW];
+    state.compute_times[0] = Duration::from_secs(2);
+
+    state.record_compute_time();
+    assert_eq!(state.compute_times.len(), COMPUTE_TIMES_WINDOW);
+    assert_eq!(state.compute_times[0], Duration::from_secs(1));
+}
+
+#[tokio::test]
+async fn prunes_identity_checkins_by_age() {
+    let mut state = AppState::default();
+    let start = Instant::now();
+    let interval = Duration::from_secs(10);
+    for (uid, checked_in_at) in [
+        ("github | old", start),
+        ("github | recent", start + interval),
+    ] {
+        state
+            .identity_checkins
+            .entry(uid.to_string())
+            .or_default()
+            .check_in(checked_in_at, 2);
     }
+
+    // The old check-in has left the window of two intervals, the recent one
+    // hasn't yet
+    state.prune_identity_checkins(start + interval * 2, interval, 2);
+    assert!(!state.identity_checkins.contains_key("github | old"));
+    assert!(state.identity_checkins.contains_key("github | recent"));
 }
 
 fn parse_url(url: &Url) -> EyreResult<(SocketAddr, &str)> {
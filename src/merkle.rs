@@ -0,0 +1,185 @@
+use ring::digest::{digest, Context, SHA256};
+use serde::Serialize;
+
+pub type Hash = [u8; 32];
+
+// Append-only Merkle tree over contribution records. Leaves and inner nodes
+// are domain separated, and a node without a sibling is promoted unchanged to
+// the next level.
+#[derive(Debug, Default, Clone)]
+pub struct OrderCommitment {
+    leaves: Vec<Hash>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Side {
+    Left,
+    Right,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ProofStep {
+    // Which side of the running hash the sibling goes on
+    pub side:    Side,
+    #[serde(serialize_with = "serialize_hash")]
+    pub sibling: Hash,
+}
+
+impl OrderCommitment {
+    // The leaf commits to the position and the record, e.g. a receipt, so it
+    // reveals nothing about the contributor until they disclose the record
+    pub fn leaf(index: usize, record: &[u8]) -> Hash {
+        let mut context = Context::new(&SHA256);
+        context.update(&[0]);
+        context.update(&(index as u64).to_be_bytes());
+        context.update(record);
+        to_hash(context.finish().as_ref())
+    }
+
+    pub fn push(&mut self, record: &[u8]) -> usize {
+        let index = self.leaves.len();
+        self.leaves.push(Self::leaf(index, record));
+        index
+    }
+
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    pub fn root(&self) -> Option<Hash> {
+        let mut level = self.leaves.clone();
+        if level.is_empty() {
+            return None;
+        }
+        while level.len() > 1 {
+            level = next_level(&level);
+        }
+        Some(level[0])
+    }
+
+    pub fn proof(&self, mut index: usize) -> Option<Vec<ProofStep>> {
+        if index >= self.leaves.len() {
+            return None;
+        }
+        let mut proof = Vec::new();
+        let mut level = self.leaves.clone();
+        while level.len() > 1 {
+            let sibling = index ^ 1;
+            if sibling < level.len() {
+                proof.push(ProofStep {
+                    side:    if sibling < index {
+                        Side::Left
+                    } else {
+                        Side::Right
+                    },
+                    sibling: level[sibling],
+                });
+            }
+            level = next_level(&level);
+            index /= 2;
+        }
+        Some(proof)
+    }
+}
+
+pub fn verify_proof(leaf: Hash, proof: &[ProofStep], root: Hash) -> bool {
+    let computed = proof.iter().fold(leaf, |node, step| match step.side {
+        Side::Left => node_hash(&step.sibling, &node),
+        Side::Right => node_hash(&node, &step.sibling),
+    });
+    computed == root
+}
+
+fn next_level(level: &[Hash]) -> Vec<Hash> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => node_hash(left, right),
+            [single] => *single,
+            _ => unreachable!("chunks are never empty"),
+        })
+        .collect()
+}
+
+fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    let mut data = [0_u8; 65];
+    data[0] = 1;
+    data[1..33].copy_from_slice(left);
+    data[33..].copy_from_slice(right);
+    to_hash(digest(&SHA256, &data).as_ref())
+}
+
+fn to_hash(bytes: &[u8]) -> Hash {
+    bytes.try_into().expect("SHA256 digests are 32 bytes")
+}
+
+pub fn hash_to_hex(hash: &Hash) -> String {
+    format!("0x{}", hex::encode(hash))
+}
+
+fn serialize_hash<S: serde::Serializer>(hash: &Hash, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&hash_to_hex(hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commitment(size: usize) -> OrderCommitment {
+        let mut commitment = OrderCommitment::default();
+        for i in 0..size {
+            commitment.push(format!("receipt {}", i).as_bytes());
+        }
+        commitment
+    }
+
+    #[test]
+    fn inclusion_proofs_verify_against_root() {
+        for size in 1..=9 {
+            let commitment = commitment(size);
+            let root = commitment.root().unwrap();
+            for index in 0..size {
+                let leaf = OrderCommitment::leaf(index, format!("receipt {}", index).as_bytes());
+                let proof = commitment.proof(index).unwrap();
+                assert!(
+                    verify_proof(leaf, &proof, root),
+                    "size {} index {}",
+                    size,
+                    index
+                );
+            }
+            assert!(commitment.proof(size).is_none());
+        }
+    }
+
+    #[test]
+    fn tampering_breaks_inclusion_proof() {
+        let commitment = commitment(5);
+        let root = commitment.root().unwrap();
+        let mut proof = commitment.proof(2).unwrap();
+
+        // A different record, or the same record at another position
+        assert!(!verify_proof(
+            OrderCommitment::leaf(2, b"forged receipt"),
+            &proof,
+            root
+        ));
+        assert!(!verify_proof(
+            OrderCommitment::leaf(3, b"receipt 2"),
+            &proof,
+            root
+        ));
+
+        proof[0].sibling[0] ^= 1;
+        assert!(!verify_proof(
+            OrderCommitment::leaf(2, b"receipt 2"),
+            &proof,
+            root
+        ));
+    }
+}
@@ -0,0 +1,428 @@
+use std::{
+    collections::BTreeSet,
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+};
+
+use eyre::{bail, ensure, eyre, Result as EyreResult};
+use sqlx::{sqlite::SqliteConnectOptions, Connection, SqliteConnection};
+
+use crate::{
+    data::transcript::{read_transcript_signature, resolve_transcript_path, Transcript},
+    keys::Keys,
+    verification::VerificationLimiter,
+    AppConfig,
+};
+
+// The identity providers `IdentityProviders::from_config` can set up, with
+// the environment variables holding the OAuth client id and secret of each
+const KNOWN_PROVIDERS: &[(&str, [&str; 2])] = &[
+    ("github", ["GITHUB_CLIENT_ID", "GITHUB_CLIENT_SECRET"]),
+    ("siwe", ["SIWE_CLIENT_ID", "SIWE_CLIENT_SECRET"]),
+];
+
+// What a dry run found out about the deployment
+#[derive(Debug)]
+pub struct PreflightReport {
+    pub transcript_file:    PathBuf,
+    // `None` if the transcript doesn't exist yet and will be created
+    pub num_contributions:  Option<usize>,
+    // Migrations that will be applied to the database at startup
+    pub pending_migrations: usize,
+    pub identity_providers: Vec<String>,
+}
+
+impl fmt::Display for PreflightReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Deployment is valid")?;
+        writeln!(
+            f,
+            "  transcript:         {}",
+            self.transcript_file.display()
+        )?;
+        match self.num_contributions {
+            Some(count) => writeln!(f, "  contributions:      {}", count)?,
+            None => writeln!(f, "  contributions:      none, transcript will be created")?,
+        }
+        writeln!(
+            f,
+            "  migrations:         {} pending",
+            self.pending_migrations
+        )?;
+        write!(
+            f,
+            "  identity providers: {}",
+            self.identity_providers.join(", ")
+        )
+    }
+}
+
+// Runs the checks done at startup, without changing anything on disk
+pub async fn preflight<T>(
+    config: &AppConfig,
+    keys: &Keys,
+    verification_limiter: &VerificationLimiter,
+    database_url: &str,
+) -> EyreResult<PreflightReport>
+where
+    T: Transcript + Send + Sync + 'static,
+    T::ValidationError: Send,
+{
+    config.validate()?;
+    let transcript_file = resolve_transcript_path(&config.transcript_file)?;
+
+    let num_contributions = if tokio::fs::metadata(&transcript_file).await.is_ok() {
+        let json = tokio::fs::read(&transcript_file).await?;
+        let transcript = Arc::new(
+            serde_json::from_slice::<T>(&json)
+                .map_err(|e| eyre!("Cannot parse transcript: {}", e))?,
+        );
+        verification_limiter
+            .verify_all(transcript.clone(), T::initial(config)?)
+            .await
+            .map_err(|_| eyre!("Transcript does not verify"))?;
+        let signature = read_transcript_signature(config.transcript_signature_file.clone())
+            .await
+            .map_err(|e| eyre!("Cannot read transcript signature: {}", e))?;
+        ensure!(
+            transcript.verify_signature(keys, &signature),
+            "Transcript signature does not match the transcript"
+        );
+        Some(transcript.num_contributions())
+    } else {
+        None
+    };
+
+    let pending_migrations = pending_migrations(database_url).await?;
+
+    Ok(PreflightReport {
+        transcript_file,
+        num_contributions,
+        pending_migrations,
+        identity_providers: config.identity_providers.clone(),
+    })
+}
+
+// Counts the migrations the database still needs, without applying them. The
+// database is only opened read-only, and one that doesn't exist yet needs all
+// of them. Fails on an applied migration that was changed since, or that this
+// build doesn't know, as either stops the sequencer at startup.
+async fn pending_migrations(database_url: &str) -> EyreResult<usize> {
+    let migrator = sqlx::migrate!();
+    if database_path(database_url).map_or(false, |path| !path.exists()) {
+        return Ok(migrator.iter().count());
+    }
+    let options = SqliteConnectOptions::from_str(database_url)
+        .map_err(|e| eyre!("Invalid DATABASE_URL: {}", e))?
+        .read_only(true)
+        .create_if_missing(false);
+    let mut connection = SqliteConnection::connect_with(&options)
+        .await
+        .map_err(|e| eyre!("Cannot open database: {}", e))?;
+
+    let has_migrations = sqlx::query_scalar::<_, i64>(
+        "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = '_sqlx_migrations'",
+    )
+    .fetch_one(&mut connection)
+    .await?
+        > 0;
+    let applied = if has_migrations {
+        sqlx::query_as::<_, (i64, Vec<u8>)>(
+            "SELECT version, checksum FROM _sqlx_migrations WHERE success",
+        )
+        .fetch_all(&mut connection)
+        .await?
+    } else {
+        vec![]
+    };
+    connection.close().await?;
+
+    for (version, checksum) in &applied {
+        match migrator
+            .iter()
+            .find(|migration| migration.version == *version)
+        {
+            Some(migration) if *migration.checksum == **checksum => {}
+            Some(_) => bail!("Migration {} was changed after it was applied", version),
+            None => bail!("Database has migration {} this build doesn't know", version),
+        }
+    }
+    let applied = applied
+        .iter()
+        .map(|(version, _)| *version)
+        .collect::<BTreeSet<_>>();
+    Ok(migrator
+        .iter()
+        .filter(|migration| !applied.contains(&migration.version))
+        .count())
+}
+
+// The file of a `sqlite:` database URL, `None` for an in-memory database
+fn database_path(database_url: &str) -> Option<&Path> {
+    let path = database_url
+        .strip_prefix("sqlite://")
+        .or_else(|| database_url.strip_prefix("sqlite:"))?;
+    let path = path.split('?').next().unwrap_or_default();
+    (!path.is_empty() && path != ":memory:").then_some(Path::new(path))
+}
+
+// Everything that is wrong with a config, so it can all be fixed at once
+#[derive(Debug)]
+pub struct ConfigProblems(pub Vec<String>);
+
+impl fmt::Display for ConfigProblems {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid configuration:")?;
+        for problem in &self.0 {
+            write!(f, "\n  - {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigProblems {}
+
+impl AppConfig {
+    // Checks the invariants the sequencer relies on. Unlike the checks that
+    // follow at startup, this reports every problem, not just the first.
+    pub fn validate(&self) -> Result<(), ConfigProblems> {
+        let checks = [
+            (
+                self.max_concurrent_verifications > 0,
+                "MAX_CONCURRENT_VERIFICATIONS must be at least 1",
+            ),
+            (
+                self.max_connections > 0,
+                "MAX_CONNECTIONS must be at least 1",
+            ),
+            (
+                !self.compute_deadline.is_zero(),
+                "COMPUTE_DEADLINE must be positive",
+            ),
+            (
+                self.contribute_timeout > self.verify_timeout,
+                "CONTRIBUTE_TIMEOUT_SECS must be above VERIFY_TIMEOUT_SECS",
+            ),
+            (
+                self.lobby_checkin_tolerance < self.lobby_checkin_frequency,
+                "LOBBY_CHECKIN_TOLERANCE must be below LOBBY_CHECKIN_FREQUENCY",
+            ),
+            (
+                !self.ceremony_sizes.is_empty(),
+                "CEREMONY_SIZES must list at least one sub-ceremony",
+            ),
+            (
+                self.ceremony_sizes
+                    .iter()
+                    .all(|(num_g1, num_g2)| *num_g1 > 0 && *num_g2 > 0),
+                "CEREMONY_SIZES must give every sub-ceremony G1 and G2 powers",
+            ),
+            (
+                !self.identity_providers.is_empty(),
+                "IDENTITY_PROVIDERS must enable at least one provider",
+            ),
+            (
+                self.required_auth_providers
+                    .iter()
+                    .all(|provider| self.identity_providers.contains(provider)),
+                "REQUIRED_AUTH_PROVIDERS must only list enabled providers",
+            ),
+        ];
+        let mut problems = checks
+            .iter()
+            .filter(|(valid, _)| !valid)
+            .map(|(_, problem)| (*problem).to_string())
+            .collect::<Vec<_>>();
+        for provider in &self.identity_providers {
+            if !KNOWN_PROVIDERS.iter().any(|(known, _)| known == provider) {
+                problems.push(format!("Unknown identity provider {}", provider));
+            }
+        }
+        if let Err(error) = resolve_transcript_path(&self.transcript_file) {
+            problems.push(error.to_string());
+        }
+        for (name, path) in [
+            ("ALLOWLIST_FILE", &self.allowlist_file),
+            ("DENYLIST_FILE", &self.denylist_file),
+            ("INITIAL_TRANSCRIPT_FILE", &self.initial_transcript_file),
+        ] {
+            if let Some(path) = path.as_ref().filter(|path| !path.is_file()) {
+                problems.push(format!("{} {} is not a file", name, path.display()));
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigProblems(problems))
+        }
+    }
+
+    // The OAuth credentials that enabled providers need, but that are unset or
+    // empty in the environment
+    pub fn missing_provider_credentials(&self) -> Vec<String> {
+        KNOWN_PROVIDERS
+            .iter()
+            .filter(|(provider, _)| self.identity_providers.iter().any(|p| p == provider))
+            .flat_map(|(provider, vars)| {
+                vars.iter()
+                    .filter(|var| std::env::var(var).map_or(true, |value| value.is_empty()))
+                    .map(move |var| {
+                        format!("Missing {}, required by the {} provider", var, provider)
+                    })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        keys::KEYS,
+        test_transcript::{TestContribution, TestTranscript},
+        test_util::{init_keys, test_config},
+    };
+    use std::time::Duration;
+
+    // A database that doesn't exist yet
+    const NO_DATABASE: &str = "sqlite:///nonexistent/sequencer.db";
+
+    // A config whose transcript no other test writes to
+    fn isolated_config(name: &str) -> AppConfig {
+        let dir = std::env::temp_dir();
+        AppConfig {
+            transcript_file: dir.join(format!("{}.json", name)),
+            transcript_signature_file: dir.join(format!("{}.sig.json", name)),
+            ..test_config()
+        }
+    }
+
+    fn write_transcript(config: &AppConfig, transcript: &TestTranscript, signed: &TestTranscript) {
+        let signature = signed.sign(KEYS.get().unwrap()).unwrap();
+        std::fs::write(
+            &config.transcript_file,
+            serde_json::to_vec_pretty(transcript).unwrap(),
+        )
+        .unwrap();
+        std::fs::write(
+            &config.transcript_signature_file,
+            serde_json::to_vec_pretty(&signature).unwrap(),
+        )
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn accepts_valid_deployment() {
+        init_keys().await;
+        let config = isolated_config("preflight_valid");
+        let transcript =
+            TestTranscript::generate(&[]).update(&TestContribution::ValidContribution(1));
+        write_transcript(&config, &transcript, &transcript);
+
+        let limiter = VerificationLimiter::new(1);
+        let report =
+            preflight::<TestTranscript>(&config, KEYS.get().unwrap(), &limiter, NO_DATABASE)
+                .await
+                .unwrap();
+        assert_eq!(report.num_contributions, Some(1));
+        assert_eq!(report.pending_migrations, sqlx::migrate!().iter().count());
+        assert!(report.to_string().starts_with("Deployment is valid"));
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_deployments() {
+        init_keys().await;
+        let keys = KEYS.get().unwrap();
+        let limiter = VerificationLimiter::new(1);
+
+        let config = AppConfig {
+            max_concurrent_verifications: 0,
+            ..isolated_config("preflight_no_verifications")
+        };
+        assert!(
+            preflight::<TestTranscript>(&config, keys, &limiter, NO_DATABASE)
+                .await
+                .is_err()
+        );
+
+        let config = isolated_config("preflight_tampered");
+        let signed = TestTranscript::generate(&[]).update(&TestContribution::ValidContribution(1));
+        let tampered = signed.update(&TestContribution::ValidContribution(2));
+        write_transcript(&config, &tampered, &signed);
+        let error = preflight::<TestTranscript>(&config, keys, &limiter, NO_DATABASE)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("signature does not match"));
+
+        let config = isolated_config("preflight_unverifiable");
+        let invalid = signed.update(&TestContribution::InvalidContribution(2));
+        write_transcript(&config, &invalid, &invalid);
+        let error = preflight::<TestTranscript>(&config, keys, &limiter, NO_DATABASE)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("does not verify"));
+    }
+
+    #[tokio::test]
+    async fn counts_pending_migrations_without_applying_them() {
+        let path = std::env::temp_dir().join("preflight_migrations.db");
+        std::fs::remove_file(&path).ok();
+        let url = format!("sqlite://{}", path.display());
+        let all = sqlx::migrate!().iter().count();
+
+        assert_eq!(pending_migrations(&url).await.unwrap(), all);
+        assert!(!path.exists());
+
+        let options = SqliteConnectOptions::from_str(&url)
+            .unwrap()
+            .create_if_missing(true);
+        let mut connection = SqliteConnection::connect_with(&options).await.unwrap();
+        sqlx::migrate!().run(&mut connection).await.unwrap();
+        assert_eq!(pending_migrations(&url).await.unwrap(), 0);
+
+        sqlx::query("UPDATE _sqlx_migrations SET checksum = x'00' WHERE version = 20220913140414")
+            .execute(&mut connection)
+            .await
+            .unwrap();
+        let error = pending_migrations(&url).await.unwrap_err();
+        assert!(error.to_string().contains("was changed"));
+    }
+
+    #[test]
+    fn reports_all_config_problems_at_once() {
+        assert!(test_config().validate().is_ok());
+
+        let config = AppConfig {
+            max_concurrent_verifications: 0,
+            contribute_timeout: Duration::from_secs(30),
+            lobby_checkin_tolerance: Duration::from_secs(60),
+            lobby_checkin_frequency: Duration::from_secs(30),
+            ceremony_sizes: vec![],
+            identity_providers: vec!["github".to_string(), "gitlab".to_string()],
+            transcript_file: PathBuf::from("/nonexistent/transcript.json"),
+            ..test_config()
+        };
+        let problems = config.validate().unwrap_err();
+
+        assert_eq!(problems.0.len(), 6, "{}", problems);
+        let report = problems.to_string();
+        for expected in [
+            "MAX_CONCURRENT_VERIFICATIONS",
+            "CONTRIBUTE_TIMEOUT_SECS",
+            "LOBBY_CHECKIN_TOLERANCE",
+            "CEREMONY_SIZES",
+            "Unknown identity provider gitlab",
+            "/nonexistent",
+        ] {
+            assert!(
+                report.contains(expected),
+                "{} missing from {}",
+                expected,
+                report
+            );
+        }
+    }
+}
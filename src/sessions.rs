@@ -1,16 +1,21 @@
-use std::fmt::{Display, Formatter};
+use std::{
+    collections::VecDeque,
+    fmt::{Display, Formatter},
+};
 
 use crate::jwt::{errors::JwtError, IdToken};
 use async_session::async_trait;
-use axum::{
-    extract::{FromRequest, RequestParts},
-    TypedHeader,
-};
-use headers::{authorization::Bearer, Authorization};
+use axum::extract::{FromRequest, RequestParts};
+use headers::{authorization::Bearer, Authorization, HeaderMapExt};
+use http::HeaderMap;
+use ring::digest::{digest, SHA256};
 use serde::{Deserialize, Serialize};
-use tokio::time::Instant;
+use tokio::time::{Duration, Instant};
 use uuid::Uuid;
 
+// Reported as the provider of anonymous participants
+pub const ANONYMOUS_PROVIDER: &str = "anonymous";
+
 #[derive(Debug, Hash, Serialize, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord)]
 #[serde(rename = "session_id")]
 pub struct SessionId(String);
@@ -20,6 +25,20 @@ impl SessionId {
     pub fn new() -> Self {
         Self(Uuid::new_v4().to_string())
     }
+
+    // The session whose bearer token `headers` carry
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        headers
+            .typed_get::<Authorization<Bearer>>()
+            .map(|Authorization(bearer)| Self(bearer.token().to_owned()))
+    }
+
+    // A short handle to show the session to spectators by. It is derived from
+    // the random session id, so it can't be linked to the identity behind the
+    // session, nor used to act as it
+    pub fn public_handle(&self) -> String {
+        hex::encode(&digest(&SHA256, self.0.as_bytes()).as_ref()[..4])
+    }
 }
 
 impl Default for SessionId {
@@ -36,12 +55,162 @@ impl Display for SessionId {
 
 #[derive(Debug, Clone)]
 pub struct SessionInfo {
-    pub token:                 IdToken,
+    // The identity the user signed in with, unset for anonymous participants
+    pub token:                 Option<IdToken>,
+    // Stands in for the identity of anonymous participants, see
+    // `unique_identifier`. Empty for signed in users.
+    anonymous_id:              String,
+    // Specifies when the user entered the lobby, which is when the session
+    // was issued. Signing in again or resuming keeps the original time.
+    pub joined_at:             Instant,
     // Specifies the last time the user pinged
     pub last_ping_time:        Instant,
     // Indicates whether an early /lobby/try_contribute call is accepted.
-    // (only allowed right after authentication)
+    // (only allowed right after authentication, see `in_first_checkin_grace`)
     pub is_first_ping_attempt: bool,
+    // Paces the check-ins of this session
+    pub checkins:              CheckinPacer,
+}
+
+impl SessionInfo {
+    pub fn signed_in(token: IdToken) -> SessionInfoBuilder {
+        SessionInfoBuilder::new(Some(token), String::new())
+    }
+
+    // Anonymous participants get a random identity of their own, so each of
+    // their sessions can still contribute only once
+    pub fn anonymous() -> SessionInfoBuilder {
+        let id = format!("{} | {}", ANONYMOUS_PROVIDER, Uuid::new_v4());
+        SessionInfoBuilder::new(None, id)
+    }
+
+    pub fn unique_identifier(&self) -> &str {
+        self.token
+            .as_ref()
+            .map_or(self.anonymous_id.as_str(), IdToken::unique_identifier)
+    }
+
+    pub fn provider(&self) -> &str {
+        self.token
+            .as_ref()
+            .map_or(ANONYMOUS_PROVIDER, |token| &token.provider)
+    }
+
+    // Whether the next check-in is the first, made within `grace` of joining,
+    // which isn't rate limited
+    pub fn in_first_checkin_grace(&self, grace: Duration, now: Instant) -> bool {
+        self.is_first_ping_attempt && now.saturating_duration_since(self.joined_at) <= grace
+    }
+
+    // Whether the session is older than `max_lifetime`, regardless of how
+    // regularly it checked in
+    pub fn is_past_lifetime(&self, max_lifetime: Option<Duration>, now: Instant) -> bool {
+        max_lifetime.map_or(false, |max_lifetime| {
+            now.saturating_duration_since(self.joined_at) > max_lifetime
+        })
+    }
+}
+
+// Creates a `SessionInfo` for a fresh lobby entry. Entries that are refreshed
+// keep their timing from before.
+#[must_use]
+pub struct SessionInfoBuilder {
+    token:                 Option<IdToken>,
+    anonymous_id:          String,
+    joined_at:             Instant,
+    is_first_ping_attempt: bool,
+    checkins:              CheckinPacer,
+}
+
+impl SessionInfoBuilder {
+    fn new(token: Option<IdToken>, anonymous_id: String) -> Self {
+        Self {
+            token,
+            anonymous_id,
+            joined_at: Instant::now(),
+            is_first_ping_attempt: true,
+            checkins: CheckinPacer::default(),
+        }
+    }
+
+    pub const fn token(&self) -> Option<&IdToken> {
+        self.token.as_ref()
+    }
+
+    pub fn unique_identifier(&self) -> &str {
+        self.token
+            .as_ref()
+            .map_or(self.anonymous_id.as_str(), IdToken::unique_identifier)
+    }
+
+    pub const fn joined_at(mut self, joined_at: Instant) -> Self {
+        self.joined_at = joined_at;
+        self
+    }
+
+    pub const fn first_ping_attempt(mut self, is_first_ping_attempt: bool) -> Self {
+        self.is_first_ping_attempt = is_first_ping_attempt;
+        self
+    }
+
+    pub fn checkins(mut self, checkins: CheckinPacer) -> Self {
+        self.checkins = checkins;
+        self
+    }
+
+    pub fn build(self) -> SessionInfo {
+        SessionInfo {
+            token:                 self.token,
+            anonymous_id:          self.anonymous_id,
+            joined_at:             self.joined_at,
+            last_ping_time:        Instant::now(),
+            is_first_ping_attempt: self.is_first_ping_attempt,
+            checkins:              self.checkins,
+        }
+    }
+}
+
+// Paces check-ins with a sliding window: at most `burst` check-ins are allowed
+// in any window of `burst` intervals. A burst of one is a minimum interval
+// between check-ins. Larger bursts let an early check-in follow a late one,
+// but unlike a token bucket, which refills while the window slides, never let
+// more than `burst` check-ins through around the edge of a window.
+#[derive(Debug, Clone, Default)]
+pub struct CheckinPacer {
+    // The latest check-ins, oldest first, no more than the burst
+    recent: VecDeque<Instant>,
+}
+
+impl CheckinPacer {
+    // How long until a check-in is allowed, `None` if it is allowed now
+    pub fn wait(&self, now: Instant, interval: Duration, burst: u32) -> Option<Duration> {
+        let burst = burst.max(1);
+        if self.recent.len() < window_len(burst) {
+            return None;
+        }
+        // The window ends when its oldest check-in leaves it
+        let wait = (self.recent[0] + interval * burst).saturating_duration_since(now);
+        (!wait.is_zero()).then_some(wait)
+    }
+
+    pub fn check_in(&mut self, now: Instant, burst: u32) {
+        self.recent.push_back(now);
+        while self.recent.len() > window_len(burst.max(1)) {
+            self.recent.pop_front();
+        }
+    }
+
+    // Whether every check-in has left the window, so that the pacer limits
+    // no more than a new one would
+    pub fn is_idle(&self, now: Instant, interval: Duration, burst: u32) -> bool {
+        self.recent.back().map_or(true, |last| {
+            now.saturating_duration_since(*last) >= interval * burst.max(1)
+        })
+    }
+}
+
+fn window_len(burst: u32) -> usize {
+    usize::try_from(burst).unwrap_or(usize::MAX)
 }
 
 #[async_trait]
@@ -53,11 +222,73 @@ where
 
     async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
         // Extract the token from the authorization header
-        let TypedHeader(Authorization(bearer)) =
-            TypedHeader::<Authorization<Bearer>>::from_request(req)
-                .await
-                .map_err(|_| JwtError::InvalidToken)?;
+        Self::from_headers(req.headers()).ok_or(JwtError::InvalidToken)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Checks in at each of the offsets in seconds, returning the wait of each
+    fn waits(burst: u32, offsets: &[u64]) -> Vec<Option<Duration>> {
+        let start = Instant::now();
+        let interval = Duration::from_secs(28);
+        let mut pacer = CheckinPacer::default();
+        offsets
+            .iter()
+            .map(|offset| {
+                let now = start + Duration::from_secs(*offset);
+                let wait = pacer.wait(now, interval, burst);
+                if wait.is_none() {
+                    pacer.check_in(now, burst);
+                }
+                wait
+            })
+            .collect()
+    }
+
+    #[test]
+    fn burst_of_one_is_a_minimum_interval() {
+        assert_eq!(waits(1, &[0, 10, 28, 60, 70]), vec![
+            None,
+            Some(Duration::from_secs(18)),
+            None,
+            None,
+            Some(Duration::from_secs(18)),
+        ]);
+    }
+
+    #[test]
+    fn larger_bursts_let_an_early_check_in_follow_a_late_one() {
+        // After the late check-in at 40, a minimum interval rejects the one at
+        // 60, while a window of two intervals lets it through. Either way the
+        // next check-in is paced again
+        assert_eq!(waits(1, &[0, 40, 60, 62]), vec![
+            None,
+            None,
+            Some(Duration::from_secs(8)),
+            Some(Duration::from_secs(6)),
+        ]);
+        assert_eq!(waits(2, &[0, 40, 60, 62]), vec![
+            None,
+            None,
+            None,
+            Some(Duration::from_secs(34)),
+        ]);
+    }
 
-        Ok(Self(bearer.token().to_owned()))
+    #[test]
+    fn window_allows_no_burst_at_its_edge() {
+        // By 56, a token bucket of two would have refilled after the check-ins
+        // at 0 and 1, and let both check-ins at 56 through, three within two
+        // intervals. The window lets the second one wait for the check-in at 1
+        // to leave it
+        assert_eq!(waits(2, &[0, 1, 56, 56]), vec![
+            None,
+            None,
+            None,
+            Some(Duration::from_secs(1)),
+        ]);
     }
 }
@@ -1,87 +1,1158 @@
-use std::env;
+use std::{collections::BTreeSet, env, sync::Arc};
 
 use axum::{
     response::{IntoResponse, Response},
     Json,
 };
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use http::StatusCode;
-use serde_json::json;
-use sqlx::{sqlite::SqlitePoolOptions, Executor, Pool, Row, Sqlite};
+use rand::RngCore;
+use ring::{
+    aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN},
+    digest::{digest, SHA256},
+    hmac,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::{
+    sqlite::{SqlitePoolOptions, SqliteRow},
+    Executor, Pool, Row, Sqlite,
+};
+
+use crate::{attestation_chain, data::hash::TranscriptHash, AppConfig};
 
 #[derive(Debug)]
 pub enum StorageError {
     DatabaseError(sqlx::error::Error),
+    CorruptedIdentity,
+    CorruptedAttestation,
+}
+
+impl StorageError {
+    // Whether a write was turned down because a row with the same key exists
+    pub fn is_conflict(&self) -> bool {
+        match self {
+            Self::DatabaseError(error) => error
+                .as_database_error()
+                .and_then(|error| error.code())
+                // SQLITE_CONSTRAINT_PRIMARYKEY and SQLITE_CONSTRAINT_UNIQUE
+                .map_or(false, |code| code == "1555" || code == "2067"),
+            _ => false,
+        }
+    }
 }
 
 impl IntoResponse for StorageError {
     fn into_response(self) -> Response {
         let message = match self {
             Self::DatabaseError(error) => error.to_string(),
+            Self::CorruptedIdentity => "could not decrypt stored identity".to_string(),
+            Self::CorruptedAttestation => "could not decode stored attestation".to_string(),
         };
         let body = Json(json!({ "error": message }));
         (StatusCode::INTERNAL_SERVER_ERROR, body).into_response()
     }
 }
 
+// The participation record of a single accepted contribution
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Attestation {
+    // The ceremony phase the contribution was made in. Contribution indices
+    // start over in a phase that resets the transcript.
+    pub phase:              i64,
+    pub contribution_index: i64,
+    // Hex encoded HMAC of the contributor's identity, see
+    // `PersistentStorage::identity_hash`
+    pub identity_hash:      String,
+    pub attested_at:        DateTime<Utc>,
+    pub provider:           String,
+    pub pubkeys:            Vec<String>,
+    pub identity_signature: Option<String>,
+    // Absent for attestations stored before they were chained, see
+    // `attestation_chain`
+    pub nonce:              Option<String>,
+    pub chain_hash:         Option<String>,
+}
+
+impl Attestation {
+    // The attestation of a contribution that was just accepted, linked to
+    // the chain ending in `prev_hash`
+    pub fn new(
+        phase: i64,
+        contribution_index: usize,
+        identity_hash: String,
+        provider: &str,
+        pubkeys: Vec<String>,
+        identity_signature: Option<String>,
+        prev_hash: &str,
+    ) -> Self {
+        let mut attestation = Self {
+            phase,
+            contribution_index: i64::try_from(contribution_index).expect("index fits i64"),
+            identity_hash,
+            attested_at: Utc::now(),
+            provider: provider.to_owned(),
+            pubkeys,
+            identity_signature,
+            nonce: Some(attestation_chain::nonce()),
+            chain_hash: None,
+        };
+        attestation.chain_hash = Some(attestation_chain::link(prev_hash, &attestation));
+        attestation
+    }
+
+    // Orders attestations, see `attestations_page`
+    pub const fn key(&self) -> (i64, i64) {
+        (self.phase, self.contribution_index)
+    }
+}
+
+// The parts of a contribution bundle stored with its attestation besides the
+// receipt. The identity isn't stored, it is known to whoever looks it up.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BundleRecord {
+    pub witness:         Value,
+    pub transcript_hash: TranscriptHash,
+}
+
+// A phase the ceremony moved to, see `transition_phase`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CeremonyPhase {
+    pub phase:      i64,
+    pub name:       String,
+    pub started_at: DateTime<Utc>,
+}
+
+// Protects contributor identities at rest.
+// The `uid` column holds a deterministic HMAC of the identity, so that
+// uniqueness checks keep working, while the identity itself is sealed
+// with AES-256-GCM into `uid_sealed`. Attestations refer to the identity by
+// an HMAC under a third key, so published attestations can't be linked to
+// the `uid` column.
+pub struct IdentityCipher {
+    index_key:       hmac::Key,
+    attestation_key: hmac::Key,
+    sealing_key:     LessSafeKey,
+}
+
+impl IdentityCipher {
+    // Derives independent indexing and sealing keys from the operator key
+    pub fn new(key: &[u8]) -> Self {
+        let master = hmac::Key::new(hmac::HMAC_SHA256, key);
+        let index_key = hmac::Key::new(
+            hmac::HMAC_SHA256,
+            hmac::sign(&master, b"identity-index").as_ref(),
+        );
+        let attestation_key = hmac::Key::new(
+            hmac::HMAC_SHA256,
+            hmac::sign(&master, b"identity-attestation").as_ref(),
+        );
+        let sealing_key = LessSafeKey::new(
+            UnboundKey::new(
+                &AES_256_GCM,
+                hmac::sign(&master, b"identity-sealing").as_ref(),
+            )
+            .expect("HMAC-SHA256 output is a valid AES-256 key"),
+        );
+        Self {
+            index_key,
+            attestation_key,
+            sealing_key,
+        }
+    }
+
+    pub fn index(&self, uid: &str) -> String {
+        hex::encode(hmac::sign(&self.index_key, uid.as_bytes()))
+    }
+
+    pub fn attestation_hash(&self, uid: &str) -> String {
+        hex::encode(hmac::sign(&self.attestation_key, uid.as_bytes()))
+    }
+
+    // Returns the random nonce followed by the ciphertext and tag
+    pub fn seal(&self, uid: &str) -> Vec<u8> {
+        let mut nonce = [0_u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let mut ciphertext = uid.as_bytes().to_vec();
+        self.sealing_key
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::empty(),
+                &mut ciphertext,
+            )
+            .expect("identity is too large to seal");
+        let mut sealed = nonce.to_vec();
+        sealed.append(&mut ciphertext);
+        sealed
+    }
+
+    pub fn open(&self, sealed: &[u8]) -> Result<String, StorageError> {
+        if sealed.len() < NONCE_LEN {
+            return Err(StorageError::CorruptedIdentity);
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let nonce =
+            Nonce::try_assume_unique_for_key(nonce).map_err(|_| StorageError::CorruptedIdentity)?;
+        let mut buffer = ciphertext.to_vec();
+        let plaintext = self
+            .sealing_key
+            .open_in_place(nonce, Aad::empty(), &mut buffer)
+            .map_err(|_| StorageError::CorruptedIdentity)?;
+        String::from_utf8(plaintext.to_vec()).map_err(|_| StorageError::CorruptedIdentity)
+    }
+}
+
 #[derive(Clone)]
-pub struct PersistentStorage(Pool<Sqlite>);
+pub struct PersistentStorage {
+    pool:            Pool<Sqlite>,
+    identity_cipher: Option<Arc<IdentityCipher>>,
+}
 
 impl PersistentStorage {
+    pub fn new(pool: Pool<Sqlite>, identity_encryption_key: Option<&[u8]>) -> Self {
+        Self {
+            pool,
+            identity_cipher: identity_encryption_key.map(|key| Arc::new(IdentityCipher::new(key))),
+        }
+    }
+
+    // Lets tests hold a connection to simulate a slow database
+    #[cfg(test)]
+    pub async fn acquire_connection(&self) -> sqlx::pool::PoolConnection<Sqlite> {
+        self.pool.acquire().await.unwrap()
+    }
+
+    // How attestations refer to the identity, hex encoded. This is an HMAC
+    // keyed with IDENTITY_ENCRYPTION_KEY, so identities, which are easy to
+    // enumerate, can't be found by hashing candidates. Without the key it
+    // falls back to an unkeyed SHA256, which is not pseudonymous.
+    pub fn identity_hash(&self, uid: &str) -> String {
+        self.identity_cipher.as_ref().map_or_else(
+            || unkeyed_identity_hash(uid),
+            |cipher| cipher.attestation_hash(uid),
+        )
+    }
+
+    // Whether attestations are pseudonymous, see `identity_hash`
+    pub const fn has_identity_key(&self) -> bool {
+        self.identity_cipher.is_some()
+    }
+
+    // The hashes attestations of the identity may be stored under.
+    // Attestations stored before the key was configured keep their unkeyed
+    // hash, as it is covered by the attestation chain.
+    fn identity_hashes(&self, uid: &str) -> [String; 2] {
+        [self.identity_hash(uid), unkeyed_identity_hash(uid)]
+    }
+
+    // The value of the `uid` column for the given identity
+    fn stored_uid(&self, uid: &str) -> String {
+        self.identity_cipher
+            .as_ref()
+            .map_or_else(|| uid.to_owned(), |cipher| cipher.index(uid))
+    }
+
     pub async fn has_contributed(&self, uid: &str) -> Result<bool, StorageError> {
-        let sql = "SELECT EXISTS(SELECT 1 FROM contributors WHERE uid = ?1";
-        self.0
-            .fetch_one(sqlx::query(sql).bind(uid))
+        let sql = "SELECT EXISTS(SELECT 1 FROM contributors WHERE uid = ?1)";
+        self.pool
+            .fetch_one(sqlx::query(sql).bind(self.stored_uid(uid)))
             .await
             .map(|row| row.get(0))
             .map_err(StorageError::DatabaseError)
     }
 
     pub async fn insert_contributor(&self, uid: &str) {
-        let sql = "INSERT INTO contributors (uid, started_at) VALUES (?1, ?2)";
-        self.0
-            .execute(sqlx::query(sql).bind(uid).bind(Utc::now()))
+        let sealed = self.identity_cipher.as_ref().map(|cipher| cipher.seal(uid));
+        let sql = "INSERT INTO contributors (uid, uid_sealed, started_at) VALUES (?1, ?2, ?3)";
+        self.pool
+            .execute(
+                sqlx::query(sql)
+                    .bind(self.stored_uid(uid))
+                    .bind(sealed)
+                    .bind(Utc::now()),
+            )
             .await
             .ok();
     }
 
     pub async fn finish_contribution(&self, uid: &str) {
         let sql = "UPDATE contributors SET finished_at = ?1 WHERE uid = ?2";
-        self.0
-            .execute(sqlx::query(sql).bind(Utc::now()).bind(uid))
+        self.pool
+            .execute(sqlx::query(sql).bind(Utc::now()).bind(self.stored_uid(uid)))
             .await
             .ok();
     }
 
     pub async fn expire_contribution(&self, uid: &str) {
         let sql = "UPDATE contributors SET expired_at = ?1 WHERE uid = ?2";
-        self.0
-            .execute(sqlx::query(sql).bind(Utc::now()).bind(uid))
+        self.pool
+            .execute(sqlx::query(sql).bind(Utc::now()).bind(self.stored_uid(uid)))
+            .await
+            .ok();
+    }
+
+    pub async fn insert_rejected_contribution(
+        &self,
+        uid: &str,
+        contribution_hash: &str,
+        reason: &str,
+    ) {
+        let sql = "INSERT INTO rejected_contributions (uid, rejected_at, contribution_hash, \
+                   reason) VALUES (?1, ?2, ?3, ?4)";
+        self.pool
+            .execute(
+                sqlx::query(sql)
+                    .bind(self.stored_uid(uid))
+                    .bind(Utc::now())
+                    .bind(contribution_hash)
+                    .bind(reason),
+            )
+            .await
+            .ok();
+    }
+
+    // Keeps a rejected contribution in full, with the hash of the transcript it
+    // was verified against
+    pub async fn quarantine_contribution(
+        &self,
+        uid: &str,
+        contribution_hash: &str,
+        reason: &str,
+        transcript_hash: &str,
+        contribution: &[u8],
+    ) {
+        let sql = "INSERT INTO quarantined_contributions (uid, quarantined_at, contribution_hash, \
+                   reason, transcript_hash, contribution) VALUES (?1, ?2, ?3, ?4, ?5, ?6)";
+        self.pool
+            .execute(
+                sqlx::query(sql)
+                    .bind(self.stored_uid(uid))
+                    .bind(Utc::now())
+                    .bind(contribution_hash)
+                    .bind(reason)
+                    .bind(transcript_hash)
+                    .bind(contribution),
+            )
             .await
             .ok();
     }
+
+    // Returns the reason, transcript hash and contribution quarantined under
+    // the contribution hash
+    #[cfg(test)]
+    pub async fn quarantined_contribution(
+        &self,
+        contribution_hash: &str,
+    ) -> Option<(String, String, Vec<u8>)> {
+        let sql = "SELECT reason, transcript_hash, contribution FROM quarantined_contributions \
+                   WHERE contribution_hash = ?1";
+        self.pool
+            .fetch_optional(sqlx::query(sql).bind(contribution_hash))
+            .await
+            .unwrap()
+            .map(|row| (row.get(0), row.get(1), row.get(2)))
+    }
+
+    // Returns the stored uid, contribution hash and reason of each rejection
+    #[cfg(test)]
+    pub async fn rejected_contributions(&self) -> Vec<(String, String, String)> {
+        let sql = "SELECT uid, contribution_hash, reason FROM rejected_contributions";
+        self.pool
+            .fetch_all(sqlx::query(sql))
+            .await
+            .unwrap()
+            .iter()
+            .map(|row| (row.get(0), row.get(1), row.get(2)))
+            .collect()
+    }
+
+    pub async fn insert_invite_codes(&self, codes: &[String]) -> Result<(), StorageError> {
+        let sql = "INSERT INTO invite_codes (code, created_at) VALUES (?1, ?2)";
+        for code in codes {
+            self.pool
+                .execute(sqlx::query(sql).bind(code).bind(Utc::now()))
+                .await
+                .map_err(StorageError::DatabaseError)?;
+        }
+        Ok(())
+    }
+
+    // Whether the identity redeemed an invite code
+    pub async fn is_admitted(&self, uid: &str) -> Result<bool, StorageError> {
+        let sql = "SELECT EXISTS(SELECT 1 FROM invite_codes WHERE used_by = ?1)";
+        self.pool
+            .fetch_one(sqlx::query(sql).bind(self.stored_uid(uid)))
+            .await
+            .map(|row| row.get(0))
+            .map_err(StorageError::DatabaseError)
+    }
+
+    // Admits the identity by marking the code as used by it, in a single
+    // statement, so concurrent redemptions can't both succeed. Returns false
+    // if the code is unknown or already used. An identity that is already
+    // admitted doesn't use up the code, as the unique index on `used_by`
+    // turns the update down.
+    pub async fn redeem_invite_code(&self, code: &str, uid: &str) -> Result<bool, StorageError> {
+        let sql = "UPDATE invite_codes SET used_at = ?1, used_by = ?2 WHERE code = ?3 AND used_at \
+                   IS NULL";
+        let redeemed = self
+            .pool
+            .execute(
+                sqlx::query(sql)
+                    .bind(Utc::now())
+                    .bind(self.stored_uid(uid))
+                    .bind(code),
+            )
+            .await
+            .map_err(StorageError::DatabaseError);
+        match redeemed {
+            Ok(result) if result.rows_affected() == 1 => Ok(true),
+            Ok(_) => self.is_admitted(uid).await,
+            Err(err) if err.is_conflict() => Ok(true),
+            Err(err) => Err(err),
+        }
+    }
+
+    // Records the first check-in of the identity, which joined the lobby at
+    // `joined_at`. Returns whether this is the first check-in, and the join
+    // time recorded with the first one.
+    pub async fn record_first_checkin(
+        &self,
+        uid: &str,
+        joined_at: DateTime<Utc>,
+    ) -> Result<(bool, DateTime<Utc>), StorageError> {
+        let uid = self.stored_uid(uid);
+        let insert = "INSERT OR IGNORE INTO first_checkins (uid, joined_at) VALUES (?1, ?2)";
+        let first = self
+            .pool
+            .execute(sqlx::query(insert).bind(&uid).bind(joined_at))
+            .await
+            .map_err(StorageError::DatabaseError)?
+            .rows_affected()
+            == 1;
+        let select = "SELECT joined_at FROM first_checkins WHERE uid = ?1";
+        self.pool
+            .fetch_one(sqlx::query(select).bind(&uid))
+            .await
+            .map(|row| (first, row.get(0)))
+            .map_err(StorageError::DatabaseError)
+    }
+
+    pub async fn insert_attestation(
+        &self,
+        attestation: &Attestation,
+        receipt: Option<&str>,
+        bundle: Option<&BundleRecord>,
+    ) -> Result<(), StorageError> {
+        let sql = "INSERT INTO attestations (phase, contribution_index, identity_hash, \
+                   attested_at, provider, pubkeys, identity_signature, nonce, chain_hash, \
+                   receipt, bundle) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)";
+        let pubkeys =
+            serde_json::to_string(&attestation.pubkeys).expect("Cannot serialize pubkeys");
+        let bundle =
+            bundle.map(|bundle| serde_json::to_string(bundle).expect("Cannot serialize bundle"));
+        self.pool
+            .execute(
+                sqlx::query(sql)
+                    .bind(attestation.phase)
+                    .bind(attestation.contribution_index)
+                    .bind(&attestation.identity_hash)
+                    .bind(attestation.attested_at)
+                    .bind(&attestation.provider)
+                    .bind(pubkeys)
+                    .bind(&attestation.identity_signature)
+                    .bind(&attestation.nonce)
+                    .bind(&attestation.chain_hash)
+                    .bind(receipt)
+                    .bind(bundle),
+            )
+            .await
+            .map(|_| ())
+            .map_err(StorageError::DatabaseError)
+    }
+
+    // Whether the attestation with the given chain hash is stored. Chain
+    // hashes cover a random nonce, so they identify a single attestation.
+    pub async fn has_attestation(&self, chain_hash: &str) -> Result<bool, StorageError> {
+        let sql = "SELECT 1 FROM attestations WHERE chain_hash = ?1";
+        self.pool
+            .fetch_optional(sqlx::query(sql).bind(chain_hash))
+            .await
+            .map(|row| row.is_some())
+            .map_err(StorageError::DatabaseError)
+    }
+
+    // Attestations stored before they were chained have neither a nonce nor a
+    // chain hash, and the first one chained after them linked to the genesis.
+    // Gives them a nonce, and links the chain again from the first of them on,
+    // in one transaction. Returns how many attestations were linked again.
+    pub async fn chain_legacy_attestations(&self) -> Result<usize, StorageError> {
+        let mut relinked = Vec::new();
+        let mut prev = attestation_chain::GENESIS.to_string();
+        let mut after = None;
+        loop {
+            let page = self
+                .attestations_page(after, LEGACY_CHAIN_PAGE_SIZE)
+                .await?;
+            if page.is_empty() {
+                break;
+            }
+            for mut attestation in page {
+                after = Some(attestation.key());
+                if attestation.chain_hash.is_none() || !relinked.is_empty() {
+                    attestation
+                        .nonce
+                        .get_or_insert_with(attestation_chain::nonce);
+                    attestation.chain_hash = Some(attestation_chain::link(&prev, &attestation));
+                    relinked.push(attestation.clone());
+                }
+                prev = attestation.chain_hash.unwrap_or_default();
+            }
+        }
+        if relinked.is_empty() {
+            return Ok(0);
+        }
+
+        let sql = "UPDATE attestations SET nonce = ?1, chain_hash = ?2 WHERE phase = ?3 AND \
+                   contribution_index = ?4";
+        let mut transaction = self
+            .pool
+            .begin()
+            .await
+            .map_err(StorageError::DatabaseError)?;
+        for attestation in &relinked {
+            sqlx::query(sql)
+                .bind(&attestation.nonce)
+                .bind(&attestation.chain_hash)
+                .bind(attestation.phase)
+                .bind(attestation.contribution_index)
+                .execute(&mut transaction)
+                .await
+                .map_err(StorageError::DatabaseError)?;
+        }
+        transaction
+            .commit()
+            .await
+            .map_err(StorageError::DatabaseError)?;
+        Ok(relinked.len())
+    }
+
+    // The chain hash of the latest attestation, which later ones link to
+    pub async fn attestation_chain_head(&self) -> Result<Option<String>, StorageError> {
+        let sql = "SELECT chain_hash FROM attestations ORDER BY phase DESC, contribution_index \
+                   DESC LIMIT 1";
+        self.pool
+            .fetch_optional(sqlx::query(sql))
+            .await
+            .map(|row| row.and_then(|row| row.get(0)))
+            .map_err(StorageError::DatabaseError)
+    }
+
+    // The receipt of the identity's first contribution
+    pub async fn receipt_of(&self, uid: &str) -> Result<Option<String>, StorageError> {
+        let sql = "SELECT receipt FROM attestations WHERE identity_hash IN (?1, ?2) ORDER BY \
+                   phase, contribution_index LIMIT 1";
+        let [identity_hash, unkeyed] = self.identity_hashes(uid);
+        self.pool
+            .fetch_optional(sqlx::query(sql).bind(identity_hash).bind(unkeyed))
+            .await
+            .map(|row| row.and_then(|row| row.get(0)))
+            .map_err(StorageError::DatabaseError)
+    }
+
+    // The index and receipt of every contribution in the phase that has its
+    // receipt stored, in contribution order
+    pub async fn receipts_of_phase(&self, phase: i64) -> Result<Vec<(i64, String)>, StorageError> {
+        let sql = "SELECT contribution_index, receipt FROM attestations WHERE phase = ?1 AND \
+                   receipt IS NOT NULL ORDER BY contribution_index";
+        self.pool
+            .fetch_all(sqlx::query(sql).bind(phase))
+            .await
+            .map(|rows| rows.iter().map(|row| (row.get(0), row.get(1))).collect())
+            .map_err(StorageError::DatabaseError)
+    }
+
+    // The index, receipt and bundle record of the identity's contribution in
+    // the phase, if its bundle was stored
+    pub async fn bundle_of(
+        &self,
+        uid: &str,
+        phase: i64,
+    ) -> Result<Option<(i64, String, BundleRecord)>, StorageError> {
+        let sql = "SELECT contribution_index, receipt, bundle FROM attestations WHERE \
+                   identity_hash IN (?1, ?2) AND phase = ?3 AND receipt IS NOT NULL AND bundle IS \
+                   NOT NULL ORDER BY contribution_index LIMIT 1";
+        let [identity_hash, unkeyed] = self.identity_hashes(uid);
+        let row = self
+            .pool
+            .fetch_optional(
+                sqlx::query(sql)
+                    .bind(identity_hash)
+                    .bind(unkeyed)
+                    .bind(phase),
+            )
+            .await
+            .map_err(StorageError::DatabaseError)?;
+        row.map(|row| {
+            let bundle: String = row.get(2);
+            let bundle =
+                serde_json::from_str(&bundle).map_err(|_| StorageError::CorruptedAttestation)?;
+            Ok((row.get(0), row.get(1), bundle))
+        })
+        .transpose()
+    }
+
+    // Returns up to `limit` attestations following the one with key `after`,
+    // in contribution order, that is by phase and then by index
+    pub async fn attestations_page(
+        &self,
+        after: Option<(i64, i64)>,
+        limit: u32,
+    ) -> Result<Vec<Attestation>, StorageError> {
+        let sql = "SELECT phase, contribution_index, identity_hash, attested_at, provider, \
+                   pubkeys, identity_signature, nonce, chain_hash FROM attestations WHERE (phase, \
+                   contribution_index) > (?1, ?2) ORDER BY phase, contribution_index LIMIT ?3";
+        let (phase, index) = after.unwrap_or((-1, -1));
+        let rows = self
+            .pool
+            .fetch_all(sqlx::query(sql).bind(phase).bind(index).bind(limit))
+            .await
+            .map_err(StorageError::DatabaseError)?;
+        rows.iter().map(attestation_from_row).collect()
+    }
+
+    // The first attestation of the identity with the given hex encoded hash
+    pub async fn attestation_of(
+        &self,
+        identity_hash: &str,
+    ) -> Result<Option<Attestation>, StorageError> {
+        let sql = "SELECT phase, contribution_index, identity_hash, attested_at, provider, \
+                   pubkeys, identity_signature, nonce, chain_hash FROM attestations WHERE \
+                   identity_hash = ?1 ORDER BY phase, contribution_index LIMIT 1";
+        let row = self
+            .pool
+            .fetch_optional(sqlx::query(sql).bind(identity_hash))
+            .await
+            .map_err(StorageError::DatabaseError)?;
+        row.as_ref().map(attestation_from_row).transpose()
+    }
+
+    pub async fn insert_phase(
+        &self,
+        phase: i64,
+        name: &str,
+    ) -> Result<CeremonyPhase, StorageError> {
+        let started_at = Utc::now();
+        let sql = "INSERT INTO ceremony_phases (phase, name, started_at) VALUES (?1, ?2, ?3)";
+        self.pool
+            .execute(sqlx::query(sql).bind(phase).bind(name).bind(started_at))
+            .await
+            .map_err(StorageError::DatabaseError)?;
+        Ok(CeremonyPhase {
+            phase,
+            name: name.to_owned(),
+            started_at,
+        })
+    }
+
+    // The most recent phase, `None` while the ceremony is in its first
+    pub async fn current_phase(&self) -> Result<Option<CeremonyPhase>, StorageError> {
+        let sql = "SELECT phase, name, started_at FROM ceremony_phases ORDER BY phase DESC LIMIT 1";
+        let row = self
+            .pool
+            .fetch_optional(sqlx::query(sql))
+            .await
+            .map_err(StorageError::DatabaseError)?;
+        Ok(row.map(|row| CeremonyPhase {
+            phase:      row.get(0),
+            name:       row.get(1),
+            started_at: row.get(2),
+        }))
+    }
+
+    // Returns the identities of all contributors, decrypting them if
+    // identity encryption is enabled
+    pub async fn contributors(&self) -> Result<Vec<String>, StorageError> {
+        let sql = "SELECT uid, uid_sealed FROM contributors ORDER BY started_at";
+        let rows = self
+            .pool
+            .fetch_all(sqlx::query(sql))
+            .await
+            .map_err(StorageError::DatabaseError)?;
+        rows.iter().map(|row| self.identity_from_row(row)).collect()
+    }
+
+    // Expects the `uid` and `uid_sealed` columns first
+    fn identity_from_row(&self, row: &SqliteRow) -> Result<String, StorageError> {
+        let sealed: Option<Vec<u8>> = row.get(1);
+        match (&self.identity_cipher, sealed) {
+            (Some(cipher), Some(sealed)) => cipher.open(&sealed),
+            // Rows written before encryption was enabled are plaintext
+            _ => Ok(row.get(0)),
+        }
+    }
+
+    // Reservations are made when a contribution spot is granted and closed
+    // once it is finished or expires. A crash in between leaves them open,
+    // which would keep the identity from ever contributing. Nobody holds the
+    // spot at startup, so every open reservation is left over from before.
+    //
+    // Those whose attestation, stored or still `pending`, is in the
+    // transcript are finished. The others are only released if every
+    // contribution in the transcript is accounted for by an attestation, by
+    // count and by pubkeys, as otherwise one of them may be the contribution
+    // the crash kept from being attested. Anything else is kept open.
+    pub async fn recover_orphaned_reservations(
+        &self,
+        transcript_pubkeys: &BTreeSet<String>,
+        transcript_contributions: usize,
+        pending: &[Attestation],
+    ) -> Result<RecoveredReservations, StorageError> {
+        let sql = "SELECT identity_hash, pubkeys FROM attestations";
+        let mut attestations = Vec::new();
+        for row in self
+            .pool
+            .fetch_all(sqlx::query(sql))
+            .await
+            .map_err(StorageError::DatabaseError)?
+        {
+            let pubkeys: String = row.get(1);
+            let pubkeys = serde_json::from_str::<Vec<String>>(&pubkeys)
+                .map_err(|_| StorageError::CorruptedAttestation)?;
+            attestations.push((row.get::<String, _>(0), pubkeys));
+        }
+        attestations.extend(pending.iter().map(|attestation| {
+            (
+                attestation.identity_hash.clone(),
+                attestation.pubkeys.clone(),
+            )
+        }));
+
+        let mut attested = BTreeSet::new();
+        let mut attested_pubkeys = BTreeSet::new();
+        let mut attested_contributions = 0;
+        for (identity_hash, pubkeys) in attestations {
+            if !pubkeys.is_empty()
+                && pubkeys
+                    .iter()
+                    .all(|pubkey| transcript_pubkeys.contains(pubkey))
+            {
+                attested.insert(identity_hash);
+                attested_pubkeys.extend(pubkeys);
+                attested_contributions += 1;
+            }
+        }
+        let all_attested = attested_contributions == transcript_contributions
+            && attested_pubkeys == *transcript_pubkeys;
+
+        let sql = "SELECT uid, uid_sealed FROM contributors WHERE finished_at IS NULL AND \
+                   expired_at IS NULL";
+        let rows = self
+            .pool
+            .fetch_all(sqlx::query(sql))
+            .await
+            .map_err(StorageError::DatabaseError)?;
+        let mut recovered = RecoveredReservations::default();
+        for row in &rows {
+            let uid = self.identity_from_row(row)?;
+            if self
+                .identity_hashes(&uid)
+                .iter()
+                .any(|hash| attested.contains(hash))
+            {
+                self.finish_contribution(&uid).await;
+            } else if all_attested {
+                let sql = "DELETE FROM contributors WHERE uid = ?1";
+                self.pool
+                    .execute(sqlx::query(sql).bind(self.stored_uid(&uid)))
+                    .await
+                    .map_err(StorageError::DatabaseError)?;
+                recovered.released += 1;
+            } else {
+                recovered.kept += 1;
+            }
+        }
+        Ok(recovered)
+    }
 }
 
-pub async fn persistent_storage_client() -> PersistentStorage {
+// What `recover_orphaned_reservations` did with the reservations it found
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct RecoveredReservations {
+    pub released: usize,
+    // Open reservations that can't be told apart from a contribution that
+    // is in the transcript but was never attested
+    pub kept:     usize,
+}
+
+// Number of attestations read at a time by `chain_legacy_attestations`
+const LEGACY_CHAIN_PAGE_SIZE: u32 = 1_000;
+
+// How attestations referred to an identity before they were keyed
+fn unkeyed_identity_hash(uid: &str) -> String {
+    hex::encode(digest(&SHA256, uid.as_bytes()))
+}
+
+// Expects the columns in the order of `Attestation`
+fn attestation_from_row(row: &SqliteRow) -> Result<Attestation, StorageError> {
+    let pubkeys: String = row.get(5);
+    Ok(Attestation {
+        phase:              row.get(0),
+        contribution_index: row.get(1),
+        identity_hash:      row.get(2),
+        attested_at:        row.get(3),
+        provider:           row.get(4),
+        pubkeys:            serde_json::from_str(&pubkeys)
+            .map_err(|_| StorageError::CorruptedAttestation)?,
+        identity_signature: row.get(6),
+        nonce:              row.get(7),
+        chain_hash:         row.get(8),
+    })
+}
+
+// Storage calls run concurrently on up to `storage_pool_size` connections.
+// Once all of them are busy, calls wait at most `storage_acquire_timeout`
+// for one and then fail, instead of queueing up without bound.
+fn pool_options(config: &AppConfig) -> SqlitePoolOptions {
+    SqlitePoolOptions::new()
+        .max_connections(config.storage_pool_size)
+        .acquire_timeout(config.storage_acquire_timeout)
+}
+
+pub async fn persistent_storage_client(config: &AppConfig) -> PersistentStorage {
     let url = env::var("DATABASE_URL").expect("Missing DATABASE_URL!");
-    let db_pool = SqlitePoolOptions::new()
+    let db_pool = pool_options(config)
         .connect(&url)
         .await
         .expect("Unable to connect to DATABASE_URL");
 
     sqlx::migrate!().run(&db_pool).await.unwrap();
 
-    PersistentStorage(db_pool)
+    PersistentStorage::new(db_pool, config.identity_encryption_key.as_deref())
 }
 
 #[cfg(test)]
 pub async fn test_storage_client() -> PersistentStorage {
+    test_storage_client_with_key(None).await
+}
+
+#[cfg(test)]
+pub async fn test_storage_client_with_key(key: Option<&[u8]>) -> PersistentStorage {
+    // Every connection to `:memory:` opens a separate database, so we
+    // must stick to a single connection.
     let db_pool = SqlitePoolOptions::new()
+        .max_connections(1)
         .connect("sqlite://:memory:")
         .await
         .expect("Unable to connect to memory database");
 
     sqlx::migrate!().run(&db_pool).await.unwrap();
 
-    PersistentStorage(db_pool)
+    PersistentStorage::new(db_pool, key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attestation_chain::GENESIS;
+
+    const KEY: [u8; 32] = [7; 32];
+
+    #[tokio::test]
+    async fn detects_duplicates_with_encrypted_identities() {
+        let storage = test_storage_client_with_key(Some(&KEY)).await;
+
+        assert!(!storage.has_contributed("github | foo").await.unwrap());
+        storage.insert_contributor("github | foo").await;
+        assert!(storage.has_contributed("github | foo").await.unwrap());
+        assert!(!storage.has_contributed("github | bar").await.unwrap());
+
+        assert_eq!(storage.contributors().await.unwrap(), vec![
+            "github | foo".to_string()
+        ]);
+    }
+
+    #[tokio::test]
+    async fn does_not_store_plaintext_identities() {
+        let storage = test_storage_client_with_key(Some(&KEY)).await;
+        storage.insert_contributor("github | foo").await;
+
+        let row = storage
+            .pool
+            .fetch_one("SELECT uid, uid_sealed FROM contributors")
+            .await
+            .unwrap();
+        let uid: String = row.get(0);
+        let sealed: Vec<u8> = row.get(1);
+        assert!(!uid.contains("foo"));
+        assert!(!sealed.windows(3).any(|window| window == b"foo"));
+    }
+
+    #[tokio::test]
+    async fn keys_identity_hashes_of_attestations() {
+        let storage = test_storage_client_with_key(Some(&KEY)).await;
+        let other = test_storage_client_with_key(Some(&[8; 32])).await;
+        let identity_hash = storage.identity_hash("github | foo");
+        assert_ne!(identity_hash, unkeyed_identity_hash("github | foo"));
+        assert_ne!(identity_hash, other.identity_hash("github | foo"));
+        assert_ne!(identity_hash, storage.stored_uid("github | foo"));
+
+        // Attestations from before the key was configured are still found
+        let legacy = Attestation::new(
+            0,
+            0,
+            unkeyed_identity_hash("github | foo"),
+            "Github",
+            vec![],
+            None,
+            GENESIS,
+        );
+        storage
+            .insert_attestation(&legacy, Some("receipt"), None)
+            .await
+            .unwrap();
+        assert_eq!(
+            storage.receipt_of("github | foo").await.unwrap(),
+            Some("receipt".to_string())
+        );
+        assert_eq!(storage.receipt_of("github | bar").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn releases_orphaned_reservations() {
+        let storage = test_storage_client_with_key(Some(&KEY)).await;
+        let transcript_pubkeys = BTreeSet::from(["0xa1".to_string()]);
+
+        // Crashed before contributing
+        storage.insert_contributor("github | foo").await;
+        // Crashed after the contribution was attested
+        storage.insert_contributor("github | bar").await;
+        let attestation = Attestation::new(
+            0,
+            0,
+            storage.identity_hash("github | bar"),
+            "Github",
+            vec!["0xa1".to_string()],
+            None,
+            GENESIS,
+        );
+        storage
+            .insert_attestation(&attestation, None, None)
+            .await
+            .unwrap();
+        // Contribution expired, this is final
+        storage.insert_contributor("github | baz").await;
+        storage.expire_contribution("github | baz").await;
+
+        let recovered = storage
+            .recover_orphaned_reservations(&transcript_pubkeys, 1, &[])
+            .await
+            .unwrap();
+        assert_eq!(recovered, RecoveredReservations {
+            released: 1,
+            kept:     0,
+        });
+        assert!(!storage.has_contributed("github | foo").await.unwrap());
+        assert!(storage.has_contributed("github | bar").await.unwrap());
+        assert!(storage.has_contributed("github | baz").await.unwrap());
+
+        // The released identity can reserve again, and nothing is left over
+        storage.insert_contributor("github | foo").await;
+        assert!(storage.has_contributed("github | foo").await.unwrap());
+        storage.finish_contribution("github | foo").await;
+        let recovered = storage
+            .recover_orphaned_reservations(&transcript_pubkeys, 1, &[])
+            .await
+            .unwrap();
+        assert_eq!(recovered, RecoveredReservations::default());
+    }
+
+    #[tokio::test]
+    async fn keeps_reservations_that_may_have_contributed() {
+        let storage = test_storage_client_with_key(Some(&KEY)).await;
+        let attested = Attestation::new(
+            0,
+            0,
+            storage.identity_hash("github | bar"),
+            "Github",
+            vec!["0xa1".to_string()],
+            None,
+            GENESIS,
+        );
+        storage
+            .insert_attestation(&attested, None, None)
+            .await
+            .unwrap();
+        // Its attestation is only in the journal
+        storage.insert_contributor("github | pending").await;
+        let pending = Attestation::new(
+            0,
+            1,
+            storage.identity_hash("github | pending"),
+            "Github",
+            vec!["0xb2".to_string()],
+            None,
+            attested.chain_hash.as_deref().unwrap(),
+        );
+        // Crashed before its contribution was attested, or before contributing
+        storage.insert_contributor("github | foo").await;
+
+        // The transcript has a contribution nobody attested
+        let transcript_pubkeys = BTreeSet::from(["0xa1", "0xb2", "0xc3"].map(String::from));
+        let recovered = storage
+            .recover_orphaned_reservations(&transcript_pubkeys, 3, &[pending.clone()])
+            .await
+            .unwrap();
+        assert_eq!(recovered, RecoveredReservations {
+            released: 0,
+            kept:     1,
+        });
+        assert!(storage.has_contributed("github | foo").await.unwrap());
+        assert!(storage.has_contributed("github | pending").await.unwrap());
+
+        // Once every contribution is accounted for, it is released
+        let transcript_pubkeys = BTreeSet::from(["0xa1", "0xb2"].map(String::from));
+        let recovered = storage
+            .recover_orphaned_reservations(&transcript_pubkeys, 2, &[pending])
+            .await
+            .unwrap();
+        assert_eq!(recovered, RecoveredReservations {
+            released: 1,
+            kept:     0,
+        });
+        assert!(!storage.has_contributed("github | foo").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn indices_start_over_in_every_phase() {
+        let storage = test_storage_client().await;
+        let first = Attestation::new(
+            0,
+            0,
+            storage.identity_hash("github | foo"),
+            "Github",
+            vec![],
+            None,
+            GENESIS,
+        );
+        storage
+            .insert_attestation(&first, None, None)
+            .await
+            .unwrap();
+        // The next phase reset the transcript
+        let second = Attestation::new(
+            1,
+            0,
+            storage.identity_hash("github | bar"),
+            "Github",
+            vec![],
+            None,
+            first.chain_hash.as_deref().unwrap(),
+        );
+        storage
+            .insert_attestation(&second, None, None)
+            .await
+            .unwrap();
+
+        let attestations = storage.attestations_page(None, 10).await.unwrap();
+        assert_eq!(attestations, vec![first.clone(), second.clone()]);
+        assert_eq!(attestation_chain::verify(&attestations), Ok(()));
+        assert_eq!(
+            storage
+                .attestations_page(Some(first.key()), 10)
+                .await
+                .unwrap(),
+            vec![second.clone()]
+        );
+        assert_eq!(
+            storage.attestation_chain_head().await.unwrap(),
+            second.chain_hash
+        );
+    }
+
+    #[tokio::test]
+    async fn links_attestations_stored_before_chaining() {
+        let storage = test_storage_client().await;
+        let mut prev = GENESIS.to_string();
+        for index in 0..4 {
+            let mut attestation = Attestation::new(
+                0,
+                index,
+                storage.identity_hash(&format!("github | {}", index)),
+                "Github",
+                vec![],
+                None,
+                &prev,
+            );
+            // The first two were stored before chaining, so the third one
+            // linked to the genesis
+            if index < 2 {
+                attestation.nonce = None;
+                attestation.chain_hash = None;
+            } else {
+                prev = attestation.chain_hash.clone().unwrap();
+            }
+            storage
+                .insert_attestation(&attestation, None, None)
+                .await
+                .unwrap();
+        }
+        let attestations = storage.attestations_page(None, 10).await.unwrap();
+        assert_eq!(attestation_chain::verify(&attestations), Err(0));
+
+        assert_eq!(storage.chain_legacy_attestations().await.unwrap(), 4);
+        let attestations = storage.attestations_page(None, 10).await.unwrap();
+        assert_eq!(attestation_chain::verify(&attestations), Ok(()));
+        assert_eq!(
+            storage.attestation_chain_head().await.unwrap(),
+            attestations[3].chain_hash
+        );
+        assert_eq!(storage.chain_legacy_attestations().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn concurrent_calls_share_a_bounded_pool() {
+        use crate::test_util::test_config;
+        use futures::future::join_all;
+        use std::time::Duration;
+
+        let path = std::env::temp_dir().join("storage_pool.db");
+        std::fs::remove_file(&path).ok();
+        let config = AppConfig {
+            storage_pool_size: 4,
+            storage_acquire_timeout: Duration::from_millis(200),
+            ..test_config()
+        };
+        let db_pool = pool_options(&config)
+            .connect(&format!("sqlite://{}?mode=rwc", path.display()))
+            .await
+            .unwrap();
+        sqlx::migrate!().run(&db_pool).await.unwrap();
+        let storage = PersistentStorage::new(db_pool, None);
+
+        let calls = (0..64).map(|i| {
+            let storage = storage.clone();
+            tokio::spawn(async move {
+                let uid = format!("github | {}", i);
+                storage.insert_contributor(&uid).await;
+                storage.has_contributed(&uid).await
+            })
+        });
+        for contributed in join_all(calls).await {
+            assert!(contributed.unwrap().unwrap());
+        }
+        assert!(storage.pool.size() <= 4);
+
+        // With every connection taken, calls fail instead of queueing
+        let mut held = Vec::new();
+        for _ in 0..4 {
+            held.push(storage.acquire_connection().await);
+        }
+        assert!(matches!(
+            storage.has_contributed("github | 0").await,
+            Err(StorageError::DatabaseError(sqlx::Error::PoolTimedOut))
+        ));
+        drop(held);
+        assert!(storage.has_contributed("github | 0").await.unwrap());
+    }
 }
@@ -1,10 +1,15 @@
-use crate::{Contribution, Transcript};
+use crate::{
+    data::transcript::{VerificationWork, VerifyAllError},
+    Contribution, Transcript,
+};
 use serde::{Deserialize, Serialize};
 
 #[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
 pub enum TestContribution {
     ValidContribution(i64),
     InvalidContribution(i64),
+    // Passes verification, but leaves out the G2 powers
+    TruncatedContribution(i64),
 }
 
 impl Contribution for TestContribution {
@@ -12,9 +17,15 @@ impl Contribution for TestContribution {
 
     fn get_receipt(&self) -> Self::Receipt {
         match self {
-            Self::InvalidContribution(i) | Self::ValidContribution(i) => *i,
+            Self::InvalidContribution(i)
+            | Self::ValidContribution(i)
+            | Self::TruncatedContribution(i) => *i,
         }
     }
+
+    fn pubkeys(&self) -> Vec<String> {
+        vec![self.get_receipt().to_string()]
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
@@ -36,13 +47,55 @@ impl Transcript for TestTranscript {
     type ContributionType = TestContribution;
     type ValidationError = ();
 
-    fn verify_contribution(&self, contribution: &TestContribution) -> Result<(), ()> {
+    fn generate(_ceremony_sizes: &[(usize, usize)]) -> Self {
+        Self::default()
+    }
+
+    fn verify_contribution(
+        &self,
+        initial: &Self,
+        contribution: &TestContribution,
+    ) -> Result<(), ()> {
+        if self.contributions.is_empty() && self != initial {
+            return Err(());
+        }
         match contribution {
-            TestContribution::ValidContribution(_) => Ok(()),
+            TestContribution::ValidContribution(_) | TestContribution::TruncatedContribution(_) => {
+                Ok(())
+            }
             TestContribution::InvalidContribution(_) => Err(()),
         }
     }
 
+    fn verification_work(&self, _contribution: &TestContribution) -> VerificationWork {
+        VerificationWork {
+            powers:   1,
+            pairings: 2,
+        }
+    }
+
+    // Test contributions contain no encoded points, instead an invalid one is
+    // rejected as soon as its variant name arrives
+    fn check_point(point: &str) -> Result<(), ()> {
+        if point == "InvalidContribution" {
+            return Err(());
+        }
+        Ok(())
+    }
+
+    fn verification_checks(&self) -> &'static [&'static str] {
+        &["extends_initial_state", "valid_contribution"]
+    }
+
+    // A contribution stands in for the delta applied to the previous one
+    fn suspicious_pattern(&self, contribution: &TestContribution) -> Option<&'static str> {
+        let value = contribution.get_receipt();
+        if value == self.get_contribution().get_receipt() {
+            return Some("unchanged_powers");
+        }
+        (-1..=1).contains(&value).then_some("small_scalar_delta")
+    }
+
     fn update(&self, contribution: &TestContribution) -> Self {
         let mut new_contributions = self.contributions.clone();
         new_contributions.push(contribution.clone());
@@ -55,4 +108,88 @@ impl Transcript for TestTranscript {
     fn get_contribution(&self) -> TestContribution {
         self.contributions.last().unwrap_or(&self.initial).clone()
     }
+
+    fn num_contributions(&self) -> usize {
+        self.contributions.len()
+    }
+
+    // A single power of each group, which a truncated contribution drops
+    fn dimensions(&self) -> Vec<(usize, usize)> {
+        match self.get_contribution() {
+            TestContribution::TruncatedContribution(_) => vec![(1, 0)],
+            _ => vec![(1, 1)],
+        }
+    }
+
+    fn verify_all_with_progress(
+        &self,
+        initial: &Self,
+        progress: &mut dyn FnMut(usize),
+    ) -> Result<(), VerifyAllError<()>> {
+        if self.initial != initial.initial {
+            return Err(VerifyAllError::TamperedInitialState);
+        }
+        let mut transcript = initial.clone();
+        for (index, contribution) in self.contributions.iter().enumerate() {
+            transcript
+                .verify_contribution(initial, contribution)
+                .map_err(|error| VerifyAllError::InvalidContribution(index, error))?;
+            transcript = transcript.update(contribution);
+            progress(index + 1);
+        }
+        Ok(())
+    }
+
+    fn pubkeys(&self) -> Vec<String> {
+        self.contributions
+            .iter()
+            .flat_map(Contribution::pubkeys)
+            .collect()
+    }
+}
+
+#[test]
+fn first_contribution_must_extend_initial_state() {
+    let contribution = TestContribution::ValidContribution(1);
+    let initial = TestTranscript::generate(&[]);
+    assert!(initial.verify_contribution(&initial, &contribution).is_ok());
+
+    let tampered = TestTranscript {
+        initial:       TestContribution::ValidContribution(42),
+        contributions: vec![],
+    };
+    assert!(tampered
+        .verify_contribution(&initial, &contribution)
+        .is_err());
+    // Unless the ceremony was configured to start from there
+    assert!(tampered
+        .verify_contribution(&tampered, &contribution)
+        .is_ok());
+}
+
+#[tokio::test]
+async fn invalid_contribution_is_rejected_before_the_upload_ends() {
+    use crate::framing::decode_framed_checked;
+    use axum::body::Bytes;
+    use futures::{stream, StreamExt};
+    use std::convert::Infallible;
+
+    let encoded = serde_json::to_vec(&TestContribution::InvalidContribution(7)).unwrap();
+    let mut frame = u32::try_from(encoded.len()).unwrap().to_be_bytes().to_vec();
+    // The client sends the variant name, but never the value
+    let name_end = encoded.iter().rposition(|&byte| byte == b'"').unwrap();
+    frame.extend_from_slice(&encoded[..=name_end]);
+    let body = stream::iter([Ok::<_, Infallible>(Bytes::from(frame))]).chain(stream::pending());
+
+    let decoded = tokio::time::timeout(
+        std::time::Duration::from_secs(5),
+        decode_framed_checked::<TestContribution, _, _, _, _>(
+            Box::pin(body),
+            usize::MAX,
+            TestTranscript::check_point,
+        ),
+    )
+    .await
+    .expect("decoding should stop at the invalid contribution");
+    assert_eq!(decoded, Ok(Err(())));
 }
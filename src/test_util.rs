@@ -1,6 +1,43 @@
-use tokio::time::Instant;
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
 
-use crate::{jwt, sessions::SessionInfo};
+use axum::{body::HttpBody, http::HeaderMap, response::Response, Extension, Json};
+use chrono::DateTime;
+use tokio::time::Duration;
+
+use crate::{
+    api::v1::{
+        contribute::{contribute, ContributeError, ContributeReceipt},
+        info::MissingTranscript,
+    },
+    connections::ExcessConnections,
+    constants,
+    data::hash::HashAlgorithm,
+    jwt, keys,
+    sessions::{SessionId, SessionInfo},
+    storage::{test_storage_client, PersistentStorage},
+    test_transcript::{TestContribution, TestTranscript},
+    verification::{FullVerifier, SharedVerifier, VerificationLimiter},
+    AppConfig, Keys, SharedState, SharedTranscript,
+};
+
+pub async fn init_keys() {
+    keys::KEYS
+        .set(
+            Keys::new(keys::Options {
+                private_key: PathBuf::from("private.key"),
+                public_key:  PathBuf::from("publickey.pem"),
+            })
+            .await
+            .unwrap(),
+        )
+        .ok();
+}
 
 pub fn test_jwt(exp: u64) -> jwt::IdToken {
     jwt::IdToken {
@@ -12,9 +49,178 @@ pub fn test_jwt(exp: u64) -> jwt::IdToken {
 }
 
 pub fn create_test_session_info(exp: u64) -> SessionInfo {
-    SessionInfo {
-        token:                 test_jwt(exp),
-        last_ping_time:        Instant::now(),
-        is_first_ping_attempt: true,
+    SessionInfo::signed_in(test_jwt(exp)).build()
+}
+
+pub fn test_config() -> AppConfig {
+    let mut transcript = std::env::temp_dir();
+    transcript.push("transcript.json");
+    let mut transcript_work = std::env::temp_dir();
+    transcript_work.push("transcript.json.new");
+    let mut transcript_signature = std::env::temp_dir();
+    transcript_signature.push("transcript.json.sig");
+    AppConfig {
+        eth_check_nonce_at_block:     "".to_string(),
+        eth_min_nonce:                0,
+        github_max_creation_time:     DateTime::parse_from_rfc3339(
+            constants::GITHUB_ACCOUNT_CREATION_DEADLINE,
+        )
+        .unwrap(),
+        eth_rpc_url:                  "".to_string(),
+        identity_providers:           vec!["github".to_string(), "siwe".to_string()],
+        required_auth_providers:      vec![],
+        transcript_file:              transcript,
+        transcript_in_progress_file:  transcript_work,
+        transcript_signature_file:    transcript_signature,
+        transcript_objects:           None,
+        initial_transcript_file:      None,
+        identity_encryption_key:      None,
+        max_concurrent_verifications: 1,
+        storage_pool_size:            constants::STORAGE_POOL_SIZE as u32,
+        storage_acquire_timeout:      Duration::from_secs(
+            constants::STORAGE_ACQUIRE_TIMEOUT_SEC as u64,
+        ),
+        verify_timeout:               Duration::from_secs(60),
+        contribute_timeout:           Duration::from_secs(constants::CONTRIBUTE_TIMEOUT_SEC as u64),
+        info_timeout:                 Duration::from_secs(constants::INFO_TIMEOUT_SEC as u64),
+        max_header_bytes:             constants::MAX_HEADER_BYTES,
+        max_connections:              constants::MAX_CONNECTIONS,
+        excess_connections:           ExcessConnections::Refuse,
+        response_headers:             HeaderMap::new(),
+        compute_deadline:             Duration::from_secs(constants::COMPUTE_DEADLINE as u64),
+        compute_deadline_per_power:   None,
+        compute_heartbeat_timeout:    None,
+        min_compute_time:             None,
+        max_deadline_extension:       Duration::from_secs(
+            constants::MAX_DEADLINE_EXTENSION_SEC as u64,
+        ),
+        lobby_checkin_frequency:      Duration::from_secs(
+            constants::LOBBY_CHECKIN_FREQUENCY_SEC as u64,
+        ),
+        lobby_checkin_tolerance:      Duration::from_secs(
+            constants::LOBBY_CHECKIN_TOLERANCE_SEC as u64,
+        ),
+        checkin_burst:                constants::CHECKIN_BURST,
+        first_checkin_grace:          Duration::from_secs(
+            constants::FIRST_CHECKIN_GRACE_SEC as u64,
+        ),
+        ceremony_sizes:               kzg_ceremony_crypto::SIZES.to_vec(),
+        denied_client_versions:       Vec::new(),
+        client_upgrade_url:           None,
+        admin_token:                  None,
+        allowlist_file:               None,
+        denylist_file:                None,
+        admission_hook:               None,
+        rejoin_cooldown:              None,
+        order_commitment:             false,
+        clock_skew:                   Duration::from_secs(constants::CLOCK_SKEW_SEC as u64),
+        preverification_key:          None,
+        pretty_responses:             false,
+        public_contribution_bundles:  false,
+        identity_lookups_per_minute:  60,
+        transcript_hash_algorithm:    HashAlgorithm::Sha256,
+        missing_transcript:           MissingTranscript::Initial,
+        contributed_message:          "Thank you!".to_string(),
+        require_invite_code:          false,
+        anonymous_participants:       false,
+        anonymous_joins_per_minute:   constants::ANONYMOUS_JOINS_PER_MINUTE,
+        shutdown_when_drained:        false,
+        strict_requests:              false,
+        overload_lobby_size:          constants::OVERLOAD_LOBBY_SIZE,
+        max_contributions:            None,
+        drain_at_max_contributions:   false,
+        session_max_lifetime:         None,
+        error_response_floor:         None,
+        log_rejected_contributions:   false,
+        quarantine_contributions:     false,
+        check_contribution_entropy:   false,
+        require_identity_signature:   true,
+    }
+}
+
+pub async fn response_body(response: Response) -> Vec<u8> {
+    let mut body = response.into_body();
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        bytes.extend_from_slice(&chunk.unwrap());
+    }
+    bytes
+}
+
+// A ceremony for tests to submit contributions to. Its transcript files are in
+// a directory of its own, so tests running at the same time, in this or
+// another process, don't overwrite each other's.
+pub struct TestCeremony {
+    pub config:     AppConfig,
+    pub store:      SharedState,
+    pub transcript: SharedTranscript<TestTranscript>,
+    pub storage:    PersistentStorage,
+}
+
+impl TestCeremony {
+    pub async fn new(name: &str) -> Self {
+        static CEREMONIES: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "{}_{}_{}",
+            name,
+            std::process::id(),
+            CEREMONIES.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::create_dir_all(&dir).unwrap();
+        Self {
+            config:     AppConfig {
+                transcript_file: dir.join("transcript.json"),
+                transcript_in_progress_file: dir.join("transcript.json.new"),
+                transcript_signature_file: dir.join("transcript.json.sig"),
+                ..test_config()
+            },
+            store:      SharedState::default(),
+            transcript: SharedTranscript::default(),
+            storage:    test_storage_client().await,
+        }
+    }
+
+    // Submits the contribution as `session_id`, with the given headers
+    pub async fn contribute_with(
+        &self,
+        session_id: SessionId,
+        headers: HeaderMap,
+        contribution: TestContribution,
+    ) -> Result<ContributeReceipt, ContributeError> {
+        let verifier: SharedVerifier<TestTranscript> =
+            Arc::new(FullVerifier::new(TestTranscript::default()));
+        contribute::<TestTranscript>(
+            session_id,
+            headers,
+            Json(contribution),
+            Extension(self.store.clone()),
+            Extension(self.config.clone()),
+            Extension(self.transcript.clone()),
+            Extension(self.storage.clone()),
+            Extension(VerificationLimiter::new(1)),
+            Extension(verifier),
+        )
+        .await
+    }
+
+    pub async fn contribute(
+        &self,
+        session_id: SessionId,
+        contribution: TestContribution,
+    ) -> Result<ContributeReceipt, ContributeError> {
+        self.contribute_with(session_id, HeaderMap::new(), contribution)
+            .await
+    }
+
+    // Grants the spot to a new session and submits the contribution as it
+    pub async fn contribute_as_new_participant(
+        &self,
+        contribution: TestContribution,
+    ) -> Result<ContributeReceipt, ContributeError> {
+        let participant = SessionId::new();
+        self.store.write().await.participant =
+            Some((participant.clone(), create_test_session_info(100)));
+        self.contribute(participant, contribution).await
     }
 }
@@ -0,0 +1,728 @@
+use std::{
+    collections::HashMap,
+    ops::Deref,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_gauge, register_int_counter, register_int_counter_vec, Gauge, IntCounter,
+    IntCounterVec,
+};
+use ring::{
+    digest::{digest, Context, SHA256},
+    hmac,
+};
+use serde::Serialize;
+use serde_json::{json, Value};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::{
+    data::{hash::TranscriptHash, transcript::VerifyAllError},
+    Transcript,
+};
+
+static VERIFICATION_POWERS_PER_SECOND: Lazy<Gauge> = Lazy::new(|| {
+    register_gauge!(
+        "verification_powers_per_second",
+        "Throughput of the most recent contribution verification"
+    )
+    .unwrap()
+});
+
+static VERIFIED_POWERS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!("verified_powers_total", "Total number of powers verified").unwrap()
+});
+
+static VERIFICATION_PAIRINGS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!(
+        "verification_pairings_total",
+        "Total number of pairings computed during verification"
+    )
+    .unwrap()
+});
+
+static REJECTED_CONTRIBUTIONS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "rejected_contributions_total",
+        "Number of contributions that failed verification, by failed check and auth provider",
+        &["reason", "provider"]
+    )
+    .unwrap()
+});
+
+// How often background work checks whether a verification slot is spare
+const SPARE_SLOT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// Bounds the number of contribution verifications that can run at once,
+// so that verification work can't saturate the blocking thread pool
+#[derive(Clone)]
+pub struct VerificationLimiter {
+    slots:    Arc<Semaphore>,
+    capacity: usize,
+}
+
+impl VerificationLimiter {
+    pub fn new(max_concurrent_verifications: usize) -> Self {
+        Self {
+            slots:    Arc::new(Semaphore::new(max_concurrent_verifications)),
+            capacity: max_concurrent_verifications,
+        }
+    }
+
+    // Returns `None` if all verification slots are taken.
+    // The slot is released when the permit is dropped.
+    pub fn try_acquire(&self) -> Option<OwnedSemaphorePermit> {
+        self.slots.clone().try_acquire_owned().ok()
+    }
+
+    // Whether background work can ever get a slot without taking the last one
+    pub const fn has_spare_slot(&self) -> bool {
+        self.capacity > 1
+    }
+
+    // Waits until a slot can be taken while another one stays free, for
+    // background work that must not turn contributions away. Waiting in the
+    // semaphore's queue instead would hold back released slots from
+    // contributions. Returns `None` if there is only a single slot.
+    pub async fn acquire_spare(&self) -> Option<OwnedSemaphorePermit> {
+        if !self.has_spare_slot() {
+            return None;
+        }
+        loop {
+            if self.slots.available_permits() > 1 {
+                if let Ok(permit) = self.slots.clone().try_acquire_owned() {
+                    // A contribution may have taken the other slot meanwhile
+                    if self.slots.available_permits() > 0 {
+                        return Some(permit);
+                    }
+                }
+            }
+            tokio::time::sleep(SPARE_SLOT_POLL_INTERVAL).await;
+        }
+    }
+
+    // Waits for any slot, for work done before contributions are taken, such
+    // as checking the transcript at startup or in a dry run
+    pub async fn acquire(&self) -> OwnedSemaphorePermit {
+        self.slots
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("verification slots are never closed")
+    }
+
+    // Verifies a whole transcript from `initial` in a slot, on the blocking
+    // pool rather than the async runtime
+    pub async fn verify_all<T>(
+        &self,
+        transcript: impl Deref<Target = T> + Send + 'static,
+        initial: T,
+    ) -> Result<(), VerifyAllError<T::ValidationError>>
+    where
+        T: Transcript + Send + 'static,
+        T::ValidationError: Send,
+    {
+        let permit = self.acquire().await;
+        tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            transcript.verify_all(&initial)
+        })
+        .await
+        .expect("transcript verification panicked")
+    }
+
+    // Whether every slot is taken. New sign-ins and anonymous joins are shed
+    // while it is and the lobby is long.
+    pub fn is_saturated(&self) -> bool {
+        self.slots.available_permits() == 0
+    }
+}
+
+pub fn recent_throughput() -> f64 {
+    VERIFICATION_POWERS_PER_SECOND.get()
+}
+
+// Verifies the contribution and records the verification throughput
+#[allow(clippy::cast_precision_loss)] // Power counts are far below 2^52
+pub fn verify_contribution<T: Transcript>(
+    transcript: &T,
+    initial: &T,
+    contribution: &T::ContributionType,
+) -> Result<(), T::ValidationError> {
+    let work = transcript.verification_work(contribution);
+    let start = Instant::now();
+    let result = transcript.verify_contribution(initial, contribution);
+    let elapsed = start.elapsed().as_secs_f64().max(f64::EPSILON);
+
+    VERIFICATION_POWERS_PER_SECOND.set(work.powers as f64 / elapsed);
+    VERIFIED_POWERS.inc_by(work.powers as u64);
+    VERIFICATION_PAIRINGS.inc_by(work.pairings as u64);
+    result
+}
+
+// How a failed verification is recorded: the check that failed, taken from
+// the serialized validation error, and a hash of the rejected contribution
+pub struct RejectionFingerprint {
+    pub contribution_hash: String,
+    pub reason:            String,
+}
+
+impl RejectionFingerprint {
+    pub fn new<T: Transcript>(
+        contribution: &T::ContributionType,
+        error: &T::ValidationError,
+    ) -> Self {
+        Self {
+            contribution_hash: hex::encode(json_hash(contribution)),
+            reason:            serde_json::to_string(error).expect("Cannot serialize error"),
+        }
+    }
+
+    // For a contribution that passed verification, but matched a degenerate
+    // `pattern`
+    pub fn suspicious<T: Transcript>(contribution: &T::ContributionType, pattern: &str) -> Self {
+        Self {
+            contribution_hash: hex::encode(json_hash(contribution)),
+            reason:            json!({ "SuspiciousContribution": pattern }).to_string(),
+        }
+    }
+
+    // For an upload that was rejected before it was fully received, so there
+    // is no contribution to hash
+    pub fn mid_stream<T: Transcript>(error: &T::ValidationError) -> Self {
+        Self {
+            contribution_hash: String::new(),
+            reason:            serde_json::to_string(error).expect("Cannot serialize error"),
+        }
+    }
+
+    // Counts the rejection by the name of the failed check, and the auth
+    // provider of the contributor. Details such as indices are left out to
+    // keep the number of label values small.
+    pub fn record(&self, provider: &str) {
+        let check = match serde_json::from_str::<Value>(&self.reason) {
+            Ok(Value::String(check)) => check,
+            Ok(Value::Object(fields)) if fields.len() == 1 => {
+                fields.keys().next().cloned().unwrap_or_default()
+            }
+            _ => "other".to_string(),
+        };
+        REJECTED_CONTRIBUTIONS
+            .with_label_values(&[&check, provider])
+            .inc();
+    }
+}
+
+pub type SharedVerifier<T> = Arc<dyn Verifier<T>>;
+
+// Decides whether a contribution is a valid extension of the transcript,
+// whose hash `transcript_hash` the caller already knows. `mac` is an optional
+// MAC of a trusted verifier submitted with the contribution, see
+// `PreverifiedVerifier`.
+pub trait Verifier<T: Transcript>: Send + Sync {
+    fn verify(
+        &self,
+        transcript: &T,
+        transcript_hash: &TranscriptHash,
+        contribution: &T::ContributionType,
+        mac: Option<&str>,
+    ) -> Result<(), T::ValidationError>;
+}
+
+// Runs the full pairing checks. The first contribution must build on
+// `initial`, the state the ceremony started from.
+pub struct FullVerifier<T> {
+    initial: T,
+}
+
+impl<T> FullVerifier<T> {
+    pub const fn new(initial: T) -> Self {
+        Self { initial }
+    }
+}
+
+impl<T: Transcript + Send + Sync> Verifier<T> for FullVerifier<T> {
+    fn verify(
+        &self,
+        transcript: &T,
+        _transcript_hash: &TranscriptHash,
+        contribution: &T::ContributionType,
+        _mac: Option<&str>,
+    ) -> Result<(), T::ValidationError> {
+        verify_contribution(transcript, &self.initial, contribution)
+    }
+}
+
+// Accepts contributions that a trusted out-of-band verifier has already
+// checked. The verifier shares the key with the sequencer and hands the
+// contributor an HMAC over the transcript and contribution. This is a MAC,
+// not a proof of validity: anyone holding the key can have any contribution
+// accepted without verification, so the key must be kept as safe as the
+// transcript itself. Contributions without a valid MAC get the full
+// verification.
+pub struct PreverifiedVerifier<T> {
+    key:  hmac::Key,
+    full: FullVerifier<T>,
+}
+
+impl<T: Transcript> PreverifiedVerifier<T> {
+    pub fn new(key: &[u8], initial: T) -> Self {
+        Self {
+            key:  hmac::Key::new(hmac::HMAC_SHA256, key),
+            full: FullVerifier::new(initial),
+        }
+    }
+
+    // The MAC the out-of-band verifier hands to the contributor
+    pub fn mac(&self, transcript: &T, contribution: &T::ContributionType) -> String {
+        hex::encode(hmac::sign(
+            &self.key,
+            &mac_message(transcript, contribution),
+        ))
+    }
+}
+
+impl<T: Transcript + Send + Sync> Verifier<T> for PreverifiedVerifier<T> {
+    fn verify(
+        &self,
+        transcript: &T,
+        transcript_hash: &TranscriptHash,
+        contribution: &T::ContributionType,
+        mac: Option<&str>,
+    ) -> Result<(), T::ValidationError> {
+        let mac_is_valid = mac
+            .and_then(|mac| hex::decode(mac).ok())
+            .map_or(false, |mac| {
+                hmac::verify(&self.key, &mac_message(transcript, contribution), &mac).is_ok()
+            });
+        if mac_is_valid {
+            return Ok(());
+        }
+        self.full
+            .verify(transcript, transcript_hash, contribution, None)
+    }
+}
+
+// Reuses the outcome of a recent verification when the same contribution is
+// submitted again with the same MAC, e.g. a retry after a network error.
+// Outcomes are only valid for the transcript they were computed against, so
+// the cache is emptied whenever the transcript hash changes.
+pub struct CachedVerifier<T: Transcript> {
+    inner: SharedVerifier<T>,
+    ttl:   Duration,
+    cache: Mutex<VerificationCache<T::ValidationError>>,
+}
+
+// Outcomes by contribution hash and submitted MAC
+type CacheKey = (Vec<u8>, Option<String>);
+
+struct VerificationCache<E> {
+    transcript: TranscriptHash,
+    outcomes:   HashMap<CacheKey, (Instant, Result<(), E>)>,
+    hits:       u64,
+    misses:     u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct CacheStats {
+    pub hits:   u64,
+    pub misses: u64,
+    pub size:   usize,
+}
+
+// A cache operators can inspect and flush through the admin endpoints,
+// whatever the transcript type it caches for
+pub trait ManagedCache: Send + Sync {
+    fn stats(&self) -> CacheStats;
+    fn clear(&self);
+}
+
+pub type SharedCache = Arc<dyn ManagedCache>;
+
+impl<T: Transcript> CachedVerifier<T> {
+    pub fn new(inner: SharedVerifier<T>, ttl: Duration) -> Self {
+        Self {
+            inner,
+            ttl,
+            cache: Mutex::new(VerificationCache {
+                transcript: TranscriptHash::default(),
+                outcomes:   HashMap::new(),
+                hits:       0,
+                misses:     0,
+            }),
+        }
+    }
+}
+
+impl<T> ManagedCache for CachedVerifier<T>
+where
+    T: Transcript,
+    T::ValidationError: Send,
+{
+    fn stats(&self) -> CacheStats {
+        let cache = self.cache.lock().unwrap();
+        CacheStats {
+            hits:   cache.hits,
+            misses: cache.misses,
+            size:   cache.outcomes.len(),
+        }
+    }
+
+    // Only drops the cached outcomes, the hit and miss counts are kept
+    fn clear(&self) {
+        self.cache.lock().unwrap().outcomes.clear();
+    }
+}
+
+impl<T> Verifier<T> for CachedVerifier<T>
+where
+    T: Transcript,
+    T::ValidationError: Send,
+{
+    fn verify(
+        &self,
+        transcript: &T,
+        transcript_hash: &TranscriptHash,
+        contribution: &T::ContributionType,
+        mac: Option<&str>,
+    ) -> Result<(), T::ValidationError> {
+        let key = (json_hash(contribution), mac.map(str::to_owned));
+        {
+            let mut cache = self.cache.lock().unwrap();
+            if &cache.transcript != transcript_hash {
+                cache.transcript = transcript_hash.clone();
+                cache.outcomes.clear();
+            }
+            if let Some((verified_at, outcome)) = cache.outcomes.get(&key) {
+                if verified_at.elapsed() < self.ttl {
+                    let outcome = outcome.clone();
+                    cache.hits += 1;
+                    return outcome;
+                }
+            }
+            cache.misses += 1;
+        }
+
+        let outcome = self
+            .inner
+            .verify(transcript, transcript_hash, contribution, mac);
+
+        let mut cache = self.cache.lock().unwrap();
+        // The transcript may have moved on while we were verifying
+        if &cache.transcript == transcript_hash {
+            let ttl = self.ttl;
+            cache
+                .outcomes
+                .retain(|_, (verified_at, _)| verified_at.elapsed() < ttl);
+            cache
+                .outcomes
+                .insert(key, (Instant::now(), outcome.clone()));
+        }
+        outcome
+    }
+}
+
+pub fn json_hash<S: Serialize + ?Sized>(value: &S) -> Vec<u8> {
+    let json = serde_json::to_vec(value).expect("Cannot serialize for hashing");
+    digest(&SHA256, &json).as_ref().to_vec()
+}
+
+// Binds the MAC to both the transcript and the contribution, so it can't be
+// replayed against a later transcript. They are serialized straight into the
+// hash, so the transcript is never held in memory as JSON.
+fn mac_message<T: Transcript>(transcript: &T, contribution: &T::ContributionType) -> Vec<u8> {
+    let mut writer = HashWriter(Context::new(&SHA256));
+    serde_json::to_writer(&mut writer, transcript).expect("Cannot serialize transcript");
+    serde_json::to_writer(&mut writer, contribution).expect("Cannot serialize contribution");
+    writer.0.finish().as_ref().to_vec()
+}
+
+// Feeds everything written to it into a running hash
+struct HashWriter(Context);
+
+impl std::io::Write for HashWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        data::transcript::transcript_hash, test_transcript::TestContribution, TestTranscript,
+    };
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn caps_concurrent_verifications() {
+        let limiter = VerificationLimiter::new(3);
+
+        let handles = (0..10)
+            .map(|_| {
+                let limiter = limiter.clone();
+                tokio::spawn(async move { limiter.try_acquire() })
+            })
+            .collect::<Vec<_>>();
+        let mut permits = Vec::new();
+        for handle in handles {
+            permits.push(handle.await.unwrap());
+        }
+
+        assert_eq!(permits.iter().filter(|permit| permit.is_some()).count(), 3);
+        assert!(limiter.is_saturated());
+
+        drop(permits);
+        assert!(!limiter.is_saturated());
+        assert!(limiter.try_acquire().is_some());
+    }
+
+    #[tokio::test]
+    async fn background_work_leaves_a_slot_for_contributions() {
+        tokio::time::pause();
+        assert!(VerificationLimiter::new(1).acquire_spare().await.is_none());
+
+        let limiter = VerificationLimiter::new(2);
+        let contribution = limiter.try_acquire().unwrap();
+        let waiting = limiter.clone();
+        let background = tokio::spawn(async move { waiting.acquire_spare().await });
+        tokio::time::sleep(SPARE_SLOT_POLL_INTERVAL * 3).await;
+        assert!(!background.is_finished());
+        assert!(!limiter.is_saturated());
+
+        drop(contribution);
+        let permit = background.await.unwrap();
+        assert!(permit.is_some());
+        assert!(limiter.try_acquire().is_some());
+    }
+
+    #[tokio::test]
+    async fn full_transcript_is_verified_in_a_slot() {
+        let limiter = VerificationLimiter::new(1);
+        let contribution = limiter.try_acquire().unwrap();
+        let transcript =
+            Arc::new(TestTranscript::default().update(&TestContribution::ValidContribution(1)));
+        let verifying = limiter.clone();
+        let verification = tokio::spawn(async move {
+            verifying
+                .verify_all(transcript, TestTranscript::default())
+                .await
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!verification.is_finished());
+
+        drop(contribution);
+        assert_eq!(verification.await.unwrap(), Ok(()));
+        assert!(!limiter.is_saturated());
+    }
+
+    #[test]
+    fn records_verification_throughput() {
+        let pairings_before = VERIFICATION_PAIRINGS.get();
+
+        assert!(verify_contribution(
+            &TestTranscript::default(),
+            &TestTranscript::default(),
+            &TestContribution::ValidContribution(1)
+        )
+        .is_ok());
+
+        assert!(VERIFICATION_POWERS_PER_SECOND.get() > 0.0);
+        assert!(VERIFIED_POWERS.get() >= 1);
+        assert!(VERIFICATION_PAIRINGS.get() >= pairings_before + 2);
+    }
+
+    #[test]
+    fn verifiers_agree_on_accept_and_reject() {
+        let full = FullVerifier::new(TestTranscript::default());
+        let fast = PreverifiedVerifier::new(b"verifier key", TestTranscript::default());
+        let forger = PreverifiedVerifier::new(b"some other key", TestTranscript::default());
+        let transcript = TestTranscript::default();
+        let hash = transcript_hash(&transcript);
+
+        for contribution in [
+            TestContribution::ValidContribution(1),
+            TestContribution::InvalidContribution(1),
+        ] {
+            let expected = full.verify(&transcript, &hash, &contribution, None).is_ok();
+            // The trusted verifier only attests contributions it checked
+            let honest_mac = expected.then(|| fast.mac(&transcript, &contribution));
+            let forged_mac = forger.mac(&transcript, &contribution);
+
+            for mac in [
+                honest_mac.as_deref(),
+                Some(forged_mac.as_str()),
+                Some("not hex"),
+                None,
+            ] {
+                assert_eq!(
+                    fast.verify(&transcript, &hash, &contribution, mac).is_ok(),
+                    expected
+                );
+            }
+        }
+    }
+
+    // Counts how often the full verification runs
+    struct CountingVerifier(AtomicUsize);
+
+    impl Verifier<TestTranscript> for CountingVerifier {
+        fn verify(
+            &self,
+            transcript: &TestTranscript,
+            transcript_hash: &TranscriptHash,
+            contribution: &TestContribution,
+            mac: Option<&str>,
+        ) -> Result<(), ()> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            PreverifiedVerifier::new(b"verifier key", TestTranscript::default()).verify(
+                transcript,
+                transcript_hash,
+                contribution,
+                mac,
+            )
+        }
+    }
+
+    #[test]
+    fn counts_rejections_by_failed_check() {
+        let rejection = RejectionFingerprint {
+            contribution_hash: String::new(),
+            reason:            r#"{"InvalidG1Power":[3,"BigIntError"]}"#.to_string(),
+        };
+        let before = REJECTED_CONTRIBUTIONS
+            .with_label_values(&["InvalidG1Power", "github"])
+            .get();
+        rejection.record("github");
+        assert_eq!(
+            REJECTED_CONTRIBUTIONS
+                .with_label_values(&["InvalidG1Power", "github"])
+                .get(),
+            before + 1
+        );
+    }
+
+    #[test]
+    fn caches_outcome_of_resubmission() {
+        let counter = Arc::new(CountingVerifier(AtomicUsize::new(0)));
+        let cached = CachedVerifier::new(counter.clone(), Duration::from_secs(60));
+        let transcript = TestTranscript::default();
+        let hash = transcript_hash(&transcript);
+
+        for _ in 0..3 {
+            assert!(cached
+                .verify(
+                    &transcript,
+                    &hash,
+                    &TestContribution::ValidContribution(1),
+                    None
+                )
+                .is_ok());
+            assert!(cached
+                .verify(
+                    &transcript,
+                    &hash,
+                    &TestContribution::InvalidContribution(1),
+                    None
+                )
+                .is_err());
+        }
+        assert_eq!(counter.0.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn cached_outcome_is_not_served_for_another_mac() {
+        let counter = Arc::new(CountingVerifier(AtomicUsize::new(0)));
+        let cached = CachedVerifier::new(counter.clone(), Duration::from_secs(60));
+        let fast = PreverifiedVerifier::new(b"verifier key", TestTranscript::default());
+        let transcript = TestTranscript::default();
+        let hash = transcript_hash(&transcript);
+        let contribution = TestContribution::InvalidContribution(1);
+
+        let mac = fast.mac(&transcript, &contribution);
+        assert!(cached
+            .verify(&transcript, &hash, &contribution, Some(&mac))
+            .is_ok());
+        assert!(cached
+            .verify(&transcript, &hash, &contribution, Some("forged"))
+            .is_err());
+        assert!(cached
+            .verify(&transcript, &hash, &contribution, None)
+            .is_err());
+        assert!(cached
+            .verify(&transcript, &hash, &contribution, Some(&mac))
+            .is_ok());
+        assert_eq!(counter.0.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn cache_is_invalidated_when_transcript_advances() {
+        let counter = Arc::new(CountingVerifier(AtomicUsize::new(0)));
+        let cached = CachedVerifier::new(counter.clone(), Duration::from_secs(60));
+        let transcript = TestTranscript::default();
+        let hash = transcript_hash(&transcript);
+        let contribution = TestContribution::ValidContribution(1);
+
+        assert!(cached
+            .verify(&transcript, &hash, &contribution, None)
+            .is_ok());
+        assert!(cached
+            .verify(&transcript, &hash, &contribution, None)
+            .is_ok());
+        assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+
+        // The same bytes against the new head must be verified from scratch
+        let transcript = transcript.update(&contribution);
+        let hash = transcript_hash(&transcript);
+        assert!(cached
+            .verify(&transcript, &hash, &contribution, None)
+            .is_ok());
+        assert_eq!(counter.0.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn mac_message_hashes_the_serialized_transcript_and_contribution() {
+        let transcript = TestTranscript::default().update(&TestContribution::ValidContribution(1));
+        let contribution = TestContribution::ValidContribution(2);
+        let mut buffered = serde_json::to_vec(&transcript).unwrap();
+        buffered.extend(serde_json::to_vec(&contribution).unwrap());
+        assert_eq!(
+            mac_message(&transcript, &contribution),
+            digest(&SHA256, &buffered).as_ref()
+        );
+    }
+
+    #[test]
+    fn preverification_mac_is_bound_to_transcript() {
+        let fast = PreverifiedVerifier::new(b"verifier key", TestTranscript::default());
+        let contribution = TestContribution::InvalidContribution(1);
+        let transcript = TestTranscript::default();
+        let later_transcript = transcript.update(&TestContribution::ValidContribution(2));
+
+        // A (misbehaving) attestation for one transcript doesn't carry over
+        let mac = fast.mac(&transcript, &contribution);
+        assert!(fast
+            .verify(
+                &transcript,
+                &transcript_hash(&transcript),
+                &contribution,
+                Some(&mac)
+            )
+            .is_ok());
+        assert!(fast
+            .verify(
+                &later_transcript,
+                &transcript_hash(&later_transcript),
+                &contribution,
+                Some(&mac)
+            )
+            .is_err());
+    }
+}
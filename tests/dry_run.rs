@@ -0,0 +1,100 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+// A directory of its own, so the test can tell whether the dry run wrote to it
+fn empty_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("{}_{}", name, std::process::id()));
+    fs::remove_dir_all(&dir).ok();
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn dry_run(dir: &Path, envs: &[(&str, &str)]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_kzg-ceremony-sequencer"))
+        .args([
+            "--dry-run",
+            "--private-key",
+            "private.key",
+            "--public-key",
+            "publickey.pem",
+        ])
+        .env(
+            "DATABASE_URL",
+            format!("sqlite://{}", dir.join("sequencer.db").display()),
+        )
+        .env("TRANSCRIPT_FILE", dir.join("transcript.json"))
+        .env("ETH_RPC_URL", "http://127.0.0.1:8545")
+        .envs(
+            [
+                "GITHUB_CLIENT_ID",
+                "GITHUB_CLIENT_SECRET",
+                "SIWE_CLIENT_ID",
+                "SIWE_CLIENT_SECRET",
+            ]
+            .map(|name| (name, "dry-run")),
+        )
+        .envs(envs.iter().copied())
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn dry_run_leaves_a_new_deployment_untouched() {
+    let dir = empty_dir("dry_run_valid");
+
+    let output = dry_run(&dir, &[]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "{}", stdout);
+    assert!(stdout.contains("Deployment is valid"), "{}", stdout);
+    assert!(
+        stdout.contains("none, transcript will be created"),
+        "{}",
+        stdout
+    );
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+}
+
+#[test]
+fn dry_run_fails_on_an_invalid_config() {
+    let dir = empty_dir("dry_run_invalid");
+
+    let output = dry_run(&dir, &[("MAX_CONCURRENT_VERIFICATIONS", "0")]);
+    assert!(!output.status.success());
+    assert!(!String::from_utf8_lossy(&output.stdout).contains("Deployment is valid"));
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+}
+
+#[test]
+fn dry_run_reports_every_malformed_variable() {
+    let dir = empty_dir("dry_run_malformed");
+
+    let output = dry_run(&dir, &[
+        ("MAX_CONNECTIONS", "many"),
+        ("CEREMONY_SIZES", "4096"),
+        ("MAX_CONCURRENT_VERIFICATIONS", "0"),
+        ("SIWE_CLIENT_SECRET", ""),
+    ]);
+    assert!(!output.status.success());
+    let report = format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    for variable in [
+        "Invalid MAX_CONNECTIONS",
+        "Invalid CEREMONY_SIZES",
+        "MAX_CONCURRENT_VERIFICATIONS must be at least 1",
+        "Missing SIWE_CLIENT_SECRET",
+    ] {
+        assert!(
+            report.contains(variable),
+            "{} missing from {}",
+            variable,
+            report
+        );
+    }
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+}